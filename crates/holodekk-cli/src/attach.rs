@@ -0,0 +1,122 @@
+use std::io::{self, Read, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::thread;
+
+use clap::Args;
+use log::warn;
+use tokio::signal::unix::{signal, SignalKind};
+
+use holodekk::attach::{
+    AttachClient, AttachRequest, AttachResult, AttachStdin, FrameKind, WindowSize,
+};
+use holodekk::utils::libsee;
+
+use crate::logs::{subroutine_socket, LogsCommandError};
+
+#[derive(Args)]
+pub struct AttachOptions {
+    /// Subroutine to attach to.
+    subroutine: String,
+
+    /// Only show output, rather than also forwarding our stdin to the subroutine.
+    #[arg(long)]
+    no_stdin: bool,
+
+    /// Exec root path
+    #[arg(long, default_value = "/run/holodekk")]
+    exec_root: PathBuf,
+}
+
+/// Follows the subroutine's output, forwarding our stdin to it (until it ends, when its stdin
+/// is closed).
+pub fn execute(options: &AttachOptions) -> Result<(), LogsCommandError> {
+    let mut request = AttachRequest::default().with_follow();
+    if !options.no_stdin {
+        request = request.with_stdin();
+    }
+
+    let socket = subroutine_socket(&options.exec_root, &options.subroutine);
+    let mut client = AttachClient::connect(socket, &request)?;
+    if !options.no_stdin {
+        let stdin = client.stdin()?;
+        let (input, inputs) = mpsc::channel();
+        if client.tty() {
+            forward_resizes(input.clone())?;
+        }
+        thread::spawn(move || read_stdin(input));
+        thread::spawn(move || {
+            if let Err(err) = forward_input(stdin, inputs) {
+                warn!("Unable to forward stdin: {}", err);
+            }
+        });
+    }
+
+    while let Some(frame) = client.next_frame()? {
+        match frame.kind {
+            FrameKind::Stdout => io::stdout().write_all(&frame.payload)?,
+            FrameKind::Stderr => io::stderr().write_all(&frame.payload)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Input for the subroutine, sent by a single writer (so frames aren't interleaved).
+enum Input {
+    Data(Vec<u8>),
+    Resize(WindowSize),
+    Close,
+}
+
+fn read_stdin(input: Sender<Input>) {
+    let mut stdin = io::stdin().lock();
+    let mut buf = [0; 4096];
+    loop {
+        match stdin.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => {
+                if input.send(Input::Data(buf[..read].to_vec())).is_err() {
+                    return;
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => {
+                warn!("Unable to read stdin: {}", err);
+                break;
+            }
+        }
+    }
+    let _ = input.send(Input::Close);
+}
+
+fn forward_input(mut stdin: AttachStdin, inputs: Receiver<Input>) -> AttachResult<()> {
+    for input in inputs {
+        match input {
+            Input::Data(data) => stdin.write_all(&data)?,
+            Input::Resize(size) => stdin.resize(size)?,
+            Input::Close => return stdin.close(),
+        }
+    }
+    Ok(())
+}
+
+/// Sizes the subroutine's terminal to match ours, now and whenever ours is resized.
+fn forward_resizes(input: Sender<Input>) -> io::Result<()> {
+    let mut resized = signal(SignalKind::window_change())?;
+    let resize = move || match libsee::window_size(libsee::STDOUT_FILENO) {
+        Ok((rows, cols)) => input.send(Input::Resize(WindowSize { rows, cols })).is_ok(),
+        // not a terminal (so never resized)
+        Err(_) => false,
+    };
+    if resize() {
+        tokio::spawn(async move {
+            while resized.recv().await.is_some() {
+                if !resize() {
+                    break;
+                }
+            }
+        });
+    }
+    Ok(())
+}
@@ -0,0 +1,150 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use clap::Subcommand;
+use thiserror::Error;
+
+use holodekk::bundles::{image_from_directory, write_bundle, BundleCache, BundleError};
+use holodekk::entities::SubroutineEntityId;
+use holodekk::runtimes::RuntimeRegistry;
+use holodekk::secrets::{SecretError, SecretStore};
+use holodekk::shim::ShimLauncher;
+use holodekk::utils::process::DaemonizeError;
+use holodekk::HolodekkPaths;
+
+#[derive(Subcommand)]
+pub enum BundleCommands {
+    /// Package a subroutine into a single runnable bundle.
+    Build {
+        /// Directory where the subroutine is located.
+        #[arg(short, long, default_value = "holodekk")]
+        directory: PathBuf,
+
+        /// Name of the bundled image.
+        #[arg(short, long)]
+        name: String,
+
+        /// File to write the bundle to.
+        #[arg(short, long)]
+        output: PathBuf,
+    },
+    /// Run a bundle under the subroutine shim.
+    Run {
+        /// Bundle to run.
+        bundle: PathBuf,
+
+        /// Name of the subroutine to run
+        #[arg(long, default_value = "default")]
+        subroutine: String,
+
+        /// Data root path
+        #[arg(long, default_value = "/var/lib/holodekk")]
+        data_root: PathBuf,
+
+        /// Exec root path
+        #[arg(long, default_value = "/run/holodekk")]
+        exec_root: PathBuf,
+
+        /// Holodekk bin directory
+        #[arg(long, default_value = "/usr/local/bin/")]
+        bin_path: PathBuf,
+    },
+}
+
+#[derive(Debug, Error)]
+pub enum BundleCommandError {
+    #[error("Invalid bundle: {0}")]
+    Bundle(#[from] BundleError),
+    #[error("Invalid subroutine environment: {0}")]
+    Environment(#[from] SecretError),
+    #[error("Unable to launch subroutine shim: {0}")]
+    Shim(#[from] DaemonizeError),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+pub fn execute(command: &BundleCommands) -> Result<(), BundleCommandError> {
+    match command {
+        BundleCommands::Build {
+            directory,
+            name,
+            output,
+        } => {
+            let image = image_from_directory(
+                &name.as_str().into(),
+                directory,
+                &RuntimeRegistry::default(),
+            );
+            write_bundle(&image, BufWriter::new(File::create(output)?))?;
+            println!("Bundled {} into {}", image.name, output.display());
+        }
+        BundleCommands::Run {
+            bundle,
+            subroutine,
+            data_root,
+            exec_root,
+            bin_path,
+        } => {
+            let paths = Arc::new(HolodekkPaths::new(data_root, exec_root, bin_path));
+            let image = BundleCache::new(&paths).unpack(bundle)?;
+
+            let id = SubroutineEntityId::generate();
+            let launcher = ShimLauncher::new(paths.clone(), Arc::new(SecretStore::new(&paths)));
+            let environment = launcher.image_environment(&image)?;
+            let pid = launcher.launch_image(&id, &image, subroutine, environment)?;
+            println!(
+                "Running {} as subroutine {} (shim pid {})",
+                image.name, id, pid
+            );
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use holodekk::entities::MANIFEST_FILE;
+    use holodekk::enums::SubroutineKind;
+    use holodekk::shim::SubroutineSpec;
+
+    use super::*;
+
+    #[test]
+    fn runs_built_bundles_from_their_manifest() {
+        let temp = tempdir().unwrap();
+        let directory = temp.path().join("src");
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("holodekk.rb"), "puts 'hello'\n").unwrap();
+        fs::write(
+            directory.join(MANIFEST_FILE),
+            r#"{"name": "widgets", "port": 4567}"#,
+        )
+        .unwrap();
+        let output = temp.path().join("widgets.hdk");
+
+        execute(&BundleCommands::Build {
+            directory,
+            name: "acme/widgets".into(),
+            output: output.clone(),
+        })
+        .unwrap();
+        // unpacked as `bundle run` does, before launching its shim
+        let paths = HolodekkPaths::new(
+            temp.path().join("data"),
+            temp.path().join("run"),
+            temp.path().join("bin"),
+        );
+        let image = BundleCache::new(&paths).unpack(&output).unwrap();
+
+        assert_eq!(image.name, "acme/widgets".into());
+        assert_eq!(image.kind, SubroutineKind::Ruby);
+        assert!(image.path.join("holodekk.rb").exists());
+        assert_eq!(SubroutineSpec::for_image(&image).host_port, Some(4567));
+    }
+}
@@ -1,3 +1,6 @@
+pub mod attach;
+pub mod bundle;
+pub mod logs;
 pub mod runtime;
 
 use std::path::PathBuf;
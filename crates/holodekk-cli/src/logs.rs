@@ -0,0 +1,100 @@
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Utc};
+use clap::Args;
+use thiserror::Error;
+
+use holodekk::attach::{AttachClient, AttachError, AttachRequest, Backpressure, FrameKind};
+use holodekk::logs::OutputStream;
+
+#[derive(Args)]
+pub struct LogsOptions {
+    /// Subroutine to show the output of.
+    subroutine: String,
+
+    /// Only show the last N lines of the log.
+    #[arg(short = 'n', long)]
+    tail: Option<usize>,
+
+    /// Only show output since this time (RFC 3339).
+    #[arg(long)]
+    since: Option<DateTime<Utc>>,
+
+    /// Keep following the subroutine's output.
+    #[arg(short, long)]
+    follow: bool,
+
+    /// If output can't be shown fast enough, skip the oldest rather than giving up.
+    #[arg(long)]
+    drop_oldest: bool,
+
+    /// If output can't be shown fast enough, hold up to this many bytes of it (skipping the
+    /// oldest beyond that) rather than giving up.
+    #[arg(long, conflicts_with = "drop_oldest")]
+    buffer: Option<usize>,
+
+    /// Only show stdout.
+    #[arg(long, conflicts_with = "stderr")]
+    stdout: bool,
+
+    /// Only show stderr.
+    #[arg(long)]
+    stderr: bool,
+
+    /// Exec root path
+    #[arg(long, default_value = "/run/holodekk")]
+    exec_root: PathBuf,
+}
+
+#[derive(Debug, Error)]
+pub enum LogsCommandError {
+    #[error("Unable to attach to subroutine: {0}")]
+    Attach(#[from] AttachError),
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+}
+
+pub fn execute(options: &LogsOptions) -> Result<(), LogsCommandError> {
+    let mut request = AttachRequest::default();
+    if let Some(tail) = options.tail {
+        request = request.with_tail(tail);
+    }
+    if let Some(since) = options.since {
+        request = request.with_since(since);
+    }
+    if options.stdout {
+        request = request.with_streams(vec![OutputStream::Stdout]);
+    } else if options.stderr {
+        request = request.with_streams(vec![OutputStream::Stderr]);
+    }
+    if options.follow {
+        request = request.with_follow();
+    }
+    if let Some(bytes) = options.buffer {
+        request = request.with_backpressure(Backpressure::RingBuffer { bytes });
+    } else if options.drop_oldest {
+        request = request.with_backpressure(Backpressure::DropOldest);
+    }
+
+    let socket = subroutine_socket(&options.exec_root, &options.subroutine);
+    let mut client = AttachClient::connect(socket, &request)?;
+    while let Some(frame) = client.next_frame()? {
+        match frame.kind {
+            FrameKind::Stdout => io::stdout().write_all(&frame.payload)?,
+            FrameKind::Stderr => io::stderr().write_all(&frame.payload)?,
+            FrameKind::Dropped => eprintln!("[{} bytes skipped]", frame.dropped_bytes()?),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// The attach socket of the subroutine's shim.
+pub(crate) fn subroutine_socket(exec_root: &Path, subroutine: &str) -> PathBuf {
+    let mut socket = exec_root.to_owned();
+    socket.push("subroutines");
+    socket.push(subroutine);
+    socket.push("log.sock");
+    socket
+}
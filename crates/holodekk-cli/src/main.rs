@@ -1,5 +1,9 @@
 use clap::{Parser, Subcommand};
 
+use holodekk_cli::attach::{self, AttachOptions};
+use holodekk_cli::bundle::{self, BundleCommands};
+use holodekk_cli::logs::{self, LogsOptions};
+
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 pub struct Options {
@@ -18,11 +22,41 @@ pub enum Commands {
         #[arg(default_value = "default")]
         name: String,
     },
+    /// Build and run self-contained subroutine bundles
+    Bundle {
+        #[command(subcommand)]
+        command: BundleCommands,
+    },
+    /// Show (and follow) a running subroutine's output
+    Logs(LogsOptions),
+    /// Follow a running subroutine's output, forwarding stdin to it
+    Attach(AttachOptions),
 }
 
 #[tokio::main]
 async fn main() -> std::io::Result<()> {
-    // let options = Options::parse();
+    let options = Options::parse();
+
+    if let Commands::Bundle { command } = &options.command {
+        if let Err(err) = bundle::execute(command) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
+
+    if let Commands::Logs(logs_options) = &options.command {
+        if let Err(err) = logs::execute(logs_options) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
+
+    if let Commands::Attach(attach_options) = &options.command {
+        if let Err(err) = attach::execute(attach_options) {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    }
 
     // Start a Holodekk
     // let holodekk_options = HolodekkConfig {
@@ -1,23 +1,23 @@
 use std::path::{Path, PathBuf};
 
 use holodekk::repositories::RepositoryKind;
-use holodekk_common::config::HolodekkConfig;
+use holodekk::shim::{CONTROL_SOCKET, STATE_FILE};
+use holodekk::HolodekkPaths;
 
 #[derive(Clone, Debug)]
 pub struct SubroutineConfig {
     _path: PathBuf,
-    data_root: PathBuf,
-    exec_root: PathBuf,
-    projectors_root: PathBuf,
-    subroutines_root: PathBuf,
-    bin_root: PathBuf,
-    repo_kind: RepositoryKind,
+    _paths: HolodekkPaths,
+    _repo_kind: RepositoryKind,
     // projector_socket: PathBuf,
     _subroutine_id: String,
     shim_pidfile: PathBuf,
     pidfile: PathBuf,
     logfile: PathBuf,
     log_socket: PathBuf,
+    control_socket: PathBuf,
+    state_file: PathBuf,
+    rootfs: PathBuf,
 }
 
 impl SubroutineConfig {
@@ -35,15 +35,11 @@ impl SubroutineConfig {
         S: Into<String>,
     {
         let path: PathBuf = path.into();
-
-        let mut projectors_root = exec_root.as_ref().to_owned();
-        projectors_root.push("projectors");
-        let mut subroutines_root = exec_root.as_ref().to_owned();
-        subroutines_root.push("subroutines");
+        let paths = HolodekkPaths::new(data_root, exec_root, bin_root);
 
         let subroutine_id: String = subroutine_id.into();
 
-        let mut root = subroutines_root.clone();
+        let mut root = paths.subroutines_root().clone();
         root.push(subroutine_id.clone());
 
         let mut shim_pidfile = root.clone();
@@ -55,23 +51,31 @@ impl SubroutineConfig {
         let mut logfile = root.clone();
         logfile.push("subroutine.log");
 
-        let mut log_socket = root;
+        let mut log_socket = root.clone();
         log_socket.push("log.sock");
 
+        let mut control_socket = root.clone();
+        control_socket.push(CONTROL_SOCKET);
+
+        let mut state_file = root.clone();
+        state_file.push(STATE_FILE);
+
+        let mut rootfs = root;
+        rootfs.push("rootfs");
+
         Self {
             _path: path,
-            data_root: data_root.into(),
-            exec_root: exec_root.into(),
-            projectors_root,
-            subroutines_root,
-            bin_root: bin_root.into(),
-            repo_kind,
+            _paths: paths,
+            _repo_kind: repo_kind,
             // projector_socket: projector_socket.into(),
             _subroutine_id: subroutine_id,
             shim_pidfile,
             pidfile,
             logfile,
             log_socket,
+            control_socket,
+            state_file,
+            rootfs,
         }
     }
 
@@ -99,33 +103,25 @@ impl SubroutineConfig {
         &self.log_socket
     }
 
-    // pub fn subroutine_id(&self) -> &str {
-    //     &self.subroutine_id
-    // }
-}
-
-impl HolodekkConfig for SubroutineConfig {
-    fn data_root(&self) -> &PathBuf {
-        &self.data_root
-    }
-
-    fn exec_root(&self) -> &PathBuf {
-        &self.exec_root
+    pub fn control_socket(&self) -> &PathBuf {
+        &self.control_socket
     }
 
-    fn projectors_root(&self) -> &PathBuf {
-        &self.projectors_root
+    /// Where the shim's state is recorded.
+    pub fn state_file(&self) -> &PathBuf {
+        &self.state_file
     }
 
-    fn subroutines_root(&self) -> &PathBuf {
-        &self.subroutines_root
+    /// Where the subroutine's root filesystem is composed (when it's built from layers).
+    pub fn rootfs(&self) -> &PathBuf {
+        &self.rootfs
     }
 
-    fn bin_root(&self) -> &PathBuf {
-        &self.bin_root
+    pub fn layers_root(&self) -> &PathBuf {
+        self._paths.layers_root()
     }
 
-    fn repo_kind(&self) -> RepositoryKind {
-        self.repo_kind
-    }
+    // pub fn subroutine_id(&self) -> &str {
+    //     &self.subroutine_id
+    // }
 }
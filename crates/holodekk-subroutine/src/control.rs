@@ -0,0 +1,117 @@
+use std::io::{ErrorKind, Read, Write};
+
+use mio::net::UnixStream;
+
+use holodekk::shim::{ControlRequest, ControlResponse};
+
+/// A client connected to the shim's control socket (see [`holodekk::shim::ShimClient`]).
+///
+/// Requests are read as they arrive, and responses queued for delivery as the client will take
+/// them, so a slow client never holds up the event loop.
+pub(crate) struct ControlConnection {
+    stream: UnixStream,
+    /// Partial request line (still waiting on its newline).
+    pending: Vec<u8>,
+    buffer: Vec<u8>,
+    /// Waiting on the subroutine to stop.
+    stopping: bool,
+    subscribed: bool,
+    hung_up: bool,
+}
+
+impl ControlConnection {
+    pub fn new(stream: UnixStream) -> Self {
+        Self {
+            stream,
+            pending: vec![],
+            buffer: vec![],
+            stopping: false,
+            subscribed: false,
+            hung_up: false,
+        }
+    }
+
+    pub fn stream(&mut self) -> &mut UnixStream {
+        &mut self.stream
+    }
+
+    /// Reads whatever the client has sent, returning the complete requests.
+    ///
+    /// Requests which can't be parsed are answered with an error right away.
+    pub fn read_requests(&mut self) -> std::io::Result<Vec<ControlRequest>> {
+        let mut buf = [0; 4096];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => {
+                    self.hung_up = true;
+                    break;
+                }
+                Ok(n) => self.pending.extend_from_slice(&buf[..n]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+
+        let mut requests = Vec::new();
+        while let Some(end) = self.pending.iter().position(|c| *c == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=end).collect();
+            match serde_json::from_slice(&line) {
+                Ok(request) => requests.push(request),
+                Err(err) => self.send(&ControlResponse::Error {
+                    message: format!("invalid request: {}", err),
+                }),
+            }
+        }
+        Ok(requests)
+    }
+
+    pub fn hung_up(&self) -> bool {
+        self.hung_up
+    }
+
+    pub fn stopping(&self) -> bool {
+        self.stopping
+    }
+
+    pub fn set_stopping(&mut self) {
+        self.stopping = true;
+    }
+
+    pub fn subscribed(&self) -> bool {
+        self.subscribed
+    }
+
+    pub fn subscribe(&mut self) {
+        self.subscribed = true;
+    }
+
+    /// Queues a response.
+    pub fn send(&mut self, response: &ControlResponse) {
+        serde_json::to_writer(&mut self.buffer, response).expect("responses always serialize");
+        self.buffer.push(b'\n');
+    }
+
+    pub fn data_pending(&self) -> bool {
+        !self.buffer.is_empty()
+    }
+
+    /// Sends as much of the pending responses as the client will take.
+    pub fn deliver_data(&mut self) -> std::io::Result<()> {
+        let mut written = 0;
+        let res = loop {
+            if written == self.buffer.len() {
+                break Ok(());
+            }
+            match self.stream.write(&self.buffer[written..]) {
+                Ok(0) => break Err(ErrorKind::WriteZero.into()),
+                Ok(n) => written += n,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break Ok(()),
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => break Err(err),
+            }
+        };
+        self.buffer.drain(..written);
+        res
+    }
+}
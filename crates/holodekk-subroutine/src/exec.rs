@@ -0,0 +1,148 @@
+use std::cell::RefCell;
+use std::io::Result;
+use std::os::fd::{AsRawFd, IntoRawFd, OwnedFd, RawFd};
+use std::rc::Rc;
+
+use log::warn;
+use mio::{event::Source, Interest, Registry, Token};
+
+use nix::{
+    errno::Errno,
+    fcntl::{fcntl, FcntlArg, OFlag},
+    sys::signal::{kill, SIGKILL},
+    unistd::{close, pipe2, Pid},
+};
+
+use holodekk::attach::{Frame, FrameKind};
+
+use super::launcher::Launcher;
+use super::streams::{LogStream, LogStreamKind, StdinPipe, StdioSink};
+
+/// A process exec'd alongside the subroutine, on behalf of an attached client.
+///
+/// Its output goes to that client alone (it isn't logged), and the client's input (if it asked
+/// for stdin) to it.  Without stdin, the process reads EOF.
+pub(crate) struct ExecProcess {
+    pid: Pid,
+    stdout: LogStream,
+    stderr: LogStream,
+    stdin: Option<StdinPipe>,
+    /// The shim's ends of the output pipes (which the streams read, but don't close).
+    pipes: [RawFd; 2],
+    /// Tokens for stdout, stderr and stdin (in that order).
+    tokens: [Token; 3],
+}
+
+impl ExecProcess {
+    /// Runs the command (see [`Launcher::exec`]), scattering its output to the given sink.
+    pub fn spawn(
+        launcher: &Launcher,
+        command: &[String],
+        stdin: bool,
+        sink: (Token, Rc<RefCell<StdioSink>>),
+        tokens: [Token; 3],
+        registry: &Registry,
+    ) -> Result<Self> {
+        let (stdin_rd, stdin_wr) = pipe()?;
+        let (stdout_rd, stdout_wr) = pipe()?;
+        let (stderr_rd, stderr_wr) = pipe()?;
+        let pid = launcher.exec(
+            command,
+            stdin_rd.as_raw_fd(),
+            stdout_wr.as_raw_fd(),
+            stderr_wr.as_raw_fd(),
+        )?;
+        // the process has its own copies of these
+        drop((stdin_rd, stdout_wr, stderr_wr));
+
+        let pipes = [stdout_rd.into_raw_fd(), stderr_rd.into_raw_fd()];
+        for fd in pipes {
+            fcntl(fd, FcntlArg::F_SETFL(OFlag::O_NONBLOCK))?;
+        }
+
+        let (sink_token, sink) = sink;
+        let mut stdout = LogStream::new(pipes[0], LogStreamKind::Stdout);
+        let mut stderr = LogStream::new(pipes[1], LogStreamKind::Stderr);
+        for (stream, token) in [(&mut stdout, tokens[0]), (&mut stderr, tokens[1])] {
+            stream.add_sink(sink_token, sink.clone());
+            stream.register(registry, token, Interest::READABLE)?;
+        }
+
+        Ok(Self {
+            pid,
+            stdout,
+            stderr,
+            // closing our end of an unused stdin leaves the process reading EOF
+            stdin: stdin.then(|| StdinPipe::new(stdin_wr.into_raw_fd())),
+            pipes,
+            tokens,
+        })
+    }
+
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    pub fn tokens(&self) -> [Token; 3] {
+        self.tokens
+    }
+
+    pub fn has_stdin(&self) -> bool {
+        self.stdin.is_some()
+    }
+
+    /// Handles readiness of one of the process' pipes.
+    pub fn handle_event(&mut self, token: Token, registry: &Registry) -> Result<()> {
+        if token == self.tokens[2] {
+            if let Some(stdin) = self.stdin.as_mut() {
+                stdin.deliver(registry, token)?;
+            }
+            Ok(())
+        } else if token == self.tokens[0] {
+            drain(&mut self.stdout)
+        } else {
+            drain(&mut self.stderr)
+        }
+    }
+
+    /// Forwards input (a `Stdin` or `CloseStdin` frame) to the process.
+    pub fn input(&mut self, frame: &Frame, registry: &Registry) -> Result<()> {
+        let Some(stdin) = self.stdin.as_mut() else {
+            return Ok(());
+        };
+        match frame.kind {
+            FrameKind::CloseStdin => stdin.close(registry, self.tokens[2]),
+            _ => stdin.write(&frame.payload, registry, self.tokens[2]),
+        }
+    }
+
+    /// Kills the process (which is then reaped like any other child).
+    pub fn kill(&self) {
+        match kill(self.pid, SIGKILL) {
+            Ok(()) | Err(Errno::ESRCH) => {}
+            Err(err) => warn!("Failed to kill exec'd process {}: {}", self.pid, err),
+        }
+    }
+
+    /// Scatters whatever output is left, then closes the pipes.
+    pub fn finish(mut self, registry: &Registry) -> Result<()> {
+        drain(&mut self.stdout)?;
+        drain(&mut self.stderr)?;
+        self.stdout.deregister(registry)?;
+        self.stderr.deregister(registry)?;
+        for fd in self.pipes {
+            close(fd)?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads until the (non-blocking) pipe is empty, or closed.
+fn drain(stream: &mut LogStream) -> Result<()> {
+    while stream.scatter()? > 0 {}
+    Ok(())
+}
+
+fn pipe() -> Result<(OwnedFd, OwnedFd)> {
+    Ok(pipe2(OFlag::O_CLOEXEC)?)
+}
@@ -0,0 +1,200 @@
+use std::fs::OpenOptions;
+use std::io::Result;
+use std::os::fd::AsRawFd;
+use std::sync::{
+    mpsc::{channel, Receiver, Sender},
+    Arc,
+};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{debug, info, warn};
+use mio::{Registry, Token, Waker};
+use nix::{
+    errno::Errno,
+    sys::signal::{kill, SIGKILL},
+    unistd::Pid,
+};
+
+use holodekk::enums::SubroutineHealth;
+use holodekk::health::{
+    exec_result, HealthMonitor, HealthProbe, HealthProbeError, HealthProbeResult,
+};
+
+use super::launcher::Launcher;
+use super::signals::SignalHandler;
+
+/// Runs a subroutine's health probe on behalf of the [Server](super::server::Server).
+///
+/// Probes block (for up to their timeout), so each one runs on its own thread, waking the event
+/// loop when it completes.  Exec probes are the exception: their commands are our children, so
+/// they're launched (in the subroutine's context) and reaped by the event loop itself, as any
+/// other child would be.
+pub struct HealthChecker {
+    monitor: HealthMonitor,
+    host_port: Option<u16>,
+    waker: Arc<Waker>,
+    results_tx: Sender<HealthProbeResult<()>>,
+    results_rx: Receiver<HealthProbeResult<()>>,
+    next_probe: Instant,
+    in_flight: bool,
+    /// The running exec probe (if any), and when it times out.
+    exec: Option<(Pid, Instant)>,
+}
+
+impl HealthChecker {
+    pub fn new(
+        registry: &Registry,
+        token: Token,
+        probe: HealthProbe,
+        host_port: Option<u16>,
+    ) -> Result<Self> {
+        let (results_tx, results_rx) = channel();
+        Ok(Self {
+            next_probe: Instant::now() + probe.initial_delay(),
+            monitor: HealthMonitor::new(probe),
+            host_port,
+            waker: Arc::new(Waker::new(registry, token)?),
+            results_tx,
+            results_rx,
+            in_flight: false,
+            exec: None,
+        })
+    }
+
+    /// Time remaining until the next probe is due (or the running exec probe times out).
+    pub fn timeout(&self) -> Option<Duration> {
+        if let Some((_, deadline)) = self.exec {
+            Some(deadline.saturating_duration_since(Instant::now()))
+        } else if self.in_flight {
+            None
+        } else {
+            Some(self.next_probe.saturating_duration_since(Instant::now()))
+        }
+    }
+
+    /// Starts the next probe, if it's due.
+    ///
+    /// Exec probes need a launcher to run their commands (and fail without one).
+    pub fn tick(&mut self, launcher: Option<&Launcher>, signals: &mut SignalHandler) {
+        if self.in_flight || Instant::now() < self.next_probe {
+            return;
+        }
+        self.in_flight = true;
+
+        if let Some(command) = self.monitor.probe().exec_command() {
+            let Some(launcher) = launcher else {
+                self.complete(Err(HealthProbeError::Failed(
+                    "exec probes aren't supported for this subroutine".to_string(),
+                )));
+                return;
+            };
+            match spawn_exec(launcher, command) {
+                Ok(pid) => {
+                    signals.track(pid);
+                    self.exec = Some((pid, Instant::now() + self.monitor.probe().timeout()));
+                }
+                Err(err) => self.complete(Err(HealthProbeError::Failed(format!(
+                    "unable to run {}: {}",
+                    command[0], err
+                )))),
+            }
+            return;
+        }
+
+        let probe = self.monitor.probe().clone();
+        let host_port = self.host_port;
+        let results = self.results_tx.clone();
+        let waker = self.waker.clone();
+        thread::spawn(move || {
+            let _ = results.send(probe.run(host_port));
+            if let Err(err) = waker.wake() {
+                warn!("Failed to wake event loop with probe result: {}", err);
+            }
+        });
+    }
+
+    /// Completes the running exec probe if its command has been reaped, or kills it if it has
+    /// timed out.
+    pub fn check_exec(&mut self, signals: &mut SignalHandler) -> Result<()> {
+        let Some((pid, deadline)) = self.exec else {
+            return Ok(());
+        };
+        let command = self.monitor.probe().exec_command().unwrap_or_default();
+        let result = if let Some(status) = signals.take_exit(pid) {
+            exec_result(command, status.into())
+        } else if Instant::now() >= deadline {
+            kill_exec(pid, signals)?;
+            Err(HealthProbeError::TimedOut(self.monitor.probe().timeout()))
+        } else {
+            return Ok(());
+        };
+        self.exec = None;
+        self.complete(result);
+        Ok(())
+    }
+
+    /// Hands a probe result to the event loop (as the probe threads do).
+    fn complete(&self, result: HealthProbeResult<()>) {
+        let _ = self.results_tx.send(result);
+        if let Err(err) = self.waker.wake() {
+            warn!("Failed to wake event loop with probe result: {}", err);
+        }
+    }
+
+    /// Starts over (with the probe's initial delay) for a new incarnation of the subroutine.
+    ///
+    /// The result of any probe still running against the old process is discarded (and a
+    /// running exec probe is killed).
+    pub fn reset(&mut self, signals: &mut SignalHandler) -> Result<()> {
+        if let Some((pid, _)) = self.exec.take() {
+            kill_exec(pid, signals)?;
+        }
+        let (results_tx, results_rx) = channel();
+        self.results_tx = results_tx;
+        self.results_rx = results_rx;
+        self.in_flight = false;
+        self.monitor = HealthMonitor::new(self.monitor.probe().clone());
+        self.next_probe = Instant::now() + self.monitor.probe().initial_delay();
+        Ok(())
+    }
+
+    /// Processes completed probes, returning the new health if it changed.
+    pub fn handle_results(&mut self) -> Option<SubroutineHealth> {
+        let mut changed = None;
+        while let Ok(result) = self.results_rx.try_recv() {
+            self.in_flight = false;
+            self.next_probe = Instant::now() + self.monitor.probe().interval();
+            if let Err(err) = result.as_ref() {
+                debug!("health probe failed: {}", err);
+            }
+            if let Some(health) = self.monitor.record(&result) {
+                info!("subroutine is now {:?}", health);
+                changed = Some(health);
+            }
+        }
+        changed
+    }
+}
+
+/// Launches an exec probe's command, with its stdio on `/dev/null`.
+fn spawn_exec(launcher: &Launcher, command: &[String]) -> Result<Pid> {
+    let dev_null = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")?;
+    let fd = dev_null.as_raw_fd();
+    let pid = launcher.exec(command, fd, fd, fd)?;
+    debug!("started exec probe {:?} with pid {}", command, pid);
+    Ok(pid)
+}
+
+/// Kills an exec probe, reaping it straight away.
+fn kill_exec(pid: Pid, signals: &mut SignalHandler) -> Result<()> {
+    match kill(pid, SIGKILL) {
+        Ok(()) | Err(Errno::ESRCH) => {}
+        Err(err) => warn!("Failed to kill exec probe {}: {}", pid, err),
+    }
+    signals.wait_for(pid)?;
+    Ok(())
+}
@@ -0,0 +1,439 @@
+use std::ffi::{CStr, CString, OsStr};
+use std::fs::{self, File};
+use std::io::Read;
+use std::os::fd::{AsRawFd, OwnedFd, RawFd};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use log::{debug, error};
+use nix::{
+    errno::Errno,
+    fcntl::OFlag,
+    sys::signal::{sigprocmask, SigSet, SigmaskHow, SIGKILL},
+    sys::wait::waitpid,
+    unistd::{dup2, fork, getpid, getppid, pipe2, ForkResult, Pid},
+};
+
+use holodekk::cgroups::Cgroup;
+use holodekk::privileges::{PreparedRunAs, RunAs};
+use holodekk::rlimits::ProcessLimits;
+use holodekk::runtimes::{LaunchCommand, RuntimeResult};
+use holodekk::utils::libsee;
+
+/// Forks (and re-forks, on restart) the subroutine process, along with any processes exec'd
+/// alongside it.
+///
+/// The stdio pipes are created once, by the shim, and shared by every incarnation of the
+/// subroutine; so log readers see a single, continuous stream.
+///
+/// Everything a forked child needs is built before the fork: the child only makes system calls
+/// (never allocating, locking or logging), and reports any failure to set itself up over a
+/// close-on-exec pipe before exiting.
+pub struct Launcher {
+    argv: Vec<CString>,
+    envp: Vec<CString>,
+    working_dir: PathBuf,
+    stdin: RawFd,
+    stdout: RawFd,
+    stderr: RawFd,
+    /// stdio is a terminal (which becomes the subroutine's controlling terminal).
+    tty: bool,
+    signal_mask: SigSet,
+    pidfile: PathBuf,
+    cgroup: Option<Cgroup>,
+    run_as: Option<PreparedRunAs>,
+    rlimits: Vec<(libsee::Resource, u64)>,
+}
+
+impl Launcher {
+    pub fn new(
+        command: &LaunchCommand,
+        signal_mask: SigSet,
+        pidfile: &PathBuf,
+    ) -> RuntimeResult<Self> {
+        Ok(Self {
+            argv: command.argv()?,
+            envp: command.envp()?,
+            working_dir: command.working_dir.to_owned(),
+            stdin: libsee::STDIN_FILENO,
+            stdout: libsee::STDOUT_FILENO,
+            stderr: libsee::STDERR_FILENO,
+            tty: false,
+            signal_mask,
+            pidfile: pidfile.to_owned(),
+            cgroup: None,
+            run_as: None,
+            rlimits: vec![],
+        })
+    }
+
+    pub fn with_stdio(self, stdin: RawFd, stdout: RawFd, stderr: RawFd) -> Self {
+        Self {
+            stdin,
+            stdout,
+            stderr,
+            ..self
+        }
+    }
+
+    /// Runs the subroutine with the given (slave) pseudo-terminal as its controlling terminal
+    /// and stdio.
+    pub fn with_tty(self, tty: RawFd) -> Self {
+        Self {
+            stdin: tty,
+            stdout: tty,
+            stderr: tty,
+            tty: true,
+            ..self
+        }
+    }
+
+    /// Moves the subroutine into the given cgroup before it's executed.
+    pub fn with_cgroup(self, cgroup: Cgroup) -> Self {
+        Self {
+            cgroup: Some(cgroup),
+            ..self
+        }
+    }
+
+    /// Drops to the given user and privileges before the subroutine is executed.
+    pub fn with_run_as(self, run_as: RunAs) -> Self {
+        Self {
+            run_as: Some(run_as.prepare()),
+            ..self
+        }
+    }
+
+    /// Applies the given rlimits before the subroutine is executed.
+    pub fn with_process_limits(self, process_limits: ProcessLimits) -> Self {
+        Self {
+            rlimits: process_limits.rlimits(),
+            ..self
+        }
+    }
+
+    /// Forks and executes the subroutine, returning its pid.
+    ///
+    /// A child that fails to set itself up exits (with 127) like a subroutine that fails
+    /// straight away, so the restart policy still applies; the failure is logged here.
+    pub fn spawn(&self) -> nix::Result<Pid> {
+        let (child, failure) =
+            self.launch(&self.argv, [self.stdin, self.stdout, self.stderr], self.tty)?;
+        debug!("forked child with pid: {}", child);
+        if let Some(failure) = failure {
+            error!("subroutine process {} failed to start: {}", child, failure);
+        }
+        if let Err(err) = fs::write(&self.pidfile, format!("{}", child)) {
+            panic!(
+                "write() to pidfile {} failed: {}",
+                self.pidfile.display(),
+                err
+            );
+        }
+        Ok(child)
+    }
+
+    /// Forks and executes another command in the subroutine's context (its working directory,
+    /// environment, cgroup, user and rlimits), with the given stdio.
+    ///
+    /// Fails (having reaped the child) if the command couldn't be executed.
+    pub fn exec(
+        &self,
+        command: &[String],
+        stdin: RawFd,
+        stdout: RawFd,
+        stderr: RawFd,
+    ) -> nix::Result<Pid> {
+        let argv = command
+            .iter()
+            .map(|arg| CString::new(arg.as_bytes()).map_err(|_| Errno::EINVAL))
+            .collect::<nix::Result<Vec<_>>>()?;
+        if argv.is_empty() {
+            return Err(Errno::EINVAL);
+        }
+        let (child, failure) = self.launch(&argv, [stdin, stdout, stderr], false)?;
+        if let Some(failure) = failure {
+            debug!("exec'd process {} failed to start: {}", child, failure);
+            let _ = waitpid(child, None);
+            return Err(failure.errno);
+        }
+        debug!("forked exec'd process with pid: {}", child);
+        Ok(child)
+    }
+
+    /// Forks and executes `argv`, returning the child's pid along with how it failed to set
+    /// itself up (if it did).
+    fn launch(
+        &self,
+        argv: &[CString],
+        stdio: [RawFd; 3],
+        tty: bool,
+    ) -> nix::Result<(Pid, Option<SetupFailure>)> {
+        let setup = ChildSetup {
+            program: resolve_program(&argv[0], &self.envp, &self.working_dir),
+            argv: null_terminated(argv),
+            envp: null_terminated(&self.envp),
+            working_dir: path_cstring(&self.working_dir)?,
+            cgroup_procs: self
+                .cgroup
+                .as_ref()
+                .map(|cgroup| path_cstring(&cgroup.path().join("cgroup.procs")))
+                .transpose()?,
+            stdio,
+            tty,
+            shim: getpid(),
+        };
+        let (failures_rd, failures_wr) = pipe2(OFlag::O_CLOEXEC)?;
+        match unsafe { fork() }? {
+            ForkResult::Parent { child, .. } => {
+                drop(failures_wr);
+                Ok((child, read_failure(failures_rd)?))
+            }
+            ForkResult::Child => {
+                let failure = match self.setup_child(&setup) {
+                    Ok(never) => match never {},
+                    Err(failure) => failure,
+                };
+                report_failure(failures_wr.as_raw_fd(), failure)
+            }
+        }
+    }
+
+    /// Sets up the forked child and executes its command, only returning on failure.
+    ///
+    /// Only system calls are made here (see [`Launcher`]).
+    fn setup_child(&self, setup: &ChildSetup) -> Result<std::convert::Infallible, SetupFailure> {
+        // ensure we die if our parent disappears
+        set_death_signal(setup.shim).map_err(SetupStep::DeathSignal.failed())?;
+
+        // join the cgroup before exec, so limits apply from the start (writing 0 moves the
+        // writing process)
+        if let Some(procs) = setup.cgroup_procs.as_ref() {
+            join_cgroup(procs).map_err(SetupStep::Cgroup.failed())?;
+        }
+
+        // restore signals
+        sigprocmask(SigmaskHow::SIG_SETMASK, Some(&self.signal_mask), None)
+            .map_err(SetupStep::Signals.failed())?;
+
+        // a terminal needs a session of its own to control
+        if setup.tty {
+            libsee::setsid()
+                .and_then(|_| libsee::set_controlling_terminal(setup.stdio[0]))
+                .map_err(libsee_errno)
+                .map_err(SetupStep::Terminal.failed())?;
+        }
+
+        // capture io (stdin is either /dev/null, or fed by attached clients)
+        for (fd, target) in setup.stdio.iter().zip([
+            libsee::STDIN_FILENO,
+            libsee::STDOUT_FILENO,
+            libsee::STDERR_FILENO,
+        ]) {
+            dup2(*fd, target).map_err(SetupStep::Stdio.failed())?;
+        }
+
+        // set rlimits while we can still raise hard limits
+        for (resource, limit) in self.rlimits.iter() {
+            libsee::setrlimit(*resource, *limit)
+                .map_err(libsee_errno)
+                .map_err(SetupStep::Rlimits.failed())?;
+        }
+
+        // drop privileges (last, as joining the cgroup needs them).  changing credentials
+        // clears the death signal, so it's set again afterwards.
+        if let Some(run_as) = self.run_as.as_ref() {
+            run_as
+                .apply()
+                .map_err(libsee_errno)
+                .map_err(SetupStep::Privileges.failed())?;
+            set_death_signal(setup.shim).map_err(SetupStep::DeathSignal.failed())?;
+        }
+
+        // launch the subroutine
+        libsee::chdir(&setup.working_dir)
+            .map_err(libsee_errno)
+            .map_err(SetupStep::WorkingDir.failed())?;
+        let Some(program) = setup.program.as_ref() else {
+            return Err(SetupStep::Exec.failed()(Errno::ENOENT));
+        };
+        let err = unsafe { libsee::execve(program, &setup.argv, &setup.envp) };
+        Err(SetupStep::Exec.failed()(libsee_errno(err)))
+    }
+}
+
+/// Everything a forked child needs, built before forking.
+struct ChildSetup {
+    /// Path to the program (`None` when it isn't on the `PATH`).
+    program: Option<CString>,
+    /// Null-terminated pointers into the argument strings (which outlive the fork).
+    argv: Vec<*const libsee::c_char>,
+    /// Null-terminated pointers into the environment strings.
+    envp: Vec<*const libsee::c_char>,
+    working_dir: CString,
+    cgroup_procs: Option<CString>,
+    stdio: [RawFd; 3],
+    tty: bool,
+    shim: Pid,
+}
+
+/// Steps the forked child takes before executing its command.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum SetupStep {
+    DeathSignal,
+    Cgroup,
+    Signals,
+    Terminal,
+    Stdio,
+    Rlimits,
+    Privileges,
+    WorkingDir,
+    Exec,
+}
+
+const SETUP_STEPS: [SetupStep; 9] = [
+    SetupStep::DeathSignal,
+    SetupStep::Cgroup,
+    SetupStep::Signals,
+    SetupStep::Terminal,
+    SetupStep::Stdio,
+    SetupStep::Rlimits,
+    SetupStep::Privileges,
+    SetupStep::WorkingDir,
+    SetupStep::Exec,
+];
+
+impl SetupStep {
+    fn failed(self) -> impl Fn(Errno) -> SetupFailure {
+        move |errno| SetupFailure { step: self, errno }
+    }
+}
+
+impl std::fmt::Display for SetupStep {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            SetupStep::DeathSignal => "set death signal",
+            SetupStep::Cgroup => "join cgroup",
+            SetupStep::Signals => "restore signals",
+            SetupStep::Terminal => "set controlling terminal",
+            SetupStep::Stdio => "redirect stdio",
+            SetupStep::Rlimits => "set rlimits",
+            SetupStep::Privileges => "drop privileges",
+            SetupStep::WorkingDir => "change to working directory",
+            SetupStep::Exec => "execute command",
+        })
+    }
+}
+
+/// A forked child's failure to set itself up (sent to the shim as the step and errno).
+#[derive(Debug)]
+struct SetupFailure {
+    step: SetupStep,
+    errno: Errno,
+}
+
+impl std::fmt::Display for SetupFailure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "failed to {}: {}", self.step, self.errno)
+    }
+}
+
+/// Sends the failure to the shim, then exits (in the forked child).
+fn report_failure(fd: RawFd, failure: SetupFailure) -> ! {
+    let mut report = [0u8; 8];
+    report[..4].copy_from_slice(&(failure.step as i32).to_ne_bytes());
+    report[4..].copy_from_slice(&(failure.errno as i32).to_ne_bytes());
+    let _ = libsee::write(fd, &report);
+    libsee::_exit(127)
+}
+
+/// Waits for the forked child to either exec (closing its end of the pipe) or report a failure.
+fn read_failure(fd: OwnedFd) -> nix::Result<Option<SetupFailure>> {
+    let mut report = vec![];
+    File::from(fd)
+        .read_to_end(&mut report)
+        .map_err(|err| Errno::from_raw(err.raw_os_error().unwrap_or(libsee::EIO)))?;
+    if report.len() < 8 {
+        return Ok(None);
+    }
+    let step = i32::from_ne_bytes(report[..4].try_into().unwrap());
+    let errno = i32::from_ne_bytes(report[4..8].try_into().unwrap());
+    Ok(Some(SetupFailure {
+        step: SETUP_STEPS
+            .get(step as usize)
+            .copied()
+            .unwrap_or(SetupStep::Exec),
+        errno: Errno::from_raw(errno),
+    }))
+}
+
+/// Has the kernel kill the calling (forked) process when the shim exits, failing if the shim
+/// already has (before the signal was set).
+fn set_death_signal(shim: Pid) -> nix::Result<()> {
+    libsee::prctl(
+        libsee::PR_SET_PDEATHSIG,
+        SIGKILL as libsee::c_ulong,
+        0,
+        0,
+        0,
+    )
+    .map_err(libsee_errno)?;
+    if getppid() != shim {
+        return Err(Errno::ESRCH);
+    }
+    Ok(())
+}
+
+/// Moves the calling (forked) process into the cgroup whose `cgroup.procs` file is given.
+fn join_cgroup(procs: &CStr) -> nix::Result<()> {
+    let fd =
+        libsee::open_cstr(procs, libsee::O_WRONLY | libsee::O_CLOEXEC).map_err(libsee_errno)?;
+    let written = libsee::write(fd, b"0");
+    let _ = libsee::close(fd);
+    written.map_err(libsee_errno)?;
+    Ok(())
+}
+
+/// Finds the program to execute, searching the `PATH` in `envp` (as `execvpe` would) when it's
+/// given by name alone.
+fn resolve_program(program: &CStr, envp: &[CString], working_dir: &Path) -> Option<CString> {
+    let name = program.to_bytes();
+    if name.is_empty() {
+        return None;
+    }
+    if name.contains(&b'/') {
+        return Some(program.to_owned());
+    }
+    let path = envp
+        .iter()
+        .find_map(|var| var.to_bytes().strip_prefix(b"PATH="))?;
+    path.split(|byte| *byte == b':')
+        .map(|dir| {
+            working_dir
+                .join(OsStr::from_bytes(dir))
+                .join(OsStr::from_bytes(name))
+        })
+        .find(|candidate| {
+            fs::metadata(candidate)
+                .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+                .unwrap_or(false)
+        })
+        .and_then(|candidate| CString::new(candidate.into_os_string().into_vec()).ok())
+}
+
+fn libsee_errno(err: libsee::Error) -> Errno {
+    Errno::from_raw(err.errno())
+}
+
+fn null_terminated(strings: &[CString]) -> Vec<*const libsee::c_char> {
+    strings
+        .iter()
+        .map(|string| string.as_ptr())
+        .chain(std::iter::once(std::ptr::null()))
+        .collect()
+}
+
+fn path_cstring(path: &Path) -> nix::Result<CString> {
+    CString::new(path.as_os_str().as_bytes()).map_err(|_| Errno::EINVAL)
+}
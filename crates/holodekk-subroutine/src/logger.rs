@@ -1,57 +1,62 @@
 use std::cell::RefCell;
-use std::fs::{File, OpenOptions};
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
-use chrono::Utc;
+use holodekk::logs::{LogFormat, LogRotation, LogWriter, OutputStream};
 
 pub struct Logger {
-    file: File,
+    log: LogWriter,
 }
 
 impl Logger {
-    pub fn new(path: &PathBuf) -> Self {
+    pub fn new(path: &PathBuf, format: LogFormat, rotation: LogRotation) -> Self {
         Self {
-            file: OpenOptions::new()
-                .create(true)
-                .write(true)
-                .truncate(true)
-                .open(path)
-                .unwrap(),
+            log: LogWriter::open(path, format)
+                .unwrap()
+                .with_rotation(rotation),
         }
     }
 
-    pub fn write(&mut self, stream: &'static str, buf: &[u8]) -> std::io::Result<()> {
-        for line in buf.split(|c| *c == b'\n').filter(|l| !l.is_empty()) {
-            let message = format!(
-                "{} {} {}\n",
-                Utc::now().to_rfc3339(),
-                stream,
-                String::from_utf8_lossy(line)
-            );
-            self.file.write_all(message.as_bytes())?;
-        }
-        Ok(())
+    pub fn path(&self) -> &Path {
+        self.log.path()
+    }
+
+    pub fn format(&self) -> LogFormat {
+        self.log.format()
+    }
+
+    pub fn write(&mut self, stream: OutputStream, buf: &[u8]) -> std::io::Result<()> {
+        self.log.write(stream, buf)
+    }
+
+    /// The last lines the subroutine wrote to stderr.
+    pub fn stderr_tail(&self) -> Vec<String> {
+        self.log.stderr_tail()
+    }
+
+    /// Writes out any unterminated lines.
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.log.flush()
     }
 }
 
 pub struct Writer {
     logger: Rc<RefCell<Logger>>,
-    stream: &'static str,
+    stream: OutputStream,
 }
 
 impl Writer {
     pub fn stdout(logger: Rc<RefCell<Logger>>) -> Self {
         Self {
             logger,
-            stream: "stdout",
+            stream: OutputStream::Stdout,
         }
     }
     pub fn stderr(logger: Rc<RefCell<Logger>>) -> Self {
         Self {
             logger,
-            stream: "stderr",
+            stream: OutputStream::Stderr,
         }
     }
 }
@@ -1,13 +1,18 @@
 mod config;
+mod control;
+mod exec;
+mod health;
+mod launcher;
 mod logger;
+mod reporter;
 mod server;
 mod signals;
 mod streams;
 
-use std::ffi::CString;
+use std::collections::HashMap;
 use std::fs::{self, File};
-use std::io::Write;
-use std::os::unix::io::FromRawFd;
+use std::io::{Read, Write};
+use std::os::unix::io::{FromRawFd, IntoRawFd};
 use std::panic;
 use std::path::PathBuf;
 use std::sync::Arc;
@@ -17,25 +22,41 @@ use clap::Parser;
 use log::{debug, error, info, warn, LevelFilter};
 
 use nix::{
-    fcntl::OFlag,
+    fcntl::{fcntl, FcntlArg, FdFlag, OFlag},
     sys::{
         signal::{
             kill, sigprocmask, SigSet, SigmaskHow, SIGCHLD, SIGINT, SIGKILL, SIGQUIT, SIGTERM,
         },
         wait::waitpid,
     },
-    unistd::{close, dup2, execv, fork, pipe2, setsid, ForkResult, Pid},
+    unistd::{dup2, fork, pipe2, setsid, ForkResult, Pid},
 };
 
 use syslog::{BasicLogger, Facility, Formatter3164};
 
+use holodekk::cgroups::{
+    Cgroup, Cgroups, ResourceLimits, DEFAULT_CGROUP_ROOT, DEFAULT_CGROUP_SLICE,
+};
+use holodekk::entities::{SceneEntityId, SubroutineEntityId};
+use holodekk::enums::SubroutineKind;
+use holodekk::health::HealthProbe;
+use holodekk::images::SubroutineImageId;
+use holodekk::logs::{LogFormat, LogRotation};
+use holodekk::privileges::RunAs;
 use holodekk::repositories::RepositoryKind;
-use holodekk_common::process::PidSyncMessage;
-use holodekk_common::utils::libsee;
+use holodekk::restart::{RestartPolicy, RestartTracker};
+use holodekk::rlimits::ProcessLimits;
+use holodekk::rootfs::{LayerCache, Rootfs};
+use holodekk::runtimes::RuntimeRegistry;
+use holodekk::shim::{ShimState, SubroutineSpec};
+use holodekk::utils::libsee;
+use holodekk::utils::process::PidSyncMessage;
 
 use config::SubroutineConfig;
+use launcher::Launcher;
+use reporter::Reporter;
 use server::Server;
-use signals::signal_mask;
+use signals::{signal_mask, ExitStatus};
 use streams::open_dev_null;
 
 #[derive(Debug, Parser)]
@@ -57,21 +78,137 @@ pub struct Options {
     #[arg(long = "sync-pipe")]
     syncpipe_fd: Option<i32>,
 
+    /// FD to read the subroutine's environment from (a JSON object of variables)
+    #[arg(long = "environment-fd")]
+    environment_fd: Option<i32>,
+
     /// Subroutine ID
     #[arg(long = "id", value_name = "subroutine id", required = true)]
     subroutine_id: String,
 
-    /// Path to the subroutine to be executed
+    /// Path to the subroutine to be executed (within the root filesystem, if layers are given)
     #[arg(long, required = true)]
     path: PathBuf,
 
+    /// File (JSON) describing how to run the subroutine; other options override it
+    #[arg(long)]
+    spec: Option<PathBuf>,
+
+    /// Kind of subroutine (selects the runtime used to launch it)
+    #[arg(long, value_parser = parse_kind)]
+    kind: Option<SubroutineKind>,
+
+    /// Program to run, instead of the runtime's launch command
+    #[arg(long)]
+    command: Option<String>,
+
+    /// Argument appended to the launch command (repeat for each argument)
+    #[arg(long = "arg", value_name = "arg", allow_hyphen_values = true)]
+    args: Vec<String>,
+
+    /// Layer (tar archive) to build the subroutine's root filesystem from; repeat for each
+    /// layer, base first
+    #[arg(long = "layer", value_name = "archive")]
+    layers: Vec<PathBuf>,
+
     /// Variant to execute
     #[arg(long = "subroutine", value_name = "name", required = true)]
     subroutine: String,
 
-    /// Projector socket
-    #[arg(long, required = true)]
-    projector_socket: PathBuf,
+    /// Scene the subroutine belongs to
+    #[arg(long = "scene", value_name = "scene id")]
+    scene_id: Option<String>,
+
+    /// Image the subroutine was created from
+    #[arg(long = "image", value_name = "image id")]
+    image_id: Option<String>,
+
+    /// Host port allocated to the subroutine
+    #[arg(long)]
+    host_port: Option<u16>,
+
+    /// Health probe to run against the subroutine (as JSON)
+    #[arg(long, value_parser = parse_health_probe)]
+    health_probe: Option<HealthProbe>,
+
+    /// cgroup limits to apply to the subroutine (as JSON)
+    #[arg(long, value_parser = parse_limits)]
+    limits: Option<ResourceLimits>,
+
+    /// User and privileges to run the subroutine with (as JSON)
+    #[arg(long, value_parser = parse_run_as)]
+    run_as: Option<RunAs>,
+
+    /// rlimits and deadline to apply to the subroutine (as JSON)
+    #[arg(long, value_parser = parse_process_limits)]
+    process_limits: Option<ProcessLimits>,
+
+    /// Root of the cgroup v2 hierarchy
+    #[arg(long, default_value = DEFAULT_CGROUP_ROOT)]
+    cgroup_root: PathBuf,
+
+    /// cgroup the subroutine's group is created under
+    #[arg(long, default_value = DEFAULT_CGROUP_SLICE)]
+    cgroup_slice: String,
+
+    /// When to restart the subroutine (never, always or on-failure[:retries])
+    #[arg(long)]
+    restart_policy: Option<RestartPolicy>,
+
+    /// Format of the subroutine's log file (text or json)
+    #[arg(long)]
+    log_format: Option<LogFormat>,
+
+    /// Log rotation (and retention) settings (JSON)
+    #[arg(long, value_parser = parse_log_rotation)]
+    log_rotation: Option<LogRotation>,
+
+    /// Let attached clients write to the subroutine's stdin (otherwise it reads /dev/null)
+    #[arg(long)]
+    stdin: bool,
+
+    /// Run the subroutine in a pseudo-terminal (rather than with stdio pipes)
+    #[arg(long)]
+    tty: bool,
+
+    /// holodekkd API endpoint status and health changes are reported to
+    #[arg(long, default_value = "http://127.0.0.1:7979")]
+    api_endpoint: String,
+}
+
+fn parse_kind(kind: &str) -> Result<SubroutineKind, String> {
+    serde_json::from_value(serde_json::Value::String(kind.to_string()))
+        .map_err(|err| err.to_string())
+}
+
+fn parse_health_probe(json: &str) -> Result<HealthProbe, String> {
+    let probe: HealthProbe = serde_json::from_str(json).map_err(|err| err.to_string())?;
+    probe.validate().map_err(|err| err.to_string())?;
+    Ok(probe)
+}
+
+fn parse_limits(json: &str) -> Result<ResourceLimits, String> {
+    let limits: ResourceLimits = serde_json::from_str(json).map_err(|err| err.to_string())?;
+    limits.validate().map_err(|err| err.to_string())?;
+    Ok(limits)
+}
+
+fn parse_run_as(json: &str) -> Result<RunAs, String> {
+    let run_as: RunAs = serde_json::from_str(json).map_err(|err| err.to_string())?;
+    run_as.validate().map_err(|err| err.to_string())?;
+    Ok(run_as)
+}
+
+fn parse_process_limits(json: &str) -> Result<ProcessLimits, String> {
+    let limits: ProcessLimits = serde_json::from_str(json).map_err(|err| err.to_string())?;
+    limits.validate().map_err(|err| err.to_string())?;
+    Ok(limits)
+}
+
+fn parse_log_rotation(json: &str) -> Result<LogRotation, String> {
+    let rotation: LogRotation = serde_json::from_str(json).map_err(|err| err.to_string())?;
+    rotation.validate().map_err(|err| err.to_string())?;
+    Ok(rotation)
 }
 
 fn main() {
@@ -84,8 +221,40 @@ fn main() {
         &options.bin_path,
         RepositoryKind::Memory,
         &options.subroutine_id,
-        // &options.projector_socket,
     ));
+    let spec = load_spec(&options);
+
+    // assemble the root filesystem (if the subroutine is layered)
+    let rootfs = (!spec.layers.is_empty()).then(|| compose_rootfs(&spec.layers, &config));
+    let path = match rootfs.as_ref() {
+        Some(rootfs) => rootfs
+            .path()
+            .join(options.path.strip_prefix("/").unwrap_or(&options.path)),
+        None => options.path.clone(),
+    };
+
+    // resolve the command up front, so problems are reported before detaching
+    let launch_command = spec
+        .launch_command(&path, &RuntimeRegistry::default())
+        .expect("Unable to build subroutine launch command")
+        .envs(read_environment(options.environment_fd));
+
+    // and the ids recorded in the shim's state
+    let subroutine_id: SubroutineEntityId = options
+        .subroutine_id
+        .parse()
+        .expect("Invalid subroutine id");
+    let scene_id: Option<SceneEntityId> = options
+        .scene_id
+        .as_ref()
+        .map(|id| id.parse().expect("Invalid scene id"));
+    let image_id: Option<SubroutineImageId> = options
+        .image_id
+        .as_ref()
+        .map(|id| id.parse().expect("Invalid image id"));
+
+    // likewise the subroutine's cgroup (if it's constrained)
+    let cgroup = spec.limits.map(|limits| create_cgroup(&options, &limits));
 
     // Perform the initial fork
     match unsafe { fork() } {
@@ -124,76 +293,96 @@ fn main() {
     sigprocmask(SigmaskHow::SIG_BLOCK, Some(&signals), Some(&mut oldmask))
         .expect("failed to block signals");
 
-    // create our io pipes
-    let (main_stdout, worker_stdout) =
-        pipe2(OFlag::O_CLOEXEC).expect("Failed to create stdout pipes");
-    let (main_stderr, worker_stderr) =
-        pipe2(OFlag::O_CLOEXEC).expect("Failed to create stderr pipes");
-
-    // fork again
-    let child_pid = match unsafe { fork() } {
-        Ok(ForkResult::Parent { child, .. }) => {
-            write_child_pidfile(config.pidfile(), child);
-            child
+    // create our io pipes (or the subroutine's terminal).  the worker ends stay open, so
+    // restarts can reuse them.
+    let mut launcher = Launcher::new(&launch_command, oldmask, config.pidfile())
+        .expect("Invalid subroutine launch command");
+    let mut builder = Server::build();
+    if spec.tty {
+        let (master, slave) = libsee::openpty().expect("Failed to open subroutine terminal");
+        for fd in [master, slave] {
+            fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))
+                .expect("Failed to set close-on-exec for subroutine terminal");
         }
-        Ok(ForkResult::Child) => {
-            // ensure we die if our parent disappears
-            libsee::prctl(
-                libsee::PR_SET_PDEATHSIG,
-                SIGKILL as libsee::c_ulong,
-                0,
-                0,
-                0,
-            )
-            .expect("failed to set death signal");
-
-            // restore signals
-            sigprocmask(SigmaskHow::SIG_SETMASK, Some(&oldmask), None)
-                .expect("failed to restore signals");
-            info!("signals restored");
-
-            // close the master pipes
-            close(main_stdout).expect("Failed to close main stdout in worker process");
-            close(main_stderr).expect("Failed to close main stderr in worker process");
-
-            // capture io (ignoring stdin)
-            dup2(dev_null_rd, libsee::STDIN_FILENO)
-                .expect("Failed to redirect stdin to /dev/null in worker process");
-            dup2(worker_stdout, libsee::STDOUT_FILENO)
-                .expect("Failed to redirect stdout in worker process");
-            dup2(worker_stderr, libsee::STDERR_FILENO)
-                .expect("Failed to redirect stderr in worker process");
-
-            // launch the subroutine
-            // let argv = vec![
-            //     CString::new(options.subroutine).unwrap(),
-            //     // CString::new("--pid-file").unwrap(),
-            //     // CString::new(pidfile).unwrap(),
-            //     CString::new("--projector-port").unwrap(),
-            //     CString::new(options.projector_port).unwrap(),
-            // ];
-            let argv = vec![
-                CString::new("/usr/bin/ping").unwrap(),
-                CString::new("127.0.0.1").unwrap(),
-            ];
-            execv(&argv[0], &argv).unwrap_or_else(|_| libsee::_exit(127));
-            panic!("we should never get here");
+        launcher = launcher.with_tty(slave);
+        builder = builder.with_tty(master);
+    } else {
+        let (main_stdout, worker_stdout) =
+            pipe2(OFlag::O_CLOEXEC).expect("Failed to create stdout pipes");
+        let (main_stdout, worker_stdout) = (main_stdout.into_raw_fd(), worker_stdout.into_raw_fd());
+        let (main_stderr, worker_stderr) =
+            pipe2(OFlag::O_CLOEXEC).expect("Failed to create stderr pipes");
+        let (main_stderr, worker_stderr) = (main_stderr.into_raw_fd(), worker_stderr.into_raw_fd());
+        let (worker_stdin, main_stdin) = if spec.stdin {
+            let (worker_stdin, main_stdin) =
+                pipe2(OFlag::O_CLOEXEC).expect("Failed to create stdin pipes");
+            (worker_stdin.into_raw_fd(), Some(main_stdin.into_raw_fd()))
+        } else {
+            (dev_null_rd, None)
+        };
+        launcher = launcher.with_stdio(worker_stdin, worker_stdout, worker_stderr);
+        builder = builder.with_stdio(main_stdout, main_stderr);
+        if let Some(stdin) = main_stdin {
+            builder = builder.with_stdin(stdin);
         }
-        Err(err) => {
-            eprintln!("Fork failed");
-            panic!("fork() of the subroutine process failed: {}", err);
-        }
-    };
+    }
 
-    // close the worker pipes
-    close(worker_stdout).expect("Failed to close worker stdout in main process");
-    close(worker_stderr).expect("failed to close worker stderr in main process");
+    // launch the subroutine
+    if let Some(cgroup) = cgroup.as_ref() {
+        launcher = launcher.with_cgroup(cgroup.clone());
+    }
+    if let Some(run_as) = spec.run_as.clone() {
+        launcher = launcher.with_run_as(run_as);
+    }
+    if let Some(process_limits) = spec.process_limits {
+        launcher = launcher.with_process_limits(process_limits);
+    }
+    let child_pid = launcher
+        .spawn()
+        .expect("fork() of the subroutine process failed");
 
     // start the server to monitor the subroutine and serve logs
-    let result = Server::build()
-        .with_child(child_pid)
-        .with_stdio(main_stdout, main_stderr)
-        .with_log_file(config.logfile())
+    builder = builder.with_child(child_pid).with_log_file(
+        config.logfile(),
+        spec.log_format,
+        spec.log_rotation,
+    );
+    if spec.restart_policy != RestartPolicy::Never {
+        builder = builder.with_restarts(RestartTracker::new(spec.restart_policy));
+    }
+    if let Some(scene_id) = options.scene_id.as_ref() {
+        match Reporter::start(&options.api_endpoint, scene_id, &options.subroutine_id) {
+            Ok(reporter) => builder = builder.with_reporter(reporter),
+            Err(err) => warn!("Unable to start status reporter: {}", err),
+        }
+    }
+    if let Some(probe) = spec.health_probe.clone() {
+        builder = builder.with_health_probe(probe, spec.host_port);
+    }
+    if let Some(limits) = spec.process_limits {
+        if let Some(deadline) = limits.deadline() {
+            builder = builder.with_deadline(deadline, limits.grace_period());
+        }
+    }
+    if let Some(cgroup) = cgroup.as_ref() {
+        builder = builder.with_cgroup(cgroup.clone());
+    }
+    let mut state = ShimState::new(
+        &subroutine_id,
+        env!("CARGO_PKG_VERSION"),
+        config.control_socket(),
+        config.log_socket(),
+    );
+    if let Some(scene_id) = scene_id.as_ref() {
+        state = state.with_scene(scene_id);
+    }
+    if let Some(image_id) = image_id.as_ref() {
+        state = state.with_image(image_id);
+    }
+    let result = builder
+        .with_launcher(launcher)
+        .with_control_socket(config.control_socket())
+        .with_state_file(config.state_file(), state)
         .listen_uds(config.log_socket());
 
     match result {
@@ -215,8 +404,11 @@ fn main() {
 
             // Run the server
             match server.run() {
-                Ok(status) => {
-                    debug!("subroutine exited with status: {:?}", status);
+                Ok(ExitStatus::Normal(pid, code)) => {
+                    debug!("subroutine {} exited with status {}", pid, code);
+                }
+                Ok(ExitStatus::Signaled(pid, signal)) => {
+                    debug!("subroutine {} terminated by {}", pid, signal);
                 }
                 Err(err) => {
                     // server terminated abnormally
@@ -231,17 +423,108 @@ fn main() {
             ensure_child_reaped(child_pid);
         }
     }
+
+    if let Some(cgroup) = cgroup {
+        if let Err(err) = cgroup.remove() {
+            warn!(
+                "Unable to remove cgroup {}: {}",
+                cgroup.path().display(),
+                err
+            );
+        }
+    }
+
+    if let Some(rootfs) = rootfs {
+        if let Err(err) = rootfs.remove() {
+            warn!(
+                "Unable to remove rootfs {}: {}",
+                rootfs.path().display(),
+                err
+            );
+        }
+    }
 }
 
-fn write_master_pidfile(pidfile: &PathBuf, pid: Pid) {
-    debug!("forked worker with pid: {}", pid);
-    if let Err(err) = fs::write(pidfile, format!("{}", pid)) {
-        panic!("write() to pidfile {} failed: {}", pidfile.display(), err);
+/// Loads the spec (if one was given), overriding it with the options set on the command line.
+fn load_spec(options: &Options) -> SubroutineSpec {
+    let mut spec = match options.spec.as_ref() {
+        Some(path) => SubroutineSpec::load(path).expect("Unable to read subroutine spec"),
+        None => SubroutineSpec::default(),
+    };
+    if let Some(kind) = options.kind {
+        spec.kind = Some(kind);
+    }
+    if let Some(command) = options.command.as_ref() {
+        spec.command = vec![command.to_owned()];
+    }
+    if !options.args.is_empty() {
+        spec.args = options.args.clone();
+    }
+    if !options.layers.is_empty() {
+        spec.layers = options.layers.clone();
+    }
+    if let Some(policy) = options.restart_policy {
+        spec.restart_policy = policy;
+    }
+    if let Some(format) = options.log_format {
+        spec.log_format = format;
+    }
+    if let Some(rotation) = options.log_rotation {
+        spec.log_rotation = rotation;
     }
+    spec.stdin |= options.stdin;
+    spec.tty |= options.tty;
+    spec.host_port = options.host_port.or(spec.host_port);
+    spec.health_probe = options.health_probe.clone().or(spec.health_probe);
+    spec.limits = options.limits.or(spec.limits);
+    spec.run_as = options.run_as.clone().or(spec.run_as);
+    spec.process_limits = options.process_limits.or(spec.process_limits);
+    spec.validate().expect("Invalid subroutine spec");
+    spec
 }
 
-fn write_child_pidfile(pidfile: &PathBuf, pid: Pid) {
-    debug!("forked child with pid: {}", pid);
+fn compose_rootfs(archives: &[PathBuf], config: &SubroutineConfig) -> Rootfs {
+    let cache = LayerCache::from_root(config.layers_root());
+    let layers: Vec<_> = archives
+        .iter()
+        .map(|archive| {
+            cache.add_file(archive).unwrap_or_else(|err| {
+                panic!("Unable to unpack layer {}: {}", archive.display(), err)
+            })
+        })
+        .collect();
+    Rootfs::compose(&layers, config.rootfs()).expect("Unable to compose subroutine rootfs")
+}
+
+fn create_cgroup(options: &Options, limits: &ResourceLimits) -> Cgroup {
+    let id = options
+        .subroutine_id
+        .parse()
+        .expect("Invalid subroutine id");
+    Cgroups::new(&options.cgroup_root, &options.cgroup_slice)
+        .create(&id, limits)
+        .expect("Unable to create subroutine cgroup")
+}
+
+/// Reads the subroutine's environment (if supplied) from the given pipe.
+///
+/// Variables are handed over on a pipe, rather than the command line or our own environment, so
+/// secrets don't show up in `/proc`.
+fn read_environment(fd: Option<i32>) -> HashMap<String, String> {
+    match fd {
+        Some(fd) => {
+            let mut pipe = unsafe { File::from_raw_fd(fd) };
+            let mut json = String::new();
+            pipe.read_to_string(&mut json)
+                .expect("Failed to read subroutine environment");
+            serde_json::from_str(&json).expect("Invalid subroutine environment")
+        }
+        None => HashMap::new(),
+    }
+}
+
+fn write_master_pidfile(pidfile: &PathBuf, pid: Pid) {
+    debug!("forked worker with pid: {}", pid);
     if let Err(err) = fs::write(pidfile, format!("{}", pid)) {
         panic!("write() to pidfile {} failed: {}", pidfile.display(), err);
     }
@@ -283,7 +566,10 @@ fn init_logger(level: LevelFilter) {
         pid: 0,
     };
 
-    let logger = syslog::unix(formatter).expect("could not connect to syslog");
+    // without a syslog daemon to talk to, we run silently
+    let Ok(logger) = syslog::unix(formatter) else {
+        return;
+    };
     log::set_boxed_logger(Box::new(BasicLogger::new(logger)))
         .map(|()| log::set_max_level(level))
         .expect("log::set_boxed_logger() failed");
@@ -0,0 +1,71 @@
+use std::io::Result;
+use std::sync::mpsc::{channel, Sender};
+use std::thread::{self, JoinHandle};
+
+use log::warn;
+
+use holodekk::apis::http::entity::subroutine::SubroutineClient;
+use holodekk::entities::SubroutineStatusChange;
+use holodekk::enums::SubroutineHealth;
+
+enum Report {
+    Health(SubroutineHealth),
+    Status(SubroutineStatusChange),
+}
+
+/// Delivers changes in the subroutine's health and status to holodekkd, off the event loop.
+pub struct Reporter {
+    sender: Option<Sender<Report>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Reporter {
+    pub fn start(endpoint: &str, scene_id: &str, subroutine_id: &str) -> Result<Self> {
+        let (sender, receiver) = channel::<Report>();
+        let client = SubroutineClient::new(endpoint, scene_id, subroutine_id);
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+
+        let handle = thread::spawn(move || {
+            while let Ok(report) = receiver.recv() {
+                let result = match report {
+                    Report::Health(health) => runtime.block_on(client.report_health(health)),
+                    Report::Status(status) => runtime.block_on(client.report_status(status)),
+                };
+                if let Err(err) = result {
+                    warn!("Failed to report to holodekkd: {}", err);
+                }
+            }
+        });
+
+        Ok(Self {
+            sender: Some(sender),
+            handle: Some(handle),
+        })
+    }
+
+    pub fn report_health(&self, health: SubroutineHealth) {
+        self.send(Report::Health(health));
+    }
+
+    pub fn report_status(&self, change: SubroutineStatusChange) {
+        self.send(Report::Status(change));
+    }
+
+    fn send(&self, report: Report) {
+        if let Some(sender) = self.sender.as_ref() {
+            let _ = sender.send(report);
+        }
+    }
+}
+
+impl Drop for Reporter {
+    fn drop(&mut self) {
+        // let any outstanding reports go out before we do
+        self.sender.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
@@ -1,36 +1,73 @@
 use std::cell::RefCell;
 use std::collections::HashMap;
-use std::io::{Read, Result};
+use std::io::Result;
 use std::os::fd::RawFd;
 use std::path::PathBuf;
 use std::rc::Rc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use log::{debug, warn};
+use chrono::{DateTime, Utc};
+
+use log::{debug, info, warn};
 
 use mio::net::UnixListener;
 use mio::{event::Event, Events, Interest, Poll, Token};
 
 use nix::{
     sys::signal::{SIGCHLD, SIGINT, SIGQUIT, SIGTERM},
-    unistd::Pid,
+    unistd::{dup, Pid},
+};
+
+use holodekk::attach::{
+    AttachError, AttachRequest, AttachResponse, AttachResult, Frame, FrameKind, Replay, WindowSize,
+    ATTACH_PROTOCOL_VERSION,
 };
+use holodekk::cgroups::Cgroup;
+use holodekk::entities::{SubroutineExit, SubroutineStatusChange};
+use holodekk::enums::{SubroutineHealth, SubroutineStatus};
+use holodekk::health::HealthProbe;
+use holodekk::logs::{LogFormat, LogRotation};
+use holodekk::restart::{RestartDecision, RestartTracker};
+use holodekk::shim::{ControlRequest, ControlResponse, ShimState, ShimStatus};
 
+use super::control::ControlConnection;
+use super::exec::ExecProcess;
+use super::health::HealthChecker;
+use super::launcher::Launcher;
 use super::logger::{Logger, Writer};
+use super::reporter::Reporter;
 use super::signals::{signal_mask, ExitStatus, SignalHandler};
-use super::streams::{LogStream, LogStreamKind, StdioSink};
+use super::streams::{LogStream, LogStreamKind, StdinPipe, StdioSink};
 
 const TOKEN_SIGNAL: Token = Token(0);
 const TOKEN_STDOUT: Token = Token(1);
 const TOKEN_STDERR: Token = Token(2);
 const TOKEN_ATTACH: Token = Token(3);
+const TOKEN_HEALTH: Token = Token(5);
+const TOKEN_STDIN: Token = Token(6);
+const TOKEN_CONTROL: Token = Token(7);
 const TOKEN_UNUSED: Token = Token(100);
 
+/// How long what's left of the subroutine's process tree has to exit before it's killed.
+const DESCENDANT_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// How long attached clients are given to receive the last of the output once we're done.
+const ATTACH_LINGER: Duration = Duration::from_secs(1);
+
 pub struct ServerBuilder {
     signal_handler: Option<SignalHandler>,
     stdout_scatterer: Option<LogStream>,
     stderr_scatterer: Option<LogStream>,
+    stdin: Option<StdinPipe>,
     logger: Option<Rc<RefCell<Logger>>>,
+    health: Option<(HealthProbe, Option<u16>)>,
+    launcher: Option<Launcher>,
+    restarts: Option<RestartTracker>,
+    reporter: Option<Reporter>,
+    deadline: Option<(Duration, Duration)>,
+    control_socket: Option<PathBuf>,
+    state: Option<(PathBuf, ShimState)>,
+    cgroup: Option<Cgroup>,
 }
 
 impl ServerBuilder {
@@ -39,7 +76,16 @@ impl ServerBuilder {
             signal_handler: None,
             stdout_scatterer: None,
             stderr_scatterer: None,
+            stdin: None,
             logger: None,
+            health: None,
+            launcher: None,
+            restarts: None,
+            reporter: None,
+            deadline: None,
+            control_socket: None,
+            state: None,
+            cgroup: None,
         }
     }
 
@@ -61,9 +107,34 @@ impl ServerBuilder {
         }
     }
 
-    pub fn with_log_file(self, logfile: &PathBuf) -> Self {
+    /// Multiplexes the subroutine's terminal (the master side of its pty): output is read from
+    /// it as stdout, and input from attached clients written to it.
+    pub fn with_tty(self, tty: RawFd) -> Self {
+        let input = dup(tty).expect("Failed to duplicate terminal");
+        Self {
+            stdout_scatterer: Some(LogStream::new(tty, LogStreamKind::Stdout)),
+            stderr_scatterer: None,
+            stdin: Some(StdinPipe::tty(input)),
+            ..self
+        }
+    }
+
+    /// Forwards input from attached clients to the subroutine (through the given pipe).
+    pub fn with_stdin(self, stdin: RawFd) -> Self {
+        Self {
+            stdin: Some(StdinPipe::new(stdin)),
+            ..self
+        }
+    }
+
+    pub fn with_log_file(
+        self,
+        logfile: &PathBuf,
+        format: LogFormat,
+        rotation: LogRotation,
+    ) -> Self {
         // setup the log
-        let logger = Rc::new(RefCell::new(Logger::new(logfile)));
+        let logger = Rc::new(RefCell::new(Logger::new(logfile, format, rotation)));
 
         Self {
             logger: Some(logger),
@@ -71,16 +142,93 @@ impl ServerBuilder {
         }
     }
 
+    /// Runs the probe against the subroutine.
+    pub fn with_health_probe(self, probe: HealthProbe, host_port: Option<u16>) -> Self {
+        Self {
+            health: Some((probe, host_port)),
+            ..self
+        }
+    }
+
+    /// Launches the subroutine on restart, and processes exec'd alongside it.
+    pub fn with_launcher(self, launcher: Launcher) -> Self {
+        Self {
+            launcher: Some(launcher),
+            ..self
+        }
+    }
+
+    /// Restarts the subroutine (using the launcher) as the tracker's policy dictates.
+    pub fn with_restarts(self, tracker: RestartTracker) -> Self {
+        Self {
+            restarts: Some(tracker),
+            ..self
+        }
+    }
+
+    /// Terminates the subroutine once it has run for `deadline`, killing it if it's still
+    /// running `grace_period` later.
+    pub fn with_deadline(self, deadline: Duration, grace_period: Duration) -> Self {
+        Self {
+            deadline: Some((deadline, grace_period)),
+            ..self
+        }
+    }
+
+    /// Reports changes in the subroutine's status and health.
+    pub fn with_reporter(self, reporter: Reporter) -> Self {
+        Self {
+            reporter: Some(reporter),
+            ..self
+        }
+    }
+
+    /// Accepts control clients (see [`holodekk::shim::ShimClient`]) on the given socket.
+    pub fn with_control_socket(self, control_socket: &PathBuf) -> Self {
+        Self {
+            control_socket: Some(control_socket.to_owned()),
+            ..self
+        }
+    }
+
+    /// Signals everything in the subroutine's cgroup (along with its descendants) on stop.
+    pub fn with_cgroup(self, cgroup: Cgroup) -> Self {
+        Self {
+            cgroup: Some(cgroup),
+            ..self
+        }
+    }
+
+    /// Keeps the shim's state (starting from `state`) up to date in the given file.
+    pub fn with_state_file(self, state_file: &PathBuf, state: ShimState) -> Self {
+        Self {
+            state: Some((state_file.to_owned(), state)),
+            ..self
+        }
+    }
+
     pub fn listen_uds(self, log_socket: &PathBuf) -> Result<Server> {
         // clean up if necessary
-        if log_socket.exists() {
-            std::fs::remove_file(log_socket).expect("Failed to remove existing listening socket");
+        for socket in [Some(log_socket), self.control_socket.as_ref()]
+            .into_iter()
+            .flatten()
+        {
+            if socket.exists() {
+                std::fs::remove_file(socket).expect("Failed to remove existing listening socket");
+            }
+        }
+        // replacing whatever an earlier shim left
+        if let Some((state_file, state)) = self.state.as_ref() {
+            state.save(state_file).expect("Failed to write state file");
         }
 
         let logger = self.logger.unwrap();
         let mut signal_handler = self.signal_handler.unwrap();
+        if let Some(cgroup) = self.cgroup {
+            signal_handler = signal_handler.with_cgroup(cgroup);
+        }
         let mut stdout_scatterer = self.stdout_scatterer.unwrap();
-        let mut stderr_scatterer = self.stderr_scatterer.unwrap();
+        let mut stderr_scatterer = self.stderr_scatterer;
 
         // attach the log to the streams
         let log_token = Token(4);
@@ -88,7 +236,12 @@ impl ServerBuilder {
             log_token,
             Rc::new(RefCell::new(Writer::stdout(logger.clone()))),
         );
-        stderr_scatterer.add_sink(log_token, Rc::new(RefCell::new(Writer::stderr(logger))));
+        if let Some(stderr_scatterer) = stderr_scatterer.as_mut() {
+            stderr_scatterer.add_sink(
+                log_token,
+                Rc::new(RefCell::new(Writer::stderr(logger.clone()))),
+            );
+        }
 
         // create the watcher
         let poll = Poll::new()?;
@@ -100,8 +253,10 @@ impl ServerBuilder {
         poll.registry()
             .register(&mut stdout_scatterer, TOKEN_STDOUT, Interest::READABLE)?;
 
-        poll.registry()
-            .register(&mut stderr_scatterer, TOKEN_STDERR, Interest::READABLE)?;
+        if let Some(stderr_scatterer) = stderr_scatterer.as_mut() {
+            poll.registry()
+                .register(stderr_scatterer, TOKEN_STDERR, Interest::READABLE)?;
+        }
 
         // Start the listener
         let mut attach_listener = UnixListener::bind(log_socket)?;
@@ -109,13 +264,50 @@ impl ServerBuilder {
         poll.registry()
             .register(&mut attach_listener, TOKEN_ATTACH, Interest::READABLE)?;
 
-        Ok(Server::new(
-            poll,
-            signal_handler,
-            stdout_scatterer,
-            stderr_scatterer,
-            attach_listener,
-        ))
+        let control_listener = match self.control_socket.as_ref() {
+            Some(control_socket) => {
+                let mut listener = UnixListener::bind(control_socket)?;
+                poll.registry()
+                    .register(&mut listener, TOKEN_CONTROL, Interest::READABLE)?;
+                Some(listener)
+            }
+            None => None,
+        };
+
+        let health = match self.health {
+            Some((probe, host_port)) => Some(HealthChecker::new(
+                poll.registry(),
+                TOKEN_HEALTH,
+                probe,
+                host_port,
+            )?),
+            None => None,
+        };
+
+        let (deadline, grace_period) = match self.deadline {
+            Some((deadline, grace_period)) => (Some(Instant::now() + deadline), grace_period),
+            None => (None, Duration::ZERO),
+        };
+
+        Ok(Server {
+            logger: Some(logger),
+            stdin: self.stdin,
+            control_listener,
+            state: self.state,
+            launcher: self.launcher,
+            restarts: self.restarts,
+            reporter: self.reporter,
+            deadline,
+            grace_period,
+            ..Server::new(
+                poll,
+                signal_handler,
+                stdout_scatterer,
+                stderr_scatterer,
+                attach_listener,
+                health,
+            )
+        })
     }
 }
 
@@ -130,8 +322,18 @@ pub struct Server {
     /// Handler for stdout messages generated by the subroutine.
     stdout_scatterer: LogStream,
 
-    /// Handler for stderr messages generated by the subroutine.
-    stderr_scatterer: LogStream,
+    /// Handler for stderr messages generated by the subroutine (unless it has a tty, where
+    /// everything arrives on "stdout").
+    stderr_scatterer: Option<LogStream>,
+
+    /// Subroutine's stdin (if attached clients can write to it).
+    stdin: Option<StdinPipe>,
+
+    /// Client currently holding stdin.
+    stdin_writer: Option<Token>,
+
+    /// Subroutine log file (flushed once the subroutine's output has been drained).
+    logger: Option<Rc<RefCell<Logger>>>,
 
     /// Unix socket listener to allow external attchment to log streams.
     attach_listener: UnixListener,
@@ -142,8 +344,68 @@ pub struct Server {
     /// Map containing token -> client connections.
     log_sinks: HashMap<Token, Rc<RefCell<StdioSink>>>,
 
+    /// Whether the subroutine's output is done (so attaching clients can't follow it).
+    finishing: bool,
+
+    /// Unix socket listener for control clients (if the shim can be controlled).
+    control_listener: Option<UnixListener>,
+
+    /// Map containing token -> control client connections.
+    controls: HashMap<Token, ControlConnection>,
+
+    /// Last status reported (which control clients can query, or subscribe to).
+    status: SubroutineStatus,
+
+    /// When the status last changed.
+    status_changed_at: DateTime<Utc>,
+
+    /// Last health reported.
+    health_status: SubroutineHealth,
+
+    /// When the current incarnation of the subroutine was started.
+    started_at: DateTime<Utc>,
+
+    /// How the subroutine last exited.
+    last_exit: Option<SubroutineExit>,
+
+    /// Where the final status is recorded (if anywhere).
+    state: Option<(PathBuf, ShimState)>,
+
+    /// Number of times the subroutine has been restarted.
+    restart_count: u32,
+
     /// Next available poll token to assign to incoming connections.
     unique_token: Token,
+
+    /// Health probe runner (if the subroutine has a probe configured).
+    health: Option<HealthChecker>,
+
+    /// Launcher for restarts and exec'd processes.
+    launcher: Option<Launcher>,
+
+    /// Restart policy (if the subroutine is to be restarted).
+    restarts: Option<RestartTracker>,
+
+    /// Processes exec'd on behalf of attached clients, by the client's token.
+    execs: HashMap<Token, ExecProcess>,
+
+    /// Exec'd processes' pipe tokens -> the owning client's token.
+    exec_streams: HashMap<Token, Token>,
+
+    /// When the subroutine is due to be restarted (while backing off).
+    restart_at: Option<Instant>,
+
+    /// Reporter for status/health changes (if we're reporting to holodekkd).
+    reporter: Option<Reporter>,
+
+    /// When the subroutine is to be terminated (if it has a deadline).
+    deadline: Option<Instant>,
+
+    /// Time allowed between terminating the subroutine and killing it.
+    grace_period: Duration,
+
+    /// When the subroutine is to be killed (once terminated for passing its deadline).
+    kill_at: Option<Instant>,
 }
 
 impl Server {
@@ -166,8 +428,9 @@ impl Server {
         poll: Poll,
         signal_handler: SignalHandler,
         stdout_scatterer: LogStream,
-        stderr_scatterer: LogStream,
+        stderr_scatterer: Option<LogStream>,
         attach_listener: UnixListener,
+        health: Option<HealthChecker>,
     ) -> Self {
         Self {
             poll,
@@ -175,9 +438,32 @@ impl Server {
             stdout_scatterer,
             stderr_scatterer,
             attach_listener,
+            logger: None,
+            stdin: None,
+            stdin_writer: None,
             log_sinks: HashMap::new(),
+            finishing: false,
+            control_listener: None,
+            controls: HashMap::new(),
+            status: SubroutineStatus::Unknown,
+            status_changed_at: Utc::now(),
+            health_status: SubroutineHealth::Unknown,
+            started_at: Utc::now(),
+            last_exit: None,
+            state: None,
+            restart_count: 0,
             timeout: Duration::from_secs(5),
             unique_token: TOKEN_UNUSED,
+            health,
+            launcher: None,
+            restarts: None,
+            execs: HashMap::new(),
+            exec_streams: HashMap::new(),
+            restart_at: None,
+            reporter: None,
+            deadline: None,
+            grace_period: Duration::ZERO,
+            kill_at: None,
         }
     }
 
@@ -189,23 +475,249 @@ impl Server {
     ///
     /// The only difference between the two states is that during log draining, events triggered
     /// by incoming connections or signals are ignored.
+    ///
+    /// When a restart policy is configured, exits are followed by a restart (after backing off)
+    /// rather than draining, for as long as the policy allows.  The log file, stdio pipes and
+    /// attach socket stay open throughout, so attached clients see every incarnation.
     pub fn run(&mut self) -> Result<ExitStatus> {
-        while self.signal_handler.status().is_none() {
-            self.poll_once()?;
-        }
+        self.report_status(SubroutineStatus::Running(
+            self.signal_handler.child_pid().as_raw() as u32,
+        ));
 
-        // Process is complete.  Drain stdio
+        let status = loop {
+            while self.signal_handler.status().is_none() {
+                self.poll_once()?;
+            }
+            let status = self.signal_handler.status().unwrap();
+            self.last_exit = Some(self.exit_record(status));
+            if let Some(health) = self.health.as_mut() {
+                health.reset(&mut self.signal_handler)?;
+            }
+            self.report_health(SubroutineHealth::Unknown);
+
+            if !self.restart(status)? {
+                break status;
+            }
+        };
+
+        // Process is complete.  Stop probing, make sure nothing it started outlives it, and
+        // drain stdio
+        self.health = None;
+        self.finishing = true;
+        self.stop_descendants()?;
         self.poll.registry().deregister(&mut self.signal_handler)?;
         self.poll.registry().deregister(&mut self.attach_listener)?;
+        if let Some(control_listener) = self.control_listener.as_mut() {
+            self.poll.registry().deregister(control_listener)?;
+        }
         self.timeout = Duration::from_millis(0);
 
         while self.poll_once()? != 0 {
             debug!("draining subroutine logs");
         }
+        if let Some(logger) = self.logger.as_ref() {
+            if let Err(err) = logger.borrow_mut().flush() {
+                warn!("Failed to flush subroutine log: {}", err);
+            }
+        }
+
+        // then report (now the whole of stderr has been seen)
+        let mut exit = self.exit_record(status);
+        if let Some(last_exit) = self.last_exit.as_ref() {
+            exit.exited_at = last_exit.exited_at;
+        }
+        self.last_exit = Some(exit.clone());
+        let change = SubroutineStatusChange::new(if self.signal_handler.terminating() {
+            SubroutineStatus::Stopped
+        } else {
+            exit.status
+        })
+        .with_exit(exit);
+        self.report_change(change);
+        // answer anyone waiting on the subroutine to stop
+        let final_status = self.shim_status();
+        for control in self.controls.values_mut() {
+            if control.stopping() {
+                control.send(&ControlResponse::Status(final_status.clone()));
+            }
+        }
+        // flush any outstanding reports
+        self.reporter = None;
+        self.finish_sinks()?;
 
         Ok(self.signal_handler.status().unwrap())
     }
 
+    /// Kills exec'd processes, then terminates whatever's left of the subroutine's process tree
+    /// (killing it if it's still around after the grace period), and waits for all of it to be
+    /// reaped.
+    fn stop_descendants(&mut self) -> Result<()> {
+        let execs: Vec<Token> = self.execs.keys().copied().collect();
+        for token in execs {
+            self.kill_exec(token)?;
+        }
+
+        self.signal_handler.reap()?;
+        if !self.signal_handler.has_children() {
+            return Ok(());
+        }
+        info!("terminating the subroutine's remaining processes");
+        self.signal_handler.signal_tree(SIGTERM);
+        let kill_at = Instant::now() + DESCENDANT_GRACE_PERIOD;
+        self.kill_at = Some(self.kill_at.map_or(kill_at, |at| at.min(kill_at)));
+        while self.signal_handler.has_children() {
+            self.poll_once()?;
+            self.signal_handler.reap()?;
+        }
+        self.kill_at = None;
+        Ok(())
+    }
+
+    /// Restarts the subroutine (after backing off) if the restart policy calls for it.
+    ///
+    /// Returns false if the subroutine is to remain stopped.
+    fn restart(&mut self, status: ExitStatus) -> Result<bool> {
+        let decision = match (self.launcher.as_ref(), self.restarts.as_mut()) {
+            (Some(_), Some(_)) if self.signal_handler.terminating() => RestartDecision::Stop,
+            (Some(_), Some(tracker)) => tracker.exited(status.success(), Instant::now()),
+            _ => RestartDecision::Stop,
+        };
+        let (delay, attempt, crash_loop) = match decision {
+            RestartDecision::Stop => return Ok(false),
+            RestartDecision::Restart {
+                delay,
+                attempt,
+                crash_loop,
+            } => (delay, attempt, crash_loop),
+        };
+
+        info!(
+            "subroutine exited ({:?}); restarting in {:?} (attempt {})",
+            status, delay, attempt
+        );
+        if let Some(exit) = self.last_exit.clone() {
+            self.report_change(SubroutineStatusChange::new(exit.status).with_exit(exit));
+        }
+        self.report_status(if crash_loop {
+            SubroutineStatus::CrashLoopBackOff(attempt)
+        } else {
+            SubroutineStatus::Restarting { attempt }
+        });
+
+        // keep serving logs and attach clients while we back off
+        let restart_at = Instant::now() + delay;
+        self.restart_at = Some(restart_at);
+        while Instant::now() < restart_at && !self.signal_handler.terminating() {
+            self.poll_once()?;
+        }
+        self.restart_at = None;
+        if self.signal_handler.terminating() {
+            return Ok(false);
+        }
+
+        self.report_status(SubroutineStatus::Starting);
+        let pid = self.launcher.as_ref().unwrap().spawn()?;
+        self.restarts.as_mut().unwrap().started(Instant::now());
+        self.started_at = Utc::now();
+        self.restart_count += 1;
+        self.signal_handler.watch(pid);
+        if let Some(health) = self.health.as_mut() {
+            health.reset(&mut self.signal_handler)?;
+        }
+        self.report_status(SubroutineStatus::Running(pid.as_raw() as u32));
+        Ok(true)
+    }
+
+    fn report_status(&mut self, status: SubroutineStatus) {
+        self.report_change(SubroutineStatusChange::new(status));
+    }
+
+    fn report_change(&mut self, change: SubroutineStatusChange) {
+        self.status = change.status;
+        self.status_changed_at = change.changed_at;
+        if let Some(reporter) = self.reporter.as_ref() {
+            reporter.report_status(change);
+        }
+        self.write_state_file();
+        self.publish_status();
+    }
+
+    /// Records how the subroutine exited (along with what it last wrote to stderr).
+    fn exit_record(&self, status: ExitStatus) -> SubroutineExit {
+        SubroutineExit {
+            status: status.status(),
+            exited_at: Utc::now(),
+            stderr_tail: self
+                .logger
+                .as_ref()
+                .map(|logger| logger.borrow().stderr_tail())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Records the shim's state, so holodekkd can find it again (and see how the subroutine
+    /// finished, once the shim has gone).
+    fn write_state_file(&mut self) {
+        let status = self.shim_status();
+        let finished = self.finishing;
+        let Some((state_file, state)) = self.state.as_mut() else {
+            return;
+        };
+        state.pid = status.pid;
+        state.started_at = status.started_at;
+        state.status = SubroutineStatusChange {
+            status: status.status,
+            changed_at: status.changed_at,
+            exit: status.last_exit,
+        };
+        state.finished = finished;
+        if let Err(err) = state.save(state_file.as_path()) {
+            warn!("Failed to write {}: {}", state_file.display(), err);
+        }
+    }
+
+    fn report_health(&mut self, health: SubroutineHealth) {
+        if let Some(reporter) = self.reporter.as_ref() {
+            reporter.report_health(health);
+        }
+        if health != self.health_status {
+            self.health_status = health;
+            self.publish_status();
+        }
+    }
+
+    /// What control clients are told of the subroutine.
+    fn shim_status(&self) -> ShimStatus {
+        let running = self.signal_handler.status().is_none();
+        ShimStatus {
+            status: self.status,
+            changed_at: self.status_changed_at,
+            health: self.health_status,
+            pid: running.then(|| self.signal_handler.child_pid().as_raw() as u32),
+            started_at: self.started_at,
+            last_exit: self.last_exit.clone(),
+            restarts: self.restart_count,
+            clients: self
+                .log_sinks
+                .values()
+                .filter_map(|sink| sink.borrow().client())
+                .collect(),
+        }
+    }
+
+    /// Sends the current status to subscribed control clients.
+    fn publish_status(&mut self) {
+        if !self.controls.values().any(ControlConnection::subscribed) {
+            return;
+        }
+        let status = ControlResponse::Status(self.shim_status());
+        for control in self.controls.values_mut() {
+            if control.subscribed() {
+                control.send(&status);
+            }
+        }
+    }
+
     /// Primary mio (epoll) reactor
     ///
     /// Performs several primary functions:
@@ -215,14 +727,30 @@ impl Server {
     fn poll_once(&mut self) -> Result<i32> {
         let mut events = Events::with_capacity(128);
 
-        self.poll.poll(&mut events, Some(self.timeout))?;
+        // wake in time for the next health probe (or restart, or deadline)
+        let now = Instant::now();
+        let timers = [self.restart_at, self.deadline, self.kill_at]
+            .into_iter()
+            .flatten()
+            .map(|at| at.saturating_duration_since(now));
+        let timeout = self
+            .health
+            .as_ref()
+            .and_then(|health| health.timeout())
+            .into_iter()
+            .chain(timers)
+            .fold(self.timeout, Duration::min);
+        self.poll.poll(&mut events, Some(timeout))?;
 
         let mut event_count = 0;
 
         for event in events.iter() {
             event_count += 1;
             match event.token() {
-                TOKEN_SIGNAL => self.signal_handler.handle_signal()?,
+                TOKEN_SIGNAL => {
+                    self.signal_handler.handle_signal()?;
+                    self.reap_execs()?;
+                }
                 TOKEN_ATTACH => {
                     self.handle_attach_event(event)?;
                 }
@@ -232,6 +760,37 @@ impl Server {
                 TOKEN_STDERR => {
                     self.handle_stderr_event(event)?;
                 }
+                TOKEN_STDIN => {
+                    if let Some(stdin) = self.stdin.as_mut() {
+                        if let Err(err) = stdin.deliver(self.poll.registry(), TOKEN_STDIN) {
+                            warn!("Failed to write to subroutine stdin: {}", err);
+                        }
+                    }
+                }
+                TOKEN_HEALTH => {
+                    let changed = self
+                        .health
+                        .as_mut()
+                        .and_then(|health| health.handle_results());
+                    if let Some(health) = changed {
+                        self.report_health(health);
+                    }
+                }
+                TOKEN_CONTROL => {
+                    self.handle_control_listener_event(event)?;
+                }
+                token if self.controls.contains_key(&token) => {
+                    self.handle_control_event(event)?;
+                }
+                token if self.exec_streams.contains_key(&token) => {
+                    let exec = self.exec_streams[&token];
+                    let registry = self.poll.registry();
+                    if let Some(exec) = self.execs.get_mut(&exec) {
+                        if let Err(err) = exec.handle_event(token, registry) {
+                            warn!("Failed to relay exec'd process' stdio: {}", err);
+                        }
+                    }
+                }
                 _ => {
                     let done = self.handle_sink_event(event)?;
                     if done {
@@ -242,6 +801,16 @@ impl Server {
             }
         }
 
+        self.enforce_deadline()?;
+
+        // only probe while the subroutine is actually running
+        if let Some(health) = self.health.as_mut() {
+            health.check_exec(&mut self.signal_handler)?;
+            if self.signal_handler.status().is_none() {
+                health.tick(self.launcher.as_ref(), &mut self.signal_handler);
+            }
+        }
+
         // After each poll is complete, any stdio data read from the subroutine has been scattered
         // to the sinks, but is just sitting in a buffer.  We need to move then to
         // Interest::WRITABLE so the data can actually be delivered.
@@ -254,10 +823,181 @@ impl Server {
                 )?;
             }
         }
+        // likewise responses to control clients
+        for (token, control) in self.controls.iter_mut() {
+            if control.data_pending() {
+                self.poll.registry().reregister(
+                    control.stream(),
+                    *token,
+                    Interest::READABLE.add(Interest::WRITABLE),
+                )?;
+            }
+        }
 
         Ok(event_count)
     }
 
+    /// Terminates (and later kills) the subroutine once its deadline has passed.
+    ///
+    /// Termination is treated like a SIGTERM from outside: the subroutine isn't restarted.
+    fn enforce_deadline(&mut self) -> Result<()> {
+        let now = Instant::now();
+        if self.deadline.is_some_and(|deadline| now >= deadline) {
+            warn!("subroutine passed its deadline.  terminating.");
+            self.deadline = None;
+            self.kill_at = Some(now + self.grace_period);
+            self.signal_handler.terminate()?;
+        }
+        if self.kill_at.is_some_and(|kill_at| now >= kill_at) {
+            self.kill_at = None;
+            if self.signal_handler.status().is_none() {
+                warn!("subroutine still running after grace period.  killing.");
+                self.signal_handler.kill()?;
+            } else if self.signal_handler.has_children() {
+                warn!("subroutine's processes still running after grace period.  killing.");
+                self.signal_handler.kill()?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends the final frame to every attached client, giving them a moment to receive it.
+    fn finish_sinks(&mut self) -> Result<()> {
+        self.finishing = true;
+        // clients still handshaking are closed once they've had their replay
+        for sink in self.log_sinks.values() {
+            let mut sink = sink.borrow_mut();
+            if !sink.handshaking() {
+                sink.end();
+            }
+        }
+
+        let linger_until = Instant::now() + ATTACH_LINGER;
+        self.timeout = Duration::from_millis(50);
+        while (!self.log_sinks.is_empty()
+            || self.controls.values().any(ControlConnection::data_pending))
+            && Instant::now() < linger_until
+        {
+            self.poll_once()?;
+        }
+        Ok(())
+    }
+
+    fn handle_control_listener_event(&mut self, event: &Event) -> Result<()> {
+        if event.is_readable() {
+            let Some(control_listener) = self.control_listener.as_ref() else {
+                return Ok(());
+            };
+            let (mut connection, _) = control_listener.accept()?;
+
+            let token = self.next_token();
+            self.poll
+                .registry()
+                .register(&mut connection, token, Interest::READABLE)?;
+            self.controls
+                .insert(token, ControlConnection::new(connection));
+            debug!("Accepted a control connection");
+        }
+        Ok(())
+    }
+
+    fn handle_control_event(&mut self, event: &Event) -> Result<()> {
+        let token = event.token();
+        // taken out of the map while it's handled (and only put back if it's still wanted)
+        let Some(mut control) = self.controls.remove(&token) else {
+            return Ok(());
+        };
+
+        if event.is_readable() {
+            let requests = match control.read_requests() {
+                Ok(requests) => requests,
+                Err(err) => {
+                    warn!("Error reading from control client: {}", err);
+                    return self.drop_control(control);
+                }
+            };
+            for request in requests {
+                self.handle_control_request(&mut control, request)?;
+            }
+            if control.hung_up() {
+                debug!("control client disconnect");
+                return self.drop_control(control);
+            }
+        }
+
+        if event.is_writable() && control.data_pending() {
+            if let Err(err) = control.deliver_data() {
+                warn!("Error delivering data to control client: {}", err);
+                return self.drop_control(control);
+            }
+            if !control.data_pending() {
+                self.poll
+                    .registry()
+                    .reregister(control.stream(), token, Interest::READABLE)?;
+            }
+        }
+
+        self.controls.insert(token, control);
+        Ok(())
+    }
+
+    fn handle_control_request(
+        &mut self,
+        control: &mut ControlConnection,
+        request: ControlRequest,
+    ) -> Result<()> {
+        let running = self.signal_handler.status().is_none();
+        match request {
+            ControlRequest::Signal { signal } => {
+                let response = match ControlRequest::parse_signal(&signal) {
+                    Err(err) => ControlResponse::Error {
+                        message: err.to_string(),
+                    },
+                    Ok(_) if !running => ControlResponse::Error {
+                        message: "subroutine isn't running".into(),
+                    },
+                    Ok(signal) => match self.signal_handler.signal(signal) {
+                        Ok(()) => ControlResponse::Ok,
+                        Err(err) => ControlResponse::Error {
+                            message: err.to_string(),
+                        },
+                    },
+                };
+                control.send(&response);
+            }
+            ControlRequest::Stop { timeout } => {
+                if self.finishing {
+                    control.send(&ControlResponse::Status(self.shim_status()));
+                } else if let Some(kill_at) =
+                    Instant::now().checked_add(Duration::from_secs(timeout))
+                {
+                    // answered once the subroutine has exited (see run())
+                    info!("stopping subroutine (at a control client's request)");
+                    control.set_stopping();
+                    self.signal_handler.terminate()?;
+                    self.kill_at = Some(self.kill_at.map_or(kill_at, |at| at.min(kill_at)));
+                } else {
+                    control.send(&ControlResponse::Error {
+                        message: format!("stop timeout too large: {}s", timeout),
+                    });
+                }
+            }
+            ControlRequest::Status => {
+                control.send(&ControlResponse::Status(self.shim_status()));
+            }
+            ControlRequest::Subscribe => {
+                control.subscribe();
+                control.send(&ControlResponse::Status(self.shim_status()));
+            }
+        }
+        Ok(())
+    }
+
+    fn drop_control(&mut self, mut control: ControlConnection) -> Result<()> {
+        self.poll.registry().deregister(control.stream())?;
+        Ok(())
+    }
+
     fn handle_attach_event(&mut self, event: &Event) -> Result<()> {
         // Ensure the socket is actually readable
         if event.is_readable() {
@@ -270,10 +1010,8 @@ impl Server {
                 .registry()
                 .register(&mut connection, token, Interest::READABLE)?;
 
-            // create a log sink
+            // create a log sink (which receives output once the client's hello arrives)
             let sink = Rc::new(RefCell::new(StdioSink::new(connection)));
-            self.stdout_scatterer.add_sink(token, sink.clone());
-            self.stderr_scatterer.add_sink(token, sink.clone());
             self.log_sinks.insert(token, sink);
             debug!("Accepted a connection from {:?}", address);
         } else {
@@ -299,8 +1037,10 @@ impl Server {
         if event.is_read_closed() {
             debug!("stderr closed");
             Ok(0)
-        } else if event.is_readable() {
-            self.stderr_scatterer.scatter()
+        } else if let (true, Some(stderr_scatterer)) =
+            (event.is_readable(), self.stderr_scatterer.as_mut())
+        {
+            stderr_scatterer.scatter()
         } else {
             warn!("stderr triggered an event, but was not readable.");
             Ok(0)
@@ -311,50 +1051,258 @@ impl Server {
         let token = event.token();
 
         // make sure we actually have this sink
-        if !self.log_sinks.contains_key(&token) {
+        let Some(sink) = self.log_sinks.get(&token).cloned() else {
             warn!("handle_sink_event() fired for non-existent sink.");
             return Ok(false);
-        }
+        };
 
-        // so that this dosn't panic
-        let sink = self.log_sinks.get(&token).unwrap();
         let mut sink = sink.borrow_mut();
         if event.is_readable() {
-            // Probably a closed socket
-            let mut buf: [u8; 1] = [0; 1];
-            match sink.stream().read(&mut buf) {
-                Ok(num_read) => {
-                    if (num_read) > 0 {
-                        warn!("Received data from log sink unexpectedly.");
-                    } else {
-                        debug!("log sink disconnect");
-                    }
-                }
+            let frames = match sink.read_frames() {
+                Ok(frames) => frames,
                 Err(err) => {
                     warn!("Error reading from log sink: {}", err);
+                    return Ok(true);
                 }
+            };
+            for frame in frames {
+                // whatever's sent after the sink is closed is of no interest
+                if sink.closing() {
+                    break;
+                }
+                if let Err(err) = self.handle_frame(token, &mut sink, frame) {
+                    warn!("Received data from log sink unexpectedly: {}", err);
+                    return Ok(true);
+                }
+            }
+            if sink.hung_up() {
+                debug!("log sink disconnect");
+                // let the poll know we are done with this sink
+                return Ok(true);
             }
+        }
+
+        if event.is_writable() && sink.data_pending() {
+            if let Err(err) = sink.deliver_data() {
+                warn!("Error delivering data for sink: {}", err);
+                // drop this sink
+                return Ok(true);
+            }
+            if !sink.data_pending() {
+                self.poll
+                    .registry()
+                    .reregister(sink.stream(), token, Interest::READABLE)?;
+            }
+        }
+
+        Ok(sink.finished())
+    }
 
-            // let the poll know we are done with this sink
-            Ok(true)
-        } else if event.is_writable() && sink.data_pending() {
-            match sink.deliver_data() {
-                Ok(_) => {
-                    self.poll
-                        .registry()
-                        .reregister(sink.stream(), token, Interest::READABLE)?;
-                    Ok(false)
+    fn handle_frame(
+        &mut self,
+        token: Token,
+        sink: &mut StdioSink,
+        frame: Frame,
+    ) -> AttachResult<()> {
+        let holds_stdin = self.stdin_writer == Some(token);
+        match frame.kind {
+            FrameKind::Hello if sink.handshaking() => {
+                let request = serde_json::from_slice(&frame.payload)?;
+                self.start_attach(token, sink, request);
+            }
+            FrameKind::Stdin | FrameKind::CloseStdin
+                if self.execs.get(&token).is_some_and(ExecProcess::has_stdin) =>
+            {
+                let exec = self.execs.get_mut(&token).unwrap();
+                if let Err(err) = exec.input(&frame, self.poll.registry()) {
+                    warn!("Failed to write to exec'd process' stdin: {}", err);
+                    sink.close(&Frame::new(FrameKind::Error, err.to_string()));
                 }
-                Err(err) => {
-                    warn!("Error delivering data for sink: {}", err);
-                    // drop this sink
-                    Ok(true)
+            }
+            FrameKind::Resize if self.execs.contains_key(&token) => {
+                debug!("ignoring resize (exec'd processes have no tty)");
+            }
+            FrameKind::Stdin if holds_stdin => {
+                let stdin = self.stdin.as_mut().unwrap();
+                if let Err(err) = stdin.write(&frame.payload, self.poll.registry(), TOKEN_STDIN) {
+                    warn!("Failed to write to subroutine stdin: {}", err);
+                    self.stdin_writer = None;
+                    sink.close(&Frame::new(FrameKind::Error, err.to_string()));
+                }
+            }
+            FrameKind::Resize if holds_stdin => {
+                let size: WindowSize = serde_json::from_slice(&frame.payload)?;
+                let stdin = self.stdin.as_ref().unwrap();
+                if !stdin.is_tty() {
+                    debug!("ignoring resize (the subroutine has no tty)");
+                } else if let Err(err) = stdin.resize(size) {
+                    warn!("Failed to resize subroutine terminal: {}", err);
+                }
+            }
+            FrameKind::CloseStdin if holds_stdin => {
+                self.stdin_writer = None;
+                let stdin = self.stdin.as_mut().unwrap();
+                if let Err(err) = stdin.close(self.poll.registry(), TOKEN_STDIN) {
+                    warn!("Failed to write to subroutine stdin: {}", err);
                 }
             }
+            kind => return Err(AttachError::UnexpectedFrame(kind)),
+        }
+        Ok(())
+    }
+
+    /// Replays what the client asked for from the log, then starts it following (if it wants).
+    fn start_attach(&mut self, token: Token, sink: &mut StdioSink, request: AttachRequest) {
+        if request.version != ATTACH_PROTOCOL_VERSION {
+            sink.close(&Frame::new(
+                FrameKind::Error,
+                format!("unsupported attach protocol version {}", request.version),
+            ));
+            return;
+        }
+        if let Some(command) = request.exec.clone() {
+            self.start_exec(token, sink, request, &command);
+            return;
+        }
+        // only one client writes to stdin at a time
+        if request.stdin {
+            let refusal = match self.stdin.as_ref() {
+                None => Some("stdin isn't enabled for this subroutine"),
+                Some(stdin) if !stdin.is_open() || self.finishing => Some("stdin is closed"),
+                Some(_) if self.stdin_writer.is_some() => Some("stdin is already attached"),
+                Some(_) if !request.follow => Some("stdin can only be attached when following"),
+                Some(_) => None,
+            };
+            if let Some(reason) = refusal {
+                sink.close(&Frame::new(FrameKind::Error, reason));
+                return;
+            }
+            self.stdin_writer = Some(token);
+        }
+        sink.queue(
+            &Frame::json(
+                FrameKind::Hello,
+                &AttachResponse {
+                    version: ATTACH_PROTOCOL_VERSION,
+                    tty: self.stdin.as_ref().is_some_and(StdinPipe::is_tty),
+                },
+            )
+            .expect("attach responses always serialize"),
+        );
+
+        // (sent as the client takes it; output is held for it meanwhile)
+        let replay = self.logger.as_ref().and_then(|logger| {
+            let logger = logger.borrow();
+            Replay::open(logger.path(), logger.format(), &request)
+                .map_err(|err| warn!("Failed to replay subroutine log: {}", err))
+                .ok()
+        });
+        let follow = request.follow && !self.finishing;
+        if follow {
+            let sink = self.log_sinks.get(&token).unwrap().clone();
+            self.stdout_scatterer.add_sink(token, sink.clone());
+            if let Some(stderr_scatterer) = self.stderr_scatterer.as_mut() {
+                stderr_scatterer.add_sink(token, sink);
+            }
+        }
+        sink.accept(request);
+        sink.replay(replay, follow);
+    }
+
+    /// Execs the client's command alongside the subroutine, relaying its stdio to the client.
+    fn start_exec(
+        &mut self,
+        token: Token,
+        sink: &mut StdioSink,
+        request: AttachRequest,
+        command: &[String],
+    ) {
+        let refusal = if command.is_empty() {
+            Some("no command to exec".to_string())
+        } else if self.launcher.is_none() {
+            Some("exec isn't supported for this subroutine".to_string())
+        } else if self.finishing || self.signal_handler.status().is_some() {
+            Some("subroutine isn't running".to_string())
         } else {
-            warn!("log sink in strange state.  dropping.");
-            Ok(true)
+            let tokens = [self.next_token(), self.next_token(), self.next_token()];
+            let client = (token, self.log_sinks[&token].clone());
+            match ExecProcess::spawn(
+                self.launcher.as_ref().unwrap(),
+                command,
+                request.stdin,
+                client,
+                tokens,
+                self.poll.registry(),
+            ) {
+                Ok(exec) => {
+                    debug!("exec'd {:?} with pid {}", command, exec.pid());
+                    self.signal_handler.track(exec.pid());
+                    for stream in tokens {
+                        self.exec_streams.insert(stream, token);
+                    }
+                    self.execs.insert(token, exec);
+                    None
+                }
+                Err(err) => Some(format!("exec failed: {}", err)),
+            }
+        };
+        if let Some(reason) = refusal {
+            sink.close(&Frame::new(FrameKind::Error, reason));
+            return;
         }
+        sink.queue(
+            &Frame::json(
+                FrameKind::Hello,
+                &AttachResponse {
+                    version: ATTACH_PROTOCOL_VERSION,
+                    tty: false,
+                },
+            )
+            .expect("attach responses always serialize"),
+        );
+        sink.accept(request);
+    }
+
+    /// Reports the exit of any exec'd processes that have been reaped (to their clients).
+    fn reap_execs(&mut self) -> Result<()> {
+        let mut exited = Vec::new();
+        for (token, exec) in self.execs.iter() {
+            if let Some(status) = self.signal_handler.take_exit(exec.pid()) {
+                exited.push((*token, status));
+            }
+        }
+        for (token, status) in exited {
+            self.finish_exec(token, status)?;
+        }
+        Ok(())
+    }
+
+    /// Kills an exec'd process, reporting its exit to its client.
+    fn kill_exec(&mut self, token: Token) -> Result<()> {
+        let Some(exec) = self.execs.get(&token) else {
+            return Ok(());
+        };
+        exec.kill();
+        let status = self.signal_handler.wait_for(exec.pid())?;
+        self.finish_exec(token, status)
+    }
+
+    /// Sends the rest of an exec'd process' output to its client, followed by its exit.
+    fn finish_exec(&mut self, token: Token, status: ExitStatus) -> Result<()> {
+        let Some(exec) = self.execs.remove(&token) else {
+            return Ok(());
+        };
+        for stream in exec.tokens() {
+            self.exec_streams.remove(&stream);
+        }
+        debug!("exec'd process {} exited ({:?})", exec.pid(), status);
+        exec.finish(self.poll.registry())?;
+        if let Some(sink) = self.log_sinks.get(&token) {
+            let mut sink = sink.borrow_mut();
+            sink.queue(&Frame::exit(status.status()));
+            sink.close(&Frame::empty(FrameKind::End));
+        }
+        Ok(())
     }
 
     /// Removes a log sink from *everything*
@@ -378,8 +1326,16 @@ impl Server {
             .registry()
             .deregister(sink.borrow_mut().stream())?;
         self.stdout_scatterer.remove_sink(token);
-        self.stderr_scatterer.remove_sink(token);
+        if let Some(stderr_scatterer) = self.stderr_scatterer.as_mut() {
+            stderr_scatterer.remove_sink(token);
+        }
         self.log_sinks.remove(&token);
+        // release stdin (without closing it), so another client can attach to it
+        if self.stdin_writer == Some(token) {
+            self.stdin_writer = None;
+        }
+        // and kill whatever the client exec'd
+        self.kill_exec(token)?;
 
         Ok(())
     }
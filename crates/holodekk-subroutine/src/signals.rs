@@ -1,18 +1,23 @@
+use std::collections::{BTreeSet, HashMap};
 use std::os::fd::AsRawFd;
+use std::os::unix::process::ExitStatusExt;
 
 use log::{debug, warn};
 use mio::{event::Source, unix::SourceFd, Interest, Registry, Token};
 
 use nix::{
+    errno::Errno,
     sys::{
         signal::{self, SigSet, Signal},
         signalfd,
         wait::{waitpid, WaitPidFlag, WaitStatus},
     },
-    unistd::Pid,
+    unistd::{getpid, Pid},
 };
 
-use holodekk::utils::libsee;
+use holodekk::cgroups::Cgroup;
+use holodekk::enums::SubroutineStatus;
+use holodekk::utils::{libsee, process::descendants};
 
 type ExitCode = i32;
 
@@ -22,14 +27,56 @@ pub enum ExitStatus {
     Signaled(Pid, Signal),
 }
 
+impl ExitStatus {
+    pub fn success(&self) -> bool {
+        matches!(self, ExitStatus::Normal(_, 0))
+    }
+
+    /// The exit as reported to holodekkd (and attached clients).
+    pub fn status(&self) -> SubroutineStatus {
+        match self {
+            ExitStatus::Normal(_, code) => SubroutineStatus::Exited { code: *code },
+            ExitStatus::Signaled(_, signal) => SubroutineStatus::Killed {
+                signal: *signal as i32,
+            },
+        }
+    }
+
+    fn from_wait(status: WaitStatus) -> Option<Self> {
+        match status {
+            WaitStatus::Exited(pid, code) => Some(ExitStatus::Normal(pid, code)),
+            WaitStatus::Signaled(pid, signal, _) => Some(ExitStatus::Signaled(pid, signal)),
+            _ => None,
+        }
+    }
+}
+
+impl From<ExitStatus> for std::process::ExitStatus {
+    fn from(status: ExitStatus) -> Self {
+        // as encoded by wait(2)
+        match status {
+            ExitStatus::Normal(_, code) => Self::from_raw((code & 0xff) << 8),
+            ExitStatus::Signaled(_, signal) => Self::from_raw(signal as i32),
+        }
+    }
+}
+
 /// Processes signals bound for the shim.
 ///
-/// Also monitors for SIGCHLD signals generated by the subroutine.
+/// Also reaps the shim's children on SIGCHLD: the subroutine, processes exec'd alongside it,
+/// and (as we're a subreaper) any of their descendants orphaned along the way.
 #[derive(Debug)]
 pub struct SignalHandler {
     child_pid: Pid,
     fd: signalfd::SignalFd,
     status: Option<ExitStatus>,
+    terminating: bool,
+    /// Other processes whose exit is wanted (see [`Self::track`]), and how they exited.
+    tracked: HashMap<Pid, Option<ExitStatus>>,
+    /// Whether any children were left after the last reaping.
+    children: bool,
+    /// The subroutine's cgroup (whose members are signalled along with its descendants).
+    cgroup: Option<Cgroup>,
 }
 
 impl SignalHandler {
@@ -38,9 +85,63 @@ impl SignalHandler {
             child_pid,
             fd: signalfd::SignalFd::new(signals).expect("Could not create a signal set"),
             status: None,
+            terminating: false,
+            tracked: HashMap::new(),
+            children: true,
+            cgroup: None,
         }
     }
 
+    pub fn with_cgroup(self, cgroup: Cgroup) -> Self {
+        Self {
+            cgroup: Some(cgroup),
+            ..self
+        }
+    }
+
+    pub fn child_pid(&self) -> Pid {
+        self.child_pid
+    }
+
+    /// Monitors a new (restarted) subroutine process.
+    pub fn watch(&mut self, child_pid: Pid) {
+        self.child_pid = child_pid;
+        self.status = None;
+        self.children = true;
+    }
+
+    /// Keeps the exit status of another of our children (rather than discarding it when the
+    /// process is reaped).
+    pub fn track(&mut self, pid: Pid) {
+        self.tracked.insert(pid, None);
+        self.children = true;
+    }
+
+    /// Returns the exit status of a tracked process (if it has exited), which is then forgotten.
+    pub fn take_exit(&mut self, pid: Pid) -> Option<ExitStatus> {
+        let status = (*self.tracked.get(&pid)?)?;
+        self.tracked.remove(&pid);
+        Some(status)
+    }
+
+    /// Waits for a tracked process to exit.
+    pub fn wait_for(&mut self, pid: Pid) -> nix::Result<ExitStatus> {
+        if let Some(status) = self.take_exit(pid) {
+            return Ok(status);
+        }
+        self.tracked.remove(&pid);
+        loop {
+            if let Some(status) = ExitStatus::from_wait(waitpid(pid, None)?) {
+                return Ok(status);
+            }
+        }
+    }
+
+    /// Whether any of our children (or their descendants) are still around.
+    pub fn has_children(&self) -> bool {
+        self.children
+    }
+
     /// Called by the event loop when notified of pending signals.
     ///
     /// Signals received here fall into one of two categories:
@@ -48,7 +149,7 @@ impl SignalHandler {
     /// - Others: these are signals received by US that need to be forwarded to the subroutine
     pub fn handle_signal(&mut self) -> nix::Result<()> {
         match self.read_signal()? {
-            Signal::SIGCHLD => self.handle_sigchld()?,
+            Signal::SIGCHLD => self.reap()?,
             other => self.forward_signal(other)?,
         };
         Ok(())
@@ -59,6 +160,52 @@ impl SignalHandler {
         self.status
     }
 
+    /// Asks the subroutine to shut down (as if we'd received SIGTERM).
+    pub fn terminate(&mut self) -> nix::Result<()> {
+        self.forward_signal(Signal::SIGTERM)
+    }
+
+    /// Kills the subroutine (and everything it started) outright.
+    pub fn kill(&mut self) -> nix::Result<()> {
+        self.forward_signal(Signal::SIGKILL)
+    }
+
+    /// Sends a signal to every process the subroutine started (directly or otherwise), and every
+    /// process in its cgroup.
+    pub fn signal_tree(&self, signal: Signal) {
+        let mut pids = BTreeSet::new();
+        match descendants(getpid()) {
+            Ok(found) => pids.extend(found),
+            Err(err) => warn!("Unable to list the subroutine's processes: {}", err),
+        }
+        if let Some(cgroup) = self.cgroup.as_ref() {
+            match cgroup.processes() {
+                Ok(found) => pids.extend(found),
+                Err(err) => warn!(
+                    "Unable to list processes in cgroup {}: {}",
+                    cgroup.path().display(),
+                    err
+                ),
+            }
+        }
+        if self.status.is_none() {
+            pids.insert(self.child_pid);
+        }
+
+        debug!("Sending {} to {} process(es)", signal, pids.len());
+        for pid in pids {
+            match signal::kill(pid, signal) {
+                Ok(()) | Err(Errno::ESRCH) => {}
+                Err(err) => warn!("Failed to send {} to {}: {}", signal, pid, err),
+            }
+        }
+    }
+
+    /// Whether we've been asked to shut down (in which case the subroutine isn't restarted).
+    pub fn terminating(&self) -> bool {
+        self.terminating
+    }
+
     /// Attempts to read the actual signal from the underlying OS.
     fn read_signal(&mut self) -> nix::Result<Signal> {
         match self.fd.read_signal() {
@@ -68,24 +215,66 @@ impl SignalHandler {
         }
     }
 
-    fn handle_sigchld(&mut self) -> nix::Result<()> {
-        match waitpid(self.child_pid, Some(WaitPidFlag::WNOHANG))? {
-            WaitStatus::Exited(pid, code) => {
-                assert!(pid == self.child_pid);
-                self.status = Some(ExitStatus::Normal(pid, code));
-            }
-            WaitStatus::Signaled(pid, sig, _) => {
-                assert!(pid == self.child_pid);
-                self.status = Some(ExitStatus::Signaled(pid, sig));
-            }
-            _ => {
-                warn!("SIGCHLD fired, but nothing interesting to report");
+    /// Reaps every child that has exited (signals coalesce, so one SIGCHLD can stand for many).
+    pub fn reap(&mut self) -> nix::Result<()> {
+        loop {
+            let status = match waitpid(None, Some(WaitPidFlag::WNOHANG)) {
+                Ok(WaitStatus::StillAlive) => {
+                    self.children = true;
+                    break;
+                }
+                Ok(status) => status,
+                Err(Errno::ECHILD) => {
+                    self.children = false;
+                    break;
+                }
+                Err(Errno::EINTR) => continue,
+                Err(err) => return Err(err),
+            };
+            let Some(status) = ExitStatus::from_wait(status) else {
+                continue;
+            };
+            let pid = match status {
+                ExitStatus::Normal(pid, _) | ExitStatus::Signaled(pid, _) => pid,
+            };
+            if pid == self.child_pid && self.status.is_none() {
+                self.status = Some(status);
+            } else if let Some(tracked) = self.tracked.get_mut(&pid) {
+                *tracked = Some(status);
+            } else {
+                debug!("reaped orphaned process {} ({:?})", pid, status);
             }
         }
         Ok(())
     }
 
-    fn forward_signal(&self, signal: Signal) -> nix::Result<()> {
+    /// Sends the subroutine a signal on behalf of a control client.
+    ///
+    /// Unlike the signals we receive ourselves, these never mark us as terminating; so a
+    /// subroutine killed this way is still restarted, as its policy dictates.
+    pub fn signal(&mut self, signal: Signal) -> nix::Result<()> {
+        self.send_signal(signal)
+    }
+
+    /// Passes signals we've received (or are acting on) to the whole process tree.
+    fn forward_signal(&mut self, signal: Signal) -> nix::Result<()> {
+        if matches!(signal, Signal::SIGINT | Signal::SIGQUIT | Signal::SIGTERM) {
+            self.terminating = true;
+        }
+        debug!(
+            "Forwarding signal {} to the processes of child {}",
+            signal, self.child_pid
+        );
+        self.signal_tree(signal);
+        Ok(())
+    }
+
+    fn send_signal(&mut self, signal: Signal) -> nix::Result<()> {
+        if self.status.is_some() {
+            debug!("Subroutine has exited.  Not forwarding signal {}", signal);
+            return Ok(());
+        }
+
         debug!(
             "Forwarding signal {} to child process {}",
             signal, self.child_pid
@@ -1,10 +1,10 @@
 use std::cell::RefCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, VecDeque};
 use std::fs::File;
-use std::io::{Read, Write};
+use std::io::{ErrorKind, Read, Write};
 use std::mem;
 use std::os::fd::RawFd;
-use std::os::unix::io::FromRawFd;
+use std::os::unix::io::{AsRawFd, FromRawFd};
 use std::rc::Rc;
 
 use log::warn;
@@ -12,12 +12,23 @@ use log::warn;
 use mio::{event::Source, net::UnixStream, unix::SourceFd, Interest, Registry, Token};
 
 use nix::{
-    fcntl::{open, OFlag},
+    fcntl::{fcntl, open, FcntlArg, OFlag},
     sys::stat::Mode,
 };
 
+use holodekk::attach::{
+    encode_header, AttachRequest, AttachResult, Backpressure, Frame, FrameDecoder, FrameKind,
+    Replay, WindowSize, FRAME_HEADER_LEN,
+};
+use holodekk::logs::OutputStream;
+use holodekk::shim::AttachedClient;
+use holodekk::utils::libsee;
+
 const BUF_SIZE: usize = 32 * 1024;
 
+/// Most replayed output (in bytes) read from the log ahead of the client.
+const REPLAY_CHUNK: usize = 64 * 1024;
+
 #[repr(u8)]
 #[derive(Copy, Clone)]
 pub enum LogStreamKind {
@@ -47,11 +58,20 @@ impl LogStream {
         res
     }
 
+    /// Reads what's available, and writes it to every sink.
+    ///
+    /// Sinks must not block (clients queue their output, the log writes to a file), so a slow
+    /// client never holds up the log, or anyone else.  Sinks that fail are dropped.
     pub fn scatter(&mut self) -> std::io::Result<usize> {
         let mut buf = [0; BUF_SIZE];
-        let nread = self
-            .read(&mut buf[1..])
-            .expect("log read should have succeeded");
+        let nread = match self.read(&mut buf[1..]) {
+            Ok(nread) => nread,
+            // a terminal with nothing left on the other end (rather than EOF)
+            Err(err) if err.raw_os_error() == Some(libsee::EIO) => 0,
+            // a terminal's master side is shared with stdin, which is non-blocking
+            Err(err) if err.kind() == ErrorKind::WouldBlock => 0,
+            Err(err) => panic!("log read should have succeeded: {}", err),
+        };
 
         buf[0] = self.kind as u8;
 
@@ -102,18 +122,43 @@ impl Source for LogStream {
     }
 }
 
+/// A client attached to the subroutine's output (see [`holodekk::attach`]).
+///
+/// Until the client's hello arrives the sink ignores output; after that it frames whatever's
+/// scattered to it (from the streams the client asked for), and queues it for delivery.  Writes
+/// never block: once the client has fallen too far behind, its [`Backpressure`] policy either
+/// drops the oldest output or gives up on the client.
+///
+/// The client's replay is read from the log a chunk at a time, as the client takes what's
+/// queued; output scattered meanwhile is held (within the same limit) until it's done.
 pub(crate) struct StdioSink {
     stream: UnixStream,
-    buffer: RefCell<Vec<u8>>,
-    buffer_bytes: usize,
+    /// Collects frames sent by the client (its hello, then any input for stdin).
+    decoder: FrameDecoder,
+    request: Option<AttachRequest>,
+    queue: OutputQueue,
+    replay: Option<PendingReplay>,
+    /// Output scattered to the sink while its replay is pending.
+    held: OutputQueue,
+    /// Output (in bytes) dropped because the client fell behind.
+    dropped: u64,
+    /// Nothing more is to be queued; the sink is dropped once the queue's delivered.
+    closing: bool,
+    hung_up: bool,
 }
 
 impl StdioSink {
     pub fn new(stream: UnixStream) -> Self {
         Self {
             stream,
-            buffer: RefCell::new(vec![]),
-            buffer_bytes: 0,
+            decoder: FrameDecoder::new(),
+            request: None,
+            queue: OutputQueue::default(),
+            replay: None,
+            held: OutputQueue::default(),
+            dropped: 0,
+            closing: false,
+            hung_up: false,
         }
     }
 
@@ -121,35 +166,222 @@ impl StdioSink {
         &mut self.stream
     }
 
+    /// Whether the client's hello is yet to be accepted.
+    pub fn handshaking(&self) -> bool {
+        self.request.is_none()
+    }
+
+    /// Reads whatever the client has sent, returning the complete frames.
+    ///
+    /// Frames sent before the client hung up are still returned (see [`Self::hung_up`]).
+    pub fn read_frames(&mut self) -> AttachResult<Vec<Frame>> {
+        let mut buf = [0; 4096];
+        loop {
+            match self.stream.read(&mut buf) {
+                Ok(0) => {
+                    self.hung_up = true;
+                    break;
+                }
+                Ok(n) => self.decoder.push(&buf[..n]),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+        let mut frames = Vec::new();
+        while let Some(frame) = self.decoder.next_frame()? {
+            frames.push(frame);
+        }
+        Ok(frames)
+    }
+
+    pub fn hung_up(&self) -> bool {
+        self.hung_up
+    }
+
+    /// Starts sending output as the client requested.
+    pub fn accept(&mut self, request: AttachRequest) {
+        self.request = Some(request);
+    }
+
+    /// Sends the client its replay (once what's already queued has been delivered), followed by
+    /// `ReplayDone`; then closes the sink, unless the client is following.
+    pub fn replay(&mut self, entries: Option<Replay>, follow: bool) {
+        self.replay = Some(PendingReplay {
+            entries,
+            next: None,
+            follow,
+        });
+    }
+
+    /// Closes the sink with an `End` (after the client's replay, if it's still pending).
+    pub fn end(&mut self) {
+        match self.replay.as_mut() {
+            Some(replay) => replay.follow = false,
+            None => self.close(&Frame::empty(FrameKind::End)),
+        }
+    }
+
+    /// How the client is keeping up (once it's been accepted).
+    pub fn client(&self) -> Option<AttachedClient> {
+        self.request.as_ref().map(|request| AttachedClient {
+            backpressure: request.backpressure,
+            pending: self.pending(),
+            dropped: self.dropped,
+        })
+    }
+
+    /// Output (in bytes) waiting to be sent.
+    fn pending(&self) -> usize {
+        self.queue.len() + self.held.len()
+    }
+
+    fn limit(&self) -> usize {
+        self.request
+            .as_ref()
+            .map_or(Backpressure::default(), |request| request.backpressure)
+            .limit()
+    }
+
+    /// Queues a frame (regardless of how much is already pending).
+    pub fn queue(&mut self, frame: &Frame) {
+        let output = matches!(frame.kind, FrameKind::Stdout | FrameKind::Stderr);
+        self.queue.push(frame.encode(), output);
+    }
+
+    /// Queues the final frame (giving up on any replay still pending), after which the sink is
+    /// dropped.
+    pub fn close(&mut self, frame: &Frame) {
+        if !self.closing {
+            self.replay = None;
+            self.held = OutputQueue::default();
+            self.queue(frame);
+            self.closing = true;
+        }
+    }
+
+    pub fn closing(&self) -> bool {
+        self.closing
+    }
+
     pub fn data_pending(&self) -> bool {
-        !self.buffer_bytes.eq(&0)
+        !self.queue.is_empty() || self.replay.is_some()
+    }
+
+    /// Whether the sink has been closed, and everything queued delivered.
+    pub fn finished(&self) -> bool {
+        self.closing && !self.data_pending()
     }
 
+    /// Sends as much of the pending output (and replay) as the client will take.
     pub fn deliver_data(&mut self) -> std::io::Result<()> {
-        let mut buf = self.buffer.borrow_mut();
-        let res = self.stream.write_all(&buf[0..self.buffer_bytes]);
-        buf.clear();
-        self.buffer_bytes = 0;
-        res
+        loop {
+            if self.queue.is_empty() {
+                self.replay_more();
+            }
+            let Some(pending) = self.queue.front() else {
+                return Ok(());
+            };
+            match self.stream.write(pending) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n) => self.queue.consume(n),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            }
+        }
+    }
+
+    /// Queues the next chunk of the replay (as much as the limit leaves room for), finishing it
+    /// once the log's exhausted.
+    fn replay_more(&mut self) {
+        // (leaving at least half the limit for output scattered meanwhile)
+        let limit = self.limit();
+        let room = (limit / 2)
+            .min(REPLAY_CHUNK)
+            .min(limit.saturating_sub(self.held.len()));
+        let Some(replay) = self.replay.as_mut() else {
+            return;
+        };
+        loop {
+            let frame = match replay.next.take() {
+                Some(frame) => frame,
+                None => match replay.entries.as_mut().and_then(Iterator::next) {
+                    Some(Ok(entry)) => {
+                        let mut line = entry.message.into_bytes();
+                        line.push(b'\n');
+                        Frame::new(entry.stream.into(), line).encode()
+                    }
+                    Some(Err(err)) => {
+                        warn!("Failed to replay subroutine log: {}", err);
+                        replay.entries = None;
+                        continue;
+                    }
+                    None => break,
+                },
+            };
+            // (a frame too large for the limit is sent on its own)
+            if !self.queue.is_empty() && self.queue.len() + frame.len() > room {
+                replay.next = Some(frame);
+                return;
+            }
+            self.queue.push(frame, true);
+        }
+
+        let follow = replay.follow;
+        self.replay = None;
+        self.queue(&Frame::empty(FrameKind::ReplayDone));
+        self.queue.append(mem::take(&mut self.held));
+        if !follow {
+            self.close(&Frame::empty(FrameKind::End));
+        }
     }
 }
 
+/// A client's replay, still to be sent.
+struct PendingReplay {
+    entries: Option<Replay>,
+    /// Read from the log, but yet to fit in the queue.
+    next: Option<Vec<u8>>,
+    /// Keep the sink open once the replay's done.
+    follow: bool,
+}
+
 impl Write for StdioSink {
     fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
-        let mut buf = self.buffer.borrow_mut();
-        let written = if data.len() + self.buffer_bytes > BUF_SIZE {
-            // take as much data as we can
-            let available = BUF_SIZE - self.buffer_bytes;
-            buf.extend_from_slice(&data[0..available]);
-            self.buffer_bytes += available;
-            available
-        } else {
-            buf.extend_from_slice(data);
-            self.buffer_bytes += data.len();
-            data.len()
+        let stream = match data[0] {
+            kind if kind == LogStreamKind::Stdout as u8 => OutputStream::Stdout,
+            _ => OutputStream::Stderr,
+        };
+        let backpressure = match self.request.as_ref() {
+            Some(request) if request.wants(stream) && !self.closing => request.backpressure,
+            _ => return Ok(data.len()),
         };
 
-        Ok(written)
+        let output = &data[1..];
+        let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + output.len());
+        encode_header(stream.into(), output.len(), &mut frame);
+        frame.extend_from_slice(output);
+
+        let limit = backpressure.limit();
+        if self.pending() + frame.len() > limit && backpressure == Backpressure::Disconnect {
+            self.dropped += output.len() as u64;
+            self.close(&Frame::new(FrameKind::Error, "client too slow"));
+            return Err(std::io::Error::other("attached client too slow"));
+        }
+        // output is held until the replay is done (the replay itself is never dropped)
+        let (queue, replayed) = match self.replay {
+            Some(_) => (&mut self.held, self.queue.len()),
+            None => (&mut self.queue, 0),
+        };
+        if replayed + queue.len() + frame.len() > limit {
+            if self.dropped == 0 {
+                warn!("attached client too slow; dropping its oldest output");
+            }
+            self.dropped += queue.make_room(frame.len(), limit.saturating_sub(replayed));
+        }
+        queue.push(frame, true);
+        Ok(data.len())
     }
 
     fn flush(&mut self) -> std::io::Result<()> {
@@ -157,6 +389,223 @@ impl Write for StdioSink {
     }
 }
 
+/// Encoded frames waiting to be sent to an attached client, oldest first.
+///
+/// Frames are only ever dropped whole, so the client never gets part of one.
+#[derive(Default)]
+struct OutputQueue {
+    entries: VecDeque<Queued>,
+    /// How much of the front entry has been sent already.
+    sent: usize,
+    /// Bytes queued (less those sent).
+    len: usize,
+}
+
+enum Queued {
+    /// An encoded frame; only output (`Stdout`/`Stderr`) is ever dropped.
+    Frame { bytes: Vec<u8>, output: bool },
+    /// Output (in bytes) dropped at this point; sent as a `Dropped` frame once it's reached.
+    Dropped(u64),
+}
+
+impl OutputQueue {
+    fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn push(&mut self, bytes: Vec<u8>, output: bool) {
+        self.len += bytes.len();
+        self.entries.push_back(Queued::Frame { bytes, output });
+    }
+
+    /// Queues everything in `other` (which mustn't have been partly sent) after what's here.
+    fn append(&mut self, mut other: OutputQueue) {
+        self.len += other.len;
+        self.entries.append(&mut other.entries);
+    }
+
+    /// Drops the oldest output until `needed` more bytes fit within `limit` (or there's no
+    /// output left to drop), returning how much output was dropped.
+    fn make_room(&mut self, needed: usize, limit: usize) -> u64 {
+        let mut dropped = 0;
+        // the front frame may be partly sent
+        let mut i = usize::from(self.sent > 0);
+        while self.len + needed > limit && i < self.entries.len() {
+            let bytes = match &self.entries[i] {
+                Queued::Frame {
+                    bytes,
+                    output: true,
+                } => bytes.len(),
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            };
+            self.entries.remove(i);
+            self.len -= bytes;
+            let skipped = (bytes - FRAME_HEADER_LEN) as u64;
+            dropped += skipped;
+            // consecutive drops share a marker
+            match i.checked_sub(1).map(|prev| &mut self.entries[prev]) {
+                Some(Queued::Dropped(total)) => *total += skipped,
+                _ => {
+                    self.entries.insert(i, Queued::Dropped(skipped));
+                    i += 1;
+                }
+            }
+        }
+        dropped
+    }
+
+    /// What's left to send of the front entry.
+    fn front(&mut self) -> Option<&[u8]> {
+        let front = self.entries.front_mut()?;
+        if let Queued::Dropped(skipped) = *front {
+            let bytes = Frame::dropped(skipped).encode();
+            self.len += bytes.len();
+            *front = Queued::Frame {
+                bytes,
+                output: false,
+            };
+        }
+        match front {
+            Queued::Frame { bytes, .. } => Some(&bytes[self.sent..]),
+            Queued::Dropped(_) => unreachable!("markers are encoded before they're sent"),
+        }
+    }
+
+    /// Marks `n` bytes (of the front entry) sent.
+    fn consume(&mut self, n: usize) {
+        self.sent += n;
+        self.len -= n;
+        if let Some(Queued::Frame { bytes, .. }) = self.entries.front() {
+            if self.sent == bytes.len() {
+                self.entries.pop_front();
+                self.sent = 0;
+            }
+        }
+    }
+}
+
+/// Most input (in bytes) held for the subroutine's stdin before the writer is cut off.
+const MAX_STDIN_PENDING: usize = 1024 * 1024;
+
+/// Sent in place of closing a terminal (which can't be closed like a pipe).
+const TTY_EOF: u8 = 0x04;
+
+/// The shim's end of the subroutine's stdin pipe (when stdin is enabled), or its terminal.
+///
+/// Input is written as the pipe will take it; the pipe is only registered (for writability)
+/// while some is left over.
+pub(crate) struct StdinPipe {
+    pipe: Option<File>,
+    buffer: Vec<u8>,
+    registered: bool,
+    closing: bool,
+    tty: bool,
+}
+
+impl StdinPipe {
+    pub fn new(fd: RawFd) -> Self {
+        fcntl(fd, FcntlArg::F_SETFL(OFlag::O_NONBLOCK))
+            .expect("Failed to make stdin pipe non-blocking");
+        Self {
+            pipe: Some(unsafe { File::from_raw_fd(fd) }),
+            buffer: vec![],
+            registered: false,
+            closing: false,
+            tty: false,
+        }
+    }
+
+    /// Input for the (master side of the) subroutine's terminal.
+    pub fn tty(fd: RawFd) -> Self {
+        Self {
+            tty: true,
+            ..Self::new(fd)
+        }
+    }
+
+    pub fn is_tty(&self) -> bool {
+        self.tty
+    }
+
+    /// Resizes the terminal.
+    pub fn resize(&self, size: WindowSize) -> libsee::Result<()> {
+        match self.pipe.as_ref() {
+            Some(pipe) => libsee::set_window_size(pipe.as_raw_fd(), size.rows, size.cols),
+            None => Ok(()),
+        }
+    }
+
+    /// Whether input can still be written (the pipe hasn't been closed).
+    pub fn is_open(&self) -> bool {
+        self.pipe.is_some() && !self.closing
+    }
+
+    /// Queues input for the subroutine, writing what the pipe will take.
+    pub fn write(&mut self, data: &[u8], registry: &Registry, token: Token) -> std::io::Result<()> {
+        if !self.is_open() {
+            return Err(ErrorKind::BrokenPipe.into());
+        }
+        if self.buffer.len() + data.len() > MAX_STDIN_PENDING {
+            return Err(std::io::Error::other("subroutine isn't reading its stdin"));
+        }
+        self.buffer.extend_from_slice(data);
+        self.deliver(registry, token)
+    }
+
+    /// Closes the pipe (so the subroutine reads EOF) once any pending input is written.
+    ///
+    /// A terminal stays open, and is sent its EOF character instead.
+    pub fn close(&mut self, registry: &Registry, token: Token) -> std::io::Result<()> {
+        if self.tty {
+            return self.write(&[TTY_EOF], registry, token);
+        }
+        self.closing = true;
+        self.deliver(registry, token)
+    }
+
+    /// Writes as much pending input as the pipe will take.
+    pub fn deliver(&mut self, registry: &Registry, token: Token) -> std::io::Result<()> {
+        let Some(pipe) = self.pipe.as_mut() else {
+            return Ok(());
+        };
+        let mut written = 0;
+        let res = loop {
+            if written == self.buffer.len() {
+                break Ok(());
+            }
+            match pipe.write(&self.buffer[written..]) {
+                Ok(n) => written += n,
+                Err(err) if err.kind() == ErrorKind::WouldBlock => break Ok(()),
+                Err(err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(err) => break Err(err),
+            }
+        };
+        self.buffer.drain(..written);
+
+        let fd = pipe.as_raw_fd();
+        let pending = res.is_ok() && !self.buffer.is_empty();
+        if pending && !self.registered {
+            SourceFd(&fd).register(registry, token, Interest::WRITABLE)?;
+            self.registered = true;
+        } else if !pending && self.registered {
+            SourceFd(&fd).deregister(registry)?;
+            self.registered = false;
+        }
+        if res.is_err() || (self.closing && !pending) {
+            self.buffer.clear();
+            self.pipe = None;
+        }
+        res
+    }
+}
+
 pub(crate) fn open_dev_null() -> (RawFd, RawFd) {
     let rd = open(
         "/dev/null",
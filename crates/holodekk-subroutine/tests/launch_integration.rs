@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tempfile::tempdir;
+
+use holodekk::entities::{SceneEntity, SceneEntityRepository, MANIFEST_FILE};
+use holodekk::images::FilesystemSubroutineImageStore;
+use holodekk::ports::{PortAllocator, PortRange};
+use holodekk::repositories::memory::{MemoryDatabase, MemoryRepository};
+use holodekk::secrets::SecretStore;
+use holodekk::services::subroutine::{
+    CreateSubroutine, CreateSubroutineInput, SubroutineEntityService,
+};
+use holodekk::shim::ShimLauncher;
+use holodekk::HolodekkPaths;
+
+/// Waits for `path` to be written (or `timeout` to pass).
+fn wait_for_file(path: &Path, timeout: Duration) -> Option<String> {
+    let deadline = Instant::now() + timeout;
+    while Instant::now() < deadline {
+        match fs::read_to_string(path) {
+            Ok(contents) if !contents.is_empty() => return Some(contents),
+            _ => std::thread::sleep(Duration::from_millis(100)),
+        }
+    }
+    None
+}
+
+#[tokio::test]
+async fn launches_created_subroutines_with_their_resolved_environment() {
+    let root = tempdir().unwrap();
+    let bin_root = Path::new(env!("CARGO_BIN_EXE_holodekk-subroutine"))
+        .parent()
+        .unwrap()
+        .to_owned();
+    let paths = Arc::new(HolodekkPaths::new(
+        root.path().join("data"),
+        root.path().to_owned(),
+        bin_root,
+    ));
+    let secrets = Arc::new(SecretStore::new(&paths));
+    secrets.init().unwrap();
+    secrets.set("api-token", "s3cret").unwrap();
+
+    // the subroutine reports its environment, then exits
+    let output = root.path().join("environment");
+    let src = root.path().join("src");
+    fs::create_dir_all(&src).unwrap();
+    fs::write(
+        src.join("holodekk.sh"),
+        "printf '%s %s %s %s' \"$MODE\" \"$REGION\" \"$TOKEN\" \"$HOLODEKK_WIDGETS_ADDR\" > \"$OUTPUT\"\n",
+    )
+    .unwrap();
+    fs::write(
+        src.join(MANIFEST_FILE),
+        r#"{"name": "widgets", "environment": {"MODE": "manifest"}, "port": 4567}"#,
+    )
+    .unwrap();
+    let images = Arc::new(FilesystemSubroutineImageStore::new(&paths));
+    images.init().unwrap();
+    let image = images
+        .create_from_directory(&"acme/widgets".into(), &src)
+        .unwrap();
+
+    let repo = Arc::new(MemoryRepository::new(Arc::new(MemoryDatabase::new())));
+    let mut scene = SceneEntity::new("reports".into());
+    scene.environment = HashMap::from([
+        ("MODE".to_string(), "scene".to_string()),
+        ("REGION".to_string(), "us-east".to_string()),
+    ]);
+    let scene = repo.scenes_create(scene).await.unwrap();
+    let ports = Arc::new(
+        PortAllocator::from_file(root.path().join("ports.json"), PortRange::default()).unwrap(),
+    );
+    let service = SubroutineEntityService::new(repo)
+        .with_images(images)
+        .with_port_allocator(ports)
+        .with_launcher(Arc::new(ShimLauncher::new(paths.clone(), secrets)));
+
+    let environment = HashMap::from([
+        ("MODE".to_string(), "subroutine".to_string()),
+        ("TOKEN".to_string(), "${secret:api-token}".to_string()),
+        ("OUTPUT".to_string(), output.to_string_lossy().to_string()),
+    ]);
+    let subroutine = service
+        .create(
+            &CreateSubroutineInput::new(&scene.id, &image.id)
+                .with_environment(&environment)
+                .with_scene_environment(&scene.environment),
+        )
+        .await
+        .unwrap();
+
+    // the subroutine's own variables (including its image's defaults) override the scene's,
+    // alongside the scene's discovery variables
+    assert_eq!(
+        wait_for_file(&output, Duration::from_secs(10)),
+        Some(format!(
+            "subroutine us-east s3cret 127.0.0.1:{}",
+            subroutine.host_port.unwrap()
+        ))
+    );
+    // and the secret was never written to the shim's spec
+    let spec = fs::read_to_string(
+        paths
+            .subroutines_root()
+            .join(&subroutine.id)
+            .join("spec.json"),
+    )
+    .unwrap();
+    assert!(!spec.contains("s3cret"));
+}
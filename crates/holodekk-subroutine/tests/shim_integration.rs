@@ -0,0 +1,224 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use tempfile::{tempdir, TempDir};
+
+use holodekk::attach::{AttachClient, AttachRequest, Backpressure, FrameKind};
+use holodekk::entities::SubroutineEntityId;
+use holodekk::enums::{SubroutineHealth, SubroutineStatus};
+use holodekk::health::{HealthProbe, ProbeCheck};
+use holodekk::shim::{ShimClient, ShimCommand, ShimControlError, SubroutineSpec};
+use holodekk::HolodekkPaths;
+
+/// A shim (built alongside these tests), running the given subroutine.
+struct Shim {
+    _root: TempDir,
+    pid: i32,
+    /// Where the shim keeps the subroutine's log and sockets.
+    dir: PathBuf,
+    client: ShimClient,
+}
+
+impl Shim {
+    fn spawn(spec: SubroutineSpec) -> Self {
+        let root = tempdir().unwrap();
+        let bin_root = Path::new(env!("CARGO_BIN_EXE_holodekk-subroutine"))
+            .parent()
+            .unwrap()
+            .to_owned();
+        // (sockets are kept directly under the root, as their paths can't be long)
+        let paths = HolodekkPaths::new(root.path().join("data"), root.path().to_owned(), bin_root);
+        let shim = ShimCommand::new(&SubroutineEntityId::generate(), root.path(), "default")
+            .with_spec(spec);
+        let pid = shim.spawn(&paths).unwrap();
+        let dir = shim.root(&paths);
+
+        let client = ShimClient::connect(shim.control_socket(&paths))
+            .and_then(|client| client.with_timeout(Duration::from_secs(5)))
+            .unwrap();
+        Self {
+            _root: root,
+            pid,
+            dir,
+            client,
+        }
+    }
+
+    /// Polls the shim until the subroutine's health is `health` (or `timeout` passes).
+    fn wait_for_health(&mut self, health: SubroutineHealth, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while Instant::now() < deadline {
+            if self.client.status().unwrap().health == health {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        false
+    }
+
+    fn stop(mut self) {
+        self.client.stop(Duration::from_secs(5)).unwrap();
+    }
+}
+
+fn shell(script: &str) -> Vec<String> {
+    vec!["/bin/sh".into(), "-c".into(), script.into()]
+}
+
+/// Whether the process is still around (and not just waiting to be reaped).
+fn alive(pid: i32) -> bool {
+    fs::read_to_string(format!("/proc/{}/stat", pid)).is_ok_and(|stat| {
+        !stat
+            .rsplit(')')
+            .next()
+            .unwrap()
+            .trim_start()
+            .starts_with('Z')
+    })
+}
+
+/// Waits for the subroutine to write a pid to `path`.
+fn wait_for_pid(path: &Path) -> i32 {
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while Instant::now() < deadline {
+        if let Some(pid) = fs::read_to_string(path)
+            .ok()
+            .and_then(|pid| pid.trim().parse().ok())
+        {
+            return pid;
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+    panic!("no pid written to {}", path.display());
+}
+
+#[test]
+fn exec_probes_run_while_orphans_are_reaped() {
+    // the subroutine keeps orphaning processes, which the shim (as subreaper) reaps while the
+    // probe's command is running
+    let mut spec =
+        SubroutineSpec::default().with_command(shell("while true; do (true &); sleep 0.05; done"));
+    spec.health_probe = Some(
+        HealthProbe::new(ProbeCheck::Exec {
+            command: shell("sleep 0.2"),
+        })
+        .with_interval(1)
+        .with_success_threshold(2),
+    );
+    let mut shim = Shim::spawn(spec);
+
+    let healthy = shim.wait_for_health(SubroutineHealth::Healthy, Duration::from_secs(10));
+    shim.stop();
+
+    assert!(healthy);
+}
+
+#[test]
+fn exec_probes_fail_for_commands_that_cannot_run() {
+    // the forked child reports the command missing, failing the probe
+    let mut spec = SubroutineSpec::default().with_command(shell("sleep 60"));
+    spec.health_probe = Some(
+        HealthProbe::new(ProbeCheck::Exec {
+            command: vec!["no-such-probe-command".into()],
+        })
+        .with_interval(1)
+        .with_failure_threshold(1),
+    );
+    let mut shim = Shim::spawn(spec);
+
+    let unhealthy = shim.wait_for_health(SubroutineHealth::Unhealthy, Duration::from_secs(10));
+    shim.stop();
+
+    assert!(unhealthy);
+}
+
+#[test]
+fn rejects_stop_timeouts_that_overflow() {
+    let mut shim = Shim::spawn(SubroutineSpec::default().with_command(shell("sleep 60")));
+
+    let res = shim.client.stop(Duration::from_secs(u64::MAX));
+    let status = shim.client.status().unwrap().status;
+    shim.stop();
+
+    // rejected, leaving the subroutine running
+    assert!(matches!(res, Err(ShimControlError::Rejected(..))));
+    assert!(status.is_supervised());
+}
+
+#[test]
+fn stop_kills_descendants_that_ignore_sigterm() {
+    let temp = tempdir().unwrap();
+    let pidfile = temp.path().join("grandchild.pid");
+    let spec = SubroutineSpec::default().with_command(shell(&format!(
+        "(trap '' TERM; exec sleep 60) & echo $! > {}; wait",
+        pidfile.display()
+    )));
+    let mut shim = Shim::spawn(spec);
+    let grandchild = wait_for_pid(&pidfile);
+    let subroutine = shim.client.status().unwrap().pid.unwrap() as i32;
+
+    let started = Instant::now();
+    let status = shim.client.stop(Duration::from_secs(1)).unwrap();
+    let elapsed = started.elapsed();
+    // the shim answers once everything is gone, and only then exits
+    let (subroutine_alive, grandchild_alive) = (alive(subroutine), alive(grandchild));
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while alive(shim.pid) && Instant::now() < deadline {
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    // the subroutine went on SIGTERM, but its child had to be killed once the timeout passed
+    assert_eq!(status.status, SubroutineStatus::Stopped);
+    assert!(elapsed >= Duration::from_secs(1));
+    assert!(!subroutine_alive);
+    assert!(!grandchild_alive);
+    assert!(!alive(shim.pid));
+}
+
+#[test]
+fn replays_large_histories_within_the_clients_limit() {
+    // (ticking, as the shim reads a chunk of output per wakeup)
+    let mut shim = Shim::spawn(SubroutineSpec::default().with_command(shell(
+        "seq 1 100000; echo done; while true; do echo tick; sleep 0.05; done",
+    )));
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while !fs::read_to_string(shim.dir.join("subroutine.log")).is_ok_and(|log| log.contains("done"))
+    {
+        assert!(
+            Instant::now() < deadline,
+            "subroutine never finished writing"
+        );
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    // ~600KiB of history, for a client holding at most 4KiB
+    let limit = 4096;
+    let request = AttachRequest::default()
+        .with_tail(usize::MAX)
+        .with_backpressure(Backpressure::RingBuffer { bytes: limit });
+    let mut client = AttachClient::connect(shim.dir.join("log.sock"), &request).unwrap();
+    thread::sleep(Duration::from_millis(200));
+    let attached = shim.client.status().unwrap().clients;
+
+    let mut output = Vec::new();
+    let mut dropped = false;
+    while let Some(frame) = client.next_frame().unwrap() {
+        match frame.kind {
+            FrameKind::Stdout => output.extend(frame.payload),
+            FrameKind::Dropped => dropped = true,
+            _ => {}
+        }
+    }
+    shim.stop();
+
+    // held back while the client wasn't reading, then sent in full
+    assert_eq!(attached.len(), 1);
+    assert!(attached[0].pending <= limit);
+    assert!(!dropped);
+    let expected: String = (1..=100000).map(|n| format!("{}\n", n)).collect();
+    assert!(String::from_utf8(output)
+        .unwrap()
+        .starts_with(&(expected + "done\n")));
+}
@@ -6,6 +6,10 @@ impl From<SubroutineKind> for RpcSubroutineKind {
     fn from(kind: SubroutineKind) -> Self {
         match kind {
             SubroutineKind::Ruby => RpcSubroutineKind::Ruby,
+            SubroutineKind::Python => RpcSubroutineKind::Python,
+            SubroutineKind::Node => RpcSubroutineKind::Node,
+            SubroutineKind::Shell => RpcSubroutineKind::Shell,
+            SubroutineKind::Executable => RpcSubroutineKind::Executable,
             SubroutineKind::Unknown => RpcSubroutineKind::UnknownSubroutineKind,
         }
     }
@@ -15,6 +19,10 @@ impl From<RpcSubroutineKind> for SubroutineKind {
     fn from(kind: RpcSubroutineKind) -> Self {
         match kind {
             RpcSubroutineKind::Ruby => SubroutineKind::Ruby,
+            RpcSubroutineKind::Python => SubroutineKind::Python,
+            RpcSubroutineKind::Node => SubroutineKind::Node,
+            RpcSubroutineKind::Shell => SubroutineKind::Shell,
+            RpcSubroutineKind::Executable => SubroutineKind::Executable,
             RpcSubroutineKind::UnknownSubroutineKind => SubroutineKind::Unknown,
         }
     }
@@ -44,6 +52,15 @@ mod tests {
         assert_eq!(subroutine_kind, SubroutineKind::Unknown);
     }
 
+    #[test]
+    fn converts_to_python_from_rpc_python() {
+        let rpc_subroutine_kind = RpcSubroutineKind::Python;
+
+        let subroutine_kind: SubroutineKind = rpc_subroutine_kind.into();
+
+        assert_eq!(subroutine_kind, SubroutineKind::Python);
+    }
+
     #[test]
     fn converts_to_rpc_ruby_from_ruby() {
         let subroutine_kind = SubroutineKind::Ruby;
@@ -53,6 +70,15 @@ mod tests {
         assert_eq!(rpc_subroutine_kind, RpcSubroutineKind::Ruby);
     }
 
+    #[test]
+    fn converts_to_rpc_executable_from_executable() {
+        let subroutine_kind = SubroutineKind::Executable;
+
+        let rpc_subroutine_kind: RpcSubroutineKind = subroutine_kind.into();
+
+        assert_eq!(rpc_subroutine_kind, RpcSubroutineKind::Executable);
+    }
+
     #[test]
     fn converts_to_rpc_unknown_from_unknown() {
         let subroutine_kind = SubroutineKind::Unknown;
@@ -35,12 +35,7 @@ impl From<SubroutineDefinitionEntity> for entities::RpcSubroutineDefinition {
             kind: 0,
         };
 
-        match definition.kind() {
-            SubroutineKind::Ruby => res.set_kind(enums::RpcSubroutineKind::Ruby),
-            SubroutineKind::Unknown => {
-                res.set_kind(enums::RpcSubroutineKind::UnknownSubroutineKind)
-            }
-        };
+        res.set_kind(definition.kind().into());
 
         res
     }
@@ -48,11 +43,9 @@ impl From<SubroutineDefinitionEntity> for entities::RpcSubroutineDefinition {
 
 impl From<entities::RpcSubroutineDefinition> for SubroutineDefinitionEntity {
     fn from(definition: entities::RpcSubroutineDefinition) -> Self {
-        let kind = match enums::RpcSubroutineKind::from_i32(definition.kind) {
-            Some(enums::RpcSubroutineKind::Ruby) => SubroutineKind::Ruby,
-            Some(enums::RpcSubroutineKind::UnknownSubroutineKind) => SubroutineKind::Unknown,
-            None => SubroutineKind::Unknown,
-        };
+        let kind = enums::RpcSubroutineKind::from_i32(definition.kind)
+            .map(SubroutineKind::from)
+            .unwrap_or(SubroutineKind::Unknown);
         Self::new(definition.name, definition.path, kind)
     }
 }
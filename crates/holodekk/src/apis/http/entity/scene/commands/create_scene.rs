@@ -20,9 +20,7 @@ where
 {
     let scene = state
         .scene_entity_service()
-        .create(&CreateSceneInput {
-            name: &new_scene.name,
-        })
+        .create(&CreateSceneInput::new(&new_scene.name).with_environment(&new_scene.environment))
         .await?;
 
     Ok(CreateResponse(scene.into()))
@@ -64,6 +62,7 @@ mod tests {
         let body = Body::from(
             serde_json::to_string(&NewScene {
                 name: "test".to_string(),
+                environment: Default::default(),
             })
             .unwrap(),
         );
@@ -0,0 +1,137 @@
+use std::sync::Arc;
+
+use axum::extract::{Path, State};
+
+use crate::apis::http::entity::scene::models::Endpoint;
+use crate::apis::http::{ApiState, GetResponse};
+use crate::services::{
+    scene::{GetScene, GetSceneInput},
+    subroutine::{FindEndpoints, FindEndpointsInput},
+    EntityServiceError,
+};
+
+pub async fn find_endpoints<A, E, U>(
+    State(state): State<Arc<A>>,
+    Path(scene): Path<String>,
+) -> Result<GetResponse<Vec<Endpoint>>, EntityServiceError>
+where
+    A: ApiState<E, U>,
+    E: GetScene,
+    U: FindEndpoints,
+{
+    let scene = state
+        .scene_entity_service()
+        .get(&GetSceneInput::new(&scene))
+        .await?;
+
+    let endpoints = state
+        .subroutine_entity_service()
+        .endpoints(&FindEndpointsInput::new(&scene.id))
+        .await?;
+
+    Ok(GetResponse(endpoints.into_iter().map(Into::into).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+        routing::get,
+        Router,
+    };
+    use rstest::*;
+    use tower::ServiceExt;
+
+    use crate::apis::http::MockApiState;
+    use crate::entities::{fixtures::mock_scene_entity, EntityId, SceneEntity, SubroutineEntityId};
+    use crate::ports::PortAssignment;
+    use crate::services::{
+        scene::{fixtures::mock_get_scene, MockGetScene},
+        subroutine::{fixtures::mock_find_endpoints, MockFindEndpoints},
+    };
+
+    use super::*;
+
+    fn mock_app(mock_get_scene: MockGetScene, mock_find: MockFindEndpoints) -> Router {
+        let mut state = MockApiState::default();
+
+        state
+            .expect_scene_entity_service()
+            .return_once(move || Arc::new(mock_get_scene));
+        state
+            .expect_subroutine_entity_service()
+            .return_once(move || Arc::new(mock_find));
+        Router::new()
+            .route("/:scene/endpoints", get(find_endpoints))
+            .with_state(Arc::new(state))
+    }
+
+    fn make_request(
+        mock_get: MockGetScene,
+        mock_find: MockFindEndpoints,
+        scene: SceneEntity,
+    ) -> tower::util::Oneshot<axum::Router, http::Request<hyper::Body>> {
+        mock_app(mock_get, mock_find).oneshot(
+            Request::builder()
+                .method("GET")
+                .uri(format!("/{}/endpoints", scene.id))
+                .body(Body::empty())
+                .unwrap(),
+        )
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn returns_not_found_when_scene_does_not_exist(
+        mut mock_get_scene: MockGetScene,
+        mock_find_endpoints: MockFindEndpoints,
+        mock_scene_entity: SceneEntity,
+    ) {
+        mock_get_scene.expect_get().return_once(move |input| {
+            let id: EntityId = input.id.parse().unwrap();
+            Err(EntityServiceError::NotFound(id))
+        });
+
+        let response = make_request(mock_get_scene, mock_find_endpoints, mock_scene_entity)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn returns_endpoints(
+        mut mock_get_scene: MockGetScene,
+        mut mock_find_endpoints: MockFindEndpoints,
+        mock_scene_entity: SceneEntity,
+    ) {
+        {
+            let entity = mock_scene_entity.clone();
+            mock_get_scene.expect_get().return_once(move |_| Ok(entity));
+        }
+        let assignment = PortAssignment {
+            scene_entity_id: mock_scene_entity.id.clone(),
+            subroutine_entity_id: SubroutineEntityId::generate(),
+            name: "api".to_string(),
+            port: 20001,
+        };
+        {
+            let assignment = assignment.clone();
+            mock_find_endpoints
+                .expect_endpoints()
+                .return_once(move |_| Ok(vec![assignment]));
+        }
+
+        let response = make_request(mock_get_scene, mock_find_endpoints, mock_scene_entity)
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let endpoints: Vec<Endpoint> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(endpoints, vec![Endpoint::from(assignment)]);
+        assert_eq!(endpoints[0].variable, "HOLODEKK_API_ADDR");
+        assert_eq!(endpoints[0].address, "127.0.0.1:20001");
+    }
+}
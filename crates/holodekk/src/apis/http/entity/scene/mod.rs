@@ -19,6 +19,7 @@ where
     Router::new()
         .route("/", get(commands::find_scenes).post(commands::create_scene))
         .route("/:scene", delete(commands::delete_scene))
+        .route("/:scene/endpoints", get(commands::find_endpoints))
         .nest("/:scene/subroutines", subroutine::router(state.clone()))
         .with_state(state)
 }
@@ -28,6 +29,8 @@ pub mod commands {
     pub use create_scene::*;
     mod delete_scene;
     pub use delete_scene::*;
+    mod find_endpoints;
+    pub use find_endpoints::*;
     mod find_scenes;
     pub use find_scenes::*;
 }
@@ -1,12 +1,17 @@
+use std::collections::HashMap;
+
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 
 use crate::entities::SceneEntity;
 use crate::enums::SceneStatus;
+use crate::ports::PortAssignment;
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct NewScene {
     pub name: String,
+    #[serde(default)]
+    pub environment: HashMap<String, String>,
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
@@ -14,6 +19,8 @@ pub struct Scene {
     pub id: String,
     pub name: String,
     pub status: SceneStatus,
+    /// Environment as configured (secret references are not resolved).
+    pub environment: HashMap<String, String>,
     pub created_at: NaiveDateTime,
     pub updated_at: Option<NaiveDateTime>,
 }
@@ -24,8 +31,30 @@ impl From<SceneEntity> for Scene {
             id: entity.id.into(),
             name: entity.name.into(),
             status: entity.status,
+            environment: entity.environment,
             created_at: entity.created_at.unwrap(),
             updated_at: entity.updated_at,
         }
     }
 }
+
+/// Address a subroutine in the scene can be reached on.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Endpoint {
+    pub subroutine_entity_id: String,
+    pub name: String,
+    pub address: String,
+    /// Environment variable the address is published to the scene under.
+    pub variable: String,
+}
+
+impl From<PortAssignment> for Endpoint {
+    fn from(assignment: PortAssignment) -> Self {
+        Self {
+            address: assignment.address().to_string(),
+            variable: assignment.discovery_variable(),
+            subroutine_entity_id: assignment.subroutine_entity_id.into(),
+            name: assignment.name,
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use hyper::{
+    client::HttpConnector,
+    http::{header, Method, Request, StatusCode},
+    Body, Client,
+};
+use serde::Serialize;
+
+use crate::entities::SubroutineStatusChange;
+use crate::enums::SubroutineHealth;
+use crate::errors::error_chain_fmt;
+
+use super::models::{SubroutineHealthReport, SubroutineStatusReport};
+
+#[derive(thiserror::Error)]
+pub enum SubroutineClientError {
+    #[error("Subroutine report failed")]
+    Http(#[from] hyper::Error),
+    #[error("Invalid subroutine report request")]
+    Request(#[from] hyper::http::Error),
+    #[error("holodekkd responded with {0}: {1}")]
+    Status(StatusCode, String),
+    #[error("Subroutine report serialization error")]
+    Serialization(#[from] serde_json::Error),
+}
+
+impl std::fmt::Debug for SubroutineClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+pub type SubroutineClientResult<T> = std::result::Result<T, SubroutineClientError>;
+
+/// Client used by subroutine shims to report on their subroutine to holodekkd.
+///
+/// `endpoint` is the base url of the holodekkd API (e.g. `http://127.0.0.1:7979`).
+#[derive(Clone, Debug)]
+pub struct SubroutineClient {
+    client: Client<HttpConnector>,
+    endpoint: String,
+    scene_entity_id: String,
+    subroutine_entity_id: String,
+}
+
+impl SubroutineClient {
+    pub fn new<S: Into<String>>(
+        endpoint: S,
+        scene_entity_id: &str,
+        subroutine_entity_id: &str,
+    ) -> Self {
+        Self {
+            client: Client::new(),
+            endpoint: endpoint.into().trim_end_matches('/').to_string(),
+            scene_entity_id: scene_entity_id.to_string(),
+            subroutine_entity_id: subroutine_entity_id.to_string(),
+        }
+    }
+
+    pub async fn report_health(&self, health: SubroutineHealth) -> SubroutineClientResult<()> {
+        self.put("health", &SubroutineHealthReport { health }).await
+    }
+
+    pub async fn report_status(
+        &self,
+        change: SubroutineStatusChange,
+    ) -> SubroutineClientResult<()> {
+        self.put("status", &SubroutineStatusReport::from(change))
+            .await
+    }
+
+    async fn put<T: Serialize>(&self, resource: &str, report: &T) -> SubroutineClientResult<()> {
+        let request = Request::builder()
+            .method(Method::PUT)
+            .uri(format!(
+                "{}/scenes/{}/subroutines/{}/{}",
+                self.endpoint, self.scene_entity_id, self.subroutine_entity_id, resource
+            ))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(serde_json::to_vec(report)?))?;
+
+        let response = self.client.request(request).await?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            let status = response.status();
+            let body = hyper::body::to_bytes(response.into_body()).await?;
+            Err(SubroutineClientError::Status(
+                status,
+                String::from_utf8_lossy(&body).to_string(),
+            ))
+        }
+    }
+}
@@ -8,6 +8,7 @@ use axum::{
 use crate::apis::http::entity::subroutine::models::{NewSubroutine, Subroutine};
 use crate::apis::http::{ApiState, CreateResponse};
 use crate::services::{
+    scene::{GetScene, GetSceneInput},
     subroutine::{CreateSubroutine, CreateSubroutineInput},
     EntityServiceError,
 };
@@ -19,16 +20,31 @@ pub async fn create_subroutine<A, E, U>(
 ) -> Result<CreateResponse<Subroutine>, EntityServiceError>
 where
     A: ApiState<E, U>,
-    E: Send + Sync + 'static,
+    E: GetScene,
     U: CreateSubroutine,
 {
-    let subroutine = state
-        .subroutine_entity_service()
-        .create(&CreateSubroutineInput::new(
-            &scene,
-            &new_subroutine.subroutine_image_id,
-        ))
+    let scene = state
+        .scene_entity_service()
+        .get(&GetSceneInput::new(&scene))
         .await?;
+
+    let mut input = CreateSubroutineInput::new(&scene.id, &new_subroutine.subroutine_image_id)
+        .with_environment(&new_subroutine.environment)
+        .with_scene_environment(&scene.environment)
+        .with_restart_policy(new_subroutine.restart_policy)
+        .with_limits(new_subroutine.limits)
+        .with_process_limits(new_subroutine.process_limits)
+        .with_log_rotation(new_subroutine.log_rotation)
+        .with_log_format(new_subroutine.log_format)
+        .with_stdin(new_subroutine.stdin)
+        .with_tty(new_subroutine.tty);
+    if let Some(health_probe) = new_subroutine.health_probe.as_ref() {
+        input = input.with_health_probe(health_probe);
+    }
+    if let Some(run_as) = new_subroutine.run_as.as_ref() {
+        input = input.with_run_as(run_as);
+    }
+    let subroutine = state.subroutine_entity_service().create(&input).await?;
     Ok(CreateResponse(subroutine.into()))
 }
 
@@ -50,14 +66,17 @@ mod tests {
     };
     use crate::images::{fixtures::mock_subroutine_image, SubroutineImage};
     use crate::services::{
-        scene::fixtures::MockSceneEntityService,
+        scene::{fixtures::mock_get_scene, MockGetScene},
         subroutine::{fixtures::mock_create_subroutine, MockCreateSubroutine},
     };
 
     use super::*;
 
-    fn mock_app(mock_create: MockCreateSubroutine) -> Router {
-        let mut state = MockApiState::<MockSceneEntityService, MockCreateSubroutine>::default();
+    fn mock_app(mock_get: MockGetScene, mock_create: MockCreateSubroutine) -> Router {
+        let mut state = MockApiState::default();
+        state
+            .expect_scene_entity_service()
+            .return_once(move || Arc::new(mock_get));
         state
             .expect_subroutine_entity_service()
             .return_once(move || Arc::new(mock_create));
@@ -67,22 +86,35 @@ mod tests {
     }
 
     fn make_request(
+        mut mock_get: MockGetScene,
         mock_create: MockCreateSubroutine,
         scene: SceneEntity,
         subroutine: SubroutineImage,
     ) -> tower::util::Oneshot<axum::Router, http::Request<hyper::Body>> {
+        let uri = format!("/{}/subroutines", scene.id);
+        mock_get.expect_get().return_once(move |_| Ok(scene));
         let body = Body::from(
             serde_json::to_string(&NewSubroutine {
                 subroutine_image_id: subroutine.id.to_string(),
+                environment: Default::default(),
+                health_probe: None,
+                restart_policy: Default::default(),
+                limits: Default::default(),
+                run_as: None,
+                process_limits: Default::default(),
+                log_rotation: Default::default(),
+                log_format: Default::default(),
+                stdin: false,
+                tty: false,
             })
             .unwrap(),
         );
 
-        mock_app(mock_create).oneshot(
+        mock_app(mock_get, mock_create).oneshot(
             Request::builder()
                 .method("POST")
                 .header("Content-Type", "application/json")
-                .uri(format!("/{}/subroutines", scene.id))
+                .uri(uri)
                 .body(body)
                 .unwrap(),
         )
@@ -91,6 +123,7 @@ mod tests {
     #[rstest]
     #[tokio::test]
     async fn responds_with_conflict_when_subroutine_exists(
+        mock_get_scene: MockGetScene,
         mut mock_create_subroutine: MockCreateSubroutine,
         mock_scene_entity: SceneEntity,
         mock_subroutine_image: SubroutineImage,
@@ -100,6 +133,7 @@ mod tests {
             .return_once(move |_| Err(EntityServiceError::NotUnique("Already exists".into())));
 
         let response = make_request(
+            mock_get_scene,
             mock_create_subroutine,
             mock_scene_entity,
             mock_subroutine_image,
@@ -113,6 +147,7 @@ mod tests {
     #[rstest]
     #[tokio::test]
     async fn responds_with_created(
+        mock_get_scene: MockGetScene,
         mut mock_create_subroutine: MockCreateSubroutine,
         mock_scene_entity: SceneEntity,
         mock_subroutine_image: SubroutineImage,
@@ -126,6 +161,7 @@ mod tests {
         }
 
         let response = make_request(
+            mock_get_scene,
             mock_create_subroutine,
             mock_scene_entity,
             mock_subroutine_image,
@@ -139,6 +175,7 @@ mod tests {
     #[rstest]
     #[tokio::test]
     async fn returns_the_new_subroutine(
+        mock_get_scene: MockGetScene,
         mut mock_create_subroutine: MockCreateSubroutine,
         mock_subroutine_entity: SubroutineEntity,
         mock_scene_entity: SceneEntity,
@@ -152,6 +189,7 @@ mod tests {
         }
 
         let response = make_request(
+            mock_get_scene,
             mock_create_subroutine,
             mock_scene_entity,
             mock_subroutine_image,
@@ -163,4 +201,68 @@ mod tests {
         let p: SubroutineEntity = serde_json::from_slice(&body).unwrap();
         assert_eq!(p, mock_subroutine_entity);
     }
+
+    #[rstest]
+    #[tokio::test]
+    async fn passes_the_scene_environment(
+        mock_get_scene: MockGetScene,
+        mut mock_create_subroutine: MockCreateSubroutine,
+        mut mock_scene_entity: SceneEntity,
+        mock_subroutine_image: SubroutineImage,
+        mock_subroutine_entity: SubroutineEntity,
+    ) {
+        mock_scene_entity
+            .environment
+            .insert("REGION".to_string(), "us-east".to_string());
+        mock_create_subroutine
+            .expect_create()
+            .withf(|input| {
+                input
+                    .scene_environment
+                    .and_then(|environment| environment.get("REGION"))
+                    .map(String::as_str)
+                    == Some("us-east")
+            })
+            .return_once(move |_| Ok(mock_subroutine_entity));
+
+        let response = make_request(
+            mock_get_scene,
+            mock_create_subroutine,
+            mock_scene_entity,
+            mock_subroutine_image,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.status(), StatusCode::CREATED);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn responds_with_not_found_for_unknown_scenes(
+        mut mock_get_scene: MockGetScene,
+        mock_create_subroutine: MockCreateSubroutine,
+        mock_scene_entity: SceneEntity,
+    ) {
+        let id = mock_scene_entity.id.clone();
+        mock_get_scene
+            .expect_get()
+            .return_once(move |_| Err(EntityServiceError::NotFound(id)));
+        let body =
+            Body::from(serde_json::json!({ "subroutine_image_id": "acme/widgets" }).to_string());
+
+        let response = mock_app(mock_get_scene, mock_create_subroutine)
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .header("Content-Type", "application/json")
+                    .uri(format!("/{}/subroutines", mock_scene_entity.id))
+                    .body(body)
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
 }
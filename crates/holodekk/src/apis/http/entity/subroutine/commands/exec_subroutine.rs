@@ -0,0 +1,237 @@
+use std::sync::Arc;
+
+use axum::{
+    body::Body,
+    extract::{Path, State},
+    http::{header, Request, StatusCode},
+    response::{IntoResponse, Response},
+};
+use log::{debug, warn};
+
+use crate::apis::http::entity::subroutine::models::SubroutineExec;
+use crate::apis::http::ApiState;
+use crate::attach::ATTACH_UPGRADE_PROTOCOL;
+use crate::services::{
+    scene::{GetScene, GetSceneInput},
+    subroutine::{ExecSubroutine, ExecSubroutineInput},
+    EntityServiceError,
+};
+
+/// Execs a command alongside the subroutine, then switches the connection over to the attach
+/// protocol (relayed to and from the subroutine's shim).
+///
+/// The request has to ask for the upgrade (`Upgrade: holodekk-attach`); the first frame the
+/// client receives is the shim's hello (or its refusal).
+pub async fn exec_subroutine<A, E, U>(
+    State(state): State<Arc<A>>,
+    Path((scene, subroutine)): Path<(String, String)>,
+    mut request: Request<Body>,
+) -> Result<Response, EntityServiceError>
+where
+    A: ApiState<E, U>,
+    E: GetScene,
+    U: ExecSubroutine,
+{
+    let upgrading = request
+        .headers()
+        .get(header::UPGRADE)
+        .and_then(|protocol| protocol.to_str().ok())
+        .is_some_and(|protocol| protocol.eq_ignore_ascii_case(ATTACH_UPGRADE_PROTOCOL));
+    if !upgrading {
+        return Ok((
+            StatusCode::UPGRADE_REQUIRED,
+            [(header::UPGRADE, ATTACH_UPGRADE_PROTOCOL)],
+            format!("exec requires Upgrade: {}", ATTACH_UPGRADE_PROTOCOL),
+        )
+            .into_response());
+    }
+    let exec: SubroutineExec = match hyper::body::to_bytes(request.body_mut()).await {
+        Ok(body) => match serde_json::from_slice(&body) {
+            Ok(exec) => exec,
+            Err(err) => return Ok((StatusCode::BAD_REQUEST, err.to_string()).into_response()),
+        },
+        Err(err) => return Ok((StatusCode::BAD_REQUEST, err.to_string()).into_response()),
+    };
+
+    let scene = state
+        .scene_entity_service()
+        .get(&GetSceneInput::new(&scene))
+        .await?;
+
+    let mut input = ExecSubroutineInput::new(&scene.id, &subroutine, &exec.command);
+    if exec.stdin {
+        input = input.with_stdin();
+    }
+    let mut shim = state.subroutine_entity_service().exec(&input).await?;
+
+    // relay once the response has gone out, and the client has switched protocols
+    let upgrade = hyper::upgrade::on(&mut request);
+    tokio::spawn(async move {
+        match upgrade.await {
+            Ok(mut client) => {
+                if let Err(err) = tokio::io::copy_bidirectional(&mut client, &mut shim).await {
+                    debug!("exec connection closed: {}", err);
+                }
+            }
+            Err(err) => warn!("Failed to upgrade exec connection: {}", err),
+        }
+    });
+
+    Ok((
+        StatusCode::SWITCHING_PROTOCOLS,
+        [
+            (header::CONNECTION, "upgrade"),
+            (header::UPGRADE, ATTACH_UPGRADE_PROTOCOL),
+        ],
+    )
+        .into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{routing::post, Router};
+    use rstest::*;
+    use tower::ServiceExt;
+
+    use crate::apis::http::MockApiState;
+    use crate::entities::{
+        fixtures::{mock_scene_entity, mock_subroutine_entity},
+        SceneEntity, SubroutineEntity,
+    };
+    use crate::services::{
+        scene::{fixtures::mock_get_scene, MockGetScene},
+        subroutine::{fixtures::mock_exec_subroutine, MockExecSubroutine},
+    };
+
+    use super::*;
+
+    fn mock_app(mock_get: MockGetScene, mock_exec: MockExecSubroutine) -> Router {
+        let mut state = MockApiState::default();
+
+        state
+            .expect_scene_entity_service()
+            .return_once(move || Arc::new(mock_get));
+        state
+            .expect_subroutine_entity_service()
+            .return_once(move || Arc::new(mock_exec));
+        Router::new()
+            .route(
+                "/:scene/subroutines/:subroutine/exec",
+                post(exec_subroutine),
+            )
+            .with_state(Arc::new(state))
+    }
+
+    fn make_request(
+        mock_get: MockGetScene,
+        mock_exec: MockExecSubroutine,
+        scene: &SceneEntity,
+        subroutine: &SubroutineEntity,
+        upgrade: bool,
+    ) -> tower::util::Oneshot<axum::Router, http::Request<hyper::Body>> {
+        let mut request = Request::builder()
+            .method("POST")
+            .uri(format!("/{}/subroutines/{}/exec", scene.id, subroutine.id))
+            .header("Content-Type", "application/json");
+        if upgrade {
+            request = request
+                .header(header::CONNECTION, "upgrade")
+                .header(header::UPGRADE, ATTACH_UPGRADE_PROTOCOL);
+        }
+        let exec = SubroutineExec {
+            command: vec!["ls".to_string()],
+            stdin: true,
+        };
+        mock_app(mock_get, mock_exec).oneshot(
+            request
+                .body(Body::from(serde_json::to_string(&exec).unwrap()))
+                .unwrap(),
+        )
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn requires_an_upgrade(
+        mock_get_scene: MockGetScene,
+        mock_exec_subroutine: MockExecSubroutine,
+        mock_scene_entity: SceneEntity,
+        mock_subroutine_entity: SubroutineEntity,
+    ) {
+        let response = make_request(
+            mock_get_scene,
+            mock_exec_subroutine,
+            &mock_scene_entity,
+            &mock_subroutine_entity,
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::UPGRADE_REQUIRED);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn returns_not_found_when_subroutine_does_not_exist(
+        mut mock_get_scene: MockGetScene,
+        mut mock_exec_subroutine: MockExecSubroutine,
+        mock_scene_entity: SceneEntity,
+        mock_subroutine_entity: SubroutineEntity,
+    ) {
+        {
+            let entity = mock_scene_entity.clone();
+            mock_get_scene.expect_get().return_once(move |_| Ok(entity));
+        }
+        mock_exec_subroutine
+            .expect_exec()
+            .return_once(move |input| Err(EntityServiceError::NotFound(input.id.parse().unwrap())));
+
+        let response = make_request(
+            mock_get_scene,
+            mock_exec_subroutine,
+            &mock_scene_entity,
+            &mock_subroutine_entity,
+            true,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn switches_protocols(
+        mut mock_get_scene: MockGetScene,
+        mut mock_exec_subroutine: MockExecSubroutine,
+        mock_scene_entity: SceneEntity,
+        mock_subroutine_entity: SubroutineEntity,
+    ) {
+        {
+            let entity = mock_scene_entity.clone();
+            mock_get_scene.expect_get().return_once(move |_| Ok(entity));
+        }
+        let scene_id = mock_scene_entity.id.clone();
+        mock_exec_subroutine
+            .expect_exec()
+            .withf(move |input| {
+                input.scene_entity_id == scene_id.to_string()
+                    && input.command == ["ls"]
+                    && input.stdin
+            })
+            .return_once(|_| Ok(tokio::net::UnixStream::pair().unwrap().0));
+
+        let response = make_request(
+            mock_get_scene,
+            mock_exec_subroutine,
+            &mock_scene_entity,
+            &mock_subroutine_entity,
+            true,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::SWITCHING_PROTOCOLS);
+        assert_eq!(
+            response.headers().get(header::UPGRADE).unwrap(),
+            ATTACH_UPGRADE_PROTOCOL
+        );
+    }
+}
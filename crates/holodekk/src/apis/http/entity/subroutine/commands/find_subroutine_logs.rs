@@ -0,0 +1,161 @@
+use std::sync::Arc;
+
+use axum::extract::{Path, State};
+
+use crate::apis::http::entity::subroutine::models::SubroutineLogs;
+use crate::apis::http::{ApiState, GetResponse};
+use crate::services::{
+    scene::{GetScene, GetSceneInput},
+    subroutine::{FindSubroutineLogs, FindSubroutineLogsInput},
+    EntityServiceError,
+};
+
+pub async fn find_subroutine_logs<A, E, U>(
+    State(state): State<Arc<A>>,
+    Path((scene, subroutine)): Path<(String, String)>,
+) -> Result<GetResponse<SubroutineLogs>, EntityServiceError>
+where
+    A: ApiState<E, U>,
+    E: GetScene,
+    U: FindSubroutineLogs,
+{
+    state
+        .scene_entity_service()
+        .get(&GetSceneInput::new(&scene))
+        .await?;
+
+    let logs = state
+        .subroutine_entity_service()
+        .logs(&FindSubroutineLogsInput::new(&subroutine))
+        .await?;
+    Ok(GetResponse(SubroutineLogs {
+        subroutine_entity_id: subroutine,
+        logs,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+        routing::get,
+        Router,
+    };
+    use rstest::*;
+    use tower::ServiceExt;
+
+    use crate::apis::http::MockApiState;
+    use crate::entities::{
+        fixtures::{mock_scene_entity, mock_subroutine_entity},
+        SceneEntity, SubroutineEntity,
+    };
+    use crate::logs::LogFile;
+    use crate::services::{
+        scene::{fixtures::mock_get_scene, MockGetScene},
+        subroutine::{fixtures::mock_find_subroutine_logs, MockFindSubroutineLogs},
+    };
+
+    use super::*;
+
+    fn mock_app(mock_get: MockGetScene, mock_logs: MockFindSubroutineLogs) -> Router {
+        let mut state = MockApiState::default();
+
+        state
+            .expect_scene_entity_service()
+            .return_once(move || Arc::new(mock_get));
+        state
+            .expect_subroutine_entity_service()
+            .return_once(move || Arc::new(mock_logs));
+        Router::new()
+            .route(
+                "/:scene/subroutines/:subroutine/logs",
+                get(find_subroutine_logs),
+            )
+            .with_state(Arc::new(state))
+    }
+
+    fn make_request(
+        mock_get: MockGetScene,
+        mock_logs: MockFindSubroutineLogs,
+        scene: &SceneEntity,
+        subroutine: &SubroutineEntity,
+    ) -> tower::util::Oneshot<axum::Router, http::Request<hyper::Body>> {
+        mock_app(mock_get, mock_logs).oneshot(
+            Request::builder()
+                .uri(format!("/{}/subroutines/{}/logs", scene.id, subroutine.id))
+                .body(Body::empty())
+                .unwrap(),
+        )
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn returns_not_found_when_subroutine_does_not_exist(
+        mut mock_get_scene: MockGetScene,
+        mut mock_find_subroutine_logs: MockFindSubroutineLogs,
+        mock_scene_entity: SceneEntity,
+        mock_subroutine_entity: SubroutineEntity,
+    ) {
+        {
+            let entity = mock_scene_entity.clone();
+            mock_get_scene.expect_get().return_once(move |_| Ok(entity));
+        }
+        mock_find_subroutine_logs
+            .expect_logs()
+            .return_once(move |input| Err(EntityServiceError::NotFound(input.id.parse().unwrap())));
+
+        let response = make_request(
+            mock_get_scene,
+            mock_find_subroutine_logs,
+            &mock_scene_entity,
+            &mock_subroutine_entity,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn returns_logs(
+        mut mock_get_scene: MockGetScene,
+        mut mock_find_subroutine_logs: MockFindSubroutineLogs,
+        mock_scene_entity: SceneEntity,
+        mock_subroutine_entity: SubroutineEntity,
+    ) {
+        let logs = vec![LogFile {
+            name: "subroutine.log".to_string(),
+            size: 1024,
+            modified: chrono::Utc::now(),
+            rotated: false,
+            compressed: false,
+        }];
+        let expected = logs.clone();
+        {
+            let entity = mock_scene_entity.clone();
+            mock_get_scene.expect_get().return_once(move |_| Ok(entity));
+        }
+        mock_find_subroutine_logs
+            .expect_logs()
+            .return_once(move |_| Ok(logs));
+
+        let response = make_request(
+            mock_get_scene,
+            mock_find_subroutine_logs,
+            &mock_scene_entity,
+            &mock_subroutine_entity,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let result: SubroutineLogs = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            result.subroutine_entity_id,
+            mock_subroutine_entity.id.to_string()
+        );
+        assert_eq!(result.logs, expected);
+    }
+}
@@ -0,0 +1,158 @@
+use std::sync::Arc;
+
+use axum::extract::{Path, State};
+
+use crate::apis::http::entity::subroutine::models::SubroutineUsage;
+use crate::apis::http::{ApiState, GetResponse};
+use crate::services::{
+    scene::{GetScene, GetSceneInput},
+    subroutine::{GetSubroutineUsage, GetSubroutineUsageInput},
+    EntityServiceError,
+};
+
+pub async fn get_subroutine_usage<A, E, U>(
+    State(state): State<Arc<A>>,
+    Path((scene, subroutine)): Path<(String, String)>,
+) -> Result<GetResponse<SubroutineUsage>, EntityServiceError>
+where
+    A: ApiState<E, U>,
+    E: GetScene,
+    U: GetSubroutineUsage,
+{
+    state
+        .scene_entity_service()
+        .get(&GetSceneInput::new(&scene))
+        .await?;
+
+    let usage = state
+        .subroutine_entity_service()
+        .usage(&GetSubroutineUsageInput::new(&subroutine))
+        .await?;
+    Ok(GetResponse(SubroutineUsage {
+        subroutine_entity_id: subroutine,
+        usage,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+        routing::get,
+        Router,
+    };
+    use rstest::*;
+    use tower::ServiceExt;
+
+    use crate::apis::http::MockApiState;
+    use crate::cgroups::ResourceUsage;
+    use crate::entities::{
+        fixtures::{mock_scene_entity, mock_subroutine_entity},
+        SceneEntity, SubroutineEntity,
+    };
+    use crate::services::{
+        scene::{fixtures::mock_get_scene, MockGetScene},
+        subroutine::{fixtures::mock_get_subroutine_usage, MockGetSubroutineUsage},
+    };
+
+    use super::*;
+
+    fn mock_app(mock_get: MockGetScene, mock_usage: MockGetSubroutineUsage) -> Router {
+        let mut state = MockApiState::default();
+
+        state
+            .expect_scene_entity_service()
+            .return_once(move || Arc::new(mock_get));
+        state
+            .expect_subroutine_entity_service()
+            .return_once(move || Arc::new(mock_usage));
+        Router::new()
+            .route(
+                "/:scene/subroutines/:subroutine/usage",
+                get(get_subroutine_usage),
+            )
+            .with_state(Arc::new(state))
+    }
+
+    fn make_request(
+        mock_get: MockGetScene,
+        mock_usage: MockGetSubroutineUsage,
+        scene: &SceneEntity,
+        subroutine: &SubroutineEntity,
+    ) -> tower::util::Oneshot<axum::Router, http::Request<hyper::Body>> {
+        mock_app(mock_get, mock_usage).oneshot(
+            Request::builder()
+                .uri(format!("/{}/subroutines/{}/usage", scene.id, subroutine.id))
+                .body(Body::empty())
+                .unwrap(),
+        )
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn returns_not_found_when_subroutine_does_not_exist(
+        mut mock_get_scene: MockGetScene,
+        mut mock_get_subroutine_usage: MockGetSubroutineUsage,
+        mock_scene_entity: SceneEntity,
+        mock_subroutine_entity: SubroutineEntity,
+    ) {
+        {
+            let entity = mock_scene_entity.clone();
+            mock_get_scene.expect_get().return_once(move |_| Ok(entity));
+        }
+        mock_get_subroutine_usage
+            .expect_usage()
+            .return_once(move |input| Err(EntityServiceError::NotFound(input.id.parse().unwrap())));
+
+        let response = make_request(
+            mock_get_scene,
+            mock_get_subroutine_usage,
+            &mock_scene_entity,
+            &mock_subroutine_entity,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn returns_usage(
+        mut mock_get_scene: MockGetScene,
+        mut mock_get_subroutine_usage: MockGetSubroutineUsage,
+        mock_scene_entity: SceneEntity,
+        mock_subroutine_entity: SubroutineEntity,
+    ) {
+        let usage = ResourceUsage {
+            cpu_usage_usec: 1500,
+            memory_current: 4096,
+            pids_current: 2,
+        };
+        {
+            let entity = mock_scene_entity.clone();
+            mock_get_scene.expect_get().return_once(move |_| Ok(entity));
+        }
+        mock_get_subroutine_usage
+            .expect_usage()
+            .return_once(move |_| Ok(usage));
+
+        let response = make_request(
+            mock_get_scene,
+            mock_get_subroutine_usage,
+            &mock_scene_entity,
+            &mock_subroutine_entity,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let result: SubroutineUsage = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            result.subroutine_entity_id,
+            mock_subroutine_entity.id.to_string()
+        );
+        assert_eq!(result.usage, usage);
+    }
+}
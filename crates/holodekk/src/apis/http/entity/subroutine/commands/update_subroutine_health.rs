@@ -0,0 +1,170 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    Json,
+};
+
+use crate::apis::http::entity::subroutine::models::{Subroutine, SubroutineHealthReport};
+use crate::apis::http::{ApiState, GetResponse};
+use crate::services::{
+    scene::{GetScene, GetSceneInput},
+    subroutine::{UpdateSubroutineHealth, UpdateSubroutineHealthInput},
+    EntityServiceError,
+};
+
+pub async fn update_subroutine_health<A, E, U>(
+    State(state): State<Arc<A>>,
+    Path((scene, subroutine)): Path<(String, String)>,
+    Json(report): Json<SubroutineHealthReport>,
+) -> Result<GetResponse<Subroutine>, EntityServiceError>
+where
+    A: ApiState<E, U>,
+    E: GetScene,
+    U: UpdateSubroutineHealth,
+{
+    state
+        .scene_entity_service()
+        .get(&GetSceneInput::new(&scene))
+        .await?;
+
+    let subroutine = state
+        .subroutine_entity_service()
+        .update_health(&UpdateSubroutineHealthInput::new(
+            &subroutine,
+            report.health,
+        ))
+        .await?;
+    Ok(GetResponse(subroutine.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+        routing::put,
+        Router,
+    };
+    use rstest::*;
+    use tower::ServiceExt;
+
+    use crate::apis::http::MockApiState;
+    use crate::entities::{
+        fixtures::{mock_scene_entity, mock_subroutine_entity},
+        SceneEntity, SubroutineEntity,
+    };
+    use crate::enums::SubroutineHealth;
+    use crate::services::{
+        scene::{fixtures::mock_get_scene, MockGetScene},
+        subroutine::{fixtures::mock_update_subroutine_health, MockUpdateSubroutineHealth},
+    };
+
+    use super::*;
+
+    fn mock_app(mock_get: MockGetScene, mock_update: MockUpdateSubroutineHealth) -> Router {
+        let mut state = MockApiState::default();
+
+        state
+            .expect_scene_entity_service()
+            .return_once(move || Arc::new(mock_get));
+        state
+            .expect_subroutine_entity_service()
+            .return_once(move || Arc::new(mock_update));
+        Router::new()
+            .route(
+                "/:scene/subroutines/:subroutine/health",
+                put(update_subroutine_health),
+            )
+            .with_state(Arc::new(state))
+    }
+
+    fn make_request(
+        mock_get: MockGetScene,
+        mock_update: MockUpdateSubroutineHealth,
+        scene: &SceneEntity,
+        subroutine: &SubroutineEntity,
+    ) -> tower::util::Oneshot<axum::Router, http::Request<hyper::Body>> {
+        let body = serde_json::to_string(&SubroutineHealthReport {
+            health: SubroutineHealth::Unhealthy,
+        })
+        .unwrap();
+
+        mock_app(mock_get, mock_update).oneshot(
+            Request::builder()
+                .method("PUT")
+                .header("Content-Type", "application/json")
+                .uri(format!(
+                    "/{}/subroutines/{}/health",
+                    scene.id, subroutine.id
+                ))
+                .body(Body::from(body))
+                .unwrap(),
+        )
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn returns_not_found_when_subroutine_does_not_exist(
+        mut mock_get_scene: MockGetScene,
+        mut mock_update_subroutine_health: MockUpdateSubroutineHealth,
+        mock_scene_entity: SceneEntity,
+        mock_subroutine_entity: SubroutineEntity,
+    ) {
+        {
+            let entity = mock_scene_entity.clone();
+            mock_get_scene.expect_get().return_once(move |_| Ok(entity));
+        }
+        mock_update_subroutine_health
+            .expect_update_health()
+            .return_once(move |input| Err(EntityServiceError::NotFound(input.id.parse().unwrap())));
+
+        let response = make_request(
+            mock_get_scene,
+            mock_update_subroutine_health,
+            &mock_scene_entity,
+            &mock_subroutine_entity,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn returns_updated_subroutine(
+        mut mock_get_scene: MockGetScene,
+        mut mock_update_subroutine_health: MockUpdateSubroutineHealth,
+        mock_scene_entity: SceneEntity,
+        mock_subroutine_entity: SubroutineEntity,
+    ) {
+        {
+            let entity = mock_scene_entity.clone();
+            mock_get_scene.expect_get().return_once(move |_| Ok(entity));
+        }
+        {
+            let mut entity = mock_subroutine_entity.clone();
+            mock_update_subroutine_health
+                .expect_update_health()
+                .withf(|input| input.health == SubroutineHealth::Unhealthy)
+                .return_once(move |input| {
+                    entity.health = input.health;
+                    Ok(entity)
+                });
+        }
+
+        let response = make_request(
+            mock_get_scene,
+            mock_update_subroutine_health,
+            &mock_scene_entity,
+            &mock_subroutine_entity,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let subroutine: Subroutine = serde_json::from_slice(&body).unwrap();
+        assert_eq!(subroutine.health, SubroutineHealth::Unhealthy);
+    }
+}
@@ -1,7 +1,7 @@
 use std::sync::Arc;
 
 use axum::{
-    routing::{delete, get},
+    routing::{delete, get, post, put},
     Router,
 };
 
@@ -21,6 +21,17 @@ where
             get(commands::find_subroutines).post(commands::create_subroutine),
         )
         .route("/:subroutine", delete(commands::delete_subroutine))
+        .route(
+            "/:subroutine/health",
+            put(commands::update_subroutine_health),
+        )
+        .route(
+            "/:subroutine/status",
+            put(commands::update_subroutine_status),
+        )
+        .route("/:subroutine/exec", post(commands::exec_subroutine))
+        .route("/:subroutine/logs", get(commands::find_subroutine_logs))
+        .route("/:subroutine/usage", get(commands::get_subroutine_usage))
         .with_state(state)
 }
 
@@ -29,8 +40,21 @@ pub mod commands {
     pub use create_subroutine::*;
     mod delete_subroutine;
     pub use delete_subroutine::*;
+    mod exec_subroutine;
+    pub use exec_subroutine::*;
+    mod find_subroutine_logs;
+    pub use find_subroutine_logs::*;
     mod find_subroutines;
     pub use find_subroutines::*;
+    mod get_subroutine_usage;
+    pub use get_subroutine_usage::*;
+    mod update_subroutine_health;
+    pub use update_subroutine_health::*;
+    mod update_subroutine_status;
+    pub use update_subroutine_status::*;
 }
 
+mod client;
+pub use client::*;
+
 pub mod models;
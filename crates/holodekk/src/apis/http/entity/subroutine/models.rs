@@ -1,12 +1,44 @@
-use chrono::NaiveDateTime;
+use std::collections::HashMap;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
 
-use crate::entities::SubroutineEntity;
-use crate::enums::SubroutineStatus;
+use crate::cgroups::{ResourceLimits, ResourceUsage};
+use crate::entities::{SubroutineEntity, SubroutineExit, SubroutineStatusChange};
+use crate::enums::{SubroutineHealth, SubroutineStatus};
+use crate::health::HealthProbe;
+use crate::logs::{LogFile, LogFormat, LogRotation};
+use crate::privileges::RunAs;
+use crate::restart::RestartPolicy;
+use crate::rlimits::ProcessLimits;
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct NewSubroutine {
     pub subroutine_image_id: String,
+    /// Overrides the defaults from the image's manifest.
+    #[serde(default)]
+    pub environment: HashMap<String, String>,
+    #[serde(default)]
+    pub health_probe: Option<HealthProbe>,
+    #[serde(default)]
+    pub restart_policy: RestartPolicy,
+    #[serde(default)]
+    pub limits: ResourceLimits,
+    /// Defaults to the scene's user (when holodekkd allocates them).
+    #[serde(default)]
+    pub run_as: Option<RunAs>,
+    #[serde(default)]
+    pub process_limits: ProcessLimits,
+    #[serde(default)]
+    pub log_rotation: LogRotation,
+    #[serde(default)]
+    pub log_format: LogFormat,
+    /// Let attached clients write to the subroutine's stdin.
+    #[serde(default)]
+    pub stdin: bool,
+    /// Run the subroutine in a pseudo-terminal.
+    #[serde(default)]
+    pub tty: bool,
 }
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
@@ -15,6 +47,23 @@ pub struct Subroutine {
     pub scene_entity_id: String,
     pub subroutine_image_id: String,
     pub status: SubroutineStatus,
+    pub status_changed_at: Option<DateTime<Utc>>,
+    /// How the subroutine last exited (with the tail of its stderr).
+    pub last_exit: Option<SubroutineExit>,
+    /// Environment as configured (secret references are not resolved).
+    pub environment: HashMap<String, String>,
+    pub port: Option<u16>,
+    pub host_port: Option<u16>,
+    pub health_probe: Option<HealthProbe>,
+    pub health: SubroutineHealth,
+    pub restart_policy: RestartPolicy,
+    pub limits: ResourceLimits,
+    pub run_as: RunAs,
+    pub process_limits: ProcessLimits,
+    pub log_rotation: LogRotation,
+    pub log_format: LogFormat,
+    pub stdin: bool,
+    pub tty: bool,
     pub created_at: NaiveDateTime,
     pub updated_at: Option<NaiveDateTime>,
 }
@@ -26,8 +75,83 @@ impl From<SubroutineEntity> for Subroutine {
             scene_entity_id: entity.scene_entity_id.into(),
             subroutine_image_id: entity.subroutine_image_id.into(),
             status: entity.status,
+            status_changed_at: entity.status_changed_at,
+            last_exit: entity.last_exit,
+            environment: entity.environment,
+            port: entity.port,
+            host_port: entity.host_port,
+            health_probe: entity.health_probe.map(|probe| *probe),
+            health: entity.health,
+            restart_policy: entity.restart_policy,
+            limits: entity.limits,
+            run_as: entity.run_as,
+            process_limits: entity.process_limits,
+            log_rotation: entity.log_rotation,
+            log_format: entity.log_format,
+            stdin: entity.stdin,
+            tty: entity.tty,
             created_at: entity.created_at.unwrap(),
             updated_at: entity.updated_at,
         }
     }
 }
+
+/// Health change reported by a subroutine's shim.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SubroutineHealthReport {
+    pub health: SubroutineHealth,
+}
+
+/// Status change reported by a subroutine's shim.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SubroutineStatusReport {
+    pub status: SubroutineStatus,
+    /// When the shim observed the change (when the report arrives, if not given).
+    #[serde(default = "Utc::now")]
+    pub changed_at: DateTime<Utc>,
+    #[serde(default)]
+    pub exit: Option<SubroutineExit>,
+}
+
+impl From<SubroutineStatusChange> for SubroutineStatusReport {
+    fn from(change: SubroutineStatusChange) -> Self {
+        Self {
+            status: change.status,
+            changed_at: change.changed_at,
+            exit: change.exit,
+        }
+    }
+}
+
+impl From<SubroutineStatusReport> for SubroutineStatusChange {
+    fn from(report: SubroutineStatusReport) -> Self {
+        Self {
+            status: report.status,
+            changed_at: report.changed_at,
+            exit: report.exit,
+        }
+    }
+}
+
+/// Command to exec alongside a subroutine.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SubroutineExec {
+    pub command: Vec<String>,
+    /// Forward the client's input to the command.
+    #[serde(default)]
+    pub stdin: bool,
+}
+
+/// Resources consumed by a subroutine, as accounted by its cgroup.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SubroutineUsage {
+    pub subroutine_entity_id: String,
+    pub usage: ResourceUsage,
+}
+
+/// A subroutine's log files (the current log first, then rotated logs, newest first).
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SubroutineLogs {
+    pub subroutine_entity_id: String,
+    pub logs: Vec<LogFile>,
+}
@@ -10,6 +10,10 @@ use log::error;
 use mockall::automock;
 use serde::Serialize;
 
+use crate::cgroups::CgroupError;
+use crate::images::{ImageVerificationError, SubroutineImageStoreError};
+use crate::ports::PortAllocatorError;
+use crate::privileges::PrivilegesError;
 use crate::services::EntityServiceError;
 
 #[cfg_attr(test, automock)]
@@ -52,10 +56,65 @@ where
 impl IntoResponse for EntityServiceError {
     fn into_response(self) -> Response {
         match self {
-            EntityServiceError::NotUnique(_) => (StatusCode::CONFLICT, self.to_string()),
+            EntityServiceError::NotUnique(_) | EntityServiceError::InUse(_) => {
+                (StatusCode::CONFLICT, self.to_string())
+            }
             EntityServiceError::NotFound(_)
             | EntityServiceError::InvalidEntityId(_)
             | EntityServiceError::InvalidImageId(_) => (StatusCode::NOT_FOUND, self.to_string()),
+            EntityServiceError::ImageVerification(ImageVerificationError::Rejected(..)) => {
+                (StatusCode::FORBIDDEN, self.to_string())
+            }
+            EntityServiceError::ImageVerification(ImageVerificationError::Store(
+                SubroutineImageStoreError::NotFound(id),
+            ))
+            | EntityServiceError::ImageStore(SubroutineImageStoreError::NotFound(id)) => (
+                StatusCode::NOT_FOUND,
+                format!("Subroutine image not found: {}", id),
+            ),
+            EntityServiceError::ImageVerification(err) => {
+                error!("Image verification error: {:?}", err);
+                (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+            }
+            EntityServiceError::InvalidEnvironment(_)
+            | EntityServiceError::InvalidHealthProbe(_)
+            | EntityServiceError::InvalidProcessLimits(_)
+            | EntityServiceError::InvalidLogRotation(_)
+            | EntityServiceError::Cgroup(CgroupError::InvalidLimits(_))
+            | EntityServiceError::Privileges(PrivilegesError::UnknownCapability(_))
+            | EntityServiceError::Privileges(PrivilegesError::InvalidUmask(_))
+            | EntityServiceError::Privileges(PrivilegesError::MissingGroup(_))
+            | EntityServiceError::Privileges(PrivilegesError::MissingUser(_)) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, self.to_string())
+            }
+            EntityServiceError::ImageStore(err) => {
+                error!("Image store error: {:?}", err);
+                (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+            }
+            EntityServiceError::Cgroup(err) => {
+                error!("cgroup error: {:?}", err);
+                (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+            }
+            EntityServiceError::PortAllocation(PortAllocatorError::Exhausted(_))
+            | EntityServiceError::Privileges(PrivilegesError::Exhausted(_)) => {
+                (StatusCode::SERVICE_UNAVAILABLE, self.to_string())
+            }
+            EntityServiceError::PortAllocation(err) => {
+                error!("Port allocation error: {:?}", err);
+                (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+            }
+            EntityServiceError::Privileges(err) => {
+                error!("Scene user allocation error: {:?}", err);
+                (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+            }
+            EntityServiceError::Attach(err) => {
+                error!("Attach error: {:?}", err);
+                (StatusCode::SERVICE_UNAVAILABLE, err.to_string())
+            }
+            EntityServiceError::Launch(err) => {
+                error!("Subroutine launch error: {:?}", err);
+                (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+            }
             EntityServiceError::Repository(err) => {
                 error!("Repository error: {:?}", err);
                 (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
@@ -73,3 +132,6 @@ pub mod entity {
     pub mod scene;
     pub mod subroutine;
 }
+
+pub mod registry;
+pub mod secrets;
@@ -0,0 +1,32 @@
+use std::sync::Arc;
+
+use axum::{
+    body::StreamBody,
+    extract::{Path, State},
+    http::header,
+    response::IntoResponse,
+};
+use tokio_util::io::ReaderStream;
+
+use crate::images::Digest;
+use crate::registry::{Registry, RegistryError};
+
+/// Streams a blob (also answers `HEAD` requests, for existence checks).
+pub async fn get_blob(
+    State(registry): State<Arc<Registry>>,
+    Path(digest): Path<String>,
+) -> Result<impl IntoResponse, RegistryError> {
+    let digest: Digest = digest
+        .parse()
+        .map_err(|_| RegistryError::InvalidRequest(format!("invalid digest: {}", digest)))?;
+    let (file, size) = registry.open_blob(&digest)?;
+
+    let body = StreamBody::new(ReaderStream::new(tokio::fs::File::from_std(file)));
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/octet-stream".to_string()),
+            (header::CONTENT_LENGTH, size.to_string()),
+        ],
+        body,
+    ))
+}
@@ -0,0 +1,51 @@
+use std::sync::Arc;
+
+use axum::{
+    body::Bytes,
+    extract::{Path, State},
+    http::{header, StatusCode},
+    response::IntoResponse,
+};
+
+use crate::images::ImageId;
+use crate::registry::{Reference, Registry, RegistryError, CONTENT_DIGEST_HEADER};
+
+fn parse_path(image: &str, reference: &str) -> Result<(ImageId, Reference), RegistryError> {
+    let image = image
+        .parse()
+        .map_err(|_| RegistryError::InvalidRequest(format!("invalid image id: {}", image)))?;
+    Ok((image, reference.parse()?))
+}
+
+pub async fn get_manifest(
+    State(registry): State<Arc<Registry>>,
+    Path((image, reference)): Path<(String, String)>,
+) -> Result<impl IntoResponse, RegistryError> {
+    let (image, reference) = parse_path(&image, &reference)?;
+    let (digest, body) = registry.get_manifest(&image, &reference)?;
+
+    Ok((
+        [
+            (
+                header::CONTENT_TYPE.as_str(),
+                "application/json".to_string(),
+            ),
+            (CONTENT_DIGEST_HEADER, digest.into()),
+        ],
+        body,
+    ))
+}
+
+pub async fn put_manifest(
+    State(registry): State<Arc<Registry>>,
+    Path((image, reference)): Path<(String, String)>,
+    body: Bytes,
+) -> Result<impl IntoResponse, RegistryError> {
+    let (image, reference) = parse_path(&image, &reference)?;
+    let digest = registry.put_manifest(&image, &reference, &body)?;
+
+    Ok((
+        StatusCode::CREATED,
+        [(CONTENT_DIGEST_HEADER, String::from(digest))],
+    ))
+}
@@ -0,0 +1,213 @@
+use std::sync::Arc;
+
+use axum::{
+    body::Bytes,
+    extract::{Path, Query, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use serde::Deserialize;
+
+use crate::apis::http::{CreateResponse, DeleteResponse};
+use crate::images::Digest;
+use crate::registry::{
+    BlobDescriptor, Registry, RegistryError, UploadId, UploadStatus, UPLOAD_OFFSET_HEADER,
+};
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct CompleteUpload {
+    pub digest: String,
+}
+
+fn parse_upload_id(upload: &str) -> Result<UploadId, RegistryError> {
+    upload
+        .parse()
+        .map_err(|_| RegistryError::InvalidRequest(format!("invalid upload id: {}", upload)))
+}
+
+pub async fn start_upload(
+    State(registry): State<Arc<Registry>>,
+) -> Result<(StatusCode, Json<UploadStatus>), RegistryError> {
+    let id = registry.start_upload()?;
+    Ok((StatusCode::ACCEPTED, Json(UploadStatus { id, offset: 0 })))
+}
+
+pub async fn append_upload(
+    State(registry): State<Arc<Registry>>,
+    Path(upload): Path<String>,
+    headers: HeaderMap,
+    chunk: Bytes,
+) -> Result<(StatusCode, Json<UploadStatus>), RegistryError> {
+    let id = parse_upload_id(&upload)?;
+    let offset = headers
+        .get(UPLOAD_OFFSET_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| {
+            RegistryError::InvalidRequest(format!("missing {} header", UPLOAD_OFFSET_HEADER))
+        })?;
+
+    let offset = registry.append_upload(&id, offset, &chunk)?;
+    Ok((StatusCode::ACCEPTED, Json(UploadStatus { id, offset })))
+}
+
+pub async fn complete_upload(
+    State(registry): State<Arc<Registry>>,
+    Path(upload): Path<String>,
+    Query(params): Query<CompleteUpload>,
+) -> Result<CreateResponse<BlobDescriptor>, RegistryError> {
+    let id = parse_upload_id(&upload)?;
+    let digest: Digest = params
+        .digest
+        .parse()
+        .map_err(|_| RegistryError::InvalidRequest(format!("invalid digest: {}", params.digest)))?;
+
+    let blob = registry.complete_upload(&id, &digest)?;
+    Ok(CreateResponse(blob))
+}
+
+pub async fn cancel_upload(
+    State(registry): State<Arc<Registry>>,
+    Path(upload): Path<String>,
+) -> Result<DeleteResponse, RegistryError> {
+    let id = parse_upload_id(&upload)?;
+    registry.cancel_upload(&id)?;
+    Ok(DeleteResponse)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{
+        body::Body,
+        http::{Request, StatusCode},
+        Router,
+    };
+    use tempfile::{tempdir, TempDir};
+    use tower::ServiceExt;
+
+    use crate::apis::http::registry::router;
+
+    use super::*;
+
+    fn mock_app() -> (TempDir, Arc<Registry>, Router) {
+        let temp = tempdir().unwrap();
+        let registry = Arc::new(Registry::from_root(temp.path()));
+        registry.init().unwrap();
+        (temp, registry.clone(), router(registry))
+    }
+
+    fn patch_request(id: &UploadId, offset: u64, chunk: &'static [u8]) -> Request<Body> {
+        Request::builder()
+            .method("PATCH")
+            .uri(format!("/uploads/{}", id))
+            .header(UPLOAD_OFFSET_HEADER, offset.to_string())
+            .body(Body::from(chunk))
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn starts_an_upload() {
+        let (_temp, registry, app) = mock_app();
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("POST")
+                    .uri("/uploads")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let status: UploadStatus = serde_json::from_slice(&body).unwrap();
+        assert_eq!(registry.upload_offset(&status.id).unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn responds_with_range_not_satisfiable_for_wrong_offset() {
+        let (_temp, registry, app) = mock_app();
+        let id = registry.start_upload().unwrap();
+
+        let response = app.oneshot(patch_request(&id, 3, b"lo")).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+    }
+
+    #[tokio::test]
+    async fn responds_with_not_found_for_unknown_upload() {
+        let (_temp, _registry, app) = mock_app();
+
+        let response = app
+            .oneshot(patch_request(&UploadId::generate(), 0, b"hello"))
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn responds_with_bad_request_for_digest_mismatch() {
+        let (_temp, registry, app) = mock_app();
+        let id = registry.start_upload().unwrap();
+        registry.append_upload(&id, 0, b"hello").unwrap();
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("PUT")
+                    .uri(format!(
+                        "/uploads/{}?digest={}",
+                        id,
+                        Digest::compute(b"goodbye")
+                    ))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+
+    #[tokio::test]
+    async fn completes_a_chunked_upload() {
+        let (_temp, registry, app) = mock_app();
+        let id = registry.start_upload().unwrap();
+
+        let response = app
+            .clone()
+            .oneshot(patch_request(&id, 0, b"hel"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        let response = app
+            .clone()
+            .oneshot(patch_request(&id, 3, b"lo"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("PUT")
+                    .uri(format!(
+                        "/uploads/{}?digest={}",
+                        id,
+                        Digest::compute(b"hello")
+                    ))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let blob: BlobDescriptor = serde_json::from_slice(&body).unwrap();
+        assert_eq!(blob.size, 5);
+    }
+}
@@ -0,0 +1,60 @@
+use std::sync::Arc;
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, patch, post},
+    Router,
+};
+use log::error;
+
+use crate::registry::{Registry, RegistryError};
+
+pub fn router(registry: Arc<Registry>) -> Router {
+    Router::new()
+        .route("/uploads", post(commands::start_upload))
+        .route(
+            "/uploads/:upload",
+            patch(commands::append_upload)
+                .put(commands::complete_upload)
+                .delete(commands::cancel_upload),
+        )
+        .route("/blobs/:digest", get(commands::get_blob))
+        .route(
+            "/images/:image/manifests/:reference",
+            get(commands::get_manifest).put(commands::put_manifest),
+        )
+        .with_state(registry)
+}
+
+pub mod commands {
+    mod blobs;
+    pub use blobs::*;
+    mod manifests;
+    pub use manifests::*;
+    mod uploads;
+    pub use uploads::*;
+}
+
+impl IntoResponse for RegistryError {
+    fn into_response(self) -> Response {
+        match self {
+            RegistryError::BlobUnknown(_)
+            | RegistryError::ManifestUnknown(_)
+            | RegistryError::UploadUnknown(_) => (StatusCode::NOT_FOUND, self.to_string()),
+            RegistryError::InvalidOffset { .. } => {
+                (StatusCode::RANGE_NOT_SATISFIABLE, self.to_string())
+            }
+            RegistryError::DigestMismatch { .. }
+            | RegistryError::InvalidManifest(_)
+            | RegistryError::InvalidReference(_)
+            | RegistryError::InvalidRequest(_)
+            | RegistryError::Serialization(_) => (StatusCode::BAD_REQUEST, self.to_string()),
+            RegistryError::Io(err) => {
+                error!("Registry error: {:?}", err);
+                (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+            }
+        }
+        .into_response()
+    }
+}
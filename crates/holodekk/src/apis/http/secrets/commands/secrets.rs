@@ -0,0 +1,127 @@
+use std::sync::Arc;
+
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Json,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::apis::http::{DeleteResponse, GetResponse};
+use crate::secrets::{SecretError, SecretStore};
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct NewSecret {
+    pub value: String,
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Secret {
+    pub name: String,
+}
+
+pub async fn list_secrets(
+    State(secrets): State<Arc<SecretStore>>,
+) -> Result<GetResponse<Vec<Secret>>, SecretError> {
+    let secrets = secrets
+        .names()?
+        .into_iter()
+        .map(|name| Secret { name })
+        .collect();
+    Ok(GetResponse(secrets))
+}
+
+pub async fn put_secret(
+    State(secrets): State<Arc<SecretStore>>,
+    Path(name): Path<String>,
+    Json(secret): Json<NewSecret>,
+) -> Result<StatusCode, SecretError> {
+    secrets.set(&name, &secret.value)?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+pub async fn delete_secret(
+    State(secrets): State<Arc<SecretStore>>,
+    Path(name): Path<String>,
+) -> Result<DeleteResponse, SecretError> {
+    secrets.delete(&name)?;
+    Ok(DeleteResponse)
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::{body::Body, http::Request, Router};
+    use tempfile::{tempdir, TempDir};
+    use tower::ServiceExt;
+
+    use crate::apis::http::secrets::router;
+
+    use super::*;
+
+    fn mock_app() -> (TempDir, Arc<SecretStore>, Router) {
+        let temp = tempdir().unwrap();
+        let secrets = Arc::new(SecretStore::from_root(temp.path()));
+        secrets.init().unwrap();
+        (temp, secrets.clone(), router(secrets))
+    }
+
+    #[tokio::test]
+    async fn stores_secret() {
+        let (_temp, secrets, app) = mock_app();
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("PUT")
+                    .uri("/db-password")
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(r#"{"value": "hunter2"}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert_eq!(secrets.get("db-password").unwrap(), "hunter2");
+    }
+
+    #[tokio::test]
+    async fn lists_names_without_values() {
+        let (_temp, secrets, app) = mock_app();
+        secrets.set("db-password", "hunter2").unwrap();
+
+        let response = app
+            .oneshot(Request::builder().uri("/").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert!(!String::from_utf8_lossy(&body).contains("hunter2"));
+        let listed: Vec<Secret> = serde_json::from_slice(&body).unwrap();
+        assert_eq!(
+            listed,
+            vec![Secret {
+                name: "db-password".to_string()
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn responds_with_not_found_for_unknown_secret() {
+        let (_temp, _secrets, app) = mock_app();
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .method("DELETE")
+                    .uri("/missing")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+    }
+}
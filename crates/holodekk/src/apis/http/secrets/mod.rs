@@ -0,0 +1,43 @@
+use std::sync::Arc;
+
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, put},
+    Router,
+};
+use log::error;
+
+use crate::secrets::{SecretError, SecretStore};
+
+/// Secrets API.  Values can be written, but are never returned.
+pub fn router(secrets: Arc<SecretStore>) -> Router {
+    Router::new()
+        .route("/", get(commands::list_secrets))
+        .route(
+            "/:name",
+            put(commands::put_secret).delete(commands::delete_secret),
+        )
+        .with_state(secrets)
+}
+
+pub mod commands {
+    mod secrets;
+    pub use secrets::*;
+}
+
+impl IntoResponse for SecretError {
+    fn into_response(self) -> Response {
+        match self {
+            SecretError::NotFound(_) => (StatusCode::NOT_FOUND, self.to_string()),
+            SecretError::InvalidName(_) | SecretError::InvalidVariable(_) => {
+                (StatusCode::BAD_REQUEST, self.to_string())
+            }
+            err => {
+                error!("Secret store error: {:?}", err);
+                (StatusCode::INTERNAL_SERVER_ERROR, err.to_string())
+            }
+        }
+        .into_response()
+    }
+}
@@ -0,0 +1,235 @@
+use std::io::{self, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+
+use super::{
+    AttachError, AttachRequest, AttachResponse, AttachResult, Frame, FrameKind, WindowSize,
+    ATTACH_PROTOCOL_VERSION, MAX_FRAME_LEN,
+};
+
+/// Attaches to a subroutine's output through its shim's attach socket.
+#[derive(Debug)]
+pub struct AttachClient {
+    stream: UnixStream,
+    tty: bool,
+}
+
+impl AttachClient {
+    /// Connects and sends the request, returning once the shim has accepted it.
+    pub fn connect<P: AsRef<Path>>(socket: P, request: &AttachRequest) -> AttachResult<Self> {
+        let mut stream = UnixStream::connect(socket)?;
+        stream.write_all(&Frame::json(FrameKind::Hello, request)?.encode())?;
+
+        let response: AttachResponse = match Frame::read_from(&mut stream)? {
+            Some(Frame {
+                kind: FrameKind::Hello,
+                payload,
+            }) => serde_json::from_slice(&payload)?,
+            Some(Frame {
+                kind: FrameKind::Error,
+                payload,
+            }) => {
+                return Err(AttachError::Rejected(
+                    String::from_utf8_lossy(&payload).into_owned(),
+                ))
+            }
+            Some(frame) => return Err(AttachError::UnexpectedFrame(frame.kind)),
+            None => return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into()),
+        };
+        if response.version != ATTACH_PROTOCOL_VERSION {
+            return Err(AttachError::UnsupportedVersion(response.version));
+        }
+        Ok(Self {
+            stream,
+            tty: response.tty,
+        })
+    }
+
+    /// Whether the subroutine is running with a tty (so all its output arrives as stdout).
+    pub fn tty(&self) -> bool {
+        self.tty
+    }
+
+    /// A writer for the subroutine's stdin (if the request asked for it).
+    ///
+    /// The writer shares the connection, so input can be sent while output is being read.
+    pub fn stdin(&self) -> AttachResult<AttachStdin> {
+        Ok(AttachStdin {
+            stream: self.stream.try_clone()?,
+        })
+    }
+
+    /// Waits for the next frame of output, returning `None` once the shim is done sending.
+    pub fn next_frame(&mut self) -> AttachResult<Option<Frame>> {
+        match Frame::read_from(&mut self.stream)? {
+            Some(Frame {
+                kind: FrameKind::End,
+                ..
+            })
+            | None => Ok(None),
+            Some(Frame {
+                kind: FrameKind::Error,
+                payload,
+            }) => Err(AttachError::Rejected(
+                String::from_utf8_lossy(&payload).into_owned(),
+            )),
+            frame => Ok(frame),
+        }
+    }
+}
+
+/// Forwards input to an attached subroutine's stdin.
+#[derive(Debug)]
+pub struct AttachStdin {
+    stream: UnixStream,
+}
+
+impl AttachStdin {
+    /// Resizes the subroutine's terminal (if it has one).
+    pub fn resize(&mut self, size: WindowSize) -> AttachResult<()> {
+        self.stream
+            .write_all(&Frame::json(FrameKind::Resize, &size)?.encode())?;
+        Ok(())
+    }
+
+    /// Closes the subroutine's stdin, so it reads EOF.
+    pub fn close(mut self) -> AttachResult<()> {
+        self.stream
+            .write_all(&Frame::empty(FrameKind::CloseStdin).encode())?;
+        Ok(())
+    }
+}
+
+impl Write for AttachStdin {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = buf.len().min(MAX_FRAME_LEN as usize);
+        self.stream
+            .write_all(&Frame::new(FrameKind::Stdin, &buf[..len]).encode())?;
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn serve_once<F>(socket: &Path, respond: F) -> thread::JoinHandle<AttachRequest>
+    where
+        F: FnOnce(&mut UnixStream) + Send + 'static,
+    {
+        let listener = UnixListener::bind(socket).unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let hello = Frame::read_from(&mut stream).unwrap().unwrap();
+            respond(&mut stream);
+            serde_json::from_slice(&hello.payload).unwrap()
+        })
+    }
+
+    #[test]
+    fn receives_frames_until_end() {
+        let temp = tempdir().unwrap();
+        let socket = temp.path().join("log.sock");
+        let server = serve_once(&socket, |stream| {
+            for frame in [
+                Frame::json(
+                    FrameKind::Hello,
+                    &AttachResponse {
+                        version: ATTACH_PROTOCOL_VERSION,
+                        tty: false,
+                    },
+                )
+                .unwrap(),
+                Frame::new(FrameKind::Stdout, "hello\n"),
+                Frame::empty(FrameKind::ReplayDone),
+                Frame::empty(FrameKind::End),
+            ] {
+                stream.write_all(&frame.encode()).unwrap();
+            }
+        });
+
+        let request = AttachRequest::default().with_tail(10);
+        let mut client = AttachClient::connect(&socket, &request).unwrap();
+
+        assert_eq!(
+            client.next_frame().unwrap(),
+            Some(Frame::new(FrameKind::Stdout, "hello\n"))
+        );
+        assert_eq!(
+            client.next_frame().unwrap(),
+            Some(Frame::empty(FrameKind::ReplayDone))
+        );
+        assert!(client.next_frame().unwrap().is_none());
+        assert_eq!(server.join().unwrap(), request);
+    }
+
+    #[test]
+    fn reports_rejections() {
+        let temp = tempdir().unwrap();
+        let socket = temp.path().join("log.sock");
+        let server = serve_once(&socket, |stream| {
+            stream
+                .write_all(&Frame::new(FrameKind::Error, "unsupported version").encode())
+                .unwrap();
+        });
+
+        let err = AttachClient::connect(&socket, &AttachRequest::default()).unwrap_err();
+
+        assert!(matches!(err, AttachError::Rejected(reason) if reason == "unsupported version"));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn forwards_terminal_input() {
+        let temp = tempdir().unwrap();
+        let socket = temp.path().join("log.sock");
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let server = serve_once(&socket, move |stream| {
+            stream
+                .write_all(
+                    &Frame::json(
+                        FrameKind::Hello,
+                        &AttachResponse {
+                            version: ATTACH_PROTOCOL_VERSION,
+                            tty: true,
+                        },
+                    )
+                    .unwrap()
+                    .encode(),
+                )
+                .unwrap();
+            while let Some(frame) = Frame::read_from(stream).unwrap() {
+                sender.send(frame).unwrap();
+            }
+        });
+
+        let request = AttachRequest::default().with_follow().with_stdin();
+        let client = AttachClient::connect(&socket, &request).unwrap();
+        assert!(client.tty());
+        let mut stdin = client.stdin().unwrap();
+        stdin.resize(WindowSize { rows: 24, cols: 80 }).unwrap();
+        stdin.write_all(b"hello\n").unwrap();
+        stdin.close().unwrap();
+        drop(client);
+
+        assert!(server.join().unwrap().stdin);
+        let frames: Vec<_> = receiver.iter().collect();
+        assert_eq!(
+            frames,
+            vec![
+                Frame::json(FrameKind::Resize, &WindowSize { rows: 24, cols: 80 }).unwrap(),
+                Frame::new(FrameKind::Stdin, "hello\n"),
+                Frame::empty(FrameKind::CloseStdin),
+            ]
+        );
+    }
+}
@@ -0,0 +1,742 @@
+//! The protocol spoken over a subroutine shim's attach socket (`log.sock`).
+//!
+//! Everything sent in either direction is a frame: a one byte kind, the payload's length (u32,
+//! big endian), then the payload itself.
+//!
+//! ```text
+//! client -> shim:  Hello (AttachRequest, JSON)
+//! shim -> client:  Hello (AttachResponse, JSON) | Error
+//!                  Stdout/Stderr ...   (replayed from the log)
+//!                  ReplayDone
+//!                  Stdout/Stderr ...   (live, when following)
+//!                  End
+//! client -> shim:  Stdin ...           (when the client asked for stdin)
+//!                  Resize              (WindowSize, JSON; when the subroutine has a tty)
+//!                  CloseStdin
+//! ```
+//!
+//! The client states what it wants up front: the last `tail` lines and/or everything `since` a
+//! point in time, from which streams, and whether to keep following once the replay is done.
+//! Replayed lines are sent whole (with their newline); live output is sent as it's read.
+//!
+//! Subroutines launched with stdin enabled accept input from one client at a time: the first
+//! to ask for `stdin` holds it until it disconnects, and anyone else asking is refused.  Sending
+//! `CloseStdin` closes the subroutine's stdin (so it reads EOF) for good.
+//!
+//! Subroutines run with a tty have a single output stream (everything arrives as `Stdout`), and
+//! always accept input.  Their stdin holder can also resize the terminal; and since a terminal
+//! can't be closed like a pipe, `CloseStdin` sends it the EOF character (`^D`) instead.
+//!
+//! A request can instead ask for a command to be `exec`ed alongside the subroutine (with the
+//! same working directory, environment, user, rlimits and cgroup).  The client then gets that
+//! process' output (and, with `stdin`, feeds its input) rather than the subroutine's:
+//!
+//! ```text
+//! shim -> client:  Hello
+//!                  Stdout/Stderr ...   (live)
+//!                  Exit                (SubroutineStatus, JSON: Exited or Killed)
+//!                  End
+//! ```
+//!
+//! Disconnecting before the exit kills the process.
+//!
+//! A client that can't keep up with the output has it held for it, up to a limit; beyond that
+//! the request's [`Backpressure`] policy decides whether it's disconnected (with an `Error`), or
+//! has the oldest output dropped.  Dropped output is replaced by a `Dropped` frame (the number
+//! of bytes skipped, JSON) where it would have been.  The replay is read from the log as the
+//! client takes it (within the same limit), so however much is asked for, it's never dropped;
+//! live output arriving meanwhile is held until the replay is done.
+mod client;
+pub use client::*;
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+
+use crate::enums::SubroutineStatus;
+use crate::errors::error_chain_fmt;
+use crate::logs::{list_logs, LogEntry, LogFormat, OutputStream};
+
+/// Version of the attach protocol spoken by this release.
+pub const ATTACH_PROTOCOL_VERSION: u32 = 1;
+
+/// Protocol named in the `Upgrade` header when the attach protocol is carried over HTTP.
+pub const ATTACH_UPGRADE_PROTOCOL: &str = "holodekk-attach";
+
+/// Largest frame either side accepts.
+pub const MAX_FRAME_LEN: u32 = 1024 * 1024;
+
+/// Bytes in a frame's header (its kind and length).
+pub const FRAME_HEADER_LEN: usize = 5;
+
+/// Most output (in bytes) held for a slow client, unless it asks for a ring buffer.
+pub const MAX_PENDING: usize = 4 * 1024 * 1024;
+
+/// Largest ring buffer (in bytes) a client can ask for.
+pub const MAX_RING_BUFFER: usize = 64 * 1024 * 1024;
+
+#[derive(thiserror::Error)]
+pub enum AttachError {
+    #[error("Unknown attach frame kind: {0}")]
+    UnknownFrame(u8),
+    #[error("Attach frame too large: {0} bytes")]
+    FrameTooLarge(u32),
+    #[error("Unsupported attach protocol version: {0}")]
+    UnsupportedVersion(u32),
+    #[error("Unexpected attach frame: {0:?}")]
+    UnexpectedFrame(FrameKind),
+    #[error("Attach rejected: {0}")]
+    Rejected(String),
+    #[error("Attach IO error")]
+    Io(#[from] io::Error),
+    #[error("Attach message serialization error")]
+    Serialization(#[from] serde_json::Error),
+}
+
+impl std::fmt::Debug for AttachError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+pub type AttachResult<T> = std::result::Result<T, AttachError>;
+
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FrameKind {
+    Hello = 0,
+    Stdout = 1,
+    Stderr = 2,
+    /// Everything requested from the log has been sent.
+    ReplayDone = 3,
+    /// Nothing more will be sent (the client isn't following, or the subroutine is gone).
+    End = 4,
+    /// The shim refused (or gave up on) the client; the payload is the reason.
+    Error = 5,
+    /// Input for the subroutine's stdin (from the client holding it).
+    Stdin = 6,
+    /// Closes the subroutine's stdin.
+    CloseStdin = 7,
+    /// Resizes the subroutine's terminal.
+    Resize = 8,
+    /// An exec'd process has exited.
+    Exit = 9,
+    /// Output was dropped (the client was too slow); the payload is how many bytes.
+    Dropped = 10,
+}
+
+impl TryFrom<u8> for FrameKind {
+    type Error = AttachError;
+
+    fn try_from(kind: u8) -> Result<Self, AttachError> {
+        match kind {
+            0 => Ok(FrameKind::Hello),
+            1 => Ok(FrameKind::Stdout),
+            2 => Ok(FrameKind::Stderr),
+            3 => Ok(FrameKind::ReplayDone),
+            4 => Ok(FrameKind::End),
+            5 => Ok(FrameKind::Error),
+            6 => Ok(FrameKind::Stdin),
+            7 => Ok(FrameKind::CloseStdin),
+            8 => Ok(FrameKind::Resize),
+            9 => Ok(FrameKind::Exit),
+            10 => Ok(FrameKind::Dropped),
+            _ => Err(AttachError::UnknownFrame(kind)),
+        }
+    }
+}
+
+impl From<OutputStream> for FrameKind {
+    fn from(stream: OutputStream) -> Self {
+        match stream {
+            OutputStream::Stdout => FrameKind::Stdout,
+            OutputStream::Stderr => FrameKind::Stderr,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub kind: FrameKind,
+    pub payload: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new<P: Into<Vec<u8>>>(kind: FrameKind, payload: P) -> Self {
+        Self {
+            kind,
+            payload: payload.into(),
+        }
+    }
+
+    pub fn empty(kind: FrameKind) -> Self {
+        Self::new(kind, Vec::new())
+    }
+
+    pub fn json<T: Serialize>(kind: FrameKind, message: &T) -> AttachResult<Self> {
+        Ok(Self::new(kind, serde_json::to_vec(message)?))
+    }
+
+    /// Reports how an exec'd process exited.
+    pub fn exit(status: SubroutineStatus) -> Self {
+        Self::json(FrameKind::Exit, &status).expect("statuses always serialize")
+    }
+
+    /// How the exec'd process exited (for an `Exit` frame).
+    pub fn exit_status(&self) -> AttachResult<SubroutineStatus> {
+        match self.kind {
+            FrameKind::Exit => Ok(serde_json::from_slice(&self.payload)?),
+            kind => Err(AttachError::UnexpectedFrame(kind)),
+        }
+    }
+
+    /// Tells a slow client how much of its output was dropped.
+    pub fn dropped(bytes: u64) -> Self {
+        Self::json(FrameKind::Dropped, &bytes).expect("counts always serialize")
+    }
+
+    /// How many bytes were dropped (for a `Dropped` frame).
+    pub fn dropped_bytes(&self) -> AttachResult<u64> {
+        match self.kind {
+            FrameKind::Dropped => Ok(serde_json::from_slice(&self.payload)?),
+            kind => Err(AttachError::UnexpectedFrame(kind)),
+        }
+    }
+
+    /// The frame as sent on the wire.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(FRAME_HEADER_LEN + self.payload.len());
+        encode_header(self.kind, self.payload.len(), &mut buf);
+        buf.extend_from_slice(&self.payload);
+        buf
+    }
+
+    /// Reads a frame, returning `None` if the stream ends cleanly before one starts.
+    pub fn read_from<R: Read>(reader: &mut R) -> AttachResult<Option<Self>> {
+        let mut header = [0; FRAME_HEADER_LEN];
+        match reader.read_exact(&mut header[..1]) {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            Err(err) => return Err(err.into()),
+        }
+        reader.read_exact(&mut header[1..])?;
+        let (kind, len) = decode_header(&header)?;
+        let mut payload = vec![0; len];
+        reader.read_exact(&mut payload)?;
+        Ok(Some(Self { kind, payload }))
+    }
+}
+
+/// Appends a frame header for a payload of `len` bytes to `buf`.
+pub fn encode_header(kind: FrameKind, len: usize, buf: &mut Vec<u8>) {
+    buf.push(kind as u8);
+    buf.extend_from_slice(&(len as u32).to_be_bytes());
+}
+
+fn decode_header(header: &[u8; FRAME_HEADER_LEN]) -> AttachResult<(FrameKind, usize)> {
+    let kind = FrameKind::try_from(header[0])?;
+    let len = u32::from_be_bytes(header[1..].try_into().unwrap());
+    if len > MAX_FRAME_LEN {
+        return Err(AttachError::FrameTooLarge(len));
+    }
+    Ok((kind, len as usize))
+}
+
+/// Splits frames out of data read in arbitrary pieces (from a non-blocking socket).
+#[derive(Debug, Default)]
+pub struct FrameDecoder {
+    buf: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.buf.extend_from_slice(data);
+    }
+
+    /// Takes the next complete frame (if there is one).
+    pub fn next_frame(&mut self) -> AttachResult<Option<Frame>> {
+        let Some(header) = self.buf.first_chunk::<FRAME_HEADER_LEN>() else {
+            return Ok(None);
+        };
+        let (kind, len) = decode_header(header)?;
+        if self.buf.len() < FRAME_HEADER_LEN + len {
+            return Ok(None);
+        }
+        let payload = self.buf[FRAME_HEADER_LEN..FRAME_HEADER_LEN + len].to_vec();
+        self.buf.drain(..FRAME_HEADER_LEN + len);
+        Ok(Some(Frame { kind, payload }))
+    }
+}
+
+fn all_streams() -> Vec<OutputStream> {
+    vec![OutputStream::Stdout, OutputStream::Stderr]
+}
+
+/// What the shim does once a client has fallen too far behind its output.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(tag = "policy", rename_all = "snake_case")]
+pub enum Backpressure {
+    /// Disconnect the client once [`MAX_PENDING`] bytes are waiting for it.
+    #[default]
+    Disconnect,
+    /// Drop the oldest output to keep what's waiting under [`MAX_PENDING`] bytes.
+    DropOldest,
+    /// Drop the oldest output to keep what's waiting under `bytes` (up to [`MAX_RING_BUFFER`]).
+    RingBuffer { bytes: usize },
+}
+
+impl Backpressure {
+    /// Most output (in bytes) held for the client.
+    pub fn limit(&self) -> usize {
+        match self {
+            Backpressure::RingBuffer { bytes } => (*bytes).min(MAX_RING_BUFFER),
+            _ => MAX_PENDING,
+        }
+    }
+}
+
+/// What an attaching client wants from the shim.
+///
+/// With neither `tail` nor `since`, nothing is replayed.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct AttachRequest {
+    pub version: u32,
+    /// Replay (at most) this many of the most recent lines.
+    #[serde(default)]
+    pub tail: Option<usize>,
+    /// Replay lines logged at (or after) this time.
+    #[serde(default)]
+    pub since: Option<DateTime<Utc>>,
+    #[serde(default = "all_streams")]
+    pub streams: Vec<OutputStream>,
+    /// Keep sending output once the replay is done.
+    #[serde(default)]
+    pub follow: bool,
+    /// Forward input to the subroutine's stdin (or the exec'd process').
+    #[serde(default)]
+    pub stdin: bool,
+    /// Run this command alongside the subroutine, rather than attaching to the subroutine.
+    #[serde(default)]
+    pub exec: Option<Vec<String>>,
+    /// What to do if the client can't keep up.
+    #[serde(default)]
+    pub backpressure: Backpressure,
+}
+
+impl Default for AttachRequest {
+    fn default() -> Self {
+        Self {
+            version: ATTACH_PROTOCOL_VERSION,
+            tail: None,
+            since: None,
+            streams: all_streams(),
+            follow: false,
+            stdin: false,
+            exec: None,
+            backpressure: Backpressure::default(),
+        }
+    }
+}
+
+impl AttachRequest {
+    pub fn with_tail(mut self, lines: usize) -> Self {
+        self.tail = Some(lines);
+        self
+    }
+
+    pub fn with_since(mut self, since: DateTime<Utc>) -> Self {
+        self.since = Some(since);
+        self
+    }
+
+    pub fn with_streams(mut self, streams: Vec<OutputStream>) -> Self {
+        self.streams = streams;
+        self
+    }
+
+    pub fn with_follow(mut self) -> Self {
+        self.follow = true;
+        self
+    }
+
+    pub fn with_stdin(mut self) -> Self {
+        self.stdin = true;
+        self
+    }
+
+    pub fn with_exec<S: Into<String>>(mut self, command: Vec<S>) -> Self {
+        self.exec = Some(command.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn with_backpressure(mut self, backpressure: Backpressure) -> Self {
+        self.backpressure = backpressure;
+        self
+    }
+
+    pub fn wants(&self, stream: OutputStream) -> bool {
+        self.streams.contains(&stream)
+    }
+
+    fn replays(&self) -> bool {
+        self.tail.is_some() || self.since.is_some()
+    }
+
+    fn matches(&self, entry: &LogEntry) -> bool {
+        self.wants(entry.stream) && self.since.is_none_or(|since| entry.timestamp >= since)
+    }
+}
+
+/// The shim's reply to an [`AttachRequest`].
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct AttachResponse {
+    pub version: u32,
+    /// The subroutine is running with a tty.
+    #[serde(default)]
+    pub tty: bool,
+}
+
+/// Size of a subroutine's terminal (sent with [`FrameKind::Resize`]).
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct WindowSize {
+    pub rows: u16,
+    pub cols: u16,
+}
+
+/// Reads the entries a request asks to be replayed from the log at `path` (and its rotated
+/// files), oldest first, as they're wanted.
+///
+/// The files are opened (and, for a `tail`, counted) up front, so neither rotating the log nor
+/// what's logged after that changes what's replayed.
+pub struct Replay {
+    /// Files still to be read, oldest first.
+    files: VecDeque<ReplayFile>,
+    reader: Option<Box<dyn BufRead>>,
+    format: LogFormat,
+    request: AttachRequest,
+    /// Matching entries still to be skipped (those before the requested tail).
+    skip: usize,
+}
+
+impl Replay {
+    pub fn open(path: &Path, format: LogFormat, request: &AttachRequest) -> io::Result<Self> {
+        let mut replay = Self {
+            files: VecDeque::new(),
+            reader: None,
+            format,
+            request: request.clone(),
+            skip: 0,
+        };
+        if !request.replays() {
+            return Ok(replay);
+        }
+
+        // newest file first, stopping once we have enough (or the files are too old)
+        let mut found = 0;
+        for log in list_logs(path)? {
+            if request.since.is_some_and(|since| log.modified < since) {
+                break;
+            }
+            let file = ReplayFile::open(&path.with_file_name(&log.name), log.compressed)?;
+            if let Some(tail) = request.tail {
+                for line in file.reader()?.split(b'\n') {
+                    if replay.parse(&line?).is_some() {
+                        found += 1;
+                    }
+                }
+                if found >= tail {
+                    replay.skip = found - tail;
+                    replay.files.push_front(file);
+                    break;
+                }
+            }
+            replay.files.push_front(file);
+        }
+        Ok(replay)
+    }
+
+    /// The entry logged on `line`, if it's one the request asks for.
+    fn parse(&self, line: &[u8]) -> Option<LogEntry> {
+        LogEntry::parse(&String::from_utf8_lossy(line), self.format)
+            .filter(|entry| self.request.matches(entry))
+    }
+}
+
+impl Iterator for Replay {
+    type Item = io::Result<LogEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let reader = match self.reader.as_mut() {
+                Some(reader) => reader,
+                None => match self.files.pop_front()?.reader() {
+                    Ok(reader) => self.reader.insert(reader),
+                    Err(err) => return Some(Err(err)),
+                },
+            };
+            let mut line = Vec::new();
+            match reader.read_until(b'\n', &mut line) {
+                Ok(0) => {
+                    self.reader = None;
+                    continue;
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    self.reader = None;
+                    self.files.clear();
+                    return Some(Err(err));
+                }
+            }
+            if line.last() == Some(&b'\n') {
+                line.pop();
+            }
+            if let Some(entry) = self.parse(&line) {
+                if self.skip == 0 {
+                    return Some(Ok(entry));
+                }
+                self.skip -= 1;
+            }
+        }
+    }
+}
+
+/// A log file opened for replay (read only as far as it went when it was opened).
+struct ReplayFile {
+    file: File,
+    len: u64,
+    compressed: bool,
+}
+
+impl ReplayFile {
+    fn open(path: &Path, compressed: bool) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+        Ok(Self {
+            file,
+            len,
+            compressed,
+        })
+    }
+
+    /// Reads the file from the start.
+    fn reader(&self) -> io::Result<Box<dyn BufRead>> {
+        let mut file = self.file.try_clone()?;
+        file.seek(SeekFrom::Start(0))?;
+        let file = file.take(self.len);
+        Ok(if self.compressed {
+            Box::new(BufReader::new(GzDecoder::new(file)))
+        } else {
+            Box::new(BufReader::new(file))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use chrono::Duration;
+    use rstest::*;
+    use tempfile::{tempdir, TempDir};
+
+    use crate::logs::{LogRotation, LogWriter};
+
+    use super::*;
+
+    #[fixture]
+    fn temp() -> TempDir {
+        tempdir().unwrap()
+    }
+
+    fn replay(path: &Path, format: LogFormat, request: &AttachRequest) -> Vec<String> {
+        Replay::open(path, format, request)
+            .unwrap()
+            .map(|entry| entry.unwrap().message)
+            .collect()
+    }
+
+    #[test]
+    fn decodes_frames_split_across_reads() {
+        let mut wire = Frame::new(FrameKind::Stdout, "hello").encode();
+        wire.extend(Frame::empty(FrameKind::End).encode());
+        let mut decoder = FrameDecoder::new();
+
+        decoder.push(&wire[..3]);
+        assert!(decoder.next_frame().unwrap().is_none());
+        decoder.push(&wire[3..]);
+
+        assert_eq!(
+            decoder.next_frame().unwrap(),
+            Some(Frame::new(FrameKind::Stdout, "hello"))
+        );
+        assert_eq!(
+            decoder.next_frame().unwrap(),
+            Some(Frame::empty(FrameKind::End))
+        );
+        assert!(decoder.next_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn rejects_oversized_frames() {
+        let mut decoder = FrameDecoder::new();
+        let mut wire = vec![FrameKind::Stdout as u8];
+        wire.extend_from_slice(&(MAX_FRAME_LEN + 1).to_be_bytes());
+        decoder.push(&wire);
+
+        assert!(matches!(
+            decoder.next_frame().unwrap_err(),
+            AttachError::FrameTooLarge(..)
+        ));
+    }
+
+    #[test]
+    fn reads_frames_until_eof() {
+        let wire = Frame::new(FrameKind::Stderr, "oops").encode();
+        let mut reader = wire.as_slice();
+
+        assert_eq!(
+            Frame::read_from(&mut reader).unwrap(),
+            Some(Frame::new(FrameKind::Stderr, "oops"))
+        );
+        assert!(Frame::read_from(&mut reader).unwrap().is_none());
+    }
+
+    #[test]
+    fn carries_exit_statuses() {
+        let frame = Frame::exit(SubroutineStatus::Killed { signal: 9 });
+
+        assert_eq!(frame.kind, FrameKind::Exit);
+        assert_eq!(
+            frame.exit_status().unwrap(),
+            SubroutineStatus::Killed { signal: 9 }
+        );
+        assert!(Frame::empty(FrameKind::End).exit_status().is_err());
+    }
+
+    #[test]
+    fn carries_dropped_counts() {
+        let frame = Frame::dropped(4096);
+        let mut decoder = FrameDecoder::new();
+        decoder.push(&frame.encode());
+
+        let frame = decoder.next_frame().unwrap().unwrap();
+        assert_eq!(frame.kind, FrameKind::Dropped);
+        assert_eq!(frame.dropped_bytes().unwrap(), 4096);
+        assert!(Frame::empty(FrameKind::End).dropped_bytes().is_err());
+    }
+
+    #[test]
+    fn parses_backpressure_policies() {
+        let request: AttachRequest = serde_json::from_str(
+            r#"{"version":1,"backpressure":{"policy":"ring_buffer","bytes":1024}}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            request,
+            AttachRequest::default().with_backpressure(Backpressure::RingBuffer { bytes: 1024 })
+        );
+        assert_eq!(request.backpressure.limit(), 1024);
+    }
+
+    #[test]
+    fn bounds_ring_buffers() {
+        assert_eq!(Backpressure::DropOldest.limit(), MAX_PENDING);
+        assert_eq!(
+            Backpressure::RingBuffer { bytes: usize::MAX }.limit(),
+            MAX_RING_BUFFER
+        );
+    }
+
+    #[test]
+    fn defaults_missing_request_fields() {
+        let request: AttachRequest = serde_json::from_str(r#"{"version":1}"#).unwrap();
+
+        assert_eq!(request, AttachRequest::default());
+    }
+
+    #[rstest]
+    #[case(LogFormat::Text)]
+    #[case(LogFormat::Json)]
+    fn replays_the_tail(temp: TempDir, #[case] format: LogFormat) {
+        let path = temp.path().join("subroutine.log");
+        let mut log = LogWriter::open(&path, format).unwrap();
+        log.write(OutputStream::Stdout, b"one\ntwo\n").unwrap();
+        log.write(OutputStream::Stderr, b"oops\n").unwrap();
+        log.write(OutputStream::Stdout, b"three\n").unwrap();
+
+        let request = AttachRequest::default().with_tail(2);
+        assert_eq!(replay(&path, format, &request), vec!["oops", "three"]);
+
+        let request = request.with_streams(vec![OutputStream::Stdout]);
+        assert_eq!(replay(&path, format, &request), vec!["two", "three"]);
+    }
+
+    #[rstest]
+    fn replays_across_rotated_logs(temp: TempDir) {
+        let path = temp.path().join("subroutine.log");
+        let mut log = LogWriter::open(&path, LogFormat::Json)
+            .unwrap()
+            .with_rotation(LogRotation::default().with_max_size(1).with_compression());
+        log.write(OutputStream::Stdout, b"one\ntwo\nthree\n")
+            .unwrap();
+
+        let request = AttachRequest::default().with_tail(10);
+        assert_eq!(
+            replay(&path, LogFormat::Json, &request),
+            vec!["one", "two", "three"]
+        );
+    }
+
+    #[rstest]
+    fn replays_what_was_logged_when_opened(temp: TempDir) {
+        let path = temp.path().join("subroutine.log");
+        let mut log = LogWriter::open(&path, LogFormat::Json)
+            .unwrap()
+            .with_rotation(LogRotation::default().with_max_size(1).with_compression());
+        log.write(OutputStream::Stdout, b"one\ntwo\n").unwrap();
+
+        let replay = Replay::open(
+            &path,
+            LogFormat::Json,
+            &AttachRequest::default().with_tail(10),
+        )
+        .unwrap();
+        log.write(OutputStream::Stdout, b"three\n").unwrap();
+
+        assert_eq!(
+            replay
+                .map(|entry| entry.unwrap().message)
+                .collect::<Vec<_>>(),
+            vec!["one", "two"]
+        );
+    }
+
+    #[rstest]
+    fn replays_since_a_time(temp: TempDir) {
+        let path = temp.path().join("subroutine.log");
+        let old = LogEntry {
+            timestamp: Utc::now() - Duration::hours(1),
+            ..LogEntry::new(OutputStream::Stdout, 0, b"old")
+        };
+        let new = LogEntry::new(OutputStream::Stdout, 1, b"new");
+        fs::write(
+            &path,
+            old.format(LogFormat::Json) + &new.format(LogFormat::Json),
+        )
+        .unwrap();
+
+        let request = AttachRequest::default().with_since(Utc::now() - Duration::minutes(5));
+        assert_eq!(replay(&path, LogFormat::Json, &request), vec!["new"]);
+        assert!(replay(&path, LogFormat::Json, &AttachRequest::default()).is_empty());
+    }
+}
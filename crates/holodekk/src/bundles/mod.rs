@@ -0,0 +1,371 @@
+//! Self-contained subroutine bundles: an image's files and manifest in a single runnable file.
+//!
+//! A bundle is a small binary header followed by a tar archive of the image's files:
+//!
+//! ```text
+//! magic (8 bytes) | version (u32, big endian) | metadata length (u32, big endian)
+//! metadata (JSON)
+//! payload (tar)
+//! ```
+//!
+//! Bundles are run by unpacking them into a [`BundleCache`] (keyed by the digest of the whole
+//! file, so each bundle is only unpacked once) and launching the result with the shim.
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::entities::SubroutineManifest;
+use crate::enums::SubroutineKind;
+use crate::errors::error_chain_fmt;
+use crate::images::{archive_directory, Digest, DigestWriter, ImageName, SubroutineImage};
+use crate::runtimes::RuntimeRegistry;
+use crate::HolodekkPaths;
+
+/// Identifies a file as a holodekk bundle.
+pub const BUNDLE_MAGIC: &[u8; 8] = b"HDKBNDL\0";
+
+/// Version of the bundle format written by this release.
+pub const BUNDLE_VERSION: u32 = 1;
+
+/// Upper bound on the metadata section (which is read into memory).
+const MAX_METADATA_LEN: u32 = 1024 * 1024;
+
+const BUNDLE_IMAGE: &str = "image.json";
+const BUNDLE_PAYLOAD: &str = "payload.tar";
+const BUNDLE_FILES: &str = "files";
+
+#[derive(thiserror::Error)]
+pub enum BundleError {
+    #[error("Not a holodekk bundle")]
+    InvalidHeader,
+    #[error("Unsupported bundle version: {0}")]
+    UnsupportedVersion(u32),
+    #[error("Bundle payload does not match its digest (expected {expected}, found {actual})")]
+    Corrupt { expected: Digest, actual: Digest },
+    #[error("Bundle IO error")]
+    Io(#[from] io::Error),
+    #[error("Bundle metadata serialization error")]
+    Serialization(#[from] serde_json::Error),
+}
+
+impl std::fmt::Debug for BundleError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+pub type BundleResult<T> = std::result::Result<T, BundleError>;
+
+/// Describes the image packaged in a bundle.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct BundleMetadata {
+    pub name: ImageName,
+    pub kind: SubroutineKind,
+    #[serde(default)]
+    pub manifest: Option<SubroutineManifest>,
+    /// Digest of the payload (tar archive of the image's files).
+    pub payload: Digest,
+}
+
+/// Describes the subroutine in `dir`.
+///
+/// The manifest is read from the subroutine's [`MANIFEST_FILE`](crate::entities::MANIFEST_FILE) when it has one (as the image
+/// store does when ingesting it), and otherwise by running its manifest command: `dir` is
+/// expected to be the developer's own checkout.
+pub fn image_from_directory<P: AsRef<Path>>(
+    name: &ImageName,
+    dir: P,
+    runtimes: &RuntimeRegistry,
+) -> SubroutineImage {
+    let dir = dir.as_ref();
+    let runtime = runtimes.detect(dir);
+    let mut image = SubroutineImage::new(
+        name.to_owned(),
+        dir,
+        runtime
+            .as_ref()
+            .map(|runtime| runtime.kind())
+            .unwrap_or(SubroutineKind::Unknown),
+    );
+    let manifest = match SubroutineManifest::load(dir) {
+        Ok(None) => runtime
+            .map(|runtime| SubroutineManifest::extract(runtime.as_ref(), dir))
+            .transpose(),
+        result => result,
+    };
+    match manifest {
+        Ok(manifest) => image.manifest = manifest,
+        Err(err) => warn!("Invalid manifest for {}: {}", name, err),
+    }
+    image
+}
+
+/// Writes a bundle of the given image (its files and manifest) to `writer`.
+pub fn write_bundle<W: Write>(image: &SubroutineImage, mut writer: W) -> BundleResult<W> {
+    let payload = archive_directory(&image.path, Vec::new())?;
+    let metadata = serde_json::to_vec(&BundleMetadata {
+        name: image.name.to_owned(),
+        kind: image.kind,
+        manifest: image.manifest.to_owned(),
+        payload: Digest::compute(&payload),
+    })?;
+
+    writer.write_all(BUNDLE_MAGIC)?;
+    writer.write_all(&BUNDLE_VERSION.to_be_bytes())?;
+    writer.write_all(&(metadata.len() as u32).to_be_bytes())?;
+    writer.write_all(&metadata)?;
+    writer.write_all(&payload)?;
+    writer.flush()?;
+    Ok(writer)
+}
+
+/// Reads a bundle's header, leaving `reader` positioned at the start of the payload.
+pub fn read_bundle_metadata<R: Read>(reader: &mut R) -> BundleResult<BundleMetadata> {
+    let mut magic = [0; 8];
+    reader
+        .read_exact(&mut magic)
+        .map_err(|_| BundleError::InvalidHeader)?;
+    if &magic != BUNDLE_MAGIC {
+        return Err(BundleError::InvalidHeader);
+    }
+
+    let mut word = [0; 4];
+    reader.read_exact(&mut word)?;
+    let version = u32::from_be_bytes(word);
+    if version != BUNDLE_VERSION {
+        return Err(BundleError::UnsupportedVersion(version));
+    }
+
+    reader.read_exact(&mut word)?;
+    let len = u32::from_be_bytes(word);
+    if len > MAX_METADATA_LEN {
+        return Err(BundleError::InvalidHeader);
+    }
+    let mut metadata = vec![0; len as usize];
+    reader.read_exact(&mut metadata)?;
+    Ok(serde_json::from_slice(&metadata)?)
+}
+
+/// Cache of unpacked bundles, addressed by the digest of the bundle file.
+///
+/// ```text
+/// <bundles_root>/<digest hex>/image.json
+/// <bundles_root>/<digest hex>/files/
+/// ```
+#[derive(Clone, Debug)]
+pub struct BundleCache {
+    root: PathBuf,
+}
+
+impl BundleCache {
+    pub fn new(paths: &HolodekkPaths) -> Self {
+        Self::from_root(paths.bundles_root())
+    }
+
+    pub fn from_root<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Unpacks the bundle (unless it's already cached), returning the image it contains.
+    ///
+    /// The image is always described from the bundle's own header; a cached `image.json` that
+    /// doesn't match it is treated as stale, and the bundle unpacked again.
+    pub fn unpack<P: AsRef<Path>>(&self, bundle: P) -> BundleResult<SubroutineImage> {
+        let digest = Digest::compute_file(bundle.as_ref())?;
+        let root = self.root.join(digest.hex());
+        let mut reader = BufReader::new(File::open(bundle.as_ref())?);
+        let metadata = read_bundle_metadata(&mut reader)?;
+        let image = bundle_image(&metadata, &root);
+
+        if root.join(BUNDLE_IMAGE).exists() {
+            let cached = fs::read(root.join(BUNDLE_IMAGE))
+                .ok()
+                .and_then(|cached| serde_json::from_slice::<SubroutineImage>(&cached).ok());
+            if cached.as_ref() == Some(&image) {
+                debug!("Bundle {} already unpacked", digest);
+                return Ok(image);
+            }
+            warn!(
+                "Unpacked bundle {} doesn't match the bundle; unpacking again",
+                digest
+            );
+            fs::remove_dir_all(&root)?;
+        }
+
+        fs::create_dir_all(&self.root)?;
+        let staging = tempfile::Builder::new()
+            .prefix(".bundle.")
+            .suffix(".partial")
+            .tempdir_in(&self.root)?;
+
+        // verify the payload before unpacking anything from it
+        let payload_path = staging.path().join(BUNDLE_PAYLOAD);
+        let mut writer = DigestWriter::new(File::create(&payload_path)?);
+        io::copy(&mut reader, &mut writer)?;
+        let (actual, _, _) = writer.finalize();
+        if actual != metadata.payload {
+            return Err(BundleError::Corrupt {
+                expected: metadata.payload,
+                actual,
+            });
+        }
+
+        let files = staging.path().join(BUNDLE_FILES);
+        fs::create_dir(&files)?;
+        let mut unpacker = tar::Archive::new(File::open(&payload_path)?);
+        unpacker.set_preserve_permissions(true);
+        unpacker.unpack(&files)?;
+        fs::remove_file(&payload_path)?;
+
+        fs::write(
+            staging.path().join(BUNDLE_IMAGE),
+            serde_json::to_vec_pretty(&image)?,
+        )?;
+
+        // (whatever's left of the staging directory is removed when it's dropped)
+        match fs::rename(staging.path(), &root) {
+            Ok(()) => {
+                debug!("Unpacked bundle {} to {}", digest, root.display());
+                Ok(image)
+            }
+            // someone else unpacked it first
+            Err(_) if root.join(BUNDLE_IMAGE).exists() => Ok(image),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+/// The image a bundle unpacks to (beneath `root`).
+fn bundle_image(metadata: &BundleMetadata, root: &Path) -> SubroutineImage {
+    let mut image = SubroutineImage::new(
+        metadata.name.to_owned(),
+        root.join(BUNDLE_FILES),
+        metadata.kind,
+    );
+    image.manifest = metadata.manifest.to_owned();
+    image.digest = Some(metadata.payload.to_owned());
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+    use tempfile::{tempdir, TempDir};
+
+    use super::*;
+
+    #[fixture]
+    fn temp() -> TempDir {
+        tempdir().unwrap()
+    }
+
+    fn sample_image(temp: &TempDir) -> SubroutineImage {
+        let dir = temp.path().join("src");
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(dir.join("holodekk.rb"), "puts 'hello'\n").unwrap();
+        fs::write(dir.join("lib").join("app.rb"), "# app\n").unwrap();
+        SubroutineImage::new("acme/widgets".into(), dir, SubroutineKind::Ruby)
+    }
+
+    fn bundle(temp: &TempDir, image: &SubroutineImage) -> PathBuf {
+        let path = temp.path().join("widgets.hdk");
+        write_bundle(image, File::create(&path).unwrap()).unwrap();
+        path
+    }
+
+    #[rstest]
+    fn unpacks_bundles(temp: TempDir) {
+        let image = sample_image(&temp);
+        let path = bundle(&temp, &image);
+        let cache = BundleCache::from_root(temp.path().join("bundles"));
+
+        let unpacked = cache.unpack(&path).unwrap();
+
+        assert_eq!(unpacked.name, image.name);
+        assert_eq!(unpacked.kind, SubroutineKind::Ruby);
+        assert!(unpacked.path.starts_with(temp.path().join("bundles")));
+        assert_eq!(
+            fs::read_to_string(unpacked.path.join("lib").join("app.rb")).unwrap(),
+            "# app\n"
+        );
+    }
+
+    #[rstest]
+    fn reuses_unpacked_bundles(temp: TempDir) {
+        let path = bundle(&temp, &sample_image(&temp));
+        let cache = BundleCache::from_root(temp.path().join("bundles"));
+
+        let first = cache.unpack(&path).unwrap();
+        let second = cache.unpack(&path).unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(
+            fs::read_dir(temp.path().join("bundles")).unwrap().count(),
+            1
+        );
+    }
+
+    #[rstest]
+    fn unpacks_bundles_again_when_the_cached_image_is_stale(temp: TempDir) {
+        let path = bundle(&temp, &sample_image(&temp));
+        let cache = BundleCache::from_root(temp.path().join("bundles"));
+        let first = cache.unpack(&path).unwrap();
+        let cached = first.path.parent().unwrap().join(BUNDLE_IMAGE);
+        let mut tampered = first.clone();
+        tampered.path = temp.path().join("elsewhere");
+        fs::write(&cached, serde_json::to_vec(&tampered).unwrap()).unwrap();
+
+        let second = cache.unpack(&path).unwrap();
+
+        assert_eq!(second, first);
+        assert_eq!(
+            serde_json::from_slice::<SubroutineImage>(&fs::read(&cached).unwrap()).unwrap(),
+            first
+        );
+    }
+
+    #[rstest]
+    fn rejects_other_files(temp: TempDir) {
+        let path = temp.path().join("widgets.tar");
+        fs::write(&path, "not a bundle").unwrap();
+        let cache = BundleCache::from_root(temp.path().join("bundles"));
+
+        assert!(matches!(
+            cache.unpack(&path).unwrap_err(),
+            BundleError::InvalidHeader
+        ));
+    }
+
+    #[test]
+    fn rejects_unsupported_versions() {
+        let mut bundle = BUNDLE_MAGIC.to_vec();
+        bundle.extend_from_slice(&99u32.to_be_bytes());
+
+        assert!(matches!(
+            read_bundle_metadata(&mut bundle.as_slice()).unwrap_err(),
+            BundleError::UnsupportedVersion(99)
+        ));
+    }
+
+    #[rstest]
+    fn rejects_corrupt_payloads(temp: TempDir) {
+        let path = bundle(&temp, &sample_image(&temp));
+        let mut contents = fs::read(&path).unwrap();
+        let last = contents.len() - 1;
+        contents[last] ^= 0xff;
+        fs::write(&path, contents).unwrap();
+        let cache = BundleCache::from_root(temp.path().join("bundles"));
+
+        assert!(matches!(
+            cache.unpack(&path).unwrap_err(),
+            BundleError::Corrupt { .. }
+        ));
+        assert_eq!(
+            fs::read_dir(temp.path().join("bundles")).unwrap().count(),
+            0
+        );
+    }
+}
@@ -0,0 +1,402 @@
+//! cgroup v2 resource limits (and usage accounting) for subroutines.
+//!
+//! Each subroutine gets its own group beneath a holodekk slice; e.g.
+//! `/sys/fs/cgroup/holodekk.slice/<subroutine id>`.  The shim creates the group and moves the
+//! subroutine into it before exec, while holodekkd reads usage from the same hierarchy.
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use nix::unistd::Pid;
+use serde::{Deserialize, Serialize};
+
+use crate::entities::SubroutineEntityId;
+use crate::errors::error_chain_fmt;
+
+/// Where the cgroup v2 hierarchy is normally mounted.
+pub const DEFAULT_CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+/// Group (beneath the root) that subroutine groups are created in.
+pub const DEFAULT_CGROUP_SLICE: &str = "holodekk.slice";
+
+/// Controllers delegated to subroutine groups.
+const CONTROLLERS: &str = "+cpu +memory +pids";
+
+/// Period (in microseconds) CPU quotas are expressed over.
+const CPU_PERIOD_USEC: u64 = 100_000;
+
+/// Smallest CPU limit, in thousandths of a core: the kernel rejects quotas under 1ms.
+pub const MIN_CPU_MILLIS: u64 = 1000 * 1000 / CPU_PERIOD_USEC;
+
+#[derive(thiserror::Error)]
+pub enum CgroupError {
+    #[error("Invalid resource limits: {0}")]
+    InvalidLimits(String),
+    #[error("Invalid value in {0}")]
+    InvalidValue(PathBuf),
+    #[error("cgroup IO error")]
+    Io(#[from] io::Error),
+}
+
+impl std::fmt::Debug for CgroupError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+pub type CgroupResult<T> = std::result::Result<T, CgroupError>;
+
+/// Resource limits applied to a subroutine (unset limits are unconstrained).
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct ResourceLimits {
+    /// CPU time, in thousandths of a core (so `500` is half a core); at least
+    /// [`MIN_CPU_MILLIS`].
+    #[serde(default)]
+    pub cpu_millis: Option<u64>,
+    /// Memory, in bytes.
+    #[serde(default)]
+    pub memory: Option<u64>,
+    /// Number of processes (and threads).
+    #[serde(default)]
+    pub pids: Option<u64>,
+}
+
+impl ResourceLimits {
+    pub fn with_cpu_millis(mut self, cpu_millis: u64) -> Self {
+        self.cpu_millis = Some(cpu_millis);
+        self
+    }
+
+    pub fn with_memory(mut self, memory: u64) -> Self {
+        self.memory = Some(memory);
+        self
+    }
+
+    pub fn with_pids(mut self, pids: u64) -> Self {
+        self.pids = Some(pids);
+        self
+    }
+
+    pub fn validate(&self) -> CgroupResult<()> {
+        if matches!(self.cpu_millis, Some(millis) if millis < MIN_CPU_MILLIS) {
+            Err(CgroupError::InvalidLimits(format!(
+                "cpu_millis must be at least {}",
+                MIN_CPU_MILLIS
+            )))
+        } else if self.memory == Some(0) {
+            Err(CgroupError::InvalidLimits("memory must be positive".into()))
+        } else if self.pids == Some(0) {
+            Err(CgroupError::InvalidLimits("pids must be positive".into()))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Value for `cpu.max` (quota and period, in microseconds).
+    fn cpu_max(&self) -> String {
+        match self.cpu_millis {
+            Some(millis) => format!("{} {}", millis * CPU_PERIOD_USEC / 1000, CPU_PERIOD_USEC),
+            None => format!("max {}", CPU_PERIOD_USEC),
+        }
+    }
+}
+
+/// Resources consumed by a subroutine's group.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct ResourceUsage {
+    /// Total CPU time consumed, in microseconds.
+    pub cpu_usage_usec: u64,
+    /// Current memory usage, in bytes.
+    pub memory_current: u64,
+    /// Current number of processes.
+    pub pids_current: u64,
+}
+
+/// The holodekk slice within a cgroup v2 hierarchy.
+#[derive(Clone, Debug)]
+pub struct Cgroups {
+    root: PathBuf,
+    slice: String,
+}
+
+impl Default for Cgroups {
+    fn default() -> Self {
+        Self::new(DEFAULT_CGROUP_ROOT, DEFAULT_CGROUP_SLICE)
+    }
+}
+
+impl Cgroups {
+    pub fn new<P>(root: P, slice: &str) -> Self
+    where
+        P: AsRef<Path>,
+    {
+        Self {
+            root: root.as_ref().to_owned(),
+            slice: slice.to_string(),
+        }
+    }
+
+    pub fn slice_path(&self) -> PathBuf {
+        self.root.join(&self.slice)
+    }
+
+    fn group_path(&self, id: &SubroutineEntityId) -> PathBuf {
+        self.slice_path().join(id)
+    }
+
+    /// Creates (or reuses) the subroutine's group, applying the given limits.
+    pub fn create(&self, id: &SubroutineEntityId, limits: &ResourceLimits) -> CgroupResult<Cgroup> {
+        limits.validate()?;
+
+        // controllers have to be enabled at each level down to the subroutine groups
+        let slice = self.slice_path();
+        fs::create_dir_all(&slice)?;
+        fs::write(self.root.join("cgroup.subtree_control"), CONTROLLERS)?;
+        fs::write(slice.join("cgroup.subtree_control"), CONTROLLERS)?;
+
+        let group = Cgroup {
+            path: self.group_path(id),
+        };
+        if !group.path.exists() {
+            fs::create_dir(&group.path)?;
+        }
+        group.apply(limits)?;
+        Ok(group)
+    }
+
+    /// Returns the subroutine's group (if it has one).
+    pub fn find(&self, id: &SubroutineEntityId) -> Option<Cgroup> {
+        let path = self.group_path(id);
+        path.is_dir().then_some(Cgroup { path })
+    }
+}
+
+/// A single subroutine's group.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cgroup {
+    path: PathBuf,
+}
+
+impl Cgroup {
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    /// Writes the limits to the group's interface files (lifting any that are unset).
+    pub fn apply(&self, limits: &ResourceLimits) -> CgroupResult<()> {
+        let max = |limit: Option<u64>| limit.map_or("max".to_string(), |l| l.to_string());
+        fs::write(self.path.join("cpu.max"), limits.cpu_max())?;
+        fs::write(self.path.join("memory.max"), max(limits.memory))?;
+        fs::write(self.path.join("pids.max"), max(limits.pids))?;
+        Ok(())
+    }
+
+    /// Moves the given process into the group.
+    pub fn add_process(&self, pid: Pid) -> CgroupResult<()> {
+        fs::write(self.path.join("cgroup.procs"), pid.to_string())?;
+        Ok(())
+    }
+
+    /// Lists the processes in the group.
+    pub fn processes(&self) -> CgroupResult<Vec<Pid>> {
+        let procs = self.read("cgroup.procs")?.unwrap_or_default();
+        procs
+            .lines()
+            .map(|pid| {
+                self.parse("cgroup.procs", pid)
+                    .map(|pid| Pid::from_raw(pid as i32))
+            })
+            .collect()
+    }
+
+    /// Reads the group's current usage (controllers that aren't enabled report zero).
+    pub fn usage(&self) -> CgroupResult<ResourceUsage> {
+        let cpu_usage_usec = match self.read("cpu.stat")? {
+            Some(stat) => stat
+                .lines()
+                .find_map(|line| line.strip_prefix("usage_usec "))
+                .map_or(Ok(0), |usage| self.parse("cpu.stat", usage))?,
+            None => 0,
+        };
+        Ok(ResourceUsage {
+            cpu_usage_usec,
+            memory_current: self.read_counter("memory.current")?,
+            pids_current: self.read_counter("pids.current")?,
+        })
+    }
+
+    /// Removes the group (which the kernel only allows once it has no processes).
+    pub fn remove(&self) -> CgroupResult<()> {
+        fs::remove_dir(&self.path)?;
+        Ok(())
+    }
+
+    fn read(&self, file: &str) -> CgroupResult<Option<String>> {
+        match fs::read_to_string(self.path.join(file)) {
+            Ok(contents) => Ok(Some(contents)),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn read_counter(&self, file: &str) -> CgroupResult<u64> {
+        match self.read(file)? {
+            Some(contents) => self.parse(file, &contents),
+            None => Ok(0),
+        }
+    }
+
+    fn parse(&self, file: &str, value: &str) -> CgroupResult<u64> {
+        value
+            .trim()
+            .parse()
+            .map_err(|_| CgroupError::InvalidValue(self.path.join(file)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+    use tempfile::{tempdir, TempDir};
+
+    use super::*;
+
+    #[fixture]
+    fn root() -> TempDir {
+        tempdir().unwrap()
+    }
+
+    fn read(path: &Path) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    #[rstest]
+    fn creates_group_with_limits(root: TempDir) {
+        let cgroups = Cgroups::new(root.path(), DEFAULT_CGROUP_SLICE);
+        let id = SubroutineEntityId::generate();
+        let limits = ResourceLimits::default()
+            .with_cpu_millis(500)
+            .with_memory(64 * 1024 * 1024)
+            .with_pids(32);
+
+        let group = cgroups.create(&id, &limits).unwrap();
+
+        assert_eq!(group.path(), &root.path().join("holodekk.slice").join(&id));
+        assert_eq!(read(&group.path().join("cpu.max")), "50000 100000");
+        assert_eq!(read(&group.path().join("memory.max")), "67108864");
+        assert_eq!(read(&group.path().join("pids.max")), "32");
+        assert_eq!(
+            read(&root.path().join("holodekk.slice/cgroup.subtree_control")),
+            CONTROLLERS
+        );
+        assert_eq!(cgroups.find(&id), Some(group));
+    }
+
+    #[rstest]
+    fn unset_limits_are_unconstrained(root: TempDir) {
+        let cgroups = Cgroups::new(root.path(), DEFAULT_CGROUP_SLICE);
+
+        let group = cgroups
+            .create(&SubroutineEntityId::generate(), &ResourceLimits::default())
+            .unwrap();
+
+        assert_eq!(read(&group.path().join("cpu.max")), "max 100000");
+        assert_eq!(read(&group.path().join("memory.max")), "max");
+        assert_eq!(read(&group.path().join("pids.max")), "max");
+    }
+
+    #[rstest]
+    fn rejects_zero_limits(root: TempDir) {
+        let cgroups = Cgroups::new(root.path(), DEFAULT_CGROUP_SLICE);
+
+        let result = cgroups.create(
+            &SubroutineEntityId::generate(),
+            &ResourceLimits::default().with_memory(0),
+        );
+
+        assert!(matches!(result, Err(CgroupError::InvalidLimits(_))));
+    }
+
+    #[rstest]
+    #[case(0)]
+    #[case(1)]
+    #[case(9)]
+    fn rejects_cpu_limits_under_the_minimum_quota(#[case] cpu_millis: u64) {
+        assert!(matches!(
+            ResourceLimits::default()
+                .with_cpu_millis(cpu_millis)
+                .validate(),
+            Err(CgroupError::InvalidLimits(_))
+        ));
+    }
+
+    #[rstest]
+    fn allows_the_minimum_cpu_limit(root: TempDir) {
+        let cgroups = Cgroups::new(root.path(), DEFAULT_CGROUP_SLICE);
+
+        let group = cgroups
+            .create(
+                &SubroutineEntityId::generate(),
+                &ResourceLimits::default().with_cpu_millis(MIN_CPU_MILLIS),
+            )
+            .unwrap();
+
+        assert_eq!(read(&group.path().join("cpu.max")), "1000 100000");
+    }
+
+    #[rstest]
+    fn adds_processes(root: TempDir) {
+        let cgroups = Cgroups::new(root.path(), DEFAULT_CGROUP_SLICE);
+        let group = cgroups
+            .create(&SubroutineEntityId::generate(), &ResourceLimits::default())
+            .unwrap();
+
+        group.add_process(Pid::from_raw(4242)).unwrap();
+
+        assert_eq!(read(&group.path().join("cgroup.procs")), "4242");
+    }
+
+    #[rstest]
+    fn lists_processes(root: TempDir) {
+        let cgroups = Cgroups::new(root.path(), DEFAULT_CGROUP_SLICE);
+        let group = cgroups
+            .create(&SubroutineEntityId::generate(), &ResourceLimits::default())
+            .unwrap();
+        fs::write(group.path().join("cgroup.procs"), "4242\n4343\n").unwrap();
+
+        assert_eq!(
+            group.processes().unwrap(),
+            vec![Pid::from_raw(4242), Pid::from_raw(4343)]
+        );
+    }
+
+    #[rstest]
+    fn reads_usage(root: TempDir) {
+        let cgroups = Cgroups::new(root.path(), DEFAULT_CGROUP_SLICE);
+        let group = cgroups
+            .create(&SubroutineEntityId::generate(), &ResourceLimits::default())
+            .unwrap();
+        fs::write(
+            group.path().join("cpu.stat"),
+            "usage_usec 1500\nuser_usec 1000\nsystem_usec 500\n",
+        )
+        .unwrap();
+        fs::write(group.path().join("memory.current"), "4096\n").unwrap();
+
+        assert_eq!(
+            group.usage().unwrap(),
+            ResourceUsage {
+                cpu_usage_usec: 1500,
+                memory_current: 4096,
+                pids_current: 0,
+            }
+        );
+    }
+
+    #[rstest]
+    fn missing_groups_are_not_found(root: TempDir) {
+        let cgroups = Cgroups::new(root.path(), DEFAULT_CGROUP_SLICE);
+
+        assert!(cgroups.find(&SubroutineEntityId::generate()).is_none());
+    }
+}
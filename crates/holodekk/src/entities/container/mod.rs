@@ -14,7 +14,7 @@ impl fmt::Display for Container {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 #[serde(tag = "type")]
 pub enum ContainerManifest {
     FromDockerContext { context: String, dockerfile: String },
@@ -1,32 +1,113 @@
 use std::collections::HashMap;
 use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::Path;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
 
+use lazy_static::lazy_static;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
-use crate::core::containers::entities::ContainerManifest;
+use crate::errors::error_chain_fmt;
+use crate::runtimes::{Runtime, RuntimeError, DEFAULT_PATH};
+
+use super::ContainerManifest;
+
+lazy_static! {
+    static ref NAME_RE: Regex = Regex::new(r"^[A-Za-z0-9][A-Za-z0-9_.-]*$").unwrap();
+    static ref ENV_NAME_RE: Regex = Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").unwrap();
+}
+
+/// How long a subroutine is given to print its manifest.
+const MANIFEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// File (at the root of an image's files) describing the subroutine.
+pub const MANIFEST_FILE: &str = "holodekk.json";
+
+/// Upper bound on the manifest file (which is read into memory).
+const MAX_MANIFEST_LEN: u64 = 64 * 1024;
+
+/// Problem with a single field of a subroutine manifest.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ManifestFieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl ManifestFieldError {
+    pub fn new<F: Into<String>, M: Into<String>>(field: F, message: M) -> Self {
+        Self {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
+}
+
+impl fmt::Display for ManifestFieldError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.field, self.message)
+    }
+}
+
+#[derive(thiserror::Error)]
+pub enum ManifestError {
+    #[error("Invalid manifest ({} invalid fields)", .0.len())]
+    Invalid(Vec<ManifestFieldError>),
+    #[error("Manifest command failed: {0}")]
+    Command(String),
+    #[error("Unable to build manifest command")]
+    Runtime(#[from] RuntimeError),
+    #[error("IO error running manifest command")]
+    Io(#[from] std::io::Error),
+}
+
+impl std::fmt::Debug for ManifestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+impl ManifestError {
+    /// Field level errors (a failure to produce a manifest at all is reported against the
+    /// manifest as a whole).
+    pub fn field_errors(self) -> Vec<ManifestFieldError> {
+        match self {
+            Self::Invalid(errors) => errors,
+            err => vec![ManifestFieldError::new("manifest", format!("{:?}", err))],
+        }
+    }
+}
+
+pub type ManifestResult<T> = std::result::Result<T, ManifestError>;
 
 /// Object derived from dumping the subroutine configured by an extension.
 ///
 /// This should be considered a read-only view of the subroutine.  It exists merely
 /// to allow the subroutine to be identified prior to actual execution.
 ///
-/// The entity actually used by the system for managing instances is `Subroutine`.
+/// The entity actually used by the system for managing instances is `SubroutineEntity`.
 ///
 /// # Examples
 ///
 /// ```rust,no_run
-/// use holodekk::core::subroutines::entities::SubroutineManifest;
+/// use holodekk::entities::SubroutineManifest;
 /// # let json = "".to_string();
 /// // load the json for a subroutine
-/// let manifest: SubroutineManifest = serde_json::from_str(&json).unwrap();
-#[derive(Debug, Deserialize, Serialize)]
+/// let manifest = SubroutineManifest::parse(json.as_bytes()).unwrap();
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub struct SubroutineManifest {
     // fleet: String,
     // namespace: String,
     name: String,
-    container: ContainerManifest,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    container: Option<ContainerManifest>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
     environment: Option<HashMap<String, String>>,
-    port: Option<i16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    port: Option<u16>,
 }
 
 impl SubroutineManifest {
@@ -35,9 +116,9 @@ impl SubroutineManifest {
         &self.name
     }
 
-    /// Container manifest included in the extension.
-    pub fn container(&self) -> &ContainerManifest {
-        &self.container
+    /// Container manifest included in the extension (if any).
+    pub fn container(&self) -> Option<&ContainerManifest> {
+        self.container.as_ref()
     }
 
     /// Environment variables to be set on subroutine execution.
@@ -46,8 +127,136 @@ impl SubroutineManifest {
     }
 
     /// Port number the specified container will expect traffic when executed.
-    pub fn port(&self) -> Option<&i16> {
-        self.port.as_ref()
+    pub fn port(&self) -> Option<u16> {
+        self.port
+    }
+
+    /// Reads the manifest shipped in the subroutine's [MANIFEST_FILE], returning `None` if it
+    /// has none.
+    ///
+    /// Nothing from `path` is run, so this is safe to use on untrusted images.
+    pub fn load<P: AsRef<Path>>(path: P) -> ManifestResult<Option<Self>> {
+        let path = path.as_ref().join(MANIFEST_FILE);
+        let metadata = match fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        if !metadata.is_file() {
+            return Err(ManifestError::Invalid(vec![ManifestFieldError::new(
+                "manifest",
+                format!("{} is not a regular file", MANIFEST_FILE),
+            )]));
+        }
+        if metadata.len() > MAX_MANIFEST_LEN {
+            return Err(ManifestError::Invalid(vec![ManifestFieldError::new(
+                "manifest",
+                format!(
+                    "{} is larger than {} bytes",
+                    MANIFEST_FILE, MAX_MANIFEST_LEN
+                ),
+            )]));
+        }
+
+        let mut json = vec![];
+        File::open(&path)?
+            .take(MAX_MANIFEST_LEN)
+            .read_to_end(&mut json)?;
+        Self::parse(&json).map(Some)
+    }
+
+    /// Runs the subroutine's manifest command and parses the result.
+    ///
+    /// This runs the subroutine's own code as the current user, so it is only meant for trusted
+    /// directories (such as a developer's checkout); images are described by their
+    /// [MANIFEST_FILE] instead.
+    pub fn extract<P: AsRef<Path>>(runtime: &dyn Runtime, path: P) -> ManifestResult<Self> {
+        let command = runtime.manifest_command(path.as_ref())?;
+        let mut child = command
+            .command()
+            .env_clear()
+            .env("PATH", DEFAULT_PATH)
+            .envs(&command.env)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        // drain the pipes off-thread, so a chatty subroutine can't block on a full pipe
+        let mut stdout = child.stdout.take().unwrap();
+        let mut stderr = child.stderr.take().unwrap();
+        let stdout = std::thread::spawn(move || {
+            let mut buf = vec![];
+            stdout.read_to_end(&mut buf).map(|_| buf)
+        });
+        let stderr = std::thread::spawn(move || {
+            let mut buf = String::new();
+            stderr.read_to_string(&mut buf).map(|_| buf)
+        });
+
+        let deadline = Instant::now() + MANIFEST_TIMEOUT;
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() > deadline {
+                child.kill()?;
+                child.wait()?;
+                return Err(ManifestError::Command(format!(
+                    "timed out after {} seconds",
+                    MANIFEST_TIMEOUT.as_secs()
+                )));
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        };
+
+        let stdout = stdout.join().unwrap()?;
+        let stderr = stderr.join().unwrap()?;
+        if !status.success() {
+            return Err(ManifestError::Command(format!(
+                "{}: {}",
+                status,
+                stderr.trim()
+            )));
+        }
+
+        Self::parse(&stdout)
+    }
+
+    /// Parses a JSON manifest, validating each field.
+    pub fn parse(json: &[u8]) -> ManifestResult<Self> {
+        let value: Value = serde_json::from_slice(json).map_err(|err| {
+            ManifestError::Invalid(vec![ManifestFieldError::new(
+                "manifest",
+                format!("invalid JSON: {}", err),
+            )])
+        })?;
+        let mut fields = match value {
+            Value::Object(fields) => fields,
+            _ => {
+                return Err(ManifestError::Invalid(vec![ManifestFieldError::new(
+                    "manifest",
+                    "expected a JSON object",
+                )]))
+            }
+        };
+
+        let mut errors = vec![];
+        let name = parse_name(fields.remove("name"), &mut errors);
+        let container = parse_container(fields.remove("container"), &mut errors);
+        let environment = parse_environment(fields.remove("environment"), &mut errors);
+        let port = parse_port(fields.remove("port"), &mut errors);
+
+        if errors.is_empty() {
+            Ok(Self {
+                name: name.unwrap(),
+                container,
+                environment,
+                port,
+            })
+        } else {
+            Err(ManifestError::Invalid(errors))
+        }
     }
 }
 
@@ -56,3 +265,263 @@ impl fmt::Display for SubroutineManifest {
         write!(f, "Name: {}", self.name)
     }
 }
+
+fn parse_name(value: Option<Value>, errors: &mut Vec<ManifestFieldError>) -> Option<String> {
+    match value {
+        Some(Value::String(name)) if NAME_RE.is_match(&name) => Some(name),
+        Some(Value::String(name)) => {
+            errors.push(ManifestFieldError::new(
+                "name",
+                format!("invalid name: {:?}", name),
+            ));
+            None
+        }
+        Some(_) => {
+            errors.push(ManifestFieldError::new("name", "expected a string"));
+            None
+        }
+        None => {
+            errors.push(ManifestFieldError::new("name", "is required"));
+            None
+        }
+    }
+}
+
+fn parse_container(
+    value: Option<Value>,
+    errors: &mut Vec<ManifestFieldError>,
+) -> Option<ContainerManifest> {
+    match value {
+        None | Some(Value::Null) => None,
+        Some(value) => match serde_json::from_value(value) {
+            Ok(container) => Some(container),
+            Err(err) => {
+                errors.push(ManifestFieldError::new("container", err.to_string()));
+                None
+            }
+        },
+    }
+}
+
+fn parse_environment(
+    value: Option<Value>,
+    errors: &mut Vec<ManifestFieldError>,
+) -> Option<HashMap<String, String>> {
+    let variables = match value {
+        None | Some(Value::Null) => return None,
+        Some(Value::Object(variables)) => variables,
+        Some(_) => {
+            errors.push(ManifestFieldError::new("environment", "expected an object"));
+            return None;
+        }
+    };
+
+    let mut environment = HashMap::new();
+    for (key, value) in variables {
+        let field = format!("environment.{}", key);
+        if !ENV_NAME_RE.is_match(&key) {
+            errors.push(ManifestFieldError::new(field, "invalid variable name"));
+            continue;
+        }
+        match value {
+            Value::String(value) => {
+                environment.insert(key, value);
+            }
+            _ => errors.push(ManifestFieldError::new(field, "expected a string")),
+        }
+    }
+    Some(environment)
+}
+
+fn parse_port(value: Option<Value>, errors: &mut Vec<ManifestFieldError>) -> Option<u16> {
+    match value {
+        None | Some(Value::Null) => None,
+        Some(Value::Number(port)) => match port.as_u64() {
+            Some(port) if (1..=u16::MAX as u64).contains(&port) => Some(port as u16),
+            _ => {
+                errors.push(ManifestFieldError::new(
+                    "port",
+                    format!("{} is not a valid port number", port),
+                ));
+                None
+            }
+        },
+        Some(_) => {
+            errors.push(ManifestFieldError::new("port", "expected a number"));
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use tempfile::tempdir;
+
+    use crate::runtimes::ScriptRuntime;
+
+    use super::*;
+
+    fn field_errors(json: &str) -> Vec<String> {
+        SubroutineManifest::parse(json.as_bytes())
+            .unwrap_err()
+            .field_errors()
+            .into_iter()
+            .map(|e| e.field)
+            .collect()
+    }
+
+    #[test]
+    fn parses_valid_manifest() {
+        let manifest = SubroutineManifest::parse(
+            br#"{
+                "name": "widgets",
+                "container": { "type": "FromDockerContext", "context": ".", "dockerfile": "Dockerfile" },
+                "environment": { "RACK_ENV": "production" },
+                "port": 8080
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(manifest.name(), "widgets");
+        assert!(manifest.container().is_some());
+        assert_eq!(
+            manifest.environment().unwrap().get("RACK_ENV").unwrap(),
+            "production"
+        );
+        assert_eq!(manifest.port(), Some(8080));
+    }
+
+    #[test]
+    fn only_requires_name() {
+        let manifest = SubroutineManifest::parse(br#"{ "name": "widgets" }"#).unwrap();
+
+        assert!(manifest.container().is_none());
+        assert!(manifest.environment().is_none());
+        assert!(manifest.port().is_none());
+    }
+
+    #[test]
+    fn reports_an_error_per_invalid_field() {
+        let errors = field_errors(
+            r#"{
+                "name": "",
+                "container": { "type": "Unknown" },
+                "environment": { "GOOD": "yes", "1BAD": "no", "NUMBER": 1 },
+                "port": 70000
+            }"#,
+        );
+
+        assert_eq!(
+            errors,
+            vec![
+                "name",
+                "container",
+                "environment.1BAD",
+                "environment.NUMBER",
+                "port"
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_missing_name() {
+        assert_eq!(field_errors(r#"{ "port": 80 }"#), vec!["name"]);
+    }
+
+    #[test]
+    fn rejects_non_object_manifests() {
+        assert_eq!(field_errors("[]"), vec!["manifest"]);
+        assert_eq!(field_errors("not json"), vec!["manifest"]);
+    }
+
+    #[test]
+    fn extracts_manifest_from_subroutine() {
+        let temp = tempdir().unwrap();
+        fs::write(
+            temp.path().join("holodekk.sh"),
+            "[ \"$1\" = manifest ] && echo '{\"name\": \"widgets\", \"port\": 3000}'\n",
+        )
+        .unwrap();
+
+        let manifest = SubroutineManifest::extract(&ScriptRuntime::shell(), temp.path()).unwrap();
+
+        assert_eq!(manifest.name(), "widgets");
+        assert_eq!(manifest.port(), Some(3000));
+    }
+
+    #[test]
+    fn reports_failing_manifest_commands() {
+        let temp = tempdir().unwrap();
+        fs::write(temp.path().join("holodekk.sh"), "echo oops >&2; exit 3\n").unwrap();
+
+        let result = SubroutineManifest::extract(&ScriptRuntime::shell(), temp.path());
+
+        assert!(matches!(result.unwrap_err(), ManifestError::Command(..)));
+    }
+
+    #[test]
+    fn loads_manifest_file() {
+        let temp = tempdir().unwrap();
+        fs::write(
+            temp.path().join(MANIFEST_FILE),
+            r#"{ "name": "widgets", "port": 3000 }"#,
+        )
+        .unwrap();
+
+        let manifest = SubroutineManifest::load(temp.path()).unwrap().unwrap();
+
+        assert_eq!(manifest.name(), "widgets");
+        assert_eq!(manifest.port(), Some(3000));
+    }
+
+    #[test]
+    fn loads_nothing_without_manifest_file() {
+        let temp = tempdir().unwrap();
+        fs::write(
+            temp.path().join("holodekk.sh"),
+            "touch ran; echo '{\"name\": \"widgets\"}'\n",
+        )
+        .unwrap();
+
+        assert!(SubroutineManifest::load(temp.path()).unwrap().is_none());
+        assert!(!temp.path().join("ran").exists());
+    }
+
+    #[test]
+    fn rejects_symlinked_manifest_files() {
+        let temp = tempdir().unwrap();
+        fs::write(
+            temp.path().join("elsewhere.json"),
+            r#"{ "name": "widgets" }"#,
+        )
+        .unwrap();
+        std::os::unix::fs::symlink(
+            temp.path().join("elsewhere.json"),
+            temp.path().join(MANIFEST_FILE),
+        )
+        .unwrap();
+
+        let errors = SubroutineManifest::load(temp.path())
+            .unwrap_err()
+            .field_errors();
+
+        assert_eq!(errors[0].field, "manifest");
+    }
+
+    #[test]
+    fn runs_manifest_command_with_a_clean_environment() {
+        let temp = tempdir().unwrap();
+        fs::write(
+            temp.path().join("holodekk.sh"),
+            "echo \"{\\\"name\\\": \\\"widgets-${HOLODEKK_MANIFEST_SECRET:-none}\\\"}\"\n",
+        )
+        .unwrap();
+        std::env::set_var("HOLODEKK_MANIFEST_SECRET", "leaked");
+
+        let manifest = SubroutineManifest::extract(&ScriptRuntime::shell(), temp.path()).unwrap();
+
+        assert_eq!(manifest.name(), "widgets-none");
+    }
+}
@@ -1,5 +1,9 @@
+mod container;
+pub use container::*;
 mod id;
 pub use id::*;
+mod manifest;
+pub use manifest::*;
 mod scene;
 pub use scene::*;
 mod subroutine;
@@ -20,7 +24,7 @@ pub mod fixtures {
         SceneEntityRepositoryQuery, SubroutineEntityRepository, SubroutineEntityRepositoryQuery,
     };
 
-    use crate::enums::{SceneStatus, SubroutineStatus};
+    use crate::enums::{SceneStatus, SubroutineHealth};
     use crate::images::{fixtures::mock_subroutine_image, SubroutineImage};
 
     use super::*;
@@ -83,7 +87,7 @@ pub mod fixtures {
                 query: SubroutineEntityRepositoryQuery<'a>,
             ) -> EntityRepositoryResult<Vec<SubroutineEntity>>;
             async fn subroutines_get(&self, id: &SubroutineEntityId) -> EntityRepositoryResult<SubroutineEntity>;
-            async fn subroutines_update(&self, id: &SubroutineEntityId, status: Option<SubroutineStatus>) -> EntityRepositoryResult<SubroutineEntity>;
+            async fn subroutines_update(&self, id: &SubroutineEntityId, status: Option<SubroutineStatusChange>, health: Option<SubroutineHealth>) -> EntityRepositoryResult<SubroutineEntity>;
         }
     }
 
@@ -3,6 +3,8 @@ pub use name::*;
 mod repository;
 pub use repository::*;
 
+use std::collections::HashMap;
+
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
 use timestamps::Timestamps;
@@ -18,6 +20,9 @@ pub struct SceneEntity {
     pub id: SceneEntityId,
     pub name: SceneName,
     pub status: SceneStatus,
+    /// Environment variables inherited by every subroutine in the scene.
+    #[serde(default)]
+    pub environment: HashMap<String, String>,
     pub created_at: Option<NaiveDateTime>,
     pub updated_at: Option<NaiveDateTime>,
 }
@@ -28,6 +33,7 @@ impl Default for SceneEntity {
             id: SceneEntityId::generate(),
             name: "".into(),
             status: SceneStatus::Unknown,
+            environment: HashMap::new(),
             created_at: None,
             updated_at: None,
         }
@@ -1,12 +1,20 @@
 mod repository;
 pub use repository::*;
 
-use chrono::NaiveDateTime;
+use std::collections::HashMap;
+
+use chrono::{DateTime, NaiveDateTime, Utc};
 use serde::{Deserialize, Serialize};
 use timestamps::Timestamps;
 
-use crate::enums::SubroutineStatus;
+use crate::cgroups::ResourceLimits;
+use crate::enums::{SubroutineHealth, SubroutineStatus};
+use crate::health::HealthProbe;
 use crate::images::SubroutineImageId;
+use crate::logs::{LogFormat, LogRotation};
+use crate::privileges::RunAs;
+use crate::restart::RestartPolicy;
+use crate::rlimits::ProcessLimits;
 
 use super::{EntityId, SceneEntityId};
 
@@ -18,6 +26,51 @@ pub struct SubroutineEntity {
     pub scene_entity_id: SceneEntityId,
     pub subroutine_image_id: SubroutineImageId,
     pub status: SubroutineStatus,
+    /// When the shim observed the current status.
+    #[serde(default)]
+    pub status_changed_at: Option<DateTime<Utc>>,
+    /// How the subroutine last exited (kept across restarts, until it next exits).
+    #[serde(default)]
+    pub last_exit: Option<SubroutineExit>,
+    /// Environment variables set when the subroutine is executed.
+    #[serde(default)]
+    pub environment: HashMap<String, String>,
+    /// Port the subroutine expects traffic on (if any).
+    #[serde(default)]
+    pub port: Option<u16>,
+    /// Host port allocated to the subroutine (when it declares a port).
+    #[serde(default)]
+    pub host_port: Option<u16>,
+    /// Probe the shim runs to check the subroutine is healthy (boxed, as it's rarely set).
+    #[serde(default)]
+    pub health_probe: Option<Box<HealthProbe>>,
+    /// Health as last reported by the shim.
+    #[serde(default)]
+    pub health: SubroutineHealth,
+    /// Whether the shim restarts the subroutine when it exits.
+    #[serde(default)]
+    pub restart_policy: RestartPolicy,
+    /// cgroup limits applied to the subroutine.
+    #[serde(default)]
+    pub limits: ResourceLimits,
+    /// User, groups and capabilities the subroutine runs with.
+    #[serde(default)]
+    pub run_as: RunAs,
+    /// rlimits and deadline applied to the subroutine's processes.
+    #[serde(default)]
+    pub process_limits: ProcessLimits,
+    /// When the subroutine's log is rotated (and how many rotated logs are kept).
+    #[serde(default)]
+    pub log_rotation: LogRotation,
+    /// How entries are written to the subroutine's log.
+    #[serde(default)]
+    pub log_format: LogFormat,
+    /// Whether attached clients can write to the subroutine's stdin.
+    #[serde(default)]
+    pub stdin: bool,
+    /// Whether the subroutine runs in a pseudo-terminal (implies `stdin`).
+    #[serde(default)]
+    pub tty: bool,
     pub created_at: Option<NaiveDateTime>,
     pub updated_at: Option<NaiveDateTime>,
 }
@@ -29,8 +82,71 @@ impl SubroutineEntity {
             scene_entity_id: scene_entity_id.to_owned(),
             subroutine_image_id: subroutine_image_id.to_owned(),
             status: SubroutineStatus::Unknown,
+            status_changed_at: None,
+            last_exit: None,
+            environment: HashMap::new(),
+            port: None,
+            host_port: None,
+            health_probe: None,
+            health: SubroutineHealth::Unknown,
+            restart_policy: RestartPolicy::Never,
+            limits: ResourceLimits::default(),
+            run_as: RunAs::default(),
+            process_limits: ProcessLimits::default(),
+            log_rotation: LogRotation::default(),
+            log_format: LogFormat::default(),
+            stdin: false,
+            tty: false,
             created_at: None,
             updated_at: None,
         }
     }
+
+    /// Applies a status change reported by the subroutine's shim.
+    pub fn change_status(&mut self, change: SubroutineStatusChange) {
+        self.status = change.status;
+        self.status_changed_at = Some(change.changed_at);
+        if let Some(exit) = change.exit {
+            self.last_exit = Some(exit);
+        }
+    }
+}
+
+/// How a subroutine exited, as recorded by its shim.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SubroutineExit {
+    /// [`SubroutineStatus::Exited`] or [`SubroutineStatus::Killed`].
+    pub status: SubroutineStatus,
+    pub exited_at: DateTime<Utc>,
+    /// The last lines the subroutine wrote to stderr (oldest first).
+    #[serde(default)]
+    pub stderr_tail: Vec<String>,
+}
+
+/// A change in a subroutine's status, as observed by its shim.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SubroutineStatusChange {
+    pub status: SubroutineStatus,
+    pub changed_at: DateTime<Utc>,
+    /// Set when the change follows the subroutine exiting.
+    #[serde(default)]
+    pub exit: Option<SubroutineExit>,
+}
+
+impl SubroutineStatusChange {
+    /// A change to `status`, as of now.
+    pub fn new(status: SubroutineStatus) -> Self {
+        Self {
+            status,
+            changed_at: Utc::now(),
+            exit: None,
+        }
+    }
+
+    pub fn with_exit(self, exit: SubroutineExit) -> Self {
+        Self {
+            exit: Some(exit),
+            ..self
+        }
+    }
 }
@@ -4,10 +4,10 @@ use mockall::{automock, predicate::*};
 use serde::{Deserialize, Serialize};
 
 use crate::entities::repository::{EntityRepositoryQuery, EntityRepositoryResult};
-use crate::enums::SubroutineStatus;
+use crate::enums::SubroutineHealth;
 use crate::images::SubroutineImageId;
 
-use super::{SceneEntityId, SubroutineEntity, SubroutineEntityId};
+use super::{SceneEntityId, SubroutineEntity, SubroutineEntityId, SubroutineStatusChange};
 
 #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
 pub enum SubroutineEntityRepositoryEvent {
@@ -17,7 +17,8 @@ pub enum SubroutineEntityRepositoryEvent {
     },
     Update {
         subroutine: SubroutineEntity,
-        orig: SubroutineEntity,
+        /// Boxed, so the variants stay close in size.
+        orig: Box<SubroutineEntity>,
     },
     Delete {
         subroutine: SubroutineEntity,
@@ -98,6 +99,7 @@ pub trait SubroutineEntityRepository: Send + Sync + 'static {
     async fn subroutines_update(
         &self,
         id: &SubroutineEntityId,
-        status: Option<SubroutineStatus>,
+        status: Option<SubroutineStatusChange>,
+        health: Option<SubroutineHealth>,
     ) -> EntityRepositoryResult<SubroutineEntity>;
 }
@@ -1,7 +1,9 @@
-use std::path::{Path, PathBuf};
+use std::path::Path;
 
 use serde::{Deserialize, Serialize};
 
+use crate::runtimes::RuntimeRegistry;
+
 #[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
 pub enum SceneStatus {
     Unknown,
@@ -23,25 +25,71 @@ pub enum ProjectorStatus {
 #[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
 pub enum SubroutineStatus {
     Unknown,
+    /// Being (re)started by its shim.
+    Starting,
     Stopped,
     Running(u32),
     Crashed,
+    /// Exiting repeatedly shortly after being started (holds the consecutive restart count).
+    CrashLoopBackOff(u32),
+    /// Exited by itself, with the given code.
+    Exited {
+        code: i32,
+    },
+    /// Terminated by the given signal (by number).
+    Killed {
+        signal: i32,
+    },
+    /// Waiting to be restarted (holds the attempt about to be made).
+    Restarting {
+        attempt: u32,
+    },
+}
+
+impl SubroutineStatus {
+    /// Whether the status records the subroutine exiting (on its own, or by a signal).
+    pub fn is_exit(&self) -> bool {
+        matches!(
+            self,
+            SubroutineStatus::Exited { .. } | SubroutineStatus::Killed { .. }
+        )
+    }
+
+    /// Whether the status means the subroutine has a shim looking after it.
+    pub fn is_supervised(&self) -> bool {
+        matches!(
+            self,
+            SubroutineStatus::Starting
+                | SubroutineStatus::Running(_)
+                | SubroutineStatus::Restarting { .. }
+                | SubroutineStatus::CrashLoopBackOff(_)
+        )
+    }
+}
+
+/// Outcome of a subroutine's health probe.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub enum SubroutineHealth {
+    /// No probe configured, or not enough results yet.
+    #[default]
+    Unknown,
+    Healthy,
+    Unhealthy,
 }
 
 #[derive(Clone, Copy, Debug, Deserialize, PartialEq, Serialize)]
 pub enum SubroutineKind {
     Unknown,
     Ruby,
+    Python,
+    Node,
+    Shell,
+    Executable,
 }
 
 impl SubroutineKind {
+    /// Detects the kind of the subroutine at `path` using the built-in runtimes.
     pub fn detect<P: AsRef<Path>>(path: P) -> SubroutineKind {
-        let mut ruby_path = PathBuf::from(path.as_ref());
-        ruby_path.push("holodekk.rb");
-        if ruby_path.try_exists().unwrap() {
-            Self::Ruby
-        } else {
-            Self::Unknown
-        }
+        RuntimeRegistry::default().detect_kind(path)
     }
 }
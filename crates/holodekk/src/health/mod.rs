@@ -0,0 +1,471 @@
+//! Health probes run against subroutines by their shims.
+//!
+//! A subroutine which is still running may nonetheless be wedged.  Probes give us a way to
+//! notice: the shim runs the configured probe on an interval, feeds the results through a
+//! [`HealthMonitor`], and reports changes in health back to holodekkd.
+
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::process::{Command, ExitStatus, Stdio};
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::enums::SubroutineHealth;
+use crate::errors::error_chain_fmt;
+use crate::ports::ENDPOINT_HOST;
+
+#[derive(thiserror::Error)]
+pub enum HealthProbeError {
+    #[error("Invalid health probe: {0}")]
+    Invalid(String),
+    #[error("Health probe failed: {0}")]
+    Failed(String),
+    #[error("Health probe timed out after {0:?}")]
+    TimedOut(Duration),
+    #[error("Health probe IO error")]
+    Io(#[from] std::io::Error),
+}
+
+impl std::fmt::Debug for HealthProbeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+pub type HealthProbeResult<T> = std::result::Result<T, HealthProbeError>;
+
+/// What a probe checks.
+///
+/// Ports default to the host port allocated to the subroutine.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum ProbeCheck {
+    /// `GET` the path, expecting a 2xx or 3xx response.
+    Http {
+        path: String,
+        #[serde(default)]
+        port: Option<u16>,
+    },
+    /// Open a connection to the port.
+    Tcp {
+        #[serde(default)]
+        port: Option<u16>,
+    },
+    /// Run a command, expecting it to exit successfully.
+    Exec { command: Vec<String> },
+}
+
+fn default_interval() -> u64 {
+    10
+}
+
+fn default_timeout() -> u64 {
+    1
+}
+
+fn default_threshold() -> u32 {
+    3
+}
+
+fn default_success_threshold() -> u32 {
+    1
+}
+
+/// Health probe configuration (intervals and timeouts are in seconds).
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct HealthProbe {
+    pub check: ProbeCheck,
+    /// Delay before the first probe.
+    #[serde(default)]
+    pub initial_delay: u64,
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+    #[serde(default = "default_timeout")]
+    pub timeout: u64,
+    /// Consecutive failures before the subroutine is considered unhealthy.
+    #[serde(default = "default_threshold")]
+    pub failure_threshold: u32,
+    /// Consecutive successes before the subroutine is considered healthy.
+    #[serde(default = "default_success_threshold")]
+    pub success_threshold: u32,
+}
+
+impl HealthProbe {
+    pub fn new(check: ProbeCheck) -> Self {
+        Self {
+            check,
+            initial_delay: 0,
+            interval: default_interval(),
+            timeout: default_timeout(),
+            failure_threshold: default_threshold(),
+            success_threshold: default_success_threshold(),
+        }
+    }
+
+    pub fn with_initial_delay(mut self, seconds: u64) -> Self {
+        self.initial_delay = seconds;
+        self
+    }
+
+    pub fn with_interval(mut self, seconds: u64) -> Self {
+        self.interval = seconds;
+        self
+    }
+
+    pub fn with_timeout(mut self, seconds: u64) -> Self {
+        self.timeout = seconds;
+        self
+    }
+
+    pub fn with_failure_threshold(mut self, threshold: u32) -> Self {
+        self.failure_threshold = threshold;
+        self
+    }
+
+    pub fn with_success_threshold(mut self, threshold: u32) -> Self {
+        self.success_threshold = threshold;
+        self
+    }
+
+    pub fn initial_delay(&self) -> Duration {
+        Duration::from_secs(self.initial_delay)
+    }
+
+    pub fn interval(&self) -> Duration {
+        Duration::from_secs(self.interval)
+    }
+
+    pub fn timeout(&self) -> Duration {
+        Duration::from_secs(self.timeout)
+    }
+
+    pub fn validate(&self) -> HealthProbeResult<()> {
+        if self.interval == 0 || self.timeout == 0 {
+            return Err(HealthProbeError::Invalid(
+                "interval and timeout must be at least one second".to_string(),
+            ));
+        }
+        if self.failure_threshold == 0 || self.success_threshold == 0 {
+            return Err(HealthProbeError::Invalid(
+                "thresholds must be at least one".to_string(),
+            ));
+        }
+        match &self.check {
+            ProbeCheck::Http { path, .. } if !path.starts_with('/') => Err(
+                HealthProbeError::Invalid(format!("http path must be absolute: {}", path)),
+            ),
+            ProbeCheck::Exec { command } if command.is_empty() => Err(HealthProbeError::Invalid(
+                "exec command is empty".to_string(),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// The command run by an exec probe.
+    ///
+    /// Shims start these themselves (rather than through [`Self::run`]), as they reap every
+    /// child they have.
+    pub fn exec_command(&self) -> Option<&[String]> {
+        match &self.check {
+            ProbeCheck::Exec { command } => Some(command),
+            _ => None,
+        }
+    }
+
+    /// Runs the probe once, blocking until it completes (or times out).
+    ///
+    /// `host_port` is probed when the check doesn't name a port of its own.
+    pub fn run(&self, host_port: Option<u16>) -> HealthProbeResult<()> {
+        let port = |port: &Option<u16>| {
+            port.or(host_port)
+                .map(|port| SocketAddr::from((ENDPOINT_HOST, port)))
+                .ok_or_else(|| HealthProbeError::Invalid("no port to probe".to_string()))
+        };
+        match &self.check {
+            ProbeCheck::Http { path, port: p } => probe_http(port(p)?, path, self.timeout()),
+            ProbeCheck::Tcp { port: p } => probe_tcp(port(p)?, self.timeout()),
+            ProbeCheck::Exec { command } => probe_exec(command, self.timeout()),
+        }
+    }
+}
+
+fn connect(address: SocketAddr, timeout: Duration) -> HealthProbeResult<TcpStream> {
+    TcpStream::connect_timeout(&address, timeout).map_err(|err| match err.kind() {
+        std::io::ErrorKind::TimedOut => HealthProbeError::TimedOut(timeout),
+        _ => HealthProbeError::Failed(format!("unable to connect to {}: {}", address, err)),
+    })
+}
+
+fn probe_tcp(address: SocketAddr, timeout: Duration) -> HealthProbeResult<()> {
+    connect(address, timeout).map(drop)
+}
+
+fn probe_http(address: SocketAddr, path: &str, timeout: Duration) -> HealthProbeResult<()> {
+    let mut stream = connect(address, timeout)?;
+    stream.set_read_timeout(Some(timeout))?;
+    stream.set_write_timeout(Some(timeout))?;
+    write!(
+        stream,
+        "GET {} HTTP/1.0\r\nHost: {}\r\nUser-Agent: holodekk\r\nConnection: close\r\n\r\n",
+        path, address
+    )?;
+
+    // the status line is all we're interested in
+    let mut response = Vec::new();
+    let mut buf = [0; 256];
+    while !response.contains(&b'\n') {
+        match stream.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => response.extend_from_slice(&buf[..n]),
+            Err(err) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                return Err(HealthProbeError::TimedOut(timeout))
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or_default();
+    match status_line
+        .split_whitespace()
+        .nth(1)
+        .and_then(|code| code.parse::<u16>().ok())
+    {
+        Some(200..=399) => Ok(()),
+        Some(_) => Err(HealthProbeError::Failed(status_line.to_string())),
+        None => Err(HealthProbeError::Failed(format!(
+            "invalid response: {}",
+            status_line
+        ))),
+    }
+}
+
+fn probe_exec(command: &[String], timeout: Duration) -> HealthProbeResult<()> {
+    let mut child = Command::new(&command[0])
+        .args(&command[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .spawn()
+        .map_err(|err| {
+            HealthProbeError::Failed(format!("unable to run {}: {}", command[0], err))
+        })?;
+
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return exec_result(command, status);
+        }
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            return Err(HealthProbeError::TimedOut(timeout));
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+}
+
+/// The result of an exec probe whose command exited with `status`.
+pub fn exec_result(command: &[String], status: ExitStatus) -> HealthProbeResult<()> {
+    if status.success() {
+        Ok(())
+    } else {
+        Err(HealthProbeError::Failed(format!(
+            "{} exited with {}",
+            command[0], status
+        )))
+    }
+}
+
+/// Tracks consecutive probe results, deciding when a subroutine's health changes.
+#[derive(Debug)]
+pub struct HealthMonitor {
+    probe: HealthProbe,
+    status: SubroutineHealth,
+    failures: u32,
+    successes: u32,
+}
+
+impl HealthMonitor {
+    pub fn new(probe: HealthProbe) -> Self {
+        Self {
+            probe,
+            status: SubroutineHealth::Unknown,
+            failures: 0,
+            successes: 0,
+        }
+    }
+
+    pub fn probe(&self) -> &HealthProbe {
+        &self.probe
+    }
+
+    pub fn status(&self) -> SubroutineHealth {
+        self.status
+    }
+
+    /// Records the result of a probe, returning the new health if it changed.
+    pub fn record<T>(&mut self, result: &HealthProbeResult<T>) -> Option<SubroutineHealth> {
+        let next = if result.is_ok() {
+            self.failures = 0;
+            self.successes = self.successes.saturating_add(1);
+            (self.successes >= self.probe.success_threshold).then_some(SubroutineHealth::Healthy)
+        } else {
+            self.successes = 0;
+            self.failures = self.failures.saturating_add(1);
+            (self.failures >= self.probe.failure_threshold).then_some(SubroutineHealth::Unhealthy)
+        };
+
+        match next {
+            Some(next) if next != self.status => {
+                self.status = next;
+                Some(next)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, TcpListener};
+
+    use rstest::*;
+
+    use super::*;
+
+    /// Answers the first request with `response` (skipping connections closed without one, as
+    /// tcp probes of a reused "closed" port make).
+    fn serve_once(response: &'static str) -> u16 {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        std::thread::spawn(move || loop {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0; 1024];
+            if matches!(stream.read(&mut buf), Ok(read) if read > 0) {
+                stream.write_all(response.as_bytes()).unwrap();
+                break;
+            }
+        });
+        port
+    }
+
+    fn closed_port() -> u16 {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        listener.local_addr().unwrap().port()
+    }
+
+    #[test]
+    fn parses_probe_configuration() {
+        let probe: HealthProbe = serde_json::from_str(
+            r#"{"check": {"type": "http", "path": "/health"}, "interval": 5}"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            probe,
+            HealthProbe::new(ProbeCheck::Http {
+                path: "/health".to_string(),
+                port: None
+            })
+            .with_interval(5)
+        );
+    }
+
+    #[rstest]
+    #[case(HealthProbe::new(ProbeCheck::Tcp { port: None }).with_interval(0))]
+    #[case(HealthProbe::new(ProbeCheck::Tcp { port: None }).with_failure_threshold(0))]
+    #[case(HealthProbe::new(ProbeCheck::Http { path: "health".to_string(), port: None }))]
+    #[case(HealthProbe::new(ProbeCheck::Exec { command: vec![] }))]
+    fn rejects_invalid_probes(#[case] probe: HealthProbe) {
+        assert!(matches!(
+            probe.validate().unwrap_err(),
+            HealthProbeError::Invalid(..)
+        ));
+    }
+
+    #[test]
+    fn http_probe_accepts_success_responses() {
+        let port = serve_once("HTTP/1.1 204 No Content\r\n\r\n");
+        let probe = HealthProbe::new(ProbeCheck::Http {
+            path: "/health".to_string(),
+            port: None,
+        });
+
+        probe.run(Some(port)).unwrap();
+    }
+
+    #[test]
+    fn http_probe_fails_on_error_responses() {
+        let port = serve_once("HTTP/1.1 503 Service Unavailable\r\n\r\n");
+        let probe = HealthProbe::new(ProbeCheck::Http {
+            path: "/health".to_string(),
+            port: Some(port),
+        });
+
+        assert!(matches!(
+            probe.run(None).unwrap_err(),
+            HealthProbeError::Failed(..)
+        ));
+    }
+
+    #[test]
+    fn tcp_probe_checks_port_is_listening() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let probe = HealthProbe::new(ProbeCheck::Tcp { port: None });
+
+        probe
+            .run(Some(listener.local_addr().unwrap().port()))
+            .unwrap();
+        assert!(probe.run(Some(closed_port())).is_err());
+    }
+
+    #[test]
+    fn probes_require_a_port() {
+        let probe = HealthProbe::new(ProbeCheck::Tcp { port: None });
+
+        assert!(matches!(
+            probe.run(None).unwrap_err(),
+            HealthProbeError::Invalid(..)
+        ));
+    }
+
+    #[test]
+    fn exec_probe_checks_exit_status() {
+        let probe = |command: &str| {
+            HealthProbe::new(ProbeCheck::Exec {
+                command: vec!["sh".to_string(), "-c".to_string(), command.to_string()],
+            })
+        };
+
+        probe("exit 0").run(None).unwrap();
+        assert!(matches!(
+            probe("exit 3").run(None).unwrap_err(),
+            HealthProbeError::Failed(..)
+        ));
+        assert!(matches!(
+            probe("sleep 5").run(None).unwrap_err(),
+            HealthProbeError::TimedOut(..)
+        ));
+    }
+
+    #[test]
+    fn monitor_applies_thresholds() {
+        let mut monitor = HealthMonitor::new(
+            HealthProbe::new(ProbeCheck::Tcp { port: None })
+                .with_failure_threshold(2)
+                .with_success_threshold(2),
+        );
+        let ok: HealthProbeResult<()> = Ok(());
+        let failed: HealthProbeResult<()> = Err(HealthProbeError::Failed("down".to_string()));
+
+        assert_eq!(monitor.record(&ok), None);
+        assert_eq!(monitor.record(&ok), Some(SubroutineHealth::Healthy));
+        assert_eq!(monitor.record(&ok), None);
+        assert_eq!(monitor.record(&failed), None);
+        assert_eq!(monitor.record(&ok), None);
+        assert_eq!(monitor.record(&failed), None);
+        assert_eq!(monitor.record(&failed), Some(SubroutineHealth::Unhealthy));
+        assert_eq!(monitor.status(), SubroutineHealth::Unhealthy);
+    }
+}
@@ -0,0 +1,238 @@
+use std::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::ops::Deref;
+use std::path::Path;
+use std::str::FromStr;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+
+lazy_static! {
+    static ref DIGEST_RE: Regex = Regex::new(r"^sha256:[0-9a-f]{64}$").unwrap();
+}
+
+#[derive(thiserror::Error, Debug)]
+pub enum DigestError {
+    #[error("Invalid Digest format")]
+    Format(String),
+}
+
+/// Content address of a blob, in the form `sha256:<hex>`.
+#[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Digest(String);
+
+impl Digest {
+    /// Computes the digest of an in-memory buffer.
+    pub fn compute(data: &[u8]) -> Self {
+        let mut hasher = Sha256::new();
+        hasher.update(data);
+        Self::from_hasher(hasher)
+    }
+
+    /// Computes the digest of the file at the given path.
+    pub fn compute_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let mut writer = DigestWriter::new(io::sink());
+        io::copy(&mut File::open(path)?, &mut writer)?;
+        Ok(writer.finalize().0)
+    }
+
+    /// Computes the digest of everything remaining in the given reader.
+    pub fn compute_reader<R: Read>(reader: &mut R) -> io::Result<Self> {
+        let mut writer = DigestWriter::new(io::sink());
+        io::copy(reader, &mut writer)?;
+        Ok(writer.finalize().0)
+    }
+
+    pub fn from_hasher(hasher: Sha256) -> Self {
+        Self(format!("sha256:{:x}", hasher.finalize()))
+    }
+
+    /// Hex encoded portion of the digest (without the algorithm prefix).
+    pub fn hex(&self) -> &str {
+        &self.0["sha256:".len()..]
+    }
+}
+
+impl FromStr for Digest {
+    type Err = DigestError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if DIGEST_RE.is_match(s) {
+            Ok(Digest(s.to_string()))
+        } else {
+            Err(DigestError::Format(s.to_string()))
+        }
+    }
+}
+
+impl TryFrom<String> for Digest {
+    type Error = DigestError;
+
+    fn try_from(value: String) -> std::result::Result<Self, Self::Error> {
+        Self::from_str(&value)
+    }
+}
+
+impl From<Digest> for String {
+    fn from(digest: Digest) -> String {
+        digest.0
+    }
+}
+
+impl std::fmt::Display for Digest {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl Deref for Digest {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl<T> AsRef<T> for Digest
+where
+    T: ?Sized,
+    <Digest as Deref>::Target: AsRef<T>,
+{
+    fn as_ref(&self) -> &T {
+        self.deref().as_ref()
+    }
+}
+
+/// [Write] adapter which hashes everything passing through it.
+pub struct DigestWriter<W> {
+    inner: W,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl<W> DigestWriter<W>
+where
+    W: Write,
+{
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            size: 0,
+        }
+    }
+
+    /// Consumes the writer, returning the digest and size of the data written along with the
+    /// wrapped writer.
+    pub fn finalize(self) -> (Digest, u64, W) {
+        (Digest::from_hasher(self.hasher), self.size, self.inner)
+    }
+}
+
+impl<W> Write for DigestWriter<W>
+where
+    W: Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// [Read] adapter which hashes everything read through it.
+pub struct DigestReader<R> {
+    inner: R,
+    hasher: Sha256,
+    size: u64,
+}
+
+impl<R> DigestReader<R>
+where
+    R: Read,
+{
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+            size: 0,
+        }
+    }
+
+    /// Reads (and hashes) whatever is left in the wrapped reader, then returns the digest and
+    /// size of everything read.
+    pub fn finalize(mut self) -> io::Result<(Digest, u64)> {
+        io::copy(&mut self, &mut io::sink())?;
+        Ok((Digest::from_hasher(self.hasher), self.size))
+    }
+}
+
+impl<R> Read for DigestReader<R>
+where
+    R: Read,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        self.size += read as u64;
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HELLO_DIGEST: &str =
+        "sha256:2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824";
+
+    #[test]
+    fn computes_sha256_digest() {
+        assert_eq!(Digest::compute(b"hello").to_string(), HELLO_DIGEST);
+    }
+
+    #[test]
+    fn parses_valid_digest() {
+        let digest: Digest = HELLO_DIGEST.parse().unwrap();
+        assert_eq!(digest.hex(), &HELLO_DIGEST[7..]);
+    }
+
+    #[test]
+    fn rejects_invalid_digest() {
+        assert!("sha256:1234".parse::<Digest>().is_err());
+        assert!("md5:2cf24dba5fb0a30e26e83b2ac5b9e29e"
+            .parse::<Digest>()
+            .is_err());
+    }
+
+    #[test]
+    fn digest_writer_matches_computed_digest() {
+        let mut writer = DigestWriter::new(Vec::new());
+        writer.write_all(b"hel").unwrap();
+        writer.write_all(b"lo").unwrap();
+        let (digest, size, inner) = writer.finalize();
+        assert_eq!(digest.to_string(), HELLO_DIGEST);
+        assert_eq!(size, 5);
+        assert_eq!(inner, b"hello");
+    }
+
+    #[test]
+    fn digest_reader_hashes_unread_remainder() {
+        let mut reader = DigestReader::new(&b"hello"[..]);
+        let mut start = [0; 3];
+        reader.read_exact(&mut start).unwrap();
+        let (digest, size) = reader.finalize().unwrap();
+        assert_eq!(&start, b"hel");
+        assert_eq!(digest.to_string(), HELLO_DIGEST);
+        assert_eq!(size, 5);
+    }
+}
@@ -1,3 +1,7 @@
+mod digest;
+pub use digest::*;
+mod signing;
+pub use signing::*;
 mod subroutine;
 pub use subroutine::*;
 
@@ -8,7 +12,7 @@ use std::str::FromStr;
 use lazy_static::lazy_static;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
+use sha2::{Digest as _, Sha256};
 
 #[derive(Clone, Debug, Deserialize, Eq, Hash, PartialEq, Serialize)]
 pub struct ImageName(String);
@@ -132,12 +136,21 @@ where
 
 #[cfg(test)]
 pub mod fixtures {
+    use ed25519_dalek::SigningKey;
+    use rand::RngCore;
     use rstest::*;
 
     use crate::enums::SubroutineKind;
 
     use super::*;
 
+    #[fixture]
+    pub fn signing_key() -> SigningKey {
+        let mut bytes: [u8; 32] = [0; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        SigningKey::from_bytes(&bytes)
+    }
+
     #[fixture]
     pub fn mock_subroutine_image() -> SubroutineImage {
         SubroutineImage::new(
@@ -0,0 +1,477 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use chrono::{NaiveDateTime, Utc};
+use clap::ValueEnum;
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
+
+use crate::errors::error_chain_fmt;
+use crate::HolodekkPaths;
+
+use super::{Digest, SubroutineImageId, SubroutineImageStore, SubroutineImageStoreError};
+
+const PUBLIC_KEY_EXTENSION: &str = "pub";
+
+#[derive(thiserror::Error)]
+pub enum SigningError {
+    #[error("Invalid key: {0}")]
+    InvalidKey(String),
+    #[error("Trust store IO error")]
+    Io(#[from] std::io::Error),
+}
+
+impl std::fmt::Debug for SigningError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+pub type SigningResult<T> = std::result::Result<T, SigningError>;
+
+#[derive(thiserror::Error)]
+pub enum ImageVerificationError {
+    #[error("Image {0} rejected: {1}")]
+    Rejected(SubroutineImageId, SignatureStatus),
+    #[error("Image store error")]
+    Store(#[from] SubroutineImageStoreError),
+    #[error("Image archive IO error")]
+    Io(#[from] std::io::Error),
+    #[error("Signature verification error")]
+    Signing(#[from] SigningError),
+}
+
+impl std::fmt::Debug for ImageVerificationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+pub type ImageVerificationResult<T> = std::result::Result<T, ImageVerificationError>;
+
+/// How subroutine creation treats image signatures.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize, ValueEnum)]
+pub enum SignaturePolicy {
+    /// Refuse images without a valid signature from a trusted key
+    Require,
+    /// Log a warning for images without a valid signature from a trusted key
+    #[default]
+    Warn,
+    /// Skip signature verification entirely
+    Off,
+}
+
+/// Detached ed25519 signature over an image digest.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ImageSignature {
+    /// Fingerprint of the public key the signature was made with.
+    pub key_id: String,
+    /// Hex encoded signature bytes.
+    pub signature: String,
+}
+
+impl ImageSignature {
+    pub fn sign(key: &SigningKey, digest: &Digest) -> Self {
+        Self {
+            key_id: key_id(&key.verifying_key()),
+            signature: hex::encode(key.sign(digest.as_bytes()).to_bytes()),
+        }
+    }
+
+    fn verify(&self, key: &VerifyingKey, digest: &Digest) -> bool {
+        let bytes = match hex::decode(&self.signature) {
+            Ok(bytes) => bytes,
+            Err(_) => return false,
+        };
+        match Signature::from_slice(&bytes) {
+            Ok(signature) => key.verify(digest.as_bytes(), &signature).is_ok(),
+            Err(_) => false,
+        }
+    }
+}
+
+/// Outcome of checking an image's signatures against the trust store.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case", tag = "status")]
+pub enum SignatureStatus {
+    /// A signature from a trusted key matched the image digest
+    Verified { key: String },
+    /// The image carries no signatures
+    Unsigned,
+    /// None of the signatures were made by a trusted key
+    Untrusted,
+    /// A trusted key signed the image, but the signature does not match its digest
+    Invalid,
+    /// The image digest is unknown, so there is nothing to verify against
+    MissingDigest,
+    /// The image's archive no longer matches its recorded digest
+    DigestMismatch,
+}
+
+impl SignatureStatus {
+    pub fn is_verified(&self) -> bool {
+        matches!(self, Self::Verified { .. })
+    }
+}
+
+impl std::fmt::Display for SignatureStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Verified { key } => write!(f, "verified by {}", key),
+            Self::Unsigned => write!(f, "unsigned"),
+            Self::Untrusted => write!(f, "not signed by a trusted key"),
+            Self::Invalid => write!(f, "invalid signature"),
+            Self::MissingDigest => write!(f, "missing digest"),
+            Self::DigestMismatch => write!(f, "archive does not match its digest"),
+        }
+    }
+}
+
+/// Verification result recorded in image metadata.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SignatureVerification {
+    #[serde(flatten)]
+    pub status: SignatureStatus,
+    pub verified_at: NaiveDateTime,
+}
+
+impl SignatureVerification {
+    pub fn new(status: SignatureStatus) -> Self {
+        Self {
+            status,
+            verified_at: Utc::now().naive_utc(),
+        }
+    }
+}
+
+/// Fingerprint identifying a public key (hex encoded sha256 of the key bytes).
+pub fn key_id(key: &VerifyingKey) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(key.as_bytes());
+    format!("{:x}", hasher.finalize())
+}
+
+/// Parses a hex encoded ed25519 secret key.
+pub fn parse_signing_key(encoded: &str) -> SigningResult<SigningKey> {
+    let bytes = hex::decode(encoded.trim()).map_err(|e| SigningError::InvalidKey(e.to_string()))?;
+    let bytes: [u8; 32] = bytes
+        .try_into()
+        .map_err(|_| SigningError::InvalidKey("expected 32 bytes".to_string()))?;
+    Ok(SigningKey::from_bytes(&bytes))
+}
+
+/// Parses a hex encoded ed25519 public key.
+pub fn parse_verifying_key(encoded: &str) -> SigningResult<VerifyingKey> {
+    let bytes = hex::decode(encoded.trim()).map_err(|e| SigningError::InvalidKey(e.to_string()))?;
+    let bytes: [u8; 32] = bytes
+        .try_into()
+        .map_err(|_| SigningError::InvalidKey("expected 32 bytes".to_string()))?;
+    VerifyingKey::from_bytes(&bytes).map_err(|e| SigningError::InvalidKey(e.to_string()))
+}
+
+/// Directory of trusted public keys, stored hex encoded as `<trust_root>/<name>.pub`.
+#[derive(Clone, Debug)]
+pub struct TrustStore {
+    root: PathBuf,
+}
+
+impl TrustStore {
+    pub fn new(paths: &HolodekkPaths) -> Self {
+        Self::from_root(paths.trust_root())
+    }
+
+    pub fn from_root<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub fn init(&self) -> io::Result<()> {
+        fs::create_dir_all(&self.root)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Adds (or replaces) a trusted key.
+    pub fn add(&self, name: &str, key: &VerifyingKey) -> SigningResult<()> {
+        if name.is_empty() || name.contains(std::path::is_separator) || name.starts_with('.') {
+            return Err(SigningError::InvalidKey(format!(
+                "invalid key name: {}",
+                name
+            )));
+        }
+        self.init()?;
+        fs::write(
+            self.root.join(format!("{}.{}", name, PUBLIC_KEY_EXTENSION)),
+            hex::encode(key.as_bytes()),
+        )?;
+        Ok(())
+    }
+
+    /// Trusted keys, by name.
+    pub fn keys(&self) -> SigningResult<Vec<(String, VerifyingKey)>> {
+        let mut keys = vec![];
+        if !self.root.exists() {
+            return Ok(keys);
+        }
+
+        for entry in fs::read_dir(&self.root)? {
+            let path = entry?.path();
+            if path.extension().and_then(|e| e.to_str()) != Some(PUBLIC_KEY_EXTENSION) {
+                continue;
+            }
+            let name = path.file_stem().unwrap().to_string_lossy().to_string();
+            keys.push((name, parse_verifying_key(&fs::read_to_string(&path)?)?));
+        }
+        Ok(keys)
+    }
+
+    /// Checks `signatures` against the trusted keys.
+    ///
+    /// `digest` must be computed from the image itself (as [`ImageVerifier`] does), rather than
+    /// taken from its metadata, for the result to say anything about the image's contents.
+    pub fn verify(
+        &self,
+        digest: Option<&Digest>,
+        signatures: &[ImageSignature],
+    ) -> SigningResult<SignatureStatus> {
+        let digest = match digest {
+            Some(digest) => digest,
+            None => return Ok(SignatureStatus::MissingDigest),
+        };
+        if signatures.is_empty() {
+            return Ok(SignatureStatus::Unsigned);
+        }
+
+        let mut status = SignatureStatus::Untrusted;
+        for (name, key) in self.keys()? {
+            let id = key_id(&key);
+            for signature in signatures.iter().filter(|s| s.key_id == id) {
+                if signature.verify(&key, digest) {
+                    return Ok(SignatureStatus::Verified { key: name });
+                }
+                status = SignatureStatus::Invalid;
+            }
+        }
+        Ok(status)
+    }
+}
+
+/// Applies a [SignaturePolicy] to images held in a store.
+pub struct ImageVerifier {
+    store: Arc<dyn SubroutineImageStore>,
+    trust: TrustStore,
+    policy: SignaturePolicy,
+}
+
+impl ImageVerifier {
+    pub fn new(
+        store: Arc<dyn SubroutineImageStore>,
+        trust: TrustStore,
+        policy: SignaturePolicy,
+    ) -> Self {
+        Self {
+            store,
+            trust,
+            policy,
+        }
+    }
+
+    pub fn policy(&self) -> SignaturePolicy {
+        self.policy
+    }
+
+    /// Checks the image's signatures, recording the result in the image metadata.
+    ///
+    /// The image's files are re-extracted from its archive first, hashing the archive as it is
+    /// unpacked: signatures only cover the recorded digest, so an archive changed after signing
+    /// is rejected, and files changed after ingestion are replaced with the signed contents.
+    ///
+    /// Returns `None` when verification is disabled, and an error if the policy requires a
+    /// trusted signature the image does not have.
+    pub fn verify(
+        &self,
+        id: &SubroutineImageId,
+    ) -> ImageVerificationResult<Option<SignatureStatus>> {
+        if self.policy == SignaturePolicy::Off {
+            return Ok(None);
+        }
+
+        let mut image = self.store.get(id)?;
+        let status = match image.digest.as_ref() {
+            Some(recorded) => {
+                if self.store.restore(id, recorded)? {
+                    self.trust.verify(Some(recorded), &image.signatures)?
+                } else {
+                    SignatureStatus::DigestMismatch
+                }
+            }
+            None => SignatureStatus::MissingDigest,
+        };
+        image.verification = Some(SignatureVerification::new(status.clone()));
+        self.store.update(&image)?;
+
+        if status.is_verified() {
+            Ok(Some(status))
+        } else if self.policy == SignaturePolicy::Require {
+            Err(ImageVerificationError::Rejected(id.to_owned(), status))
+        } else {
+            warn!(
+                "Image {} ({}) failed verification: {}",
+                image.name, id, status
+            );
+            Ok(Some(status))
+        }
+    }
+}
+
+impl std::fmt::Debug for ImageVerifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ImageVerifier")
+            .field("trust", &self.trust)
+            .field("policy", &self.policy)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use crate::images::fixtures::signing_key;
+    use crate::images::{archive_directory, sign_image, FilesystemSubroutineImageStore};
+
+    use super::*;
+
+    #[test]
+    fn verifies_signature_from_trusted_key() {
+        let temp = tempdir().unwrap();
+        let trust = TrustStore::from_root(temp.path());
+        let key = signing_key();
+        trust.add("acme", &key.verifying_key()).unwrap();
+        let digest = Digest::compute(b"hello");
+
+        let status = trust
+            .verify(Some(&digest), &[ImageSignature::sign(&key, &digest)])
+            .unwrap();
+
+        assert_eq!(
+            status,
+            SignatureStatus::Verified {
+                key: "acme".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn reports_unsigned_images() {
+        let temp = tempdir().unwrap();
+        let trust = TrustStore::from_root(temp.path());
+
+        let status = trust.verify(Some(&Digest::compute(b"hello")), &[]).unwrap();
+
+        assert_eq!(status, SignatureStatus::Unsigned);
+    }
+
+    #[test]
+    fn reports_signatures_from_unknown_keys() {
+        let temp = tempdir().unwrap();
+        let trust = TrustStore::from_root(temp.path());
+        trust.add("acme", &signing_key().verifying_key()).unwrap();
+        let digest = Digest::compute(b"hello");
+
+        let status = trust
+            .verify(
+                Some(&digest),
+                &[ImageSignature::sign(&signing_key(), &digest)],
+            )
+            .unwrap();
+
+        assert_eq!(status, SignatureStatus::Untrusted);
+    }
+
+    #[test]
+    fn reports_signatures_over_other_digests() {
+        let temp = tempdir().unwrap();
+        let trust = TrustStore::from_root(temp.path());
+        let key = signing_key();
+        trust.add("acme", &key.verifying_key()).unwrap();
+
+        let signature = ImageSignature::sign(&key, &Digest::compute(b"goodbye"));
+        let status = trust
+            .verify(Some(&Digest::compute(b"hello")), &[signature])
+            .unwrap();
+
+        assert_eq!(status, SignatureStatus::Invalid);
+    }
+
+    #[test]
+    fn round_trips_hex_encoded_keys() {
+        let key = signing_key();
+
+        let parsed = parse_signing_key(&hex::encode(key.to_bytes())).unwrap();
+        let public = parse_verifying_key(&hex::encode(key.verifying_key().as_bytes())).unwrap();
+
+        assert_eq!(parsed.verifying_key(), public);
+    }
+
+    #[test]
+    fn rejects_images_changed_after_signing() {
+        let temp = tempdir().unwrap();
+        let store = FilesystemSubroutineImageStore::from_root(temp.path().join("images"));
+        let dir = temp.path().join("src");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("holodekk.rb"), "puts 'hello'\n").unwrap();
+        let image = store
+            .create_from_directory(&"acme/widgets".into(), &dir)
+            .unwrap();
+        let trust = TrustStore::from_root(temp.path().join("trust"));
+        let key = signing_key();
+        trust.add("acme", &key.verifying_key()).unwrap();
+        sign_image(&store, &image.id, &key).unwrap();
+        let verifier = ImageVerifier::new(Arc::new(store), trust, SignaturePolicy::Require);
+        assert!(verifier.verify(&image.id).unwrap().unwrap().is_verified());
+
+        // swap the archive for one of different files
+        fs::write(dir.join("holodekk.rb"), "system 'rm -rf /'\n").unwrap();
+        let archive = temp.path().join("images").join(&image.id).join("image.tar");
+        archive_directory(&dir, fs::File::create(archive).unwrap()).unwrap();
+
+        assert!(matches!(
+            verifier.verify(&image.id).unwrap_err(),
+            ImageVerificationError::Rejected(_, SignatureStatus::DigestMismatch)
+        ));
+    }
+
+    #[test]
+    fn restores_files_changed_after_ingestion() {
+        let temp = tempdir().unwrap();
+        let store = FilesystemSubroutineImageStore::from_root(temp.path().join("images"));
+        let dir = temp.path().join("src");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("holodekk.rb"), "puts 'hello'\n").unwrap();
+        let image = store
+            .create_from_directory(&"acme/widgets".into(), &dir)
+            .unwrap();
+        let trust = TrustStore::from_root(temp.path().join("trust"));
+        let key = signing_key();
+        trust.add("acme", &key.verifying_key()).unwrap();
+        sign_image(&store, &image.id, &key).unwrap();
+
+        // tamper with the unpacked files, leaving the signed archive alone
+        fs::write(image.path.join("holodekk.rb"), "system 'rm -rf /'\n").unwrap();
+        fs::write(image.path.join("extra.rb"), "# extra\n").unwrap();
+
+        let verifier = ImageVerifier::new(Arc::new(store), trust, SignaturePolicy::Require);
+        assert!(verifier.verify(&image.id).unwrap().unwrap().is_verified());
+        assert_eq!(
+            fs::read_to_string(image.path.join("holodekk.rb")).unwrap(),
+            "puts 'hello'\n"
+        );
+        assert!(!image.path.join("extra.rb").exists());
+    }
+}
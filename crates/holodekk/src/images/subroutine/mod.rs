@@ -1,10 +1,14 @@
+mod store;
+pub use store::*;
+
 use std::path::PathBuf;
 
 use serde::{Deserialize, Serialize};
 
+use crate::entities::{ManifestFieldError, SubroutineManifest};
 use crate::enums::SubroutineKind;
 
-use super::{ImageId, ImageName};
+use super::{Digest, ImageId, ImageName, ImageSignature, SignatureVerification};
 
 pub type SubroutineImageId = ImageId;
 
@@ -14,6 +18,20 @@ pub struct SubroutineImage {
     pub name: ImageName,
     pub path: PathBuf,
     pub kind: SubroutineKind,
+    /// Digest of the archive this image was created from (if known).
+    #[serde(default)]
+    pub digest: Option<Digest>,
+    #[serde(default)]
+    pub signatures: Vec<ImageSignature>,
+    /// Result of the most recent signature check (if any).
+    #[serde(default)]
+    pub verification: Option<SignatureVerification>,
+    /// Manifest reported by the subroutine when the image was ingested.
+    #[serde(default)]
+    pub manifest: Option<SubroutineManifest>,
+    /// Problems found while extracting the manifest (one per invalid field).
+    #[serde(default)]
+    pub manifest_errors: Vec<ManifestFieldError>,
 }
 
 impl SubroutineImage {
@@ -26,6 +44,11 @@ impl SubroutineImage {
             name,
             path: path.into(),
             kind,
+            digest: None,
+            signatures: vec![],
+            verification: None,
+            manifest: None,
+            manifest_errors: vec![],
         }
     }
 }
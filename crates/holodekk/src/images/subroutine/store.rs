@@ -1,26 +1,493 @@
-use bytes::Bytes;
-use futures::Stream;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
 
+use ed25519_dalek::SigningKey;
+use log::{debug, warn};
+#[cfg(test)]
+use mockall::automock;
+
+use crate::entities::SubroutineManifest;
+use crate::enums::SubroutineKind;
 use crate::errors::error_chain_fmt;
-use crate::images::{SubroutineImage, SubroutineImageId};
+use crate::images::{
+    Digest, DigestReader, DigestWriter, ImageName, ImageSignature, SubroutineImage,
+    SubroutineImageId,
+};
+use crate::runtimes::RuntimeRegistry;
+use crate::HolodekkPaths;
 
-#[derive(thiserror::Error)]
-pub enum Error {}
+const IMAGE_METADATA: &str = "image.json";
+const IMAGE_ARCHIVE: &str = "image.tar";
+const IMAGE_FILES: &str = "files";
 
-pub type Result<T> = std::result::Result<T, Error>;
+#[derive(thiserror::Error)]
+pub enum SubroutineImageStoreError {
+    #[error("Subroutine image not found: {0}")]
+    NotFound(SubroutineImageId),
+    #[error("Subroutine image already exists: {0}")]
+    Conflict(ImageName),
+    #[error("Image store IO error")]
+    Io(#[from] std::io::Error),
+    #[error("Image metadata serialization error")]
+    Serialization(#[from] serde_json::Error),
+}
 
-impl std::fmt::Debug for Error {
+impl std::fmt::Debug for SubroutineImageStoreError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         error_chain_fmt(self, f)
     }
 }
 
-trait SubroutineImageStore {
-    fn create<S, E>(&self, name: String, stream: S) -> Result<SubroutineImage>
-    where
-        S: Stream<Item = std::result::Result<Bytes, E>>,
-        E: Into<Box<dyn std::error::Error + Send + Sync>>;
-    fn get(&self, id: SubroutineImageId) -> Result<SubroutineImage>;
-    fn find(&self) -> Result<Vec<SubroutineImage>>;
-    fn delete(&self, id: &SubroutineImageId) -> Result<()>;
+pub type SubroutineImageStoreResult<T> = std::result::Result<T, SubroutineImageStoreError>;
+
+#[cfg_attr(test, automock)]
+pub trait SubroutineImageStore: Send + Sync + 'static {
+    /// Creates a new image from a tar archive of its files.
+    fn create(
+        &self,
+        name: &ImageName,
+        archive: &mut dyn Read,
+    ) -> SubroutineImageStoreResult<SubroutineImage>;
+    fn get(&self, id: &SubroutineImageId) -> SubroutineImageStoreResult<SubroutineImage>;
+    fn find(&self) -> SubroutineImageStoreResult<Vec<SubroutineImage>>;
+    /// Replaces the stored metadata for an existing image.
+    fn update(&self, image: &SubroutineImage) -> SubroutineImageStoreResult<()>;
+    fn delete(&self, id: &SubroutineImageId) -> SubroutineImageStoreResult<()>;
+    /// Opens the archive the image was created from.
+    fn archive(&self, id: &SubroutineImageId) -> SubroutineImageStoreResult<File>;
+    /// Re-extracts the image's files from its archive, hashing the archive as it is unpacked.
+    ///
+    /// The extracted files only replace the image's current files if the archive matches
+    /// `digest`; returns whether they did.
+    fn restore(&self, id: &SubroutineImageId, digest: &Digest) -> SubroutineImageStoreResult<bool>;
+}
+
+/// Writes a tar archive of the given directory's contents to `writer`.
+pub fn archive_directory<P, W>(dir: P, writer: W) -> io::Result<W>
+where
+    P: AsRef<Path>,
+    W: Write,
+{
+    let mut builder = tar::Builder::new(writer);
+    builder.follow_symlinks(false);
+    builder.append_dir_all(".", dir)?;
+    builder.into_inner()
+}
+
+/// Image store backed by a directory under `data_root`.
+///
+/// Each image lives in a directory named for its id, containing the original archive, the
+/// unpacked files and a metadata document:
+///
+/// ```text
+/// <images_root>/<id>/image.json
+/// <images_root>/<id>/image.tar
+/// <images_root>/<id>/files/
+/// ```
+///
+/// The subroutine's manifest is read from its [`MANIFEST_FILE`](crate::entities::MANIFEST_FILE) as the image is ingested; none of
+/// the image's code is run.
+#[derive(Clone, Debug)]
+pub struct FilesystemSubroutineImageStore {
+    root: PathBuf,
+    runtimes: RuntimeRegistry,
+}
+
+impl FilesystemSubroutineImageStore {
+    pub fn new(paths: &HolodekkPaths) -> Self {
+        Self::from_root(paths.images_root())
+    }
+
+    pub fn from_root<P: Into<PathBuf>>(root: P) -> Self {
+        Self {
+            root: root.into(),
+            runtimes: RuntimeRegistry::default(),
+        }
+    }
+
+    /// Runtimes used to detect the kind of subroutine in each image.
+    pub fn with_runtimes(mut self, runtimes: RuntimeRegistry) -> Self {
+        self.runtimes = runtimes;
+        self
+    }
+
+    pub fn init(&self) -> io::Result<()> {
+        fs::create_dir_all(&self.root)
+    }
+
+    /// Convenience wrapper around [SubroutineImageStore::create] for an image whose files live
+    /// in a local directory.
+    pub fn create_from_directory<P: AsRef<Path>>(
+        &self,
+        name: &ImageName,
+        dir: P,
+    ) -> SubroutineImageStoreResult<SubroutineImage> {
+        let archive = archive_directory(dir, Vec::new())?;
+        self.create(name, &mut archive.as_slice())
+    }
+
+    fn image_root(&self, id: &SubroutineImageId) -> PathBuf {
+        self.root.join(id)
+    }
+
+    fn read_metadata(&self, root: &Path) -> SubroutineImageStoreResult<SubroutineImage> {
+        let metadata = fs::read(root.join(IMAGE_METADATA))?;
+        Ok(serde_json::from_slice(&metadata)?)
+    }
+
+    fn write_metadata(
+        &self,
+        root: &Path,
+        image: &SubroutineImage,
+    ) -> SubroutineImageStoreResult<()> {
+        fs::write(root.join(IMAGE_METADATA), serde_json::to_vec_pretty(image)?)?;
+        Ok(())
+    }
+
+    fn stage(
+        &self,
+        staging: &Path,
+        name: &ImageName,
+        archive: &mut dyn Read,
+    ) -> SubroutineImageStoreResult<SubroutineImage> {
+        fs::create_dir_all(staging)?;
+
+        // keep a copy of the original archive (so it can be shipped elsewhere unchanged)
+        let archive_path = staging.join(IMAGE_ARCHIVE);
+        let mut writer = DigestWriter::new(File::create(&archive_path)?);
+        io::copy(archive, &mut writer)?;
+        let (digest, size, file) = writer.finalize();
+        file.sync_all()?;
+        debug!(
+            "Stored image archive for {} ({}, {} bytes)",
+            name, digest, size
+        );
+
+        let files = staging.join(IMAGE_FILES);
+        fs::create_dir_all(&files)?;
+        tar::Archive::new(File::open(&archive_path)?).unpack(&files)?;
+
+        let runtime = self.runtimes.detect(&files);
+        let mut image = SubroutineImage::new(
+            name.to_owned(),
+            self.image_root(&SubroutineImageId::generate(name))
+                .join(IMAGE_FILES),
+            runtime
+                .as_ref()
+                .map(|runtime| runtime.kind())
+                .unwrap_or(SubroutineKind::Unknown),
+        );
+        image.digest = Some(digest);
+        match SubroutineManifest::load(&files) {
+            Ok(manifest) => image.manifest = manifest,
+            Err(err) => {
+                warn!("Invalid manifest for image {}: {}", name, err);
+                image.manifest_errors = err.field_errors();
+            }
+        }
+        self.write_metadata(staging, &image)?;
+        Ok(image)
+    }
+}
+
+impl SubroutineImageStore for FilesystemSubroutineImageStore {
+    fn create(
+        &self,
+        name: &ImageName,
+        archive: &mut dyn Read,
+    ) -> SubroutineImageStoreResult<SubroutineImage> {
+        let id = SubroutineImageId::generate(name);
+        let root = self.image_root(&id);
+        if root.exists() {
+            return Err(SubroutineImageStoreError::Conflict(name.to_owned()));
+        }
+
+        let staging = self.root.join(format!(".{}.partial", id));
+        if staging.exists() {
+            fs::remove_dir_all(&staging)?;
+        }
+
+        match self.stage(&staging, name, archive) {
+            Ok(image) => {
+                fs::rename(&staging, &root)?;
+                Ok(image)
+            }
+            Err(err) => {
+                if let Err(cleanup) = fs::remove_dir_all(&staging) {
+                    warn!("Failed to cleanup staged image {}: {}", id, cleanup);
+                }
+                Err(err)
+            }
+        }
+    }
+
+    fn get(&self, id: &SubroutineImageId) -> SubroutineImageStoreResult<SubroutineImage> {
+        let root = self.image_root(id);
+        if root.join(IMAGE_METADATA).exists() {
+            self.read_metadata(&root)
+        } else {
+            Err(SubroutineImageStoreError::NotFound(id.to_owned()))
+        }
+    }
+
+    fn find(&self) -> SubroutineImageStoreResult<Vec<SubroutineImage>> {
+        let mut images = vec![];
+        if !self.root.exists() {
+            return Ok(images);
+        }
+
+        for entry in fs::read_dir(&self.root)? {
+            let path = entry?.path();
+            let staged = path
+                .file_name()
+                .and_then(|n| n.to_str())
+                .map(|n| n.starts_with('.'))
+                .unwrap_or(true);
+            if !staged && path.join(IMAGE_METADATA).exists() {
+                images.push(self.read_metadata(&path)?);
+            }
+        }
+        Ok(images)
+    }
+
+    fn update(&self, image: &SubroutineImage) -> SubroutineImageStoreResult<()> {
+        let root = self.image_root(&image.id);
+        if root.join(IMAGE_METADATA).exists() {
+            self.write_metadata(&root, image)
+        } else {
+            Err(SubroutineImageStoreError::NotFound(image.id.to_owned()))
+        }
+    }
+
+    fn delete(&self, id: &SubroutineImageId) -> SubroutineImageStoreResult<()> {
+        let root = self.image_root(id);
+        if root.exists() {
+            fs::remove_dir_all(root)?;
+            Ok(())
+        } else {
+            Err(SubroutineImageStoreError::NotFound(id.to_owned()))
+        }
+    }
+
+    fn archive(&self, id: &SubroutineImageId) -> SubroutineImageStoreResult<File> {
+        let path = self.image_root(id).join(IMAGE_ARCHIVE);
+        if path.exists() {
+            Ok(File::open(path)?)
+        } else {
+            Err(SubroutineImageStoreError::NotFound(id.to_owned()))
+        }
+    }
+
+    fn restore(&self, id: &SubroutineImageId, digest: &Digest) -> SubroutineImageStoreResult<bool> {
+        let root = self.image_root(id);
+        let staging = root.join(format!(".{}.partial", IMAGE_FILES));
+        if staging.exists() {
+            fs::remove_dir_all(&staging)?;
+        }
+        fs::create_dir_all(&staging)?;
+
+        // unpack and hash in a single pass, so the files are exactly the bytes that were hashed
+        let mut reader = DigestReader::new(self.archive(id)?);
+        let unpacked = tar::Archive::new(&mut reader).unpack(&staging);
+        let computed = unpacked.and_then(|_| reader.finalize());
+        match computed {
+            Ok((computed, _)) if &computed == digest => {
+                let files = root.join(IMAGE_FILES);
+                let previous = root.join(format!(".{}.previous", IMAGE_FILES));
+                if files.exists() {
+                    fs::rename(&files, &previous)?;
+                }
+                fs::rename(&staging, &files)?;
+                if previous.exists() {
+                    fs::remove_dir_all(&previous)?;
+                }
+                Ok(true)
+            }
+            result => {
+                fs::remove_dir_all(&staging)?;
+                result
+                    .map(|_| false)
+                    .map_err(SubroutineImageStoreError::from)
+            }
+        }
+    }
+}
+
+/// Digest of an image's archive, computing it if the image predates digest tracking.
+pub fn image_digest(
+    store: &dyn SubroutineImageStore,
+    image: &SubroutineImage,
+) -> SubroutineImageStoreResult<Digest> {
+    match &image.digest {
+        Some(digest) => Ok(digest.to_owned()),
+        None => Ok(Digest::compute_reader(&mut store.archive(&image.id)?)?),
+    }
+}
+
+/// Signs an image's digest with the given key, storing the signature in its metadata.
+pub fn sign_image(
+    store: &dyn SubroutineImageStore,
+    id: &SubroutineImageId,
+    key: &SigningKey,
+) -> SubroutineImageStoreResult<SubroutineImage> {
+    let mut image = store.get(id)?;
+    let digest = image_digest(store, &image)?;
+    let signature = ImageSignature::sign(key, &digest);
+    image
+        .signatures
+        .retain(|existing| existing.key_id != signature.key_id);
+    image.signatures.push(signature);
+    image.digest = Some(digest);
+    store.update(&image)?;
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use crate::entities::MANIFEST_FILE;
+    use crate::images::fixtures::signing_key;
+
+    use super::*;
+
+    fn sample_directory(root: &Path) -> PathBuf {
+        let dir = root.join("src");
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(dir.join("holodekk.rb"), "puts 'hello'\n").unwrap();
+        fs::write(dir.join("lib").join("app.rb"), "# app\n").unwrap();
+        dir
+    }
+
+    #[test]
+    fn creates_image_from_directory() {
+        let temp = tempdir().unwrap();
+        let store = FilesystemSubroutineImageStore::from_root(temp.path().join("images"));
+        let dir = sample_directory(temp.path());
+
+        let image = store
+            .create_from_directory(&"acme/widgets".into(), &dir)
+            .unwrap();
+
+        assert_eq!(image.kind, SubroutineKind::Ruby);
+        assert!(image.digest.is_some());
+        assert_eq!(
+            fs::read_to_string(image.path.join("lib").join("app.rb")).unwrap(),
+            "# app\n"
+        );
+    }
+
+    #[test]
+    fn rejects_duplicate_image_names() {
+        let temp = tempdir().unwrap();
+        let store = FilesystemSubroutineImageStore::from_root(temp.path().join("images"));
+        let dir = sample_directory(temp.path());
+        store
+            .create_from_directory(&"acme/widgets".into(), &dir)
+            .unwrap();
+
+        let result = store.create_from_directory(&"acme/widgets".into(), &dir);
+        assert!(matches!(
+            result.unwrap_err(),
+            SubroutineImageStoreError::Conflict(..)
+        ));
+    }
+
+    #[test]
+    fn finds_and_deletes_images() {
+        let temp = tempdir().unwrap();
+        let store = FilesystemSubroutineImageStore::from_root(temp.path().join("images"));
+        let dir = sample_directory(temp.path());
+        let image = store
+            .create_from_directory(&"acme/widgets".into(), &dir)
+            .unwrap();
+
+        assert_eq!(store.find().unwrap(), vec![image.clone()]);
+        assert_eq!(store.get(&image.id).unwrap(), image);
+
+        store.delete(&image.id).unwrap();
+        assert!(store.find().unwrap().is_empty());
+        assert!(matches!(
+            store.get(&image.id).unwrap_err(),
+            SubroutineImageStoreError::NotFound(..)
+        ));
+    }
+
+    #[test]
+    fn records_subroutine_manifest() {
+        let temp = tempdir().unwrap();
+        let store = FilesystemSubroutineImageStore::from_root(temp.path().join("images"));
+        let dir = temp.path().join("src");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join(MANIFEST_FILE),
+            r#"{"name": "widgets", "environment": {"MODE": "test"}}"#,
+        )
+        .unwrap();
+
+        let image = store
+            .create_from_directory(&"acme/widgets".into(), &dir)
+            .unwrap();
+
+        let manifest = image.manifest.unwrap();
+        assert_eq!(manifest.name(), "widgets");
+        assert_eq!(manifest.environment().unwrap().get("MODE").unwrap(), "test");
+        assert!(image.manifest_errors.is_empty());
+    }
+
+    #[test]
+    fn records_manifest_errors() {
+        let temp = tempdir().unwrap();
+        let store = FilesystemSubroutineImageStore::from_root(temp.path().join("images"));
+        let dir = temp.path().join("src");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join(MANIFEST_FILE),
+            r#"{"name": "widgets", "port": "http"}"#,
+        )
+        .unwrap();
+
+        let image = store
+            .create_from_directory(&"acme/widgets".into(), &dir)
+            .unwrap();
+
+        assert!(image.manifest.is_none());
+        assert_eq!(image.manifest_errors.len(), 1);
+        assert_eq!(image.manifest_errors[0].field, "port");
+        assert_eq!(store.get(&image.id).unwrap(), image);
+    }
+
+    #[test]
+    fn archive_matches_recorded_digest() {
+        let temp = tempdir().unwrap();
+        let store = FilesystemSubroutineImageStore::from_root(temp.path().join("images"));
+        let dir = sample_directory(temp.path());
+        let image = store
+            .create_from_directory(&"acme/widgets".into(), &dir)
+            .unwrap();
+
+        let digest = Digest::compute_reader(&mut store.archive(&image.id).unwrap()).unwrap();
+        assert_eq!(Some(digest), image.digest);
+    }
+
+    #[test]
+    fn stores_signatures_in_image_metadata() {
+        let temp = tempdir().unwrap();
+        let store = FilesystemSubroutineImageStore::from_root(temp.path().join("images"));
+        let dir = sample_directory(temp.path());
+        let image = store
+            .create_from_directory(&"acme/widgets".into(), &dir)
+            .unwrap();
+        let key = signing_key();
+
+        sign_image(&store, &image.id, &key).unwrap();
+        sign_image(&store, &image.id, &key).unwrap();
+
+        let image = store.get(&image.id).unwrap();
+        assert_eq!(
+            image.signatures,
+            vec![ImageSignature::sign(&key, image.digest.as_ref().unwrap())]
+        );
+    }
 }
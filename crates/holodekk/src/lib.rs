@@ -9,6 +9,14 @@ pub struct HolodekkPaths {
     exec_root: PathBuf,
     scenes_root: PathBuf,
     subroutines_root: PathBuf,
+    images_root: PathBuf,
+    registry_root: PathBuf,
+    trust_root: PathBuf,
+    secrets_root: PathBuf,
+    ports_file: PathBuf,
+    users_file: PathBuf,
+    layers_root: PathBuf,
+    bundles_root: PathBuf,
     bin_root: PathBuf,
 }
 
@@ -21,6 +29,22 @@ impl HolodekkPaths {
         scenes_root.push("scenes");
         let mut subroutines_root = exec_root.as_ref().to_owned();
         subroutines_root.push("subroutines");
+        let mut images_root = data_root.as_ref().to_owned();
+        images_root.push("images");
+        let mut registry_root = data_root.as_ref().to_owned();
+        registry_root.push("registry");
+        let mut trust_root = data_root.as_ref().to_owned();
+        trust_root.push("trust");
+        let mut secrets_root = data_root.as_ref().to_owned();
+        secrets_root.push("secrets");
+        let mut ports_file = data_root.as_ref().to_owned();
+        ports_file.push("ports.json");
+        let mut users_file = data_root.as_ref().to_owned();
+        users_file.push("users.json");
+        let mut layers_root = data_root.as_ref().to_owned();
+        layers_root.push("layers");
+        let mut bundles_root = data_root.as_ref().to_owned();
+        bundles_root.push("bundles");
         let mut holodekk_api_socket = exec_root.as_ref().to_owned();
         holodekk_api_socket.push("holodekkd.sock");
 
@@ -29,6 +53,14 @@ impl HolodekkPaths {
             exec_root: exec_root.as_ref().to_owned(),
             scenes_root,
             subroutines_root,
+            images_root,
+            registry_root,
+            trust_root,
+            secrets_root,
+            ports_file,
+            users_file,
+            layers_root,
+            bundles_root,
             bin_root: bin_root.as_ref().into(),
         }
     }
@@ -49,6 +81,42 @@ impl HolodekkPaths {
         &self.subroutines_root
     }
 
+    pub fn images_root(&self) -> &PathBuf {
+        &self.images_root
+    }
+
+    pub fn registry_root(&self) -> &PathBuf {
+        &self.registry_root
+    }
+
+    pub fn trust_root(&self) -> &PathBuf {
+        &self.trust_root
+    }
+
+    pub fn secrets_root(&self) -> &PathBuf {
+        &self.secrets_root
+    }
+
+    /// Persisted host port assignments.
+    pub fn ports_file(&self) -> &PathBuf {
+        &self.ports_file
+    }
+
+    /// Persisted per-scene user allocations.
+    pub fn users_file(&self) -> &PathBuf {
+        &self.users_file
+    }
+
+    /// Unpacked rootfs layers, keyed by digest.
+    pub fn layers_root(&self) -> &PathBuf {
+        &self.layers_root
+    }
+
+    /// Unpacked bundles, keyed by digest.
+    pub fn bundles_root(&self) -> &PathBuf {
+        &self.bundles_root
+    }
+
     pub fn bin_root(&self) -> &PathBuf {
         &self.bin_root
     }
@@ -140,11 +208,25 @@ impl SubroutinePaths {
 }
 
 pub mod apis;
+pub mod attach;
+pub mod bundles;
+pub mod cgroups;
 pub mod entities;
 pub mod enums;
 pub mod errors;
+pub mod health;
 pub mod images;
+pub mod logs;
+pub mod ports;
+pub mod privileges;
+pub mod registry;
 pub mod repositories;
+pub mod restart;
+pub mod rlimits;
+pub mod rootfs;
+pub mod runtimes;
+pub mod secrets;
 pub mod services;
+pub mod shim;
 // pub mod stores;
 pub mod utils;
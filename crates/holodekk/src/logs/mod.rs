@@ -0,0 +1,540 @@
+//! Subroutine output logs, as written by the shim.
+//!
+//! Output is reassembled into lines per stream (so lines split across reads stay whole), and
+//! each line is written as a [`LogEntry`] in one of two formats:
+//!
+//! ```text
+//! text:  2024-01-01T00:00:00+00:00 stdout hello
+//! json:  {"timestamp":"2024-01-01T00:00:00Z","stream":"stdout","sequence":7,"message":"hello"}
+//! ```
+//!
+//! Lines which are themselves JSON objects keep their structure (as `fields`) in the JSON format.
+//! Logs are appended to, so they survive the shim being restarted, and can be rotated (see
+//! [`LogRotation`]) to keep chatty subroutines from filling the disk.
+mod rotation;
+pub use rotation::*;
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use chrono::{DateTime, Utc};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::errors::error_chain_fmt;
+
+/// Longest line kept whole; anything longer is split into several entries.
+pub const MAX_LINE_LEN: usize = 256 * 1024;
+
+/// Number of stderr lines kept for reporting why a subroutine exited.
+pub const STDERR_TAIL_LINES: usize = 20;
+
+/// How much of an existing log is searched for the last sequence number.
+const TAIL_LEN: u64 = 2 * MAX_LINE_LEN as u64;
+
+#[derive(thiserror::Error)]
+pub enum LogFormatError {
+    #[error("Invalid log format: {0} (expected text or json)")]
+    Invalid(String),
+}
+
+impl std::fmt::Debug for LogFormatError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// One line per entry: timestamp, stream and message.
+    #[default]
+    Text,
+    /// One JSON object per line (see [`LogEntry`]).
+    Json,
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogFormat::Text => write!(f, "text"),
+            LogFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+impl FromStr for LogFormat {
+    type Err = LogFormatError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(LogFormatError::Invalid(s.to_string())),
+        }
+    }
+}
+
+/// Which of the subroutine's streams a line was written to.
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Hash, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputStream {
+    Stdout,
+    Stderr,
+}
+
+impl OutputStream {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OutputStream::Stdout => "stdout",
+            OutputStream::Stderr => "stderr",
+        }
+    }
+}
+
+impl fmt::Display for OutputStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// A single line of subroutine output.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct LogEntry {
+    pub timestamp: DateTime<Utc>,
+    pub stream: OutputStream,
+    /// Position of the entry in the log (across both streams).
+    pub sequence: u64,
+    pub message: String,
+    /// The line's contents, when it's a JSON object.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fields: Option<serde_json::Map<String, serde_json::Value>>,
+}
+
+impl LogEntry {
+    pub fn new(stream: OutputStream, sequence: u64, line: &[u8]) -> Self {
+        let message = String::from_utf8_lossy(line).into_owned();
+        let fields = message
+            .trim_start()
+            .starts_with('{')
+            .then(|| serde_json::from_str(&message).ok())
+            .flatten();
+        Self {
+            timestamp: Utc::now(),
+            stream,
+            sequence,
+            message,
+            fields,
+        }
+    }
+
+    /// Parses a line of a log written in the given format.
+    ///
+    /// Text logs don't record sequence numbers (or structure), so entries read from them have
+    /// neither.
+    pub fn parse(line: &str, format: LogFormat) -> Option<Self> {
+        match format {
+            LogFormat::Json => serde_json::from_str(line).ok(),
+            LogFormat::Text => {
+                let mut parts = line.splitn(3, ' ');
+                let timestamp = DateTime::parse_from_rfc3339(parts.next()?).ok()?;
+                let stream = match parts.next()? {
+                    "stdout" => OutputStream::Stdout,
+                    "stderr" => OutputStream::Stderr,
+                    _ => return None,
+                };
+                Some(Self {
+                    timestamp: timestamp.into(),
+                    stream,
+                    sequence: 0,
+                    message: parts.next().unwrap_or_default().to_string(),
+                    fields: None,
+                })
+            }
+        }
+    }
+
+    /// Formats the entry as a line of the log (including the trailing newline).
+    pub fn format(&self, format: LogFormat) -> String {
+        match format {
+            LogFormat::Text => format!(
+                "{} {} {}\n",
+                self.timestamp.to_rfc3339(),
+                self.stream,
+                self.message
+            ),
+            LogFormat::Json => {
+                let mut line = serde_json::to_string(self).expect("log entries always serialize");
+                line.push('\n');
+                line
+            }
+        }
+    }
+}
+
+/// Reassembles a stream's output into lines.
+#[derive(Debug, Default)]
+pub struct LineBuffer {
+    pending: Vec<u8>,
+}
+
+impl LineBuffer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds output to the buffer, returning the lines it completes (without their newlines).
+    ///
+    /// Lines ending in `\r\n` (as written through a terminal) lose the `\r` too.
+    pub fn push(&mut self, buf: &[u8]) -> Vec<Vec<u8>> {
+        let mut lines = Vec::new();
+        for chunk in buf.split_inclusive(|c| *c == b'\n') {
+            match chunk.strip_suffix(b"\n") {
+                Some(rest) => {
+                    self.pending.extend_from_slice(rest);
+                    if self.pending.last() == Some(&b'\r') {
+                        self.pending.pop();
+                    }
+                    lines.push(std::mem::take(&mut self.pending));
+                }
+                None => self.pending.extend_from_slice(chunk),
+            }
+            while self.pending.len() > MAX_LINE_LEN {
+                let rest = self.pending.split_off(MAX_LINE_LEN);
+                lines.push(std::mem::replace(&mut self.pending, rest));
+            }
+        }
+        lines
+    }
+
+    /// Takes whatever's left of an unterminated line.
+    pub fn flush(&mut self) -> Option<Vec<u8>> {
+        (!self.pending.is_empty()).then(|| std::mem::take(&mut self.pending))
+    }
+}
+
+/// Writes a subroutine's output to its log file.
+#[derive(Debug)]
+pub struct LogWriter {
+    path: PathBuf,
+    file: File,
+    format: LogFormat,
+    rotation: LogRotation,
+    /// Size of the current file.
+    size: u64,
+    /// When the current file was started.
+    started: SystemTime,
+    next_sequence: u64,
+    stdout: LineBuffer,
+    stderr: LineBuffer,
+    /// The last [`STDERR_TAIL_LINES`] lines written to stderr.
+    stderr_tail: VecDeque<String>,
+}
+
+impl LogWriter {
+    /// Opens the log for appending, carrying on from the last entry already in it.
+    pub fn open<P: AsRef<Path>>(path: P, format: LogFormat) -> io::Result<Self> {
+        let path = path.as_ref().to_owned();
+        let mut file = open_log(&path)?;
+        let next_sequence = last_sequence(&mut file)?.map_or(0, |sequence| sequence + 1);
+        let metadata = file.metadata()?;
+        Ok(Self {
+            path,
+            file,
+            format,
+            rotation: LogRotation::default(),
+            size: metadata.len(),
+            started: metadata.created().unwrap_or_else(|_| SystemTime::now()),
+            next_sequence,
+            stdout: LineBuffer::new(),
+            stderr: LineBuffer::new(),
+            stderr_tail: VecDeque::with_capacity(STDERR_TAIL_LINES),
+        })
+    }
+
+    /// Rotates the log as it grows (or ages).
+    pub fn with_rotation(mut self, rotation: LogRotation) -> Self {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn format(&self) -> LogFormat {
+        self.format
+    }
+
+    /// Logs output read from one of the subroutine's streams.
+    ///
+    /// Complete lines are written straight away; the remainder waits for the rest of its line.
+    pub fn write(&mut self, stream: OutputStream, buf: &[u8]) -> io::Result<()> {
+        let lines = self.buffer(stream).push(buf);
+        for line in lines {
+            self.write_line(stream, &line)?;
+        }
+        Ok(())
+    }
+
+    /// Writes out any unterminated lines (once the subroutine has finished).
+    pub fn flush(&mut self) -> io::Result<()> {
+        for stream in [OutputStream::Stdout, OutputStream::Stderr] {
+            if let Some(line) = self.buffer(stream).flush() {
+                self.write_line(stream, &line)?;
+            }
+        }
+        self.file.flush()
+    }
+
+    /// The last lines the subroutine wrote to stderr (oldest first).
+    pub fn stderr_tail(&self) -> Vec<String> {
+        self.stderr_tail.iter().cloned().collect()
+    }
+
+    fn buffer(&mut self, stream: OutputStream) -> &mut LineBuffer {
+        match stream {
+            OutputStream::Stdout => &mut self.stdout,
+            OutputStream::Stderr => &mut self.stderr,
+        }
+    }
+
+    fn write_line(&mut self, stream: OutputStream, line: &[u8]) -> io::Result<()> {
+        let age = self.started.elapsed().unwrap_or_default();
+        if self.rotation.due(self.size, age) {
+            // a failed rotation shouldn't stop the logging
+            if let Err(err) = self.rotate() {
+                warn!("Failed to rotate {}: {}", self.path.display(), err);
+            }
+            self.size = 0;
+            self.started = SystemTime::now();
+        }
+
+        if stream == OutputStream::Stderr {
+            if self.stderr_tail.len() == STDERR_TAIL_LINES {
+                self.stderr_tail.pop_front();
+            }
+            self.stderr_tail
+                .push_back(String::from_utf8_lossy(line).into_owned());
+        }
+
+        let entry = LogEntry::new(stream, self.next_sequence, line);
+        self.next_sequence += 1;
+        let formatted = entry.format(self.format);
+        self.file.write_all(formatted.as_bytes())?;
+        self.size += formatted.len() as u64;
+        Ok(())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        self.rotation.rotate(&self.path)?;
+        self.file = open_log(&self.path)?;
+        self.rotation.prune(&self.path)
+    }
+}
+
+fn open_log(path: &Path) -> io::Result<File> {
+    OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(path)
+}
+
+/// Finds the sequence number of the last JSON entry in the log (if it has one).
+fn last_sequence(file: &mut File) -> io::Result<Option<u64>> {
+    let len = file.seek(SeekFrom::End(0))?;
+    file.seek(SeekFrom::Start(len.saturating_sub(TAIL_LEN)))?;
+    let mut last = None;
+    for line in BufReader::new(&mut *file).split(b'\n') {
+        if let Ok(entry) = serde_json::from_slice::<LogEntry>(&line?) {
+            last = Some(entry.sequence);
+        }
+    }
+    Ok(last)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use rstest::*;
+    use tempfile::{tempdir, TempDir};
+
+    use super::*;
+
+    #[fixture]
+    fn temp() -> TempDir {
+        tempdir().unwrap()
+    }
+
+    fn entries(path: &Path) -> Vec<LogEntry> {
+        fs::read_to_string(path)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn reassembles_split_lines() {
+        let mut buffer = LineBuffer::new();
+
+        assert!(buffer.push(b"hel").is_empty());
+        assert_eq!(buffer.push(b"lo\n\nwor"), vec![b"hello".to_vec(), vec![]]);
+        assert_eq!(buffer.push(b"ld\n"), vec![b"world".to_vec()]);
+        assert!(buffer.flush().is_none());
+    }
+
+    #[test]
+    fn strips_terminal_line_endings() {
+        let mut buffer = LineBuffer::new();
+
+        assert_eq!(buffer.push(b"hello\r\nwor"), vec![b"hello".to_vec()]);
+        assert_eq!(buffer.push(b"ld\r"), Vec::<Vec<u8>>::new());
+        assert_eq!(buffer.push(b"\n"), vec![b"world".to_vec()]);
+    }
+
+    #[test]
+    fn splits_overlong_lines() {
+        let mut buffer = LineBuffer::new();
+
+        let lines = buffer.push(&vec![b'x'; MAX_LINE_LEN + 10]);
+
+        assert_eq!(lines.len(), 1);
+        assert_eq!(lines[0].len(), MAX_LINE_LEN);
+        assert_eq!(buffer.flush().unwrap().len(), 10);
+    }
+
+    #[rstest]
+    #[case("text", LogFormat::Text)]
+    #[case("json", LogFormat::Json)]
+    fn parses_formats(#[case] format: &str, #[case] expected: LogFormat) {
+        assert_eq!(format.parse::<LogFormat>().unwrap(), expected);
+        assert_eq!(expected.to_string(), format);
+    }
+
+    #[test]
+    fn keeps_json_output_structured() {
+        let entry = LogEntry::new(OutputStream::Stdout, 0, br#"{"level":"info","user":7}"#);
+
+        let fields = entry.fields.unwrap();
+        assert_eq!(fields["level"], "info");
+        assert_eq!(fields["user"], 7);
+        assert!(LogEntry::new(OutputStream::Stdout, 0, b"{ not json")
+            .fields
+            .is_none());
+    }
+
+    #[rstest]
+    #[case(LogFormat::Text)]
+    #[case(LogFormat::Json)]
+    fn parses_formatted_entries(#[case] format: LogFormat) {
+        let entry = LogEntry::new(OutputStream::Stderr, 0, b"something broke");
+
+        let parsed = LogEntry::parse(entry.format(format).trim_end(), format).unwrap();
+
+        assert_eq!(parsed.timestamp, entry.timestamp);
+        assert_eq!(parsed.stream, OutputStream::Stderr);
+        assert_eq!(parsed.message, "something broke");
+        assert!(LogEntry::parse("garbage", format).is_none());
+    }
+
+    #[rstest]
+    fn writes_entries_per_stream(temp: TempDir) {
+        let path = temp.path().join("subroutine.log");
+        let mut log = LogWriter::open(&path, LogFormat::Json).unwrap();
+
+        log.write(OutputStream::Stdout, b"partial ").unwrap();
+        log.write(OutputStream::Stderr, b"oops\n").unwrap();
+        log.write(OutputStream::Stdout, b"line\n\nunterminated")
+            .unwrap();
+        log.flush().unwrap();
+
+        let entries = entries(&path);
+        let lines: Vec<_> = entries
+            .iter()
+            .map(|entry| (entry.sequence, entry.stream, entry.message.as_str()))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                (0, OutputStream::Stderr, "oops"),
+                (1, OutputStream::Stdout, "partial line"),
+                (2, OutputStream::Stdout, ""),
+                (3, OutputStream::Stdout, "unterminated"),
+            ]
+        );
+    }
+
+    #[rstest]
+    fn keeps_the_tail_of_stderr(temp: TempDir) {
+        let path = temp.path().join("subroutine.log");
+        let mut log = LogWriter::open(&path, LogFormat::Text).unwrap();
+
+        for n in 0..STDERR_TAIL_LINES + 2 {
+            log.write(OutputStream::Stderr, format!("error {}\n", n).as_bytes())
+                .unwrap();
+        }
+        log.write(OutputStream::Stdout, b"not an error\n").unwrap();
+        log.write(OutputStream::Stderr, b"last words").unwrap();
+        log.flush().unwrap();
+
+        let tail = log.stderr_tail();
+        assert_eq!(tail.len(), STDERR_TAIL_LINES);
+        assert_eq!(tail[0], "error 3");
+        assert_eq!(tail.last().unwrap(), "last words");
+    }
+
+    #[rstest]
+    fn appends_to_existing_logs(temp: TempDir) {
+        let path = temp.path().join("subroutine.log");
+        let mut log = LogWriter::open(&path, LogFormat::Json).unwrap();
+        log.write(OutputStream::Stdout, b"first\nsecond\n").unwrap();
+        drop(log);
+
+        let mut log = LogWriter::open(&path, LogFormat::Json).unwrap();
+        log.write(OutputStream::Stdout, b"third\n").unwrap();
+
+        let sequences: Vec<_> = entries(&path).iter().map(|entry| entry.sequence).collect();
+        assert_eq!(sequences, vec![0, 1, 2]);
+    }
+
+    #[rstest]
+    fn rotates_and_continues_the_sequence(temp: TempDir) {
+        let path = temp.path().join("subroutine.log");
+        let mut log = LogWriter::open(&path, LogFormat::Json)
+            .unwrap()
+            .with_rotation(LogRotation::default().with_max_size(1).with_max_files(1));
+
+        log.write(OutputStream::Stdout, b"first\nsecond\nthird\n")
+            .unwrap();
+
+        let logs = list_logs(&path).unwrap();
+        assert_eq!(logs.len(), 2);
+        let current = entries(&path);
+        assert_eq!(current.len(), 1);
+        assert_eq!(current[0].sequence, 2);
+        assert_eq!(
+            entries(&path.with_file_name(&logs[1].name))[0].message,
+            "second"
+        );
+    }
+
+    #[rstest]
+    fn writes_text_logs(temp: TempDir) {
+        let path = temp.path().join("subroutine.log");
+        let mut log = LogWriter::open(&path, LogFormat::Text).unwrap();
+
+        log.write(OutputStream::Stderr, b"hello\n").unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        assert!(contents.ends_with(" stderr hello\n"));
+    }
+}
@@ -0,0 +1,322 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use chrono::{DateTime, Utc};
+use flate2::{write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+
+use crate::errors::error_chain_fmt;
+
+const COMPRESSED_SUFFIX: &str = ".gz";
+
+#[derive(thiserror::Error)]
+pub enum LogRotationError {
+    #[error("Invalid log rotation: {0}")]
+    Invalid(&'static str),
+}
+
+impl std::fmt::Debug for LogRotationError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// When a subroutine's log is rotated, and how much of the rotated output is kept.
+///
+/// Rotated logs are renamed with the time they were rotated (`subroutine.log.<timestamp>`, plus
+/// `.gz` when compressed), and the oldest are removed once either retention limit is exceeded.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+pub struct LogRotation {
+    /// Rotate once the log reaches this many bytes.
+    #[serde(default)]
+    pub max_size: Option<u64>,
+    /// Rotate once the log has been written to for this many seconds.
+    #[serde(default)]
+    pub max_age: Option<u64>,
+    /// Gzip logs as they're rotated.
+    #[serde(default)]
+    pub compress: bool,
+    /// Number of rotated logs kept.
+    #[serde(default)]
+    pub max_files: Option<usize>,
+    /// Total size of the rotated logs kept.
+    #[serde(default)]
+    pub max_total_bytes: Option<u64>,
+}
+
+impl LogRotation {
+    pub fn with_max_size(mut self, bytes: u64) -> Self {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    pub fn with_max_age(mut self, seconds: u64) -> Self {
+        self.max_age = Some(seconds);
+        self
+    }
+
+    pub fn with_compression(mut self) -> Self {
+        self.compress = true;
+        self
+    }
+
+    pub fn with_max_files(mut self, files: usize) -> Self {
+        self.max_files = Some(files);
+        self
+    }
+
+    pub fn with_max_total_bytes(mut self, bytes: u64) -> Self {
+        self.max_total_bytes = Some(bytes);
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), LogRotationError> {
+        if self.max_size == Some(0) {
+            return Err(LogRotationError::Invalid("max_size must be greater than 0"));
+        }
+        if self.max_age == Some(0) {
+            return Err(LogRotationError::Invalid("max_age must be greater than 0"));
+        }
+        if self.max_files == Some(0) {
+            return Err(LogRotationError::Invalid(
+                "max_files must be greater than 0",
+            ));
+        }
+        if self.max_total_bytes == Some(0) {
+            return Err(LogRotationError::Invalid(
+                "max_total_bytes must be greater than 0",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Whether a log of `size` bytes, started `age` ago, is due to be rotated.
+    pub fn due(&self, size: u64, age: Duration) -> bool {
+        size > 0
+            && (self.max_size.is_some_and(|max_size| size >= max_size)
+                || self
+                    .max_age
+                    .is_some_and(|max_age| age >= Duration::from_secs(max_age)))
+    }
+
+    /// Moves the log at `path` aside (compressing it if configured), returning its new path.
+    ///
+    /// The caller is expected to start a new log at `path`.
+    pub fn rotate(&self, path: &Path) -> io::Result<PathBuf> {
+        let mut rotated = path.as_os_str().to_owned();
+        rotated.push(format!(".{}", Utc::now().format("%Y%m%dT%H%M%S%.6fZ")));
+        let rotated = PathBuf::from(rotated);
+        fs::rename(path, &rotated)?;
+        if !self.compress {
+            return Ok(rotated);
+        }
+
+        let mut compressed = rotated.as_os_str().to_owned();
+        compressed.push(COMPRESSED_SUFFIX);
+        let compressed = PathBuf::from(compressed);
+        let mut encoder = GzEncoder::new(File::create(&compressed)?, Compression::default());
+        io::copy(&mut File::open(&rotated)?, &mut encoder)?;
+        encoder.finish()?.sync_all()?;
+        fs::remove_file(&rotated)?;
+        Ok(compressed)
+    }
+
+    /// Removes the oldest of the log's rotated files until both retention limits are met.
+    pub fn prune(&self, path: &Path) -> io::Result<()> {
+        let mut rotated: Vec<_> = list_logs(path)?
+            .into_iter()
+            .filter(|log| log.rotated)
+            .collect();
+        // oldest first
+        rotated.reverse();
+
+        let mut total: u64 = rotated.iter().map(|log| log.size).sum();
+        let mut count = rotated.len();
+        for log in rotated {
+            let over_count = self.max_files.is_some_and(|max_files| count > max_files);
+            let over_size = self.max_total_bytes.is_some_and(|max| total > max);
+            if !over_count && !over_size {
+                break;
+            }
+            fs::remove_file(path.with_file_name(&log.name))?;
+            total -= log.size;
+            count -= 1;
+        }
+        Ok(())
+    }
+}
+
+/// A subroutine log file (either the current log, or one rotated out of the way).
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct LogFile {
+    pub name: String,
+    pub size: u64,
+    pub modified: DateTime<Utc>,
+    pub rotated: bool,
+    pub compressed: bool,
+}
+
+/// Lists the log at `path` and its rotated files, newest first.
+pub fn list_logs(path: &Path) -> io::Result<Vec<LogFile>> {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name().and_then(|n| n.to_str())) else {
+        return Ok(Vec::new());
+    };
+    let prefix = format!("{}.", name);
+
+    let mut logs = Vec::new();
+    let mut current = None;
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(logs),
+        Err(err) => return Err(err),
+    };
+    for entry in entries {
+        let entry = entry?;
+        let Ok(file_name) = entry.file_name().into_string() else {
+            continue;
+        };
+        let rotated = file_name.starts_with(&prefix);
+        if !rotated && file_name != name {
+            continue;
+        }
+        let metadata = entry.metadata()?;
+        if !metadata.is_file() {
+            continue;
+        }
+        let log = LogFile {
+            compressed: rotated && file_name.ends_with(COMPRESSED_SUFFIX),
+            name: file_name,
+            size: metadata.len(),
+            modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH).into(),
+            rotated,
+        };
+        if rotated {
+            logs.push(log);
+        } else {
+            current = Some(log);
+        }
+    }
+    // rotated names sort by the time they were rotated
+    logs.sort_by(|a, b| b.name.cmp(&a.name));
+    logs.splice(0..0, current);
+    Ok(logs)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::GzDecoder;
+    use rstest::*;
+    use tempfile::{tempdir, TempDir};
+
+    use super::*;
+
+    #[fixture]
+    fn temp() -> TempDir {
+        tempdir().unwrap()
+    }
+
+    #[rstest]
+    #[case(LogRotation::default().with_max_size(0))]
+    #[case(LogRotation::default().with_max_age(0))]
+    #[case(LogRotation::default().with_max_files(0))]
+    #[case(LogRotation::default().with_max_total_bytes(0))]
+    fn rejects_zero_limits(#[case] rotation: LogRotation) {
+        assert!(matches!(
+            rotation.validate().unwrap_err(),
+            LogRotationError::Invalid(..)
+        ));
+    }
+
+    #[test]
+    fn rotates_by_size_and_age() {
+        let rotation = LogRotation::default().with_max_size(1024).with_max_age(60);
+
+        assert!(!rotation.due(0, Duration::from_secs(3600)));
+        assert!(!rotation.due(512, Duration::from_secs(30)));
+        assert!(rotation.due(1024, Duration::from_secs(30)));
+        assert!(rotation.due(512, Duration::from_secs(60)));
+        assert!(!LogRotation::default().due(1 << 30, Duration::MAX));
+    }
+
+    #[rstest]
+    fn compresses_rotated_logs(temp: TempDir) {
+        let path = temp.path().join("subroutine.log");
+        fs::write(&path, "hello\n").unwrap();
+
+        let rotated = LogRotation::default()
+            .with_compression()
+            .rotate(&path)
+            .unwrap();
+
+        assert!(!path.exists());
+        let mut contents = String::new();
+        GzDecoder::new(File::open(&rotated).unwrap())
+            .read_to_string(&mut contents)
+            .unwrap();
+        assert_eq!(contents, "hello\n");
+
+        let logs = list_logs(&path).unwrap();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].rotated && logs[0].compressed);
+    }
+
+    #[rstest]
+    fn lists_logs_newest_first(temp: TempDir) {
+        let path = temp.path().join("subroutine.log");
+        fs::write(
+            path.with_file_name("subroutine.log.20240101T000000.000000Z"),
+            "a",
+        )
+        .unwrap();
+        fs::write(
+            path.with_file_name("subroutine.log.20240102T000000.000000Z"),
+            "b",
+        )
+        .unwrap();
+        fs::write(&path, "current").unwrap();
+        fs::write(temp.path().join("subroutine.pid"), "1").unwrap();
+
+        let names: Vec<_> = list_logs(&path)
+            .unwrap()
+            .into_iter()
+            .map(|log| log.name)
+            .collect();
+
+        assert_eq!(
+            names,
+            vec![
+                "subroutine.log",
+                "subroutine.log.20240102T000000.000000Z",
+                "subroutine.log.20240101T000000.000000Z",
+            ]
+        );
+    }
+
+    #[rstest]
+    #[case(LogRotation::default().with_max_files(2), 2)]
+    #[case(LogRotation::default().with_max_total_bytes(25), 2)]
+    #[case(LogRotation::default().with_max_files(2).with_max_total_bytes(15), 1)]
+    fn prunes_oldest_logs(temp: TempDir, #[case] rotation: LogRotation, #[case] kept: usize) {
+        let path = temp.path().join("subroutine.log");
+        for day in 1..=3 {
+            fs::write(
+                path.with_file_name(format!("subroutine.log.2024010{}T000000.000000Z", day)),
+                "0123456789",
+            )
+            .unwrap();
+        }
+        fs::write(&path, "current").unwrap();
+
+        rotation.prune(&path).unwrap();
+
+        let logs = list_logs(&path).unwrap();
+        assert!(!logs[0].rotated);
+        let rotated: Vec<_> = logs[1..].iter().map(|log| log.name.as_str()).collect();
+        assert_eq!(rotated.len(), kept);
+        assert_eq!(rotated[0], "subroutine.log.20240103T000000.000000Z");
+    }
+}
@@ -0,0 +1,411 @@
+//! Host port allocation for subroutines, and the discovery variables derived from it.
+//!
+//! Subroutines run directly on the host, so any that listen for traffic are handed a free port
+//! from a configured range.  Assignments are grouped by scene (so each scene can discover its
+//! own endpoints), but ports are never shared between scenes.
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, TcpListener};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::entities::{SceneEntityId, SubroutineEntityId};
+use crate::errors::error_chain_fmt;
+use crate::HolodekkPaths;
+
+/// Address subroutines are reachable on (they all share the host's loopback).
+pub const ENDPOINT_HOST: Ipv4Addr = Ipv4Addr::LOCALHOST;
+
+#[derive(thiserror::Error)]
+pub enum PortAllocatorError {
+    #[error("No free ports remaining in range {0}")]
+    Exhausted(PortRange),
+    #[error("Invalid port range: {0}")]
+    InvalidRange(String),
+    #[error("Port allocator IO error")]
+    Io(#[from] std::io::Error),
+    #[error("Port assignment serialization error")]
+    Serialization(#[from] serde_json::Error),
+}
+
+impl std::fmt::Debug for PortAllocatorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+pub type PortAllocatorResult<T> = std::result::Result<T, PortAllocatorError>;
+
+/// Inclusive range of host ports (written as `start-end`).
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct PortRange {
+    start: u16,
+    end: u16,
+}
+
+impl PortRange {
+    pub fn new(start: u16, end: u16) -> PortAllocatorResult<Self> {
+        if start == 0 || start > end {
+            Err(PortAllocatorError::InvalidRange(format!(
+                "{}-{}",
+                start, end
+            )))
+        } else {
+            Ok(Self { start, end })
+        }
+    }
+
+    pub fn start(&self) -> u16 {
+        self.start
+    }
+
+    pub fn end(&self) -> u16 {
+        self.end
+    }
+
+    pub fn contains(&self, port: u16) -> bool {
+        (self.start..=self.end).contains(&port)
+    }
+}
+
+impl Default for PortRange {
+    fn default() -> Self {
+        Self {
+            start: 20000,
+            end: 29999,
+        }
+    }
+}
+
+impl fmt::Display for PortRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+impl FromStr for PortRange {
+    type Err = PortAllocatorError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || PortAllocatorError::InvalidRange(s.to_string());
+        let (start, end) = s.split_once('-').ok_or_else(invalid)?;
+        let start = start.trim().parse().map_err(|_| invalid())?;
+        let end = end.trim().parse().map_err(|_| invalid())?;
+        Self::new(start, end)
+    }
+}
+
+/// Host port assigned to a subroutine.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct PortAssignment {
+    pub scene_entity_id: SceneEntityId,
+    pub subroutine_entity_id: SubroutineEntityId,
+    /// Name the subroutine is discovered by.
+    pub name: String,
+    pub port: u16,
+}
+
+impl PortAssignment {
+    pub fn address(&self) -> SocketAddr {
+        SocketAddr::V4(SocketAddrV4::new(ENDPOINT_HOST, self.port))
+    }
+
+    /// Name of the variable other subroutines find this one with (e.g. `HOLODEKK_API_ADDR`).
+    pub fn discovery_variable(&self) -> String {
+        let name: String = self
+            .name
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() {
+                    c.to_ascii_uppercase()
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        format!("HOLODEKK_{}_ADDR", name)
+    }
+}
+
+/// Allocates host ports to subroutines, persisting assignments across restarts.
+#[derive(Debug)]
+pub struct PortAllocator {
+    range: PortRange,
+    path: PathBuf,
+    assignments: Mutex<Vec<PortAssignment>>,
+}
+
+impl PortAllocator {
+    /// Loads the allocator's state from `<data_root>/ports.json`.
+    pub fn new(paths: &HolodekkPaths, range: PortRange) -> PortAllocatorResult<Self> {
+        Self::from_file(paths.ports_file(), range)
+    }
+
+    pub fn from_file<P: Into<PathBuf>>(path: P, range: PortRange) -> PortAllocatorResult<Self> {
+        let path = path.into();
+        let assignments = match fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => vec![],
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Self {
+            range,
+            path,
+            assignments: Mutex::new(assignments),
+        })
+    }
+
+    pub fn range(&self) -> PortRange {
+        self.range
+    }
+
+    fn persist(&self, assignments: &[PortAssignment]) -> PortAllocatorResult<()> {
+        let partial = self.path.with_extension("partial");
+        fs::write(&partial, serde_json::to_vec_pretty(assignments)?)?;
+        fs::rename(partial, &self.path)?;
+        Ok(())
+    }
+
+    /// Assigns a free port to the subroutine (or returns the one it already has).
+    ///
+    /// The `preferred` port is used when it is within range and available.
+    pub fn allocate(
+        &self,
+        scene_entity_id: &SceneEntityId,
+        subroutine_entity_id: &SubroutineEntityId,
+        name: &str,
+        preferred: Option<u16>,
+    ) -> PortAllocatorResult<PortAssignment> {
+        let mut assignments = self.assignments.lock().unwrap();
+        if let Some(existing) = assignments
+            .iter()
+            .find(|a| &a.subroutine_entity_id == subroutine_entity_id)
+        {
+            return Ok(existing.to_owned());
+        }
+
+        let available =
+            |port: u16| !assignments.iter().any(|a| a.port == port) && port_is_free(port);
+        let port = preferred
+            .filter(|port| self.range.contains(*port))
+            .filter(|port| available(*port))
+            .or_else(|| (self.range.start..=self.range.end).find(|port| available(*port)))
+            .ok_or(PortAllocatorError::Exhausted(self.range))?;
+
+        let assignment = PortAssignment {
+            scene_entity_id: scene_entity_id.to_owned(),
+            subroutine_entity_id: subroutine_entity_id.to_owned(),
+            name: name.to_string(),
+            port,
+        };
+        assignments.push(assignment.clone());
+        self.persist(&assignments)?;
+        Ok(assignment)
+    }
+
+    /// Returns the subroutine's port to the pool.
+    pub fn release(
+        &self,
+        subroutine_entity_id: &SubroutineEntityId,
+    ) -> PortAllocatorResult<Option<PortAssignment>> {
+        let mut assignments = self.assignments.lock().unwrap();
+        match assignments
+            .iter()
+            .position(|a| &a.subroutine_entity_id == subroutine_entity_id)
+        {
+            Some(index) => {
+                let assignment = assignments.remove(index);
+                self.persist(&assignments)?;
+                Ok(Some(assignment))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Ports assigned within the scene.
+    pub fn endpoints(&self, scene_entity_id: &SceneEntityId) -> Vec<PortAssignment> {
+        self.assignments
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|a| &a.scene_entity_id == scene_entity_id)
+            .cloned()
+            .collect()
+    }
+
+    /// Discovery variables (`HOLODEKK_<NAME>_ADDR`) for the scene's endpoints, which subroutines
+    /// are launched with.
+    pub fn discovery_environment(
+        &self,
+        scene_entity_id: &SceneEntityId,
+    ) -> HashMap<String, String> {
+        self.endpoints(scene_entity_id)
+            .iter()
+            .map(|a| (a.discovery_variable(), a.address().to_string()))
+            .collect()
+    }
+}
+
+fn port_is_free(port: u16) -> bool {
+    TcpListener::bind((Ipv4Addr::UNSPECIFIED, port)).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::{tempdir, TempDir};
+
+    use super::*;
+
+    fn allocator(range: PortRange) -> (TempDir, PortAllocator) {
+        let temp = tempdir().unwrap();
+        let allocator = PortAllocator::from_file(temp.path().join("ports.json"), range).unwrap();
+        (temp, allocator)
+    }
+
+    fn free_range(len: u16) -> PortRange {
+        // find a run of ports nothing is listening on
+        (30000..60000)
+            .step_by(len as usize)
+            .map(|start| PortRange::new(start, start + len - 1).unwrap())
+            .find(|range| (range.start()..=range.end()).all(port_is_free))
+            .unwrap()
+    }
+
+    #[test]
+    fn parses_port_ranges() {
+        assert_eq!(
+            "8000-8100".parse::<PortRange>().unwrap(),
+            PortRange::new(8000, 8100).unwrap()
+        );
+        assert!("8100-8000".parse::<PortRange>().is_err());
+        assert!("8000".parse::<PortRange>().is_err());
+        assert!("0-10".parse::<PortRange>().is_err());
+    }
+
+    #[test]
+    fn allocates_distinct_ports_across_scenes() {
+        let range = free_range(4);
+        let (_temp, allocator) = allocator(range);
+        let (scene1, scene2) = (SceneEntityId::generate(), SceneEntityId::generate());
+
+        let first = allocator
+            .allocate(&scene1, &SubroutineEntityId::generate(), "api", None)
+            .unwrap();
+        let second = allocator
+            .allocate(&scene2, &SubroutineEntityId::generate(), "api", None)
+            .unwrap();
+
+        assert_ne!(first.port, second.port);
+        assert!(range.contains(first.port) && range.contains(second.port));
+        assert_eq!(allocator.endpoints(&scene1), vec![first]);
+    }
+
+    #[test]
+    fn allocation_is_idempotent() {
+        let (_temp, allocator) = allocator(free_range(4));
+        let scene = SceneEntityId::generate();
+        let subroutine = SubroutineEntityId::generate();
+
+        let first = allocator
+            .allocate(&scene, &subroutine, "api", None)
+            .unwrap();
+        let second = allocator
+            .allocate(&scene, &subroutine, "api", None)
+            .unwrap();
+
+        assert_eq!(first, second);
+    }
+
+    #[test]
+    fn uses_preferred_port_when_available() {
+        let range = free_range(4);
+        let (_temp, allocator) = allocator(range);
+
+        let assignment = allocator
+            .allocate(
+                &SceneEntityId::generate(),
+                &SubroutineEntityId::generate(),
+                "api",
+                Some(range.end()),
+            )
+            .unwrap();
+
+        assert_eq!(assignment.port, range.end());
+    }
+
+    #[test]
+    fn skips_ports_in_use() {
+        let range = free_range(2);
+        let (_temp, allocator) = allocator(range);
+        let _listener = TcpListener::bind((Ipv4Addr::UNSPECIFIED, range.start())).unwrap();
+
+        let assignment = allocator
+            .allocate(
+                &SceneEntityId::generate(),
+                &SubroutineEntityId::generate(),
+                "api",
+                None,
+            )
+            .unwrap();
+
+        assert_eq!(assignment.port, range.end());
+    }
+
+    #[test]
+    fn reports_exhausted_range() {
+        let (_temp, allocator) = allocator(free_range(1));
+        let scene = SceneEntityId::generate();
+        allocator
+            .allocate(&scene, &SubroutineEntityId::generate(), "one", None)
+            .unwrap();
+
+        assert!(matches!(
+            allocator
+                .allocate(&scene, &SubroutineEntityId::generate(), "two", None)
+                .unwrap_err(),
+            PortAllocatorError::Exhausted(..)
+        ));
+    }
+
+    #[test]
+    fn persists_assignments() {
+        let range = free_range(4);
+        let (temp, allocator) = allocator(range);
+        let scene = SceneEntityId::generate();
+        let subroutine = SubroutineEntityId::generate();
+        let assignment = allocator
+            .allocate(&scene, &subroutine, "api", None)
+            .unwrap();
+
+        let reloaded = PortAllocator::from_file(temp.path().join("ports.json"), range).unwrap();
+        assert_eq!(reloaded.endpoints(&scene), vec![assignment]);
+
+        reloaded.release(&subroutine).unwrap();
+        let reloaded = PortAllocator::from_file(temp.path().join("ports.json"), range).unwrap();
+        assert!(reloaded.endpoints(&scene).is_empty());
+    }
+
+    #[test]
+    fn builds_discovery_environment() {
+        let (_temp, allocator) = allocator(free_range(4));
+        let scene = SceneEntityId::generate();
+        let assignment = allocator
+            .allocate(&scene, &SubroutineEntityId::generate(), "user-api", None)
+            .unwrap();
+
+        let environment = allocator.discovery_environment(&scene);
+
+        assert_eq!(
+            environment["HOLODEKK_USER_API_ADDR"],
+            format!("127.0.0.1:{}", assignment.port)
+        );
+        assert!(allocator
+            .discovery_environment(&SceneEntityId::generate())
+            .is_empty());
+    }
+}
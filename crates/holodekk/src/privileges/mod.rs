@@ -0,0 +1,346 @@
+//! Privileges subroutines run with: their user and groups, capabilities and umask.
+//!
+//! Settings are applied by the shim, in the forked child, just before the subroutine is
+//! executed.  By default each scene gets its own user (see [`SceneUserAllocator`]), so
+//! subroutines in different scenes can't signal (or ptrace) each other.
+mod users;
+pub use users::*;
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::error_chain_fmt;
+use crate::utils::libsee;
+
+/// Capabilities, indexed by number (as defined in `linux/capability.h`).
+const CAPABILITIES: &[&str] = &[
+    "chown",
+    "dac_override",
+    "dac_read_search",
+    "fowner",
+    "fsetid",
+    "kill",
+    "setgid",
+    "setuid",
+    "setpcap",
+    "linux_immutable",
+    "net_bind_service",
+    "net_broadcast",
+    "net_admin",
+    "net_raw",
+    "ipc_lock",
+    "ipc_owner",
+    "sys_module",
+    "sys_rawio",
+    "sys_chroot",
+    "sys_ptrace",
+    "sys_pacct",
+    "sys_admin",
+    "sys_boot",
+    "sys_nice",
+    "sys_resource",
+    "sys_time",
+    "sys_tty_config",
+    "mknod",
+    "lease",
+    "audit_write",
+    "audit_control",
+    "setfcap",
+    "mac_override",
+    "mac_admin",
+    "syslog",
+    "wake_alarm",
+    "block_suspend",
+    "audit_read",
+    "perfmon",
+    "bpf",
+    "checkpoint_restore",
+];
+
+#[derive(thiserror::Error)]
+pub enum PrivilegesError {
+    #[error("Unknown capability: {0}")]
+    UnknownCapability(String),
+    #[error("Invalid umask: {0:o}")]
+    InvalidUmask(u32),
+    #[error("No group given for user {0}")]
+    MissingGroup(u32),
+    #[error("No user given for group {0}")]
+    MissingUser(u32),
+    #[error("Invalid user range: {0}")]
+    InvalidRange(String),
+    #[error("No free users remaining in range {0}")]
+    Exhausted(UserRange),
+    #[error("Scene user IO error")]
+    Io(#[from] std::io::Error),
+    #[error("Scene user serialization error")]
+    Serialization(#[from] serde_json::Error),
+}
+
+impl std::fmt::Debug for PrivilegesError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+pub type PrivilegesResult<T> = std::result::Result<T, PrivilegesError>;
+
+/// Looks up a capability's number by name (with or without the `CAP_` prefix).
+pub fn capability_number(name: &str) -> PrivilegesResult<usize> {
+    let lower = name.to_ascii_lowercase();
+    let short = lower.strip_prefix("cap_").unwrap_or(&lower);
+    CAPABILITIES
+        .iter()
+        .position(|cap| *cap == short)
+        .ok_or_else(|| PrivilegesError::UnknownCapability(name.to_string()))
+}
+
+/// Who a subroutine runs as, and with what privileges.
+#[derive(Clone, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct RunAs {
+    /// User to switch to (defaults to the scene's user, when one is allocated).
+    #[serde(default)]
+    pub uid: Option<u32>,
+    /// Primary group to switch to (required along with `uid`, so it's never left as root's).
+    #[serde(default)]
+    pub gid: Option<u32>,
+    /// Supplementary groups (replacing the shim's own).
+    #[serde(default)]
+    pub groups: Vec<u32>,
+    /// Capabilities kept in the bounding set (all others are dropped); `None` leaves the set
+    /// untouched.
+    #[serde(default)]
+    pub capabilities: Option<Vec<String>>,
+    /// Prevents the subroutine gaining privileges through setuid binaries and the like.
+    #[serde(default = "default_no_new_privs")]
+    pub no_new_privs: bool,
+    #[serde(default)]
+    pub umask: Option<u32>,
+}
+
+fn default_no_new_privs() -> bool {
+    true
+}
+
+impl Default for RunAs {
+    fn default() -> Self {
+        Self {
+            uid: None,
+            gid: None,
+            groups: vec![],
+            capabilities: None,
+            no_new_privs: default_no_new_privs(),
+            umask: None,
+        }
+    }
+}
+
+impl RunAs {
+    pub fn with_user(mut self, uid: u32, gid: u32) -> Self {
+        self.uid = Some(uid);
+        self.gid = Some(gid);
+        self
+    }
+
+    pub fn with_groups(mut self, groups: Vec<u32>) -> Self {
+        self.groups = groups;
+        self
+    }
+
+    pub fn with_capabilities(mut self, capabilities: Vec<String>) -> Self {
+        self.capabilities = Some(capabilities);
+        self
+    }
+
+    pub fn with_no_new_privs(mut self, no_new_privs: bool) -> Self {
+        self.no_new_privs = no_new_privs;
+        self
+    }
+
+    pub fn with_umask(mut self, umask: u32) -> Self {
+        self.umask = Some(umask);
+        self
+    }
+
+    pub fn validate(&self) -> PrivilegesResult<()> {
+        if let (Some(uid), None) = (self.uid, self.gid) {
+            return Err(PrivilegesError::MissingGroup(uid));
+        }
+        if let (None, Some(gid)) = (self.uid, self.gid) {
+            return Err(PrivilegesError::MissingUser(gid));
+        }
+        if let Some(umask) = self.umask.filter(|umask| *umask > 0o777) {
+            return Err(PrivilegesError::InvalidUmask(umask));
+        }
+        for capability in self.capabilities.iter().flatten() {
+            capability_number(capability)?;
+        }
+        Ok(())
+    }
+
+    /// Capabilities dropped from the bounding set.
+    fn dropped_capabilities(&self) -> Vec<usize> {
+        match self.capabilities.as_ref() {
+            Some(kept) => {
+                let kept: Vec<usize> = kept
+                    .iter()
+                    .filter_map(|cap| capability_number(cap).ok())
+                    .collect();
+                (0..CAPABILITIES.len())
+                    .filter(|cap| !kept.contains(cap))
+                    .collect()
+            }
+            None => vec![],
+        }
+    }
+
+    /// Resolves the settings ahead of a fork, so they can be applied in the child (where only
+    /// async-signal-safe calls may be made) without allocating.
+    pub fn prepare(&self) -> PreparedRunAs {
+        PreparedRunAs {
+            umask: self.umask.map(|umask| umask as libsee::mode_t),
+            dropped_capabilities: self
+                .dropped_capabilities()
+                .into_iter()
+                .map(|cap| cap as libsee::c_ulong)
+                .collect(),
+            set_groups: self.uid.is_some() || self.gid.is_some() || !self.groups.is_empty(),
+            groups: self.groups.clone(),
+            gid: self.gid,
+            uid: self.uid,
+            no_new_privs: self.no_new_privs,
+        }
+    }
+}
+
+/// [`RunAs`] settings, resolved (see [`RunAs::prepare`]) to the system calls applying them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PreparedRunAs {
+    umask: Option<libsee::mode_t>,
+    dropped_capabilities: Vec<libsee::c_ulong>,
+    set_groups: bool,
+    groups: Vec<libsee::gid_t>,
+    gid: Option<libsee::gid_t>,
+    uid: Option<libsee::uid_t>,
+    no_new_privs: bool,
+}
+
+impl PreparedRunAs {
+    /// Applies the settings to the current process (meant for the forked child, before exec).
+    ///
+    /// Capabilities are dropped and groups set while we still have the privileges to do so;
+    /// the user is switched last.  Only system calls are made.
+    pub fn apply(&self) -> libsee::Result<()> {
+        if let Some(umask) = self.umask {
+            libsee::umask(umask);
+        }
+
+        for cap in self.dropped_capabilities.iter() {
+            match libsee::prctl(libsee::PR_CAPBSET_DROP, *cap, 0, 0, 0) {
+                // capabilities newer than the running kernel
+                Err(err) if err.errno() == libsee::EINVAL => break,
+                result => result?,
+            }
+        }
+
+        if self.set_groups {
+            libsee::setgroups(&self.groups)?;
+        }
+        if let Some(gid) = self.gid {
+            libsee::setgid(gid)?;
+        }
+        if let Some(uid) = self.uid {
+            libsee::setuid(uid)?;
+        }
+
+        if self.no_new_privs {
+            libsee::prctl(libsee::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+
+    use super::*;
+
+    #[rstest]
+    #[case("net_bind_service", 10)]
+    #[case("CAP_NET_BIND_SERVICE", 10)]
+    #[case("cap_chown", 0)]
+    #[case("checkpoint_restore", 40)]
+    fn looks_up_capabilities(#[case] name: &str, #[case] expected: usize) {
+        assert_eq!(capability_number(name).unwrap(), expected);
+    }
+
+    #[test]
+    fn rejects_unknown_capabilities() {
+        let run_as = RunAs::default().with_capabilities(vec!["cap_everything".into()]);
+
+        assert!(matches!(
+            run_as.validate().unwrap_err(),
+            PrivilegesError::UnknownCapability(..)
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_umask() {
+        let run_as = RunAs::default().with_umask(0o1022);
+
+        assert!(matches!(
+            run_as.validate().unwrap_err(),
+            PrivilegesError::InvalidUmask(..)
+        ));
+    }
+
+    #[test]
+    fn rejects_users_without_groups() {
+        let run_as: RunAs = serde_json::from_str(r#"{"uid": 1000}"#).unwrap();
+
+        assert!(matches!(
+            run_as.validate().unwrap_err(),
+            PrivilegesError::MissingGroup(1000)
+        ));
+        RunAs::default().with_user(1000, 100).validate().unwrap();
+    }
+
+    #[test]
+    fn rejects_groups_without_users() {
+        let run_as: RunAs = serde_json::from_str(r#"{"gid": 0}"#).unwrap();
+
+        assert!(matches!(
+            run_as.validate().unwrap_err(),
+            PrivilegesError::MissingUser(0)
+        ));
+    }
+
+    #[test]
+    fn drops_all_but_kept_capabilities() {
+        let run_as = RunAs::default().with_capabilities(vec!["CAP_NET_BIND_SERVICE".into()]);
+
+        let dropped = run_as.dropped_capabilities();
+
+        assert_eq!(dropped.len(), CAPABILITIES.len() - 1);
+        assert!(!dropped.contains(&10));
+    }
+
+    #[test]
+    fn prepares_groups_only_when_switching_user() {
+        assert!(!RunAs::default().prepare().set_groups);
+        assert!(RunAs::default().with_user(1000, 1000).prepare().set_groups);
+    }
+
+    #[test]
+    fn keeps_capabilities_by_default() {
+        assert!(RunAs::default().dropped_capabilities().is_empty());
+    }
+
+    #[test]
+    fn defaults_to_no_new_privs() {
+        let run_as: RunAs = serde_json::from_str(r#"{"uid": 1000}"#).unwrap();
+
+        assert!(run_as.no_new_privs);
+        assert_eq!(run_as.uid, Some(1000));
+    }
+}
@@ -0,0 +1,214 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+
+use serde::{Deserialize, Serialize};
+
+use crate::entities::SceneEntityId;
+use crate::HolodekkPaths;
+
+use super::{PrivilegesError, PrivilegesResult};
+
+/// Inclusive range of user ids scenes are allocated from (written as `start-end`).
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct UserRange {
+    start: u32,
+    end: u32,
+}
+
+impl UserRange {
+    pub fn new(start: u32, end: u32) -> PrivilegesResult<Self> {
+        // never hand out root (or anything that wraps around to it)
+        if start == 0 || start > end || end == u32::MAX {
+            Err(PrivilegesError::InvalidRange(format!("{}-{}", start, end)))
+        } else {
+            Ok(Self { start, end })
+        }
+    }
+
+    pub fn start(&self) -> u32 {
+        self.start
+    }
+
+    pub fn end(&self) -> u32 {
+        self.end
+    }
+}
+
+impl Default for UserRange {
+    fn default() -> Self {
+        Self {
+            start: 200000,
+            end: 265535,
+        }
+    }
+}
+
+impl fmt::Display for UserRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+impl FromStr for UserRange {
+    type Err = PrivilegesError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || PrivilegesError::InvalidRange(s.to_string());
+        let (start, end) = s.split_once('-').ok_or_else(invalid)?;
+        let start = start.trim().parse().map_err(|_| invalid())?;
+        let end = end.trim().parse().map_err(|_| invalid())?;
+        Self::new(start, end)
+    }
+}
+
+/// User (and group) id allocated to a scene.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct SceneUser {
+    pub scene_entity_id: SceneEntityId,
+    pub id: u32,
+}
+
+/// Allocates a distinct user id to each scene, persisting allocations across restarts.
+#[derive(Debug)]
+pub struct SceneUserAllocator {
+    range: UserRange,
+    path: PathBuf,
+    users: Mutex<Vec<SceneUser>>,
+}
+
+impl SceneUserAllocator {
+    /// Loads the allocator's state from `<data_root>/users.json`.
+    pub fn new(paths: &HolodekkPaths, range: UserRange) -> PrivilegesResult<Self> {
+        Self::from_file(paths.users_file(), range)
+    }
+
+    pub fn from_file<P: Into<PathBuf>>(path: P, range: UserRange) -> PrivilegesResult<Self> {
+        let path = path.into();
+        let users = match fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents)?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => vec![],
+            Err(err) => return Err(err.into()),
+        };
+        Ok(Self {
+            range,
+            path,
+            users: Mutex::new(users),
+        })
+    }
+
+    pub fn range(&self) -> UserRange {
+        self.range
+    }
+
+    fn persist(&self, users: &[SceneUser]) -> PrivilegesResult<()> {
+        let partial = self.path.with_extension("partial");
+        fs::write(&partial, serde_json::to_vec_pretty(users)?)?;
+        fs::rename(partial, &self.path)?;
+        Ok(())
+    }
+
+    /// Allocates a user id to the scene (or returns the one it already has).
+    pub fn allocate(&self, scene_entity_id: &SceneEntityId) -> PrivilegesResult<u32> {
+        let mut users = self.users.lock().unwrap();
+        if let Some(existing) = users.iter().find(|u| &u.scene_entity_id == scene_entity_id) {
+            return Ok(existing.id);
+        }
+
+        let id = (self.range.start..=self.range.end)
+            .find(|id| !users.iter().any(|u| u.id == *id))
+            .ok_or(PrivilegesError::Exhausted(self.range))?;
+        users.push(SceneUser {
+            scene_entity_id: scene_entity_id.to_owned(),
+            id,
+        });
+        self.persist(&users)?;
+        Ok(id)
+    }
+
+    /// Returns the scene's user id to the pool.
+    pub fn release(&self, scene_entity_id: &SceneEntityId) -> PrivilegesResult<Option<u32>> {
+        let mut users = self.users.lock().unwrap();
+        match users
+            .iter()
+            .position(|u| &u.scene_entity_id == scene_entity_id)
+        {
+            Some(index) => {
+                let user = users.remove(index);
+                self.persist(&users)?;
+                Ok(Some(user.id))
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::{tempdir, TempDir};
+
+    use super::*;
+
+    fn allocator(range: UserRange) -> (TempDir, SceneUserAllocator) {
+        let temp = tempdir().unwrap();
+        let allocator =
+            SceneUserAllocator::from_file(temp.path().join("users.json"), range).unwrap();
+        (temp, allocator)
+    }
+
+    #[test]
+    fn parses_user_ranges() {
+        assert_eq!(
+            "1000-1999".parse::<UserRange>().unwrap(),
+            UserRange::new(1000, 1999).unwrap()
+        );
+        assert!("0-1000".parse::<UserRange>().is_err());
+        assert!("2000-1000".parse::<UserRange>().is_err());
+        assert!("1000".parse::<UserRange>().is_err());
+    }
+
+    #[test]
+    fn allocates_distinct_users_per_scene() {
+        let (_temp, allocator) = allocator(UserRange::new(5000, 5009).unwrap());
+        let scene = SceneEntityId::generate();
+
+        let first = allocator.allocate(&scene).unwrap();
+        let again = allocator.allocate(&scene).unwrap();
+        let other = allocator.allocate(&SceneEntityId::generate()).unwrap();
+
+        assert_eq!(first, 5000);
+        assert_eq!(again, first);
+        assert_eq!(other, 5001);
+    }
+
+    #[test]
+    fn reports_exhausted_range() {
+        let (_temp, allocator) = allocator(UserRange::new(5000, 5000).unwrap());
+        allocator.allocate(&SceneEntityId::generate()).unwrap();
+
+        assert!(matches!(
+            allocator.allocate(&SceneEntityId::generate()).unwrap_err(),
+            PrivilegesError::Exhausted(..)
+        ));
+    }
+
+    #[test]
+    fn persists_and_releases_users() {
+        let range = UserRange::new(5000, 5009).unwrap();
+        let (temp, allocator) = allocator(range);
+        let scene = SceneEntityId::generate();
+        let id = allocator.allocate(&scene).unwrap();
+
+        let reloaded =
+            SceneUserAllocator::from_file(temp.path().join("users.json"), range).unwrap();
+        assert_eq!(reloaded.allocate(&scene).unwrap(), id);
+
+        assert_eq!(reloaded.release(&scene).unwrap(), Some(id));
+        let reloaded =
+            SceneUserAllocator::from_file(temp.path().join("users.json"), range).unwrap();
+        assert!(reloaded.release(&scene).unwrap().is_none());
+    }
+}
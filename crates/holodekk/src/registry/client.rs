@@ -0,0 +1,319 @@
+use std::io::{self, Read, Seek, SeekFrom, Write};
+
+use hyper::{
+    body::HttpBody,
+    client::HttpConnector,
+    http::{header, Method, Request, StatusCode},
+    Body, Client, Response,
+};
+use log::debug;
+
+use crate::errors::error_chain_fmt;
+use crate::images::{
+    image_digest, Digest, DigestWriter, ImageId, ImageName, SubroutineImage, SubroutineImageId,
+    SubroutineImageStore, SubroutineImageStoreError,
+};
+
+use super::{
+    BlobDescriptor, ImageManifest, Reference, UploadStatus, CONTENT_DIGEST_HEADER,
+    UPLOAD_OFFSET_HEADER,
+};
+
+/// Size of the chunks blobs are uploaded in.
+pub const DEFAULT_CHUNK_SIZE: usize = 1024 * 1024;
+
+#[derive(thiserror::Error)]
+pub enum RegistryClientError {
+    #[error("Registry request failed")]
+    Http(#[from] hyper::Error),
+    #[error("Invalid registry request")]
+    Request(#[from] hyper::http::Error),
+    #[error("Registry responded with {0}: {1}")]
+    Status(StatusCode, String),
+    #[error("Digest mismatch (expected {expected}, received {actual})")]
+    DigestMismatch { expected: Digest, actual: Digest },
+    #[error("Invalid manifest: {0}")]
+    InvalidManifest(String),
+    #[error("Image store error")]
+    Store(#[from] SubroutineImageStoreError),
+    #[error("Registry client IO error")]
+    Io(#[from] std::io::Error),
+    #[error("Registry response serialization error")]
+    Serialization(#[from] serde_json::Error),
+}
+
+impl std::fmt::Debug for RegistryClientError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+pub type RegistryClientResult<T> = std::result::Result<T, RegistryClientError>;
+
+/// Client for the registry API exposed by a remote holodekkd.
+///
+/// `endpoint` is the base url the registry is mounted at (e.g. `http://host:7979/registry`).
+#[derive(Clone, Debug)]
+pub struct RegistryClient {
+    client: Client<HttpConnector>,
+    endpoint: String,
+    chunk_size: usize,
+}
+
+impl RegistryClient {
+    pub fn new<S: Into<String>>(endpoint: S) -> Self {
+        Self {
+            client: Client::new(),
+            endpoint: endpoint.into().trim_end_matches('/').to_string(),
+            chunk_size: DEFAULT_CHUNK_SIZE,
+        }
+    }
+
+    pub fn with_chunk_size(mut self, chunk_size: usize) -> Self {
+        self.chunk_size = chunk_size;
+        self
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.endpoint, path)
+    }
+
+    async fn send(&self, request: Request<Body>) -> RegistryClientResult<Response<Body>> {
+        let response = self.client.request(request).await?;
+        if response.status().is_success() {
+            Ok(response)
+        } else {
+            let status = response.status();
+            let body = hyper::body::to_bytes(response.into_body()).await?;
+            Err(RegistryClientError::Status(
+                status,
+                String::from_utf8_lossy(&body).to_string(),
+            ))
+        }
+    }
+
+    /// Pushes a local image to the registry under the given tag, returning the manifest digest.
+    pub async fn push(
+        &self,
+        store: &dyn SubroutineImageStore,
+        id: &SubroutineImageId,
+        tag: &str,
+    ) -> RegistryClientResult<Digest> {
+        let tag: Reference = tag
+            .parse()
+            .map_err(|_| RegistryClientError::InvalidManifest(format!("invalid tag: {}", tag)))?;
+        let image = store.get(id)?;
+        let digest = image_digest(store, &image)?;
+        let mut archive = store.archive(id)?;
+        let size = archive.metadata()?.len();
+
+        if self.blob_exists(&digest).await? {
+            debug!("Registry already has blob {}", digest);
+        } else {
+            self.upload_blob(&mut archive, &digest).await?;
+        }
+
+        let mut manifest = ImageManifest::new(
+            image.name.clone(),
+            image.kind,
+            vec![BlobDescriptor { digest, size }],
+        );
+        manifest.signatures = image.signatures.clone();
+        self.put_manifest(&image, &tag, &manifest).await
+    }
+
+    /// Pulls an image from the registry into the local store.
+    pub async fn pull(
+        &self,
+        store: &dyn SubroutineImageStore,
+        name: &ImageName,
+        reference: &str,
+    ) -> RegistryClientResult<SubroutineImage> {
+        let manifest = self.get_manifest(name, reference).await?;
+        if &manifest.name != name {
+            return Err(RegistryClientError::InvalidManifest(format!(
+                "received manifest for {} (expected {})",
+                manifest.name, name
+            )));
+        }
+        let layer = match manifest.layers.as_slice() {
+            [layer] => layer,
+            layers => {
+                return Err(RegistryClientError::InvalidManifest(format!(
+                    "expected a single layer, found {}",
+                    layers.len()
+                )))
+            }
+        };
+
+        let mut archive = tempfile::tempfile()?;
+        self.download_blob(layer, &mut archive).await?;
+        archive.seek(SeekFrom::Start(0))?;
+
+        let mut image = store.create(name, &mut archive)?;
+        if !manifest.signatures.is_empty() {
+            image.signatures = manifest.signatures;
+            store.update(&image)?;
+        }
+        Ok(image)
+    }
+
+    async fn blob_exists(&self, digest: &Digest) -> RegistryClientResult<bool> {
+        let request = Request::builder()
+            .method(Method::HEAD)
+            .uri(self.url(&format!("/blobs/{}", digest)))
+            .body(Body::empty())?;
+        match self.send(request).await {
+            Ok(_) => Ok(true),
+            Err(RegistryClientError::Status(StatusCode::NOT_FOUND, _)) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn upload_blob<R: Read>(
+        &self,
+        reader: &mut R,
+        digest: &Digest,
+    ) -> RegistryClientResult<()> {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(self.url("/uploads"))
+            .body(Body::empty())?;
+        let response = self.send(request).await?;
+        let mut status: UploadStatus =
+            serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await?)?;
+
+        let mut chunk = vec![0; self.chunk_size];
+        loop {
+            let len = read_chunk(reader, &mut chunk)?;
+            if len == 0 {
+                break;
+            }
+            let request = Request::builder()
+                .method(Method::PATCH)
+                .uri(self.url(&format!("/uploads/{}", status.id)))
+                .header(UPLOAD_OFFSET_HEADER, status.offset.to_string())
+                .header(header::CONTENT_TYPE, "application/octet-stream")
+                .body(Body::from(chunk[..len].to_vec()))?;
+            let response = self.send(request).await?;
+            status = serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await?)?;
+        }
+
+        let request = Request::builder()
+            .method(Method::PUT)
+            .uri(self.url(&format!("/uploads/{}?digest={}", status.id, digest)))
+            .body(Body::empty())?;
+        self.send(request).await?;
+        debug!("Uploaded blob {} ({} bytes)", digest, status.offset);
+        Ok(())
+    }
+
+    async fn download_blob<W: Write>(
+        &self,
+        blob: &BlobDescriptor,
+        writer: W,
+    ) -> RegistryClientResult<()> {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(self.url(&format!("/blobs/{}", blob.digest)))
+            .body(Body::empty())?;
+        let mut body = self.send(request).await?.into_body();
+
+        let mut writer = DigestWriter::new(writer);
+        while let Some(chunk) = body.data().await {
+            writer.write_all(&chunk?)?;
+        }
+        let (actual, size, _) = writer.finalize();
+        if actual != blob.digest || size != blob.size {
+            return Err(RegistryClientError::DigestMismatch {
+                expected: blob.digest.to_owned(),
+                actual,
+            });
+        }
+        debug!("Downloaded blob {} ({} bytes)", actual, size);
+        Ok(())
+    }
+
+    async fn put_manifest(
+        &self,
+        image: &SubroutineImage,
+        tag: &Reference,
+        manifest: &ImageManifest,
+    ) -> RegistryClientResult<Digest> {
+        let body = serde_json::to_vec(manifest)?;
+        let expected = Digest::compute(&body);
+        let request = Request::builder()
+            .method(Method::PUT)
+            .uri(self.url(&format!("/images/{}/manifests/{}", image.id, tag)))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body))?;
+        let response = self.send(request).await?;
+
+        let actual = response_digest(&response)?;
+        if actual != expected {
+            return Err(RegistryClientError::DigestMismatch { expected, actual });
+        }
+        Ok(actual)
+    }
+
+    async fn get_manifest(
+        &self,
+        name: &ImageName,
+        reference: &str,
+    ) -> RegistryClientResult<ImageManifest> {
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(self.url(&format!(
+                "/images/{}/manifests/{}",
+                ImageId::generate(name),
+                reference
+            )))
+            .body(Body::empty())?;
+        let response = self.send(request).await?;
+
+        let expected = response_digest(&response)?;
+        let body = hyper::body::to_bytes(response.into_body()).await?;
+        let actual = Digest::compute(&body);
+        if actual != expected {
+            return Err(RegistryClientError::DigestMismatch { expected, actual });
+        }
+        // a digest reference must resolve to exactly that manifest
+        if let Ok(Reference::Digest(requested)) = reference.parse::<Reference>() {
+            if requested != actual {
+                return Err(RegistryClientError::DigestMismatch {
+                    expected: requested,
+                    actual,
+                });
+            }
+        }
+        Ok(serde_json::from_slice(&body)?)
+    }
+}
+
+fn response_digest(response: &Response<Body>) -> RegistryClientResult<Digest> {
+    response
+        .headers()
+        .get(CONTENT_DIGEST_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+        .ok_or_else(|| {
+            RegistryClientError::InvalidManifest(format!(
+                "missing {} header",
+                CONTENT_DIGEST_HEADER
+            ))
+        })
+}
+
+/// Fills `buf` as far as possible, returning the number of bytes read (0 at end of input).
+fn read_chunk<R: Read>(reader: &mut R, buf: &mut [u8]) -> io::Result<usize> {
+    let mut len = 0;
+    while len < buf.len() {
+        match reader.read(&mut buf[len..]) {
+            Ok(0) => break,
+            Ok(n) => len += n,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(len)
+}
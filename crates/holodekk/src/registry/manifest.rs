@@ -0,0 +1,37 @@
+use serde::{Deserialize, Serialize};
+
+use crate::enums::SubroutineKind;
+use crate::images::{Digest, ImageName, ImageSignature};
+
+pub const MANIFEST_SCHEMA_VERSION: u32 = 1;
+
+/// Reference to a content addressed blob.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct BlobDescriptor {
+    pub digest: Digest,
+    pub size: u64,
+}
+
+/// Document describing a pushed image and the blobs it is made of.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ImageManifest {
+    pub schema_version: u32,
+    pub name: ImageName,
+    pub kind: SubroutineKind,
+    pub layers: Vec<BlobDescriptor>,
+    /// Signatures over the digest of the image archive.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub signatures: Vec<ImageSignature>,
+}
+
+impl ImageManifest {
+    pub fn new(name: ImageName, kind: SubroutineKind, layers: Vec<BlobDescriptor>) -> Self {
+        Self {
+            schema_version: MANIFEST_SCHEMA_VERSION,
+            name,
+            kind,
+            layers,
+            signatures: vec![],
+        }
+    }
+}
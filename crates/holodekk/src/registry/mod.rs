@@ -0,0 +1,483 @@
+//! Content addressed storage for pushed images.
+//!
+//! Blobs are stored by digest and uploaded in chunks; manifests are themselves stored as blobs,
+//! with tags pointing at the digest of the manifest they name:
+//!
+//! ```text
+//! <registry_root>/blobs/sha256/<hex>
+//! <registry_root>/uploads/<upload id>
+//! <registry_root>/manifests/<image id>/<tag>
+//! ```
+mod client;
+pub use client::*;
+mod manifest;
+pub use manifest::*;
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use lazy_static::lazy_static;
+use log::debug;
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::entities::EntityId;
+use crate::errors::error_chain_fmt;
+use crate::images::{Digest, ImageId};
+use crate::HolodekkPaths;
+
+lazy_static! {
+    static ref TAG_RE: Regex = Regex::new(r"^[A-Za-z0-9_][A-Za-z0-9._-]{0,127}$").unwrap();
+}
+
+/// Header carrying the digest of a manifest in registry responses.
+pub const CONTENT_DIGEST_HEADER: &str = "holodekk-content-digest";
+/// Header carrying the offset a chunk should be written at.
+pub const UPLOAD_OFFSET_HEADER: &str = "upload-offset";
+
+pub type UploadId = EntityId;
+
+/// Progress of a chunked upload.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct UploadStatus {
+    pub id: UploadId,
+    pub offset: u64,
+}
+
+#[derive(thiserror::Error)]
+pub enum RegistryError {
+    #[error("Blob not found: {0}")]
+    BlobUnknown(Digest),
+    #[error("Manifest not found: {0}")]
+    ManifestUnknown(String),
+    #[error("Upload not found: {0}")]
+    UploadUnknown(UploadId),
+    #[error("Invalid upload offset {actual} (expected {expected})")]
+    InvalidOffset { expected: u64, actual: u64 },
+    #[error("Digest mismatch (expected {expected}, received {actual})")]
+    DigestMismatch { expected: Digest, actual: Digest },
+    #[error("Invalid manifest: {0}")]
+    InvalidManifest(String),
+    #[error("Invalid reference: {0}")]
+    InvalidReference(String),
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+    #[error("Registry IO error")]
+    Io(#[from] std::io::Error),
+    #[error("Manifest serialization error")]
+    Serialization(#[from] serde_json::Error),
+}
+
+impl std::fmt::Debug for RegistryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+pub type RegistryResult<T> = std::result::Result<T, RegistryError>;
+
+/// Manifest reference, either a tag or the digest of the manifest itself.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Reference {
+    Tag(String),
+    Digest(Digest),
+}
+
+impl std::str::FromStr for Reference {
+    type Err = RegistryError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if let Ok(digest) = s.parse() {
+            Ok(Self::Digest(digest))
+        } else if TAG_RE.is_match(s) {
+            Ok(Self::Tag(s.to_string()))
+        } else {
+            Err(RegistryError::InvalidReference(s.to_string()))
+        }
+    }
+}
+
+impl std::fmt::Display for Reference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Tag(tag) => write!(f, "{}", tag),
+            Self::Digest(digest) => write!(f, "{}", digest),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Registry {
+    root: PathBuf,
+}
+
+impl Registry {
+    pub fn new(paths: &HolodekkPaths) -> Self {
+        Self::from_root(paths.registry_root())
+    }
+
+    pub fn from_root<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into() }
+    }
+
+    pub fn init(&self) -> io::Result<()> {
+        fs::create_dir_all(self.blobs_root())?;
+        fs::create_dir_all(self.uploads_root())?;
+        fs::create_dir_all(self.root.join("manifests"))
+    }
+
+    fn blobs_root(&self) -> PathBuf {
+        self.root.join("blobs").join("sha256")
+    }
+
+    fn uploads_root(&self) -> PathBuf {
+        self.root.join("uploads")
+    }
+
+    fn blob_path(&self, digest: &Digest) -> PathBuf {
+        self.blobs_root().join(digest.hex())
+    }
+
+    fn upload_path(&self, id: &UploadId) -> PathBuf {
+        self.uploads_root().join(id)
+    }
+
+    fn tag_path(&self, image: &ImageId, tag: &str) -> PathBuf {
+        self.root.join("manifests").join(image).join(tag)
+    }
+
+    /// Size of the given blob, if present.
+    pub fn stat_blob(&self, digest: &Digest) -> RegistryResult<u64> {
+        match fs::metadata(self.blob_path(digest)) {
+            Ok(metadata) => Ok(metadata.len()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                Err(RegistryError::BlobUnknown(digest.to_owned()))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    pub fn open_blob(&self, digest: &Digest) -> RegistryResult<(File, u64)> {
+        let size = self.stat_blob(digest)?;
+        Ok((File::open(self.blob_path(digest))?, size))
+    }
+
+    pub fn start_upload(&self) -> RegistryResult<UploadId> {
+        let id = UploadId::generate();
+        fs::create_dir_all(self.uploads_root())?;
+        File::create(self.upload_path(&id))?;
+        debug!("Started upload {}", id);
+        Ok(id)
+    }
+
+    /// Number of bytes received so far for the given upload.
+    pub fn upload_offset(&self, id: &UploadId) -> RegistryResult<u64> {
+        match fs::metadata(self.upload_path(id)) {
+            Ok(metadata) => Ok(metadata.len()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                Err(RegistryError::UploadUnknown(id.to_owned()))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Appends a chunk to an upload, returning the new offset.
+    ///
+    /// Chunks must arrive in order: `offset` has to match the amount of data already received.
+    pub fn append_upload(&self, id: &UploadId, offset: u64, chunk: &[u8]) -> RegistryResult<u64> {
+        let expected = self.upload_offset(id)?;
+        if offset != expected {
+            return Err(RegistryError::InvalidOffset {
+                expected,
+                actual: offset,
+            });
+        }
+
+        let mut file = OpenOptions::new().append(true).open(self.upload_path(id))?;
+        file.write_all(chunk)?;
+        Ok(expected + chunk.len() as u64)
+    }
+
+    /// Verifies a finished upload against `digest` and moves it into blob storage.
+    pub fn complete_upload(
+        &self,
+        id: &UploadId,
+        digest: &Digest,
+    ) -> RegistryResult<BlobDescriptor> {
+        let path = self.upload_path(id);
+        let size = self.upload_offset(id)?;
+        let actual = Digest::compute_file(&path)?;
+        if &actual != digest {
+            fs::remove_file(&path)?;
+            return Err(RegistryError::DigestMismatch {
+                expected: digest.to_owned(),
+                actual,
+            });
+        }
+
+        fs::create_dir_all(self.blobs_root())?;
+        fs::rename(&path, self.blob_path(digest))?;
+        debug!("Completed upload {} as {}", id, digest);
+        Ok(BlobDescriptor {
+            digest: digest.to_owned(),
+            size,
+        })
+    }
+
+    pub fn cancel_upload(&self, id: &UploadId) -> RegistryResult<()> {
+        self.upload_offset(id)?;
+        fs::remove_file(self.upload_path(id))?;
+        Ok(())
+    }
+
+    /// Stores a manifest for the given image, returning the manifest digest.
+    ///
+    /// Every layer the manifest refers to must already have been uploaded.
+    pub fn put_manifest(
+        &self,
+        image: &ImageId,
+        reference: &Reference,
+        body: &[u8],
+    ) -> RegistryResult<Digest> {
+        let manifest: ImageManifest = serde_json::from_slice(body)?;
+        self.validate_manifest(image, &manifest)?;
+
+        let digest = Digest::compute(body);
+        if let Reference::Digest(expected) = reference {
+            if expected != &digest {
+                return Err(RegistryError::DigestMismatch {
+                    expected: expected.to_owned(),
+                    actual: digest,
+                });
+            }
+        }
+
+        self.write_atomic(&self.blob_path(&digest), body)?;
+        if let Reference::Tag(tag) = reference {
+            self.write_atomic(&self.tag_path(image, tag), digest.as_bytes())?;
+        }
+        debug!(
+            "Stored manifest {} for {}@{}",
+            digest, manifest.name, reference
+        );
+        Ok(digest)
+    }
+
+    /// Fetches the raw manifest (and its digest) for the given image reference.
+    pub fn get_manifest(
+        &self,
+        image: &ImageId,
+        reference: &Reference,
+    ) -> RegistryResult<(Digest, Vec<u8>)> {
+        let unknown = || RegistryError::ManifestUnknown(format!("{}@{}", image, reference));
+
+        let digest = match reference {
+            Reference::Digest(digest) => digest.to_owned(),
+            Reference::Tag(tag) => match fs::read_to_string(self.tag_path(image, tag)) {
+                Ok(digest) => digest
+                    .parse()
+                    .map_err(|_| RegistryError::InvalidManifest(digest))?,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => return Err(unknown()),
+                Err(err) => return Err(err.into()),
+            },
+        };
+
+        let body = match fs::read(self.blob_path(&digest)) {
+            Ok(body) => body,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Err(unknown()),
+            Err(err) => return Err(err.into()),
+        };
+
+        // digest references can point at any blob, so make sure this one is a manifest for
+        // the requested image
+        match serde_json::from_slice::<ImageManifest>(&body) {
+            Ok(manifest) if &ImageId::generate(&manifest.name) == image => Ok((digest, body)),
+            _ => Err(unknown()),
+        }
+    }
+
+    fn validate_manifest(&self, image: &ImageId, manifest: &ImageManifest) -> RegistryResult<()> {
+        if manifest.schema_version != MANIFEST_SCHEMA_VERSION {
+            return Err(RegistryError::InvalidManifest(format!(
+                "unsupported schema version {}",
+                manifest.schema_version
+            )));
+        }
+        if &ImageId::generate(&manifest.name) != image {
+            return Err(RegistryError::InvalidManifest(format!(
+                "manifest for {} does not match image {}",
+                manifest.name, image
+            )));
+        }
+        for layer in manifest.layers.iter() {
+            let size = self.stat_blob(&layer.digest)?;
+            if size != layer.size {
+                return Err(RegistryError::InvalidManifest(format!(
+                    "layer {} is {} bytes (manifest says {})",
+                    layer.digest, size, layer.size
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn write_atomic(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        let parent = path.parent().unwrap();
+        fs::create_dir_all(parent)?;
+        let staging = parent.join(format!(
+            ".{}.partial",
+            path.file_name().unwrap().to_string_lossy()
+        ));
+        fs::write(&staging, data)?;
+        fs::rename(staging, path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::{tempdir, TempDir};
+
+    use crate::enums::SubroutineKind;
+    use crate::images::ImageName;
+
+    use super::*;
+
+    fn registry() -> (TempDir, Registry) {
+        let temp = tempdir().unwrap();
+        let registry = Registry::from_root(temp.path().join("registry"));
+        registry.init().unwrap();
+        (temp, registry)
+    }
+
+    fn upload(registry: &Registry, data: &[u8]) -> BlobDescriptor {
+        let id = registry.start_upload().unwrap();
+        registry.append_upload(&id, 0, data).unwrap();
+        registry
+            .complete_upload(&id, &Digest::compute(data))
+            .unwrap()
+    }
+
+    fn manifest(name: &str, layers: Vec<BlobDescriptor>) -> Vec<u8> {
+        serde_json::to_vec(&ImageManifest::new(
+            ImageName::from(name),
+            SubroutineKind::Ruby,
+            layers,
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn accepts_chunked_uploads() {
+        let (_temp, registry) = registry();
+        let id = registry.start_upload().unwrap();
+
+        let offset = registry.append_upload(&id, 0, b"hel").unwrap();
+        let offset = registry.append_upload(&id, offset, b"lo").unwrap();
+        assert_eq!(offset, 5);
+
+        let blob = registry
+            .complete_upload(&id, &Digest::compute(b"hello"))
+            .unwrap();
+        assert_eq!(blob.size, 5);
+        assert_eq!(registry.stat_blob(&blob.digest).unwrap(), 5);
+    }
+
+    #[test]
+    fn rejects_out_of_order_chunks() {
+        let (_temp, registry) = registry();
+        let id = registry.start_upload().unwrap();
+        registry.append_upload(&id, 0, b"hel").unwrap();
+
+        let result = registry.append_upload(&id, 0, b"lo");
+        assert!(matches!(
+            result.unwrap_err(),
+            RegistryError::InvalidOffset {
+                expected: 3,
+                actual: 0
+            }
+        ));
+    }
+
+    #[test]
+    fn rejects_upload_with_wrong_digest() {
+        let (_temp, registry) = registry();
+        let id = registry.start_upload().unwrap();
+        registry.append_upload(&id, 0, b"hello").unwrap();
+
+        let result = registry.complete_upload(&id, &Digest::compute(b"goodbye"));
+        assert!(matches!(
+            result.unwrap_err(),
+            RegistryError::DigestMismatch { .. }
+        ));
+        assert!(registry.stat_blob(&Digest::compute(b"hello")).is_err());
+    }
+
+    #[test]
+    fn stores_and_fetches_manifests_by_tag_and_digest() {
+        let (_temp, registry) = registry();
+        let blob = upload(&registry, b"hello");
+        let body = manifest("acme/widgets", vec![blob]);
+        let image = ImageId::generate(&"acme/widgets".into());
+        let tag: Reference = "latest".parse().unwrap();
+
+        let digest = registry.put_manifest(&image, &tag, &body).unwrap();
+
+        assert_eq!(
+            registry.get_manifest(&image, &tag).unwrap(),
+            (digest.clone(), body.clone())
+        );
+        assert_eq!(
+            registry
+                .get_manifest(&image, &Reference::Digest(digest.clone()))
+                .unwrap(),
+            (digest, body)
+        );
+    }
+
+    #[test]
+    fn rejects_manifests_with_missing_layers() {
+        let (_temp, registry) = registry();
+        let body = manifest(
+            "acme/widgets",
+            vec![BlobDescriptor {
+                digest: Digest::compute(b"hello"),
+                size: 5,
+            }],
+        );
+        let image = ImageId::generate(&"acme/widgets".into());
+
+        let result = registry.put_manifest(&image, &"latest".parse().unwrap(), &body);
+        assert!(matches!(
+            result.unwrap_err(),
+            RegistryError::BlobUnknown(..)
+        ));
+    }
+
+    #[test]
+    fn rejects_manifests_for_other_images() {
+        let (_temp, registry) = registry();
+        let blob = upload(&registry, b"hello");
+        let body = manifest("acme/widgets", vec![blob]);
+        let image = ImageId::generate(&"acme/gadgets".into());
+
+        let result = registry.put_manifest(&image, &"latest".parse().unwrap(), &body);
+        assert!(matches!(
+            result.unwrap_err(),
+            RegistryError::InvalidManifest(..)
+        ));
+    }
+
+    #[test]
+    fn does_not_serve_blobs_as_manifests() {
+        let (_temp, registry) = registry();
+        let blob = upload(&registry, b"hello");
+        let image = ImageId::generate(&"acme/widgets".into());
+
+        let result = registry.get_manifest(&image, &Reference::Digest(blob.digest));
+        assert!(matches!(
+            result.unwrap_err(),
+            RegistryError::ManifestUnknown(..)
+        ));
+    }
+}
@@ -5,9 +5,9 @@ use timestamps::Timestamps;
 use crate::entities::{
     EntityId, EntityRepositoryError, EntityRepositoryQuery, EntityRepositoryResult,
     SubroutineEntity, SubroutineEntityId, SubroutineEntityRepository,
-    SubroutineEntityRepositoryEvent, SubroutineEntityRepositoryQuery,
+    SubroutineEntityRepositoryEvent, SubroutineEntityRepositoryQuery, SubroutineStatusChange,
 };
-use crate::enums::SubroutineStatus;
+use crate::enums::SubroutineHealth;
 
 use super::{etcd_subroutine_key, EtcdRepository};
 
@@ -24,7 +24,7 @@ impl From<etcd_client::Event> for SubroutineEntityRepositoryEvent {
 
                         Self::Update {
                             subroutine: current,
-                            orig,
+                            orig: Box::new(orig),
                         }
                     } else {
                         Self::Insert {
@@ -154,7 +154,8 @@ impl SubroutineEntityRepository for EtcdRepository {
     async fn subroutines_update(
         &self,
         id: &SubroutineEntityId,
-        status: Option<SubroutineStatus>,
+        status: Option<SubroutineStatusChange>,
+        health: Option<SubroutineHealth>,
     ) -> EntityRepositoryResult<SubroutineEntity> {
         let mut client = self.client.read().unwrap().clone().unwrap();
         let key = etcd_subroutine_key(Some(id));
@@ -163,7 +164,10 @@ impl SubroutineEntityRepository for EtcdRepository {
         if let Some(kv) = result.kvs().first() {
             let mut subroutine: SubroutineEntity = serde_json::from_slice(kv.value())?;
             if let Some(status) = status {
-                subroutine.status = status.to_owned();
+                subroutine.change_status(status);
+            }
+            if let Some(health) = health {
+                subroutine.health = health;
             }
 
             client
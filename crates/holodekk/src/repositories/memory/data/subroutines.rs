@@ -62,4 +62,14 @@ impl SubroutinesMemoryStore {
             Err(EntityRepositoryError::NotFound(id.to_owned()))
         }
     }
+
+    pub fn update(&self, subroutine: SubroutineEntity) -> EntityRepositoryResult<SubroutineEntity> {
+        match self.records.write().unwrap().get_mut(&subroutine.id) {
+            Some(record) => {
+                *record = subroutine.clone();
+                Ok(subroutine)
+            }
+            None => Err(EntityRepositoryError::NotFound(subroutine.id)),
+        }
+    }
 }
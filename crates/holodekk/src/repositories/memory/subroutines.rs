@@ -4,8 +4,9 @@ use timestamps::Timestamps;
 use crate::entities::{
     EntityRepositoryError, EntityRepositoryQuery, EntityRepositoryResult, SubroutineEntity,
     SubroutineEntityId, SubroutineEntityRepository, SubroutineEntityRepositoryQuery,
+    SubroutineStatusChange,
 };
-pub use crate::enums::SubroutineStatus;
+pub use crate::enums::{SubroutineHealth, SubroutineStatus};
 pub use crate::images::SubroutineImageId;
 
 pub(self) use super::MemoryRepository;
@@ -73,14 +74,19 @@ impl SubroutineEntityRepository for MemoryRepository {
     async fn subroutines_update(
         &self,
         id: &SubroutineEntityId,
-        status: Option<SubroutineStatus>,
+        status: Option<SubroutineStatusChange>,
+        health: Option<SubroutineHealth>,
     ) -> EntityRepositoryResult<SubroutineEntity> {
         let mut subroutine = self.subroutines_get(id).await?;
         if let Some(status) = status {
-            subroutine.status = status;
+            subroutine.change_status(status);
+        }
+        if let Some(health) = health {
+            subroutine.health = health;
         }
         subroutine.updated();
-        todo!()
+        let subroutine = self.db.subroutines().update(subroutine)?;
+        Ok(subroutine)
     }
 }
 
@@ -92,7 +98,7 @@ mod tests {
 
     use crate::entities::{
         fixtures::mock_subroutine_entity, EntityRepositoryError, SceneEntityId,
-        SubroutineEntityRepositoryQuery,
+        SubroutineEntityRepositoryQuery, SubroutineExit,
     };
     use crate::repositories::memory::MemoryDatabase;
 
@@ -293,4 +299,87 @@ mod tests {
         assert_eq!(instance.id, mock_subroutine_entity.id);
         Ok(())
     }
+
+    #[rstest]
+    #[tokio::test]
+    async fn update_fails_when_subroutine_does_not_exist(db: Arc<MemoryDatabase>) {
+        let repo = MemoryRepository::new(db.clone());
+
+        let res = repo
+            .subroutines_update(
+                &SubroutineEntityId::generate(),
+                None,
+                Some(SubroutineHealth::Healthy),
+            )
+            .await;
+
+        assert!(matches!(
+            res.unwrap_err(),
+            EntityRepositoryError::NotFound(..)
+        ));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn update_stores_changes(
+        db: Arc<MemoryDatabase>,
+        mock_subroutine_entity: SubroutineEntity,
+    ) -> EntityRepositoryResult<()> {
+        db.subroutines().add(mock_subroutine_entity.clone())?;
+        let repo = MemoryRepository::new(db.clone());
+
+        let change = SubroutineStatusChange::new(SubroutineStatus::Running(42));
+        let updated = repo
+            .subroutines_update(
+                &mock_subroutine_entity.id,
+                Some(change.clone()),
+                Some(SubroutineHealth::Unhealthy),
+            )
+            .await?;
+
+        assert_eq!(updated.status, SubroutineStatus::Running(42));
+        assert_eq!(updated.status_changed_at, Some(change.changed_at));
+        assert_eq!(updated.health, SubroutineHealth::Unhealthy);
+        assert_eq!(
+            repo.subroutines_get(&mock_subroutine_entity.id).await?,
+            updated
+        );
+        Ok(())
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn update_keeps_last_exit_across_restarts(
+        db: Arc<MemoryDatabase>,
+        mock_subroutine_entity: SubroutineEntity,
+    ) -> EntityRepositoryResult<()> {
+        db.subroutines().add(mock_subroutine_entity.clone())?;
+        let repo = MemoryRepository::new(db.clone());
+        let exit = SubroutineExit {
+            status: SubroutineStatus::Exited { code: 1 },
+            exited_at: chrono::Utc::now(),
+            stderr_tail: vec!["boom".into()],
+        };
+
+        repo.subroutines_update(
+            &mock_subroutine_entity.id,
+            Some(
+                SubroutineStatusChange::new(SubroutineStatus::Exited { code: 1 })
+                    .with_exit(exit.clone()),
+            ),
+            None,
+        )
+        .await?;
+        let updated = repo
+            .subroutines_update(
+                &mock_subroutine_entity.id,
+                Some(SubroutineStatusChange::new(SubroutineStatus::Running(43))),
+                None,
+            )
+            .await?;
+
+        assert_eq!(updated.status, SubroutineStatus::Running(43));
+        assert_eq!(updated.last_exit, Some(exit));
+        Ok(())
+    }
 }
@@ -0,0 +1,253 @@
+//! Restart policies, applied by a subroutine's shim when the subroutine exits.
+use std::fmt;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::error_chain_fmt;
+
+/// Retries allowed by `on-failure` when no count is given.
+pub const DEFAULT_MAX_RETRIES: u32 = 5;
+
+/// Consecutive quick restarts before a subroutine is considered to be crash looping.
+pub const CRASH_LOOP_THRESHOLD: u32 = 3;
+
+#[derive(thiserror::Error)]
+pub enum RestartPolicyError {
+    #[error("Invalid restart policy: {0} (expected never, always or on-failure[:retries])")]
+    Invalid(String),
+}
+
+impl std::fmt::Debug for RestartPolicyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// When a subroutine should be restarted after it exits.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq, Serialize)]
+#[serde(tag = "policy", rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    #[default]
+    Never,
+    /// Restart after non-zero exits (or signals), giving up after `max_retries` consecutive
+    /// failures.
+    OnFailure {
+        max_retries: u32,
+    },
+    Always,
+}
+
+impl fmt::Display for RestartPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RestartPolicy::Never => write!(f, "never"),
+            RestartPolicy::OnFailure { max_retries } => write!(f, "on-failure:{}", max_retries),
+            RestartPolicy::Always => write!(f, "always"),
+        }
+    }
+}
+
+impl FromStr for RestartPolicy {
+    type Err = RestartPolicyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            None if s == "never" => Ok(RestartPolicy::Never),
+            None if s == "always" => Ok(RestartPolicy::Always),
+            None if s == "on-failure" => Ok(RestartPolicy::OnFailure {
+                max_retries: DEFAULT_MAX_RETRIES,
+            }),
+            Some(("on-failure", retries)) => retries
+                .parse()
+                .map(|max_retries| RestartPolicy::OnFailure { max_retries })
+                .map_err(|_| RestartPolicyError::Invalid(s.to_string())),
+            _ => Err(RestartPolicyError::Invalid(s.to_string())),
+        }
+    }
+}
+
+/// What to do once a subroutine has exited.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RestartDecision {
+    Stop,
+    Restart {
+        delay: Duration,
+        /// Consecutive quick restarts (including this one).
+        attempt: u32,
+        crash_loop: bool,
+    },
+}
+
+/// Applies a [`RestartPolicy`] to successive exits, with exponential backoff.
+///
+/// Runs lasting at least `reset_after` are considered stable, and reset the backoff (and the
+/// count of consecutive failures).
+#[derive(Debug)]
+pub struct RestartTracker {
+    policy: RestartPolicy,
+    initial_delay: Duration,
+    max_delay: Duration,
+    reset_after: Duration,
+    attempts: u32,
+    started_at: Instant,
+}
+
+impl RestartTracker {
+    pub fn new(policy: RestartPolicy) -> Self {
+        Self {
+            policy,
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            reset_after: Duration::from_secs(10),
+            attempts: 0,
+            started_at: Instant::now(),
+        }
+    }
+
+    pub fn with_backoff(mut self, initial_delay: Duration, max_delay: Duration) -> Self {
+        self.initial_delay = initial_delay;
+        self.max_delay = max_delay;
+        self
+    }
+
+    pub fn with_reset_after(mut self, reset_after: Duration) -> Self {
+        self.reset_after = reset_after;
+        self
+    }
+
+    pub fn policy(&self) -> RestartPolicy {
+        self.policy
+    }
+
+    /// Records that the subroutine has been (re)started.
+    pub fn started(&mut self, now: Instant) {
+        self.started_at = now;
+    }
+
+    /// Decides whether (and when) to restart the subroutine, which exited at `now`.
+    pub fn exited(&mut self, success: bool, now: Instant) -> RestartDecision {
+        if now.saturating_duration_since(self.started_at) >= self.reset_after {
+            self.attempts = 0;
+        }
+
+        let restart = match self.policy {
+            RestartPolicy::Never => false,
+            RestartPolicy::OnFailure { max_retries } => !success && self.attempts < max_retries,
+            RestartPolicy::Always => true,
+        };
+        if !restart {
+            return RestartDecision::Stop;
+        }
+
+        self.attempts += 1;
+        let delay = self
+            .initial_delay
+            .checked_mul(2u32.saturating_pow(self.attempts - 1))
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay));
+        RestartDecision::Restart {
+            delay,
+            attempt: self.attempts,
+            crash_loop: self.attempts >= CRASH_LOOP_THRESHOLD,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+
+    use super::*;
+
+    fn tracker(policy: RestartPolicy) -> RestartTracker {
+        RestartTracker::new(policy)
+            .with_backoff(Duration::from_secs(1), Duration::from_secs(5))
+            .with_reset_after(Duration::from_secs(10))
+    }
+
+    #[rstest]
+    #[case("never", RestartPolicy::Never)]
+    #[case("always", RestartPolicy::Always)]
+    #[case("on-failure", RestartPolicy::OnFailure { max_retries: DEFAULT_MAX_RETRIES })]
+    #[case("on-failure:2", RestartPolicy::OnFailure { max_retries: 2 })]
+    fn parses_policies(#[case] input: &str, #[case] expected: RestartPolicy) {
+        assert_eq!(input.parse::<RestartPolicy>().unwrap(), expected);
+    }
+
+    #[rstest]
+    #[case("sometimes")]
+    #[case("on-failure:many")]
+    #[case("always:3")]
+    fn rejects_invalid_policies(#[case] input: &str) {
+        assert!(input.parse::<RestartPolicy>().is_err());
+    }
+
+    #[test]
+    fn never_stops() {
+        let mut tracker = tracker(RestartPolicy::Never);
+
+        assert_eq!(tracker.exited(false, Instant::now()), RestartDecision::Stop);
+    }
+
+    #[test]
+    fn on_failure_ignores_clean_exits_and_gives_up() {
+        let mut tracker = tracker(RestartPolicy::OnFailure { max_retries: 2 });
+        let now = Instant::now();
+        tracker.started(now);
+
+        assert_eq!(tracker.exited(true, now), RestartDecision::Stop);
+        assert!(matches!(
+            tracker.exited(false, now),
+            RestartDecision::Restart { attempt: 1, .. }
+        ));
+        assert!(matches!(
+            tracker.exited(false, now),
+            RestartDecision::Restart { attempt: 2, .. }
+        ));
+        assert_eq!(tracker.exited(false, now), RestartDecision::Stop);
+    }
+
+    #[test]
+    fn backs_off_exponentially_into_a_crash_loop() {
+        let mut tracker = tracker(RestartPolicy::Always);
+        let now = Instant::now();
+        tracker.started(now);
+
+        let delays: Vec<_> = (0..5)
+            .map(|_| match tracker.exited(true, now) {
+                RestartDecision::Restart {
+                    delay, crash_loop, ..
+                } => (delay.as_secs(), crash_loop),
+                RestartDecision::Stop => panic!("expected a restart"),
+            })
+            .collect();
+
+        assert_eq!(
+            delays,
+            vec![(1, false), (2, false), (4, true), (5, true), (5, true)]
+        );
+    }
+
+    #[test]
+    fn stable_runs_reset_the_backoff() {
+        let mut tracker = tracker(RestartPolicy::OnFailure { max_retries: 1 });
+        let now = Instant::now();
+        tracker.started(now);
+        assert!(matches!(
+            tracker.exited(false, now),
+            RestartDecision::Restart { attempt: 1, .. }
+        ));
+
+        tracker.started(now);
+        let later = now + Duration::from_secs(30);
+        assert_eq!(
+            tracker.exited(false, later),
+            RestartDecision::Restart {
+                delay: Duration::from_secs(1),
+                attempt: 1,
+                crash_loop: false
+            }
+        );
+    }
+}
@@ -0,0 +1,177 @@
+//! Classic POSIX limits (rlimits) on subroutine processes, and their wall-clock deadline.
+//!
+//! Unlike cgroup limits, rlimits apply per process, and are set by the shim in the forked child
+//! just before the subroutine is executed.  The deadline is enforced by the shim's event loop.
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::errors::error_chain_fmt;
+use crate::utils::libsee;
+
+/// Time (in seconds) between SIGTERM and SIGKILL when no grace period is given.
+pub const DEFAULT_GRACE_PERIOD: u64 = 10;
+
+#[derive(thiserror::Error)]
+pub enum ProcessLimitsError {
+    #[error("Invalid process limits: {0}")]
+    Invalid(String),
+}
+
+impl std::fmt::Debug for ProcessLimitsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// rlimits and deadline for a subroutine (unset limits are inherited from the shim).
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq, Serialize)]
+pub struct ProcessLimits {
+    /// Open file descriptors (`RLIMIT_NOFILE`).
+    #[serde(default)]
+    pub open_files: Option<u64>,
+    /// Core file size, in bytes (`RLIMIT_CORE`); `0` disables core dumps.
+    #[serde(default)]
+    pub core_size: Option<u64>,
+    /// Address space, in bytes (`RLIMIT_AS`).
+    #[serde(default)]
+    pub address_space: Option<u64>,
+    /// Processes owned by the subroutine's user (`RLIMIT_NPROC`).
+    #[serde(default)]
+    pub processes: Option<u64>,
+    /// Seconds the subroutine may run (across restarts) before it's terminated.
+    #[serde(default)]
+    pub deadline: Option<u64>,
+    /// Seconds between SIGTERM and SIGKILL, once the deadline passes.
+    #[serde(default = "default_grace_period")]
+    pub grace_period: u64,
+}
+
+fn default_grace_period() -> u64 {
+    DEFAULT_GRACE_PERIOD
+}
+
+impl Default for ProcessLimits {
+    fn default() -> Self {
+        Self {
+            open_files: None,
+            core_size: None,
+            address_space: None,
+            processes: None,
+            deadline: None,
+            grace_period: DEFAULT_GRACE_PERIOD,
+        }
+    }
+}
+
+impl ProcessLimits {
+    pub fn with_open_files(mut self, open_files: u64) -> Self {
+        self.open_files = Some(open_files);
+        self
+    }
+
+    pub fn with_core_size(mut self, core_size: u64) -> Self {
+        self.core_size = Some(core_size);
+        self
+    }
+
+    pub fn with_address_space(mut self, address_space: u64) -> Self {
+        self.address_space = Some(address_space);
+        self
+    }
+
+    pub fn with_processes(mut self, processes: u64) -> Self {
+        self.processes = Some(processes);
+        self
+    }
+
+    pub fn with_deadline(mut self, deadline: u64, grace_period: u64) -> Self {
+        self.deadline = Some(deadline);
+        self.grace_period = grace_period;
+        self
+    }
+
+    pub fn deadline(&self) -> Option<Duration> {
+        self.deadline.map(Duration::from_secs)
+    }
+
+    pub fn grace_period(&self) -> Duration {
+        Duration::from_secs(self.grace_period)
+    }
+
+    pub fn validate(&self) -> Result<(), ProcessLimitsError> {
+        let positive = [
+            ("open_files", self.open_files),
+            ("address_space", self.address_space),
+            ("processes", self.processes),
+            ("deadline", self.deadline),
+        ];
+        match positive.iter().find(|(_, limit)| *limit == Some(0)) {
+            Some((name, _)) => Err(ProcessLimitsError::Invalid(format!(
+                "{} must be positive",
+                name
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    /// The configured rlimits, as (resource, limit) pairs; resolved ahead of a fork, so the shim
+    /// can set them in the forked child (just before exec) without allocating.
+    pub fn rlimits(&self) -> Vec<(libsee::Resource, u64)> {
+        [
+            (libsee::RLIMIT_NOFILE, self.open_files),
+            (libsee::RLIMIT_CORE, self.core_size),
+            (libsee::RLIMIT_AS, self.address_space),
+            (libsee::RLIMIT_NPROC, self.processes),
+        ]
+        .into_iter()
+        .filter_map(|(resource, limit)| limit.map(|limit| (resource, limit)))
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+
+    use super::*;
+
+    #[test]
+    fn defaults_grace_period() {
+        let limits: ProcessLimits = serde_json::from_str(r#"{"deadline": 60}"#).unwrap();
+
+        assert_eq!(limits.deadline(), Some(Duration::from_secs(60)));
+        assert_eq!(
+            limits.grace_period(),
+            Duration::from_secs(DEFAULT_GRACE_PERIOD)
+        );
+    }
+
+    #[rstest]
+    #[case(ProcessLimits::default().with_open_files(0))]
+    #[case(ProcessLimits::default().with_processes(0))]
+    #[case(ProcessLimits::default().with_deadline(0, 5))]
+    fn rejects_zero_limits(#[case] limits: ProcessLimits) {
+        assert!(limits.validate().is_err());
+    }
+
+    #[test]
+    fn allows_disabling_core_dumps() {
+        assert!(ProcessLimits::default()
+            .with_core_size(0)
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    fn only_sets_configured_rlimits() {
+        let limits = ProcessLimits::default()
+            .with_open_files(1024)
+            .with_core_size(0);
+
+        assert_eq!(
+            limits.rlimits(),
+            vec![(libsee::RLIMIT_NOFILE, 1024), (libsee::RLIMIT_CORE, 0)]
+        );
+    }
+}
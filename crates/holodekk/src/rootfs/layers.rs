@@ -0,0 +1,295 @@
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+use crate::images::{Digest, DigestWriter};
+use crate::utils::libsee;
+use crate::HolodekkPaths;
+
+use super::{whiteout, RootfsError, RootfsResult, Whiteout};
+
+const LAYER_METADATA: &str = "layer.json";
+const LAYER_ARCHIVE: &str = "layer.tar";
+const LAYER_FILES: &str = "files";
+const LAYER_OVERLAY: &str = "overlay";
+
+/// Extended attribute marking a directory as opaque to overlayfs.
+const OVERLAY_OPAQUE_XATTR: &str = "trusted.overlay.opaque";
+
+/// An unpacked layer in the [`LayerCache`].
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct Layer {
+    pub digest: Digest,
+    pub size: u64,
+    /// Whether the layer hides entries from the layers beneath it.
+    pub whiteouts: bool,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl Layer {
+    pub fn path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    /// The layer's files, exactly as they were archived (whiteout markers included).
+    pub fn files(&self) -> PathBuf {
+        self.path.join(LAYER_FILES)
+    }
+
+    /// The layer's files, with whiteout markers converted to the form overlayfs expects.
+    ///
+    /// Overlayfs represents whiteouts as character devices (and opaque directories with an
+    /// extended attribute), neither of which an unprivileged unpack can create; so the
+    /// converted tree is only built (from hard links) the first time it's needed.
+    pub fn overlay_files(&self) -> RootfsResult<PathBuf> {
+        if !self.whiteouts {
+            return Ok(self.files());
+        }
+
+        let overlay = self.path.join(LAYER_OVERLAY);
+        if !overlay.exists() {
+            let staging = self.path.join(format!(".{}.partial", LAYER_OVERLAY));
+            if staging.exists() {
+                fs::remove_dir_all(&staging)?;
+            }
+            fs::create_dir(&staging)?;
+            if let Err(err) = link_overlay_tree(&self.files(), &staging) {
+                if let Err(cleanup) = fs::remove_dir_all(&staging) {
+                    warn!(
+                        "Failed to cleanup overlay layer {}: {}",
+                        self.digest, cleanup
+                    );
+                }
+                return Err(err);
+            }
+            fs::rename(&staging, &overlay)?;
+        }
+        Ok(overlay)
+    }
+}
+
+/// Mirrors a layer's files as hard links, converting whiteout markers for overlayfs.
+fn link_overlay_tree(src: &Path, dst: &Path) -> RootfsResult<()> {
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let target = dst.join(&name);
+        let file_type = entry.file_type()?;
+        match whiteout(&name)? {
+            Some(Whiteout::Opaque) => {
+                libsee::setxattr(dst, OVERLAY_OPAQUE_XATTR, b"y").map_err(RootfsError::Whiteout)?
+            }
+            Some(Whiteout::Entry(hidden)) => libsee::mknod(&dst.join(hidden), libsee::S_IFCHR, 0)
+                .map_err(RootfsError::Whiteout)?,
+            None if file_type.is_dir() => {
+                fs::create_dir(&target)?;
+                link_overlay_tree(&entry.path(), &target)?;
+                fs::set_permissions(&target, entry.metadata()?.permissions())?;
+            }
+            None if file_type.is_symlink() => symlink(fs::read_link(entry.path())?, &target)?,
+            None => fs::hard_link(entry.path(), &target)?,
+        }
+    }
+    Ok(())
+}
+
+/// Cache of unpacked layers, addressed by the digest of their archive.
+///
+/// ```text
+/// <layers_root>/<digest hex>/layer.json
+/// <layers_root>/<digest hex>/files/
+/// <layers_root>/<digest hex>/overlay/   (only for layers with whiteouts)
+/// ```
+///
+/// Layers are immutable once added, and shared by every subroutine built from them.
+#[derive(Clone, Debug)]
+pub struct LayerCache {
+    root: PathBuf,
+}
+
+impl LayerCache {
+    pub fn new(paths: &HolodekkPaths) -> Self {
+        Self::from_root(paths.layers_root())
+    }
+
+    pub fn from_root<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into() }
+    }
+
+    fn layer_root(&self, digest: &Digest) -> PathBuf {
+        self.root.join(digest.hex())
+    }
+
+    /// Unpacks a tar archive into the cache (or returns the existing layer, if it's cached).
+    pub fn add(&self, archive: &mut dyn Read) -> RootfsResult<Layer> {
+        fs::create_dir_all(&self.root)?;
+        let staging = tempfile::Builder::new()
+            .prefix(".layer.")
+            .suffix(".partial")
+            .tempdir_in(&self.root)?;
+
+        let archive_path = staging.path().join(LAYER_ARCHIVE);
+        let mut writer = DigestWriter::new(File::create(&archive_path)?);
+        io::copy(archive, &mut writer)?;
+        let (digest, size, _) = writer.finalize();
+        if let Ok(layer) = self.get(&digest) {
+            debug!("Layer {} already cached", digest);
+            return Ok(layer);
+        }
+
+        let files = staging.path().join(LAYER_FILES);
+        fs::create_dir(&files)?;
+        let mut unpacker = tar::Archive::new(File::open(&archive_path)?);
+        unpacker.set_preserve_permissions(true);
+        unpacker.unpack(&files)?;
+        fs::remove_file(&archive_path)?;
+
+        let layer = Layer {
+            whiteouts: has_whiteouts(&files)?,
+            path: self.layer_root(&digest),
+            digest,
+            size,
+        };
+        fs::write(
+            staging.path().join(LAYER_METADATA),
+            serde_json::to_vec_pretty(&layer)?,
+        )?;
+
+        // (whatever's left of the staging directory is removed when it's dropped)
+        match fs::rename(staging.path(), &layer.path) {
+            Ok(()) => {
+                debug!("Cached layer {} ({} bytes)", layer.digest, layer.size);
+                Ok(layer)
+            }
+            Err(err) => {
+                // someone else cached it first
+                if layer.path.join(LAYER_METADATA).exists() {
+                    self.get(&layer.digest)
+                } else {
+                    Err(err.into())
+                }
+            }
+        }
+    }
+
+    /// Convenience wrapper around [LayerCache::add] for an archive on disk.
+    pub fn add_file<P: AsRef<Path>>(&self, path: P) -> RootfsResult<Layer> {
+        self.add(&mut File::open(path)?)
+    }
+
+    pub fn get(&self, digest: &Digest) -> RootfsResult<Layer> {
+        let path = self.layer_root(digest);
+        let metadata = match fs::read(path.join(LAYER_METADATA)) {
+            Ok(metadata) => metadata,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Err(RootfsError::NotFound(digest.to_owned()))
+            }
+            Err(err) => return Err(err.into()),
+        };
+        let layer: Layer = serde_json::from_slice(&metadata)?;
+        Ok(Layer { path, ..layer })
+    }
+}
+
+fn has_whiteouts(files: &Path) -> RootfsResult<bool> {
+    for entry in WalkDir::new(files).min_depth(1) {
+        if whiteout(entry.map_err(io::Error::from)?.file_name())?.is_some() {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+    use tempfile::tempdir;
+
+    use crate::images::archive_directory;
+
+    use super::*;
+
+    fn archive(root: &Path, files: &[(&str, &str)]) -> Vec<u8> {
+        let dir = tempfile::tempdir_in(root).unwrap();
+        for (name, contents) in files {
+            let path = dir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        archive_directory(dir.path(), Vec::new()).unwrap()
+    }
+
+    #[test]
+    fn adds_layers_by_digest() {
+        let temp = tempdir().unwrap();
+        let cache = LayerCache::from_root(temp.path().join("layers"));
+        let archive = archive(temp.path(), &[("app/main.sh", "echo hello\n")]);
+
+        let layer = cache.add(&mut archive.as_slice()).unwrap();
+
+        assert_eq!(layer.digest, Digest::compute(&archive));
+        assert_eq!(layer.size, archive.len() as u64);
+        assert!(!layer.whiteouts);
+        assert_eq!(
+            fs::read_to_string(layer.files().join("app/main.sh")).unwrap(),
+            "echo hello\n"
+        );
+        assert_eq!(cache.get(&layer.digest).unwrap(), layer);
+    }
+
+    #[test]
+    fn reuses_cached_layers() {
+        let temp = tempdir().unwrap();
+        let cache = LayerCache::from_root(temp.path().join("layers"));
+        let archive = archive(temp.path(), &[("app/main.sh", "echo hello\n")]);
+
+        let first = cache.add(&mut archive.as_slice()).unwrap();
+        let second = cache.add(&mut archive.as_slice()).unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(fs::read_dir(temp.path().join("layers")).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn detects_whiteouts() {
+        let temp = tempdir().unwrap();
+        let cache = LayerCache::from_root(temp.path().join("layers"));
+        let archive = archive(temp.path(), &[("etc/.wh.motd", "")]);
+
+        let layer = cache.add(&mut archive.as_slice()).unwrap();
+
+        assert!(layer.whiteouts);
+    }
+
+    #[rstest]
+    #[case(".wh.")]
+    #[case(".wh..")]
+    #[case(".wh...")]
+    fn rejects_layers_with_invalid_whiteouts(#[case] name: &str) {
+        let temp = tempdir().unwrap();
+        let cache = LayerCache::from_root(temp.path().join("layers"));
+        let archive = archive(temp.path(), &[(&format!("etc/{}", name), "")]);
+
+        assert!(matches!(
+            cache.add(&mut archive.as_slice()).unwrap_err(),
+            RootfsError::InvalidWhiteout(_)
+        ));
+    }
+
+    #[test]
+    fn missing_layers_are_not_found() {
+        let temp = tempdir().unwrap();
+        let cache = LayerCache::from_root(temp.path().join("layers"));
+
+        assert!(matches!(
+            cache.get(&Digest::compute(b"missing")).unwrap_err(),
+            RootfsError::NotFound(..)
+        ));
+    }
+}
@@ -0,0 +1,384 @@
+//! Root filesystems for subroutines, assembled from ordered tar layers.
+//!
+//! Layers (typically a base layer with the runtime, and an app layer on top) are unpacked once
+//! into a [`LayerCache`], then composed into a root per subroutine.  Composition uses overlayfs
+//! when it's available, and otherwise falls back to a private copy of the merged layers.
+//!
+//! Layers follow the usual tar conventions for deletions: a `.wh.<name>` entry hides `<name>`
+//! in the layers beneath it, and a `.wh..wh..opq` entry hides everything in its directory.
+mod layers;
+pub use layers::*;
+
+use std::ffi::OsStr;
+use std::fs;
+use std::io;
+use std::os::unix::fs::symlink;
+use std::path::{Path, PathBuf};
+
+use log::{debug, warn};
+
+use crate::errors::error_chain_fmt;
+use crate::images::Digest;
+use crate::utils::libsee;
+
+const WHITEOUT_PREFIX: &str = ".wh.";
+const WHITEOUT_OPAQUE: &str = ".wh..wh..opq";
+
+const ROOTFS_MERGED: &str = "merged";
+const ROOTFS_UPPER: &str = "upper";
+const ROOTFS_WORK: &str = "work";
+
+#[derive(thiserror::Error)]
+pub enum RootfsError {
+    #[error("Layer not found: {0}")]
+    NotFound(Digest),
+    #[error("No layers to compose")]
+    NoLayers,
+    #[error("Failed to mount overlay rootfs")]
+    Mount(#[source] libsee::Error),
+    #[error("Invalid whiteout in layer: {0}")]
+    InvalidWhiteout(String),
+    #[error("Failed to create overlay whiteout")]
+    Whiteout(#[source] libsee::Error),
+    #[error("Rootfs IO error")]
+    Io(#[from] io::Error),
+    #[error("Layer metadata serialization error")]
+    Serialization(#[from] serde_json::Error),
+}
+
+impl std::fmt::Debug for RootfsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+pub type RootfsResult<T> = std::result::Result<T, RootfsError>;
+
+enum Whiteout<'a> {
+    /// Hides everything (from lower layers) in the directory.
+    Opaque,
+    /// Hides the named entry (from lower layers).
+    Entry(&'a str),
+}
+
+/// Parses a whiteout marker, rejecting those that would hide anything outside their own
+/// directory (or the directory itself).
+fn whiteout(name: &OsStr) -> RootfsResult<Option<Whiteout<'_>>> {
+    let Some(name) = name.to_str() else {
+        return Ok(None);
+    };
+    if name == WHITEOUT_OPAQUE {
+        return Ok(Some(Whiteout::Opaque));
+    }
+    match name.strip_prefix(WHITEOUT_PREFIX) {
+        Some(hidden) if hidden.is_empty() || hidden == "." || hidden == ".." => {
+            Err(RootfsError::InvalidWhiteout(name.to_string()))
+        }
+        Some(hidden) if hidden.contains('/') => Err(RootfsError::InvalidWhiteout(name.to_string())),
+        hidden => Ok(hidden.map(Whiteout::Entry)),
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RootfsKind {
+    Overlay,
+    Copy,
+}
+
+/// A subroutine's composed root filesystem.
+///
+/// ```text
+/// <root>/merged/   (what the subroutine sees)
+/// <root>/upper/    (overlay only: the subroutine's changes)
+/// <root>/work/     (overlay only)
+/// ```
+#[derive(Clone, Debug)]
+pub struct Rootfs {
+    root: PathBuf,
+    kind: RootfsKind,
+}
+
+impl Rootfs {
+    /// Composes the layers (base first) beneath `root`, replacing anything already there.
+    pub fn compose<P: AsRef<Path>>(layers: &[Layer], root: P) -> RootfsResult<Self> {
+        if overlay_supported() {
+            match Self::overlay(layers, &root) {
+                Ok(rootfs) => return Ok(rootfs),
+                Err(err) => warn!("Unable to mount overlay rootfs, copying instead: {}", err),
+            }
+        }
+        Self::copy(layers, root)
+    }
+
+    /// Composes the layers with an overlay mount (which needs `CAP_SYS_ADMIN`).
+    pub fn overlay<P: AsRef<Path>>(layers: &[Layer], root: P) -> RootfsResult<Self> {
+        let rootfs = Self::prepare(layers, root.as_ref(), RootfsKind::Overlay)?;
+        let result = layers
+            .iter()
+            .rev()
+            .map(|layer| Ok(layer.overlay_files()?.display().to_string()))
+            .collect::<RootfsResult<Vec<_>>>()
+            .and_then(|lower| {
+                let options = format!(
+                    "lowerdir={},upperdir={},workdir={}",
+                    lower.join(":"),
+                    rootfs.root.join(ROOTFS_UPPER).display(),
+                    rootfs.root.join(ROOTFS_WORK).display()
+                );
+                libsee::mount("overlay", &rootfs.path(), "overlay", 0, &options)
+                    .map_err(RootfsError::Mount)
+            });
+        match result {
+            Ok(()) => {
+                debug!("Mounted overlay rootfs at {}", rootfs.path().display());
+                Ok(rootfs)
+            }
+            Err(err) => {
+                rootfs.discard();
+                Err(err)
+            }
+        }
+    }
+
+    /// Composes the layers by copying them, in order, into a private tree.
+    pub fn copy<P: AsRef<Path>>(layers: &[Layer], root: P) -> RootfsResult<Self> {
+        let rootfs = Self::prepare(layers, root.as_ref(), RootfsKind::Copy)?;
+        for layer in layers {
+            if let Err(err) = merge_layer(&layer.files(), &rootfs.path()) {
+                rootfs.discard();
+                return Err(err);
+            }
+        }
+        debug!("Copied rootfs to {}", rootfs.path().display());
+        Ok(rootfs)
+    }
+
+    fn prepare(layers: &[Layer], root: &Path, kind: RootfsKind) -> RootfsResult<Self> {
+        if layers.is_empty() {
+            return Err(RootfsError::NoLayers);
+        }
+
+        let rootfs = Self {
+            root: root.to_owned(),
+            kind,
+        };
+        if root.exists() {
+            // left behind by a previous run
+            let _ = libsee::umount2(&rootfs.path(), libsee::MNT_DETACH);
+            fs::remove_dir_all(root)?;
+        }
+        fs::create_dir_all(rootfs.path())?;
+        if kind == RootfsKind::Overlay {
+            fs::create_dir(root.join(ROOTFS_UPPER))?;
+            fs::create_dir(root.join(ROOTFS_WORK))?;
+        }
+        Ok(rootfs)
+    }
+
+    fn discard(&self) {
+        if let Err(err) = fs::remove_dir_all(&self.root) {
+            warn!("Failed to cleanup rootfs {}: {}", self.root.display(), err);
+        }
+    }
+
+    /// The composed tree, as seen by the subroutine.
+    pub fn path(&self) -> PathBuf {
+        self.root.join(ROOTFS_MERGED)
+    }
+
+    pub fn kind(&self) -> RootfsKind {
+        self.kind
+    }
+
+    /// Unmounts (if need be) and removes the rootfs, along with any changes made to it.
+    pub fn remove(&self) -> RootfsResult<()> {
+        if self.kind == RootfsKind::Overlay {
+            libsee::umount2(&self.path(), libsee::MNT_DETACH).map_err(RootfsError::Mount)?;
+        }
+        fs::remove_dir_all(&self.root)?;
+        Ok(())
+    }
+}
+
+fn overlay_supported() -> bool {
+    fs::read_to_string("/proc/filesystems")
+        .map(|filesystems| {
+            filesystems
+                .lines()
+                .any(|line| line.split_whitespace().last() == Some("overlay"))
+        })
+        .unwrap_or(false)
+}
+
+/// Copies a layer over the layers already merged into `dst`, applying its whiteouts.
+fn merge_layer(src: &Path, dst: &Path) -> RootfsResult<()> {
+    let entries = fs::read_dir(src)?.collect::<io::Result<Vec<_>>>()?;
+
+    // whiteouts first, so they only hide entries from the layers beneath
+    for entry in entries.iter() {
+        match whiteout(&entry.file_name())? {
+            Some(Whiteout::Opaque) => {
+                for existing in fs::read_dir(dst)? {
+                    remove_path(&existing?.path())?;
+                }
+            }
+            Some(Whiteout::Entry(name)) => remove_path(&dst.join(name))?,
+            None => {}
+        }
+    }
+
+    for entry in entries
+        .iter()
+        .filter(|entry| matches!(whiteout(&entry.file_name()), Ok(None)))
+    {
+        let target = dst.join(entry.file_name());
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            let is_dir = fs::symlink_metadata(&target)
+                .map(|metadata| metadata.is_dir())
+                .unwrap_or(false);
+            if !is_dir {
+                remove_path(&target)?;
+                fs::create_dir(&target)?;
+            }
+            merge_layer(&entry.path(), &target)?;
+            fs::set_permissions(&target, entry.metadata()?.permissions())?;
+        } else if file_type.is_symlink() {
+            remove_path(&target)?;
+            symlink(fs::read_link(entry.path())?, &target)?;
+        } else if file_type.is_file() {
+            remove_path(&target)?;
+            fs::copy(entry.path(), &target)?;
+        } else {
+            debug!("Skipping special file {}", entry.path().display());
+        }
+    }
+    Ok(())
+}
+
+fn remove_path(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(path),
+        Ok(_) => fs::remove_file(path),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(err) => Err(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rstest::*;
+    use tempfile::{tempdir, TempDir};
+
+    use crate::images::archive_directory;
+
+    use super::*;
+
+    #[fixture]
+    fn temp() -> TempDir {
+        tempdir().unwrap()
+    }
+
+    fn layer(temp: &TempDir, files: &[(&str, &str)]) -> Layer {
+        let dir = tempfile::tempdir_in(temp.path()).unwrap();
+        for (name, contents) in files {
+            let path = dir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        let archive = archive_directory(dir.path(), Vec::new()).unwrap();
+        LayerCache::from_root(temp.path().join("layers"))
+            .add(&mut archive.as_slice())
+            .unwrap()
+    }
+
+    fn read(rootfs: &Rootfs, name: &str) -> Option<String> {
+        fs::read_to_string(rootfs.path().join(name)).ok()
+    }
+
+    #[rstest]
+    fn copies_layers_in_order(temp: TempDir) {
+        let base = layer(&temp, &[("etc/motd", "base"), ("bin/run", "run")]);
+        let app = layer(&temp, &[("etc/motd", "app")]);
+
+        let rootfs = Rootfs::copy(&[base, app], temp.path().join("rootfs")).unwrap();
+
+        assert_eq!(rootfs.kind(), RootfsKind::Copy);
+        assert_eq!(read(&rootfs, "etc/motd").as_deref(), Some("app"));
+        assert_eq!(read(&rootfs, "bin/run").as_deref(), Some("run"));
+    }
+
+    #[rstest]
+    fn honors_whiteouts(temp: TempDir) {
+        let base = layer(&temp, &[("etc/secret", "hush"), ("etc/motd", "hi")]);
+        let app = layer(&temp, &[("etc/.wh.secret", "")]);
+
+        let rootfs = Rootfs::copy(&[base, app], temp.path().join("rootfs")).unwrap();
+
+        assert!(read(&rootfs, "etc/secret").is_none());
+        assert!(read(&rootfs, "etc/.wh.secret").is_none());
+        assert_eq!(read(&rootfs, "etc/motd").as_deref(), Some("hi"));
+    }
+
+    #[rstest]
+    #[case(".wh.")]
+    #[case(".wh..")]
+    #[case(".wh...")]
+    #[case(".wh.etc/passwd")]
+    fn rejects_whiteouts_outside_their_directory(#[case] name: &str) {
+        assert!(matches!(
+            whiteout(OsStr::new(name)),
+            Err(RootfsError::InvalidWhiteout(_))
+        ));
+    }
+
+    #[rstest]
+    #[case(".wh.")]
+    #[case(".wh..")]
+    #[case(".wh...")]
+    fn rejects_merging_invalid_whiteouts(temp: TempDir, #[case] name: &str) {
+        let base = layer(&temp, &[("etc/motd", "hi")]);
+        let app = layer(&temp, &[("etc/motd", "app")]);
+        // (tampered with after it was cached)
+        fs::write(app.files().join("etc").join(name), "").unwrap();
+
+        let result = Rootfs::copy(&[base, app], temp.path().join("rootfs"));
+
+        assert!(matches!(result, Err(RootfsError::InvalidWhiteout(_))));
+        assert!(!temp.path().join("rootfs").exists());
+    }
+
+    #[rstest]
+    fn honors_opaque_directories(temp: TempDir) {
+        let base = layer(&temp, &[("data/old", "old"), ("etc/motd", "hi")]);
+        let app = layer(&temp, &[("data/.wh..wh..opq", ""), ("data/new", "new")]);
+
+        let rootfs = Rootfs::copy(&[base, app], temp.path().join("rootfs")).unwrap();
+
+        assert!(read(&rootfs, "data/old").is_none());
+        assert_eq!(read(&rootfs, "data/new").as_deref(), Some("new"));
+        assert_eq!(read(&rootfs, "etc/motd").as_deref(), Some("hi"));
+    }
+
+    #[rstest]
+    fn replaces_previous_rootfs(temp: TempDir) {
+        let base = layer(&temp, &[("etc/motd", "hi")]);
+        let root = temp.path().join("rootfs");
+        let previous = Rootfs::copy(std::slice::from_ref(&base), &root).unwrap();
+        fs::write(previous.path().join("scratch"), "changes").unwrap();
+
+        let rootfs = Rootfs::copy(&[base], &root).unwrap();
+
+        assert!(read(&rootfs, "scratch").is_none());
+        rootfs.remove().unwrap();
+        assert!(!root.exists());
+    }
+
+    #[rstest]
+    fn rejects_empty_layer_lists(temp: TempDir) {
+        assert!(matches!(
+            Rootfs::compose(&[], temp.path().join("rootfs")).unwrap_err(),
+            RootfsError::NoLayers
+        ));
+    }
+}
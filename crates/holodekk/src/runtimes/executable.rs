@@ -0,0 +1,53 @@
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use crate::enums::SubroutineKind;
+
+use super::{
+    LaunchCommand, Runtime, RuntimeError, RuntimeResult, MANIFEST_SUBCOMMAND, RUN_SUBCOMMAND,
+};
+
+const ENTRYPOINT: &str = "holodekk";
+
+/// Runtime for prebuilt subroutines, shipped as an executable named `holodekk`.
+#[derive(Clone, Debug)]
+pub struct ExecutableRuntime;
+
+impl ExecutableRuntime {
+    fn entrypoint(path: &Path) -> PathBuf {
+        path.join(ENTRYPOINT)
+    }
+
+    fn is_executable(path: &Path) -> bool {
+        path.metadata()
+            .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+            .unwrap_or(false)
+    }
+
+    fn command(path: &Path, subcommand: &str) -> RuntimeResult<LaunchCommand> {
+        let entrypoint = Self::entrypoint(path);
+        if !Self::is_executable(&entrypoint) {
+            return Err(RuntimeError::MissingEntrypoint(entrypoint));
+        }
+
+        Ok(LaunchCommand::new(entrypoint.to_string_lossy(), path).arg(subcommand))
+    }
+}
+
+impl Runtime for ExecutableRuntime {
+    fn kind(&self) -> SubroutineKind {
+        SubroutineKind::Executable
+    }
+
+    fn detect(&self, path: &Path) -> bool {
+        Self::is_executable(&Self::entrypoint(path))
+    }
+
+    fn manifest_command(&self, path: &Path) -> RuntimeResult<LaunchCommand> {
+        Self::command(path, MANIFEST_SUBCOMMAND)
+    }
+
+    fn launch_command(&self, path: &Path) -> RuntimeResult<LaunchCommand> {
+        Self::command(path, RUN_SUBCOMMAND)
+    }
+}
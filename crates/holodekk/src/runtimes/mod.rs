@@ -0,0 +1,354 @@
+//! Language runtimes subroutines can be written in.
+//!
+//! A [Runtime] recognizes a subroutine from the files in its image and knows how to build the
+//! commands used to query and launch it.  The [RuntimeRegistry] holds the runtimes available to
+//! the system, and is consulted in registration order when detecting a subroutine's kind.
+mod executable;
+pub use executable::*;
+mod script;
+pub use script::*;
+
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::enums::SubroutineKind;
+
+#[derive(thiserror::Error, Debug)]
+pub enum RuntimeError {
+    #[error("No runtime registered for {0:?} subroutines")]
+    Unsupported(SubroutineKind),
+    #[error("Subroutine entrypoint not found: {0}")]
+    MissingEntrypoint(PathBuf),
+    #[error("Invalid launch command argument: {0}")]
+    InvalidArgument(String),
+}
+
+pub type RuntimeResult<T> = std::result::Result<T, RuntimeError>;
+
+/// Subcommand passed to a subroutine to dump its manifest.
+pub const MANIFEST_SUBCOMMAND: &str = "manifest";
+/// Subcommand passed to a subroutine to run it.
+pub const RUN_SUBCOMMAND: &str = "run";
+/// `PATH` given to subroutines that don't set their own.
+pub const DEFAULT_PATH: &str = "/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+/// Command line (and working directory) used to execute a subroutine.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct LaunchCommand {
+    /// Program to execute (resolved via `PATH` when not absolute).
+    pub program: String,
+    pub args: Vec<String>,
+    pub working_dir: PathBuf,
+    /// Environment variables set for the subroutine.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+}
+
+impl LaunchCommand {
+    pub fn new<S, P>(program: S, working_dir: P) -> Self
+    where
+        S: Into<String>,
+        P: Into<PathBuf>,
+    {
+        Self {
+            program: program.into(),
+            args: vec![],
+            working_dir: working_dir.into(),
+            env: BTreeMap::new(),
+        }
+    }
+
+    pub fn arg<S: Into<String>>(mut self, arg: S) -> Self {
+        self.args.push(arg.into());
+        self
+    }
+
+    pub fn env<K, V>(mut self, key: K, value: V) -> Self
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.env.insert(key.into(), value.into());
+        self
+    }
+
+    pub fn envs<I, K, V>(mut self, vars: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: Into<String>,
+        V: Into<String>,
+    {
+        self.env
+            .extend(vars.into_iter().map(|(k, v)| (k.into(), v.into())));
+        self
+    }
+
+    /// Program and arguments, ready to hand to `execvp`.
+    pub fn argv(&self) -> RuntimeResult<Vec<CString>> {
+        std::iter::once(&self.program)
+            .chain(self.args.iter())
+            .map(|arg| {
+                CString::new(arg.as_str()).map_err(|_| RuntimeError::InvalidArgument(arg.clone()))
+            })
+            .collect()
+    }
+
+    /// Complete environment (as `KEY=value` strings) for `execvpe`.
+    ///
+    /// Only the variables set on the command are included, plus a default `PATH`.
+    pub fn envp(&self) -> RuntimeResult<Vec<CString>> {
+        let path = (!self.env.contains_key("PATH")).then_some(("PATH", DEFAULT_PATH));
+        path.into_iter()
+            .chain(self.env.iter().map(|(k, v)| (k.as_str(), v.as_str())))
+            .map(|(key, value)| {
+                if key.is_empty() || key.contains('=') {
+                    return Err(RuntimeError::InvalidArgument(key.to_string()));
+                }
+                CString::new(format!("{}={}", key, value))
+                    .map_err(|_| RuntimeError::InvalidArgument(key.to_string()))
+            })
+            .collect()
+    }
+
+    /// Equivalent [std::process::Command] (for running the command from within holodekk).
+    pub fn command(&self) -> std::process::Command {
+        let mut command = std::process::Command::new(&self.program);
+        command
+            .args(&self.args)
+            .current_dir(&self.working_dir)
+            .envs(&self.env);
+        command
+    }
+}
+
+pub trait Runtime: Send + Sync + 'static {
+    /// Kind of subroutine handled by this runtime.
+    fn kind(&self) -> SubroutineKind;
+
+    /// Whether the subroutine at `path` is one this runtime can run.
+    fn detect(&self, path: &Path) -> bool;
+
+    /// Command which prints the subroutine's manifest (as JSON) to stdout.
+    fn manifest_command(&self, path: &Path) -> RuntimeResult<LaunchCommand>;
+
+    /// Command which runs the subroutine.
+    fn launch_command(&self, path: &Path) -> RuntimeResult<LaunchCommand>;
+}
+
+#[derive(Clone)]
+pub struct RuntimeRegistry {
+    runtimes: Vec<Arc<dyn Runtime>>,
+}
+
+impl RuntimeRegistry {
+    /// Registry without any runtimes.
+    pub fn empty() -> Self {
+        Self { runtimes: vec![] }
+    }
+
+    /// Adds a runtime.  Runtimes registered earlier take precedence during detection.
+    pub fn register<R: Runtime>(&mut self, runtime: R) -> &mut Self {
+        self.runtimes.push(Arc::new(runtime));
+        self
+    }
+
+    pub fn get(&self, kind: SubroutineKind) -> RuntimeResult<Arc<dyn Runtime>> {
+        self.runtimes
+            .iter()
+            .find(|runtime| runtime.kind() == kind)
+            .cloned()
+            .ok_or(RuntimeError::Unsupported(kind))
+    }
+
+    /// First runtime which recognizes the subroutine at `path`.
+    pub fn detect<P: AsRef<Path>>(&self, path: P) -> Option<Arc<dyn Runtime>> {
+        self.runtimes
+            .iter()
+            .find(|runtime| runtime.detect(path.as_ref()))
+            .cloned()
+    }
+
+    pub fn detect_kind<P: AsRef<Path>>(&self, path: P) -> SubroutineKind {
+        self.detect(path)
+            .map(|runtime| runtime.kind())
+            .unwrap_or(SubroutineKind::Unknown)
+    }
+
+    pub fn kinds(&self) -> Vec<SubroutineKind> {
+        self.runtimes.iter().map(|runtime| runtime.kind()).collect()
+    }
+}
+
+impl Default for RuntimeRegistry {
+    /// Registry containing the built-in runtimes.
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry
+            .register(ScriptRuntime::ruby())
+            .register(ScriptRuntime::python())
+            .register(ScriptRuntime::node())
+            .register(ScriptRuntime::shell())
+            .register(ExecutableRuntime);
+        registry
+    }
+}
+
+impl std::fmt::Debug for RuntimeRegistry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("RuntimeRegistry")
+            .field("kinds", &self.kinds())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    use rstest::*;
+    use tempfile::tempdir;
+
+    use super::*;
+
+    #[rstest]
+    #[case("holodekk.rb", SubroutineKind::Ruby)]
+    #[case("holodekk.py", SubroutineKind::Python)]
+    #[case("holodekk.js", SubroutineKind::Node)]
+    #[case("holodekk.sh", SubroutineKind::Shell)]
+    #[case("README.md", SubroutineKind::Unknown)]
+    fn detects_script_subroutines(#[case] entrypoint: &str, #[case] kind: SubroutineKind) {
+        let temp = tempdir().unwrap();
+        fs::write(temp.path().join(entrypoint), "").unwrap();
+
+        assert_eq!(RuntimeRegistry::default().detect_kind(temp.path()), kind);
+    }
+
+    #[test]
+    fn detects_executable_subroutines() {
+        let temp = tempdir().unwrap();
+        let entrypoint = temp.path().join("holodekk");
+        fs::write(&entrypoint, "").unwrap();
+        assert_eq!(
+            RuntimeRegistry::default().detect_kind(temp.path()),
+            SubroutineKind::Unknown
+        );
+
+        fs::set_permissions(&entrypoint, fs::Permissions::from_mode(0o755)).unwrap();
+        assert_eq!(
+            RuntimeRegistry::default().detect_kind(temp.path()),
+            SubroutineKind::Executable
+        );
+    }
+
+    #[test]
+    fn builds_launch_command_for_scripts() {
+        let temp = tempdir().unwrap();
+        fs::write(temp.path().join("holodekk.py"), "").unwrap();
+        let runtime = RuntimeRegistry::default()
+            .get(SubroutineKind::Python)
+            .unwrap();
+
+        let command = runtime.launch_command(temp.path()).unwrap();
+
+        assert_eq!(command.program, "python3");
+        assert_eq!(
+            command.args,
+            vec![
+                temp.path()
+                    .join("holodekk.py")
+                    .to_string_lossy()
+                    .to_string(),
+                RUN_SUBCOMMAND.to_string()
+            ]
+        );
+        assert_eq!(command.working_dir, temp.path());
+    }
+
+    #[test]
+    fn requires_entrypoint_for_launch_command() {
+        let temp = tempdir().unwrap();
+        let runtime = RuntimeRegistry::default()
+            .get(SubroutineKind::Ruby)
+            .unwrap();
+
+        assert!(matches!(
+            runtime.launch_command(temp.path()).unwrap_err(),
+            RuntimeError::MissingEntrypoint(..)
+        ));
+    }
+
+    #[test]
+    fn earlier_runtimes_take_precedence() {
+        let temp = tempdir().unwrap();
+        fs::write(temp.path().join("holodekk.rb"), "").unwrap();
+        fs::write(temp.path().join("holodekk.sh"), "").unwrap();
+
+        assert_eq!(
+            RuntimeRegistry::default().detect_kind(temp.path()),
+            SubroutineKind::Ruby
+        );
+    }
+
+    #[test]
+    fn returns_error_for_unregistered_kinds() {
+        let registry = RuntimeRegistry::empty();
+
+        assert!(matches!(
+            registry.get(SubroutineKind::Ruby).err().unwrap(),
+            RuntimeError::Unsupported(SubroutineKind::Ruby)
+        ));
+    }
+
+    #[test]
+    fn converts_launch_command_to_argv() {
+        let command = LaunchCommand::new("ruby", "/tmp")
+            .arg("holodekk.rb")
+            .arg("run");
+
+        assert_eq!(
+            command.argv().unwrap(),
+            vec![
+                CString::new("ruby").unwrap(),
+                CString::new("holodekk.rb").unwrap(),
+                CString::new("run").unwrap(),
+            ]
+        );
+    }
+    #[test]
+    fn builds_envp_with_default_path() {
+        let command = LaunchCommand::new("ruby", "/tmp").env("RACK_ENV", "production");
+
+        assert_eq!(
+            command.envp().unwrap(),
+            vec![
+                CString::new(format!("PATH={}", DEFAULT_PATH)).unwrap(),
+                CString::new("RACK_ENV=production").unwrap(),
+            ]
+        );
+    }
+
+    #[test]
+    fn envp_keeps_explicit_path() {
+        let command = LaunchCommand::new("ruby", "/tmp").env("PATH", "/opt/bin");
+
+        assert_eq!(
+            command.envp().unwrap(),
+            vec![CString::new("PATH=/opt/bin").unwrap()]
+        );
+    }
+
+    #[test]
+    fn envp_rejects_invalid_variable_names() {
+        let command = LaunchCommand::new("ruby", "/tmp").env("BAD=NAME", "value");
+
+        assert!(matches!(
+            command.envp().unwrap_err(),
+            RuntimeError::InvalidArgument(..)
+        ));
+    }
+}
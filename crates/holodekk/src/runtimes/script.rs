@@ -0,0 +1,77 @@
+use std::path::{Path, PathBuf};
+
+use crate::enums::SubroutineKind;
+
+use super::{
+    LaunchCommand, Runtime, RuntimeError, RuntimeResult, MANIFEST_SUBCOMMAND, RUN_SUBCOMMAND,
+};
+
+/// Runtime for subroutines written in an interpreted language.
+///
+/// The subroutine is identified by its entrypoint script, which is handed to the interpreter
+/// along with the subcommand to run.
+#[derive(Clone, Debug)]
+pub struct ScriptRuntime {
+    kind: SubroutineKind,
+    entrypoint: &'static str,
+    interpreter: &'static str,
+}
+
+impl ScriptRuntime {
+    pub fn new(kind: SubroutineKind, entrypoint: &'static str, interpreter: &'static str) -> Self {
+        Self {
+            kind,
+            entrypoint,
+            interpreter,
+        }
+    }
+
+    pub fn ruby() -> Self {
+        Self::new(SubroutineKind::Ruby, "holodekk.rb", "ruby")
+    }
+
+    pub fn python() -> Self {
+        Self::new(SubroutineKind::Python, "holodekk.py", "python3")
+    }
+
+    pub fn node() -> Self {
+        Self::new(SubroutineKind::Node, "holodekk.js", "node")
+    }
+
+    pub fn shell() -> Self {
+        Self::new(SubroutineKind::Shell, "holodekk.sh", "/bin/sh")
+    }
+
+    fn entrypoint(&self, path: &Path) -> PathBuf {
+        path.join(self.entrypoint)
+    }
+
+    fn command(&self, path: &Path, subcommand: &str) -> RuntimeResult<LaunchCommand> {
+        let entrypoint = self.entrypoint(path);
+        if !entrypoint.is_file() {
+            return Err(RuntimeError::MissingEntrypoint(entrypoint));
+        }
+
+        Ok(LaunchCommand::new(self.interpreter, path)
+            .arg(entrypoint.to_string_lossy())
+            .arg(subcommand))
+    }
+}
+
+impl Runtime for ScriptRuntime {
+    fn kind(&self) -> SubroutineKind {
+        self.kind
+    }
+
+    fn detect(&self, path: &Path) -> bool {
+        self.entrypoint(path).is_file()
+    }
+
+    fn manifest_command(&self, path: &Path) -> RuntimeResult<LaunchCommand> {
+        self.command(path, MANIFEST_SUBCOMMAND)
+    }
+
+    fn launch_command(&self, path: &Path) -> RuntimeResult<LaunchCommand> {
+        self.command(path, RUN_SUBCOMMAND)
+    }
+}
@@ -0,0 +1,392 @@
+//! Secrets stored encrypted at rest, for use in subroutine environments.
+//!
+//! Environment values reference secrets by name (`${secret:db-password}`).  References are
+//! stored (and returned by the API) as written; the values themselves are only ever decrypted
+//! when a subroutine's environment is resolved for launch.
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+
+use chacha20poly1305::{
+    aead::{Aead, KeyInit, Payload},
+    ChaCha20Poly1305, Key, Nonce,
+};
+use lazy_static::lazy_static;
+use rand::{rngs::OsRng, RngCore};
+use regex::{Captures, Regex};
+
+use crate::errors::error_chain_fmt;
+use crate::HolodekkPaths;
+
+const KEY_FILE: &str = "secrets.key";
+const SECRET_EXTENSION: &str = "secret";
+const KEY_LEN: usize = 32;
+const NONCE_LEN: usize = 12;
+
+lazy_static! {
+    static ref SECRET_NAME_RE: Regex = Regex::new(r"^[A-Za-z0-9][A-Za-z0-9_.-]*$").unwrap();
+    static ref SECRET_REFERENCE_RE: Regex = Regex::new(r"\$\{secret:([^}]*)\}").unwrap();
+    static ref ENV_NAME_RE: Regex = Regex::new(r"^[A-Za-z_][A-Za-z0-9_]*$").unwrap();
+}
+
+#[derive(thiserror::Error)]
+pub enum SecretError {
+    #[error("Invalid secret name: {0:?}")]
+    InvalidName(String),
+    #[error("Secret not found: {0}")]
+    NotFound(String),
+    #[error("Invalid environment variable name: {0:?}")]
+    InvalidVariable(String),
+    #[error("Unable to decrypt secret: {0}")]
+    Decryption(String),
+    #[error("Invalid secrets key")]
+    InvalidKey,
+    #[error("Secret store IO error")]
+    Io(#[from] std::io::Error),
+}
+
+impl std::fmt::Debug for SecretError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+pub type SecretResult<T> = std::result::Result<T, SecretError>;
+
+fn validate_name(name: &str) -> SecretResult<()> {
+    if SECRET_NAME_RE.is_match(name) {
+        Ok(())
+    } else {
+        Err(SecretError::InvalidName(name.to_string()))
+    }
+}
+
+/// Names of the secrets referenced by an environment value.
+pub fn secret_references(value: &str) -> Vec<&str> {
+    SECRET_REFERENCE_RE
+        .captures_iter(value)
+        .map(|captures| captures.get(1).unwrap().as_str())
+        .collect()
+}
+
+/// Checks variable names and secret references (but not that the secrets exist).
+pub fn validate_environment(environment: &HashMap<String, String>) -> SecretResult<()> {
+    for (key, value) in environment {
+        if !ENV_NAME_RE.is_match(key) {
+            return Err(SecretError::InvalidVariable(key.to_owned()));
+        }
+        for name in secret_references(value) {
+            validate_name(name)?;
+        }
+    }
+    Ok(())
+}
+
+/// Merges environment layers (later layers win) and substitutes secret references.
+pub fn resolve_environment<'a, I>(
+    layers: I,
+    secrets: &SecretStore,
+) -> SecretResult<HashMap<String, String>>
+where
+    I: IntoIterator<Item = &'a HashMap<String, String>>,
+{
+    let mut environment = HashMap::new();
+    for layer in layers {
+        validate_environment(layer)?;
+        environment.extend(layer.iter().map(|(k, v)| (k.to_owned(), v.to_owned())));
+    }
+
+    for value in environment.values_mut() {
+        if SECRET_REFERENCE_RE.is_match(value) {
+            let mut error = None;
+            let resolved = SECRET_REFERENCE_RE.replace_all(value, |captures: &Captures| {
+                match secrets.get(&captures[1]) {
+                    Ok(secret) => secret,
+                    Err(err) => {
+                        error.get_or_insert(err);
+                        String::new()
+                    }
+                }
+            });
+            if let Some(err) = error {
+                return Err(err);
+            }
+            *value = resolved.into_owned();
+        }
+    }
+    Ok(environment)
+}
+
+/// Secret store backed by a directory under `data_root`.
+///
+/// Each secret is encrypted (ChaCha20-Poly1305, bound to its name) with a key generated when
+/// the store is initialized:
+///
+/// ```text
+/// <secrets_root>/secrets.key
+/// <secrets_root>/<name>.secret
+/// ```
+#[derive(Clone, Debug)]
+pub struct SecretStore {
+    root: PathBuf,
+}
+
+impl SecretStore {
+    pub fn new(paths: &HolodekkPaths) -> Self {
+        Self::from_root(paths.secrets_root())
+    }
+
+    pub fn from_root<P: Into<PathBuf>>(root: P) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Creates the store directory, and generates the encryption key if it doesn't exist.
+    pub fn init(&self) -> io::Result<()> {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&self.root)?;
+
+        let key_path = self.root.join(KEY_FILE);
+        if !key_path.exists() {
+            let mut key = [0; KEY_LEN];
+            OsRng.fill_bytes(&mut key);
+            write_private(&key_path, &key)?;
+        }
+        Ok(())
+    }
+
+    fn cipher(&self) -> SecretResult<ChaCha20Poly1305> {
+        let key = fs::read(self.root.join(KEY_FILE))?;
+        if key.len() != KEY_LEN {
+            return Err(SecretError::InvalidKey);
+        }
+        Ok(ChaCha20Poly1305::new(Key::from_slice(&key)))
+    }
+
+    fn secret_path(&self, name: &str) -> PathBuf {
+        self.root.join(format!("{}.{}", name, SECRET_EXTENSION))
+    }
+
+    /// Stores (or replaces) a secret.
+    pub fn set(&self, name: &str, value: &str) -> SecretResult<()> {
+        validate_name(name)?;
+        let mut nonce = [0; NONCE_LEN];
+        OsRng.fill_bytes(&mut nonce);
+        let ciphertext = self
+            .cipher()?
+            .encrypt(
+                Nonce::from_slice(&nonce),
+                Payload {
+                    msg: value.as_bytes(),
+                    aad: name.as_bytes(),
+                },
+            )
+            .map_err(|_| SecretError::Decryption(name.to_string()))?;
+
+        let mut contents = nonce.to_vec();
+        contents.extend(ciphertext);
+        write_private(&self.secret_path(name), &contents)?;
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> SecretResult<String> {
+        validate_name(name)?;
+        let contents = match fs::read(self.secret_path(name)) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                return Err(SecretError::NotFound(name.to_string()))
+            }
+            Err(err) => return Err(err.into()),
+        };
+        if contents.len() < NONCE_LEN {
+            return Err(SecretError::Decryption(name.to_string()));
+        }
+
+        let (nonce, ciphertext) = contents.split_at(NONCE_LEN);
+        let plaintext = self
+            .cipher()?
+            .decrypt(
+                Nonce::from_slice(nonce),
+                Payload {
+                    msg: ciphertext,
+                    aad: name.as_bytes(),
+                },
+            )
+            .map_err(|_| SecretError::Decryption(name.to_string()))?;
+        String::from_utf8(plaintext).map_err(|_| SecretError::Decryption(name.to_string()))
+    }
+
+    /// Names of the stored secrets (values are never listed).
+    pub fn names(&self) -> SecretResult<Vec<String>> {
+        let mut names = vec![];
+        for entry in fs::read_dir(&self.root)? {
+            let path = entry?.path();
+            if path.extension().and_then(|ext| ext.to_str()) == Some(SECRET_EXTENSION) {
+                if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+                    names.push(name.to_string());
+                }
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    pub fn delete(&self, name: &str) -> SecretResult<()> {
+        validate_name(name)?;
+        match fs::remove_file(self.secret_path(name)) {
+            Ok(()) => Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                Err(SecretError::NotFound(name.to_string()))
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+/// Writes a file readable only by its owner (replacing it atomically).
+fn write_private(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let partial = path.with_extension("partial");
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(&partial)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+    fs::rename(partial, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use tempfile::{tempdir, TempDir};
+
+    use super::*;
+
+    fn store() -> (TempDir, SecretStore) {
+        let temp = tempdir().unwrap();
+        let store = SecretStore::from_root(temp.path().join("secrets"));
+        store.init().unwrap();
+        (temp, store)
+    }
+
+    fn env(vars: &[(&str, &str)]) -> HashMap<String, String> {
+        vars.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn stores_and_retrieves_secrets() {
+        let (_temp, store) = store();
+
+        store.set("db-password", "hunter2").unwrap();
+
+        assert_eq!(store.get("db-password").unwrap(), "hunter2");
+        assert_eq!(store.names().unwrap(), vec!["db-password"]);
+    }
+
+    #[test]
+    fn encrypts_secrets_at_rest() {
+        let (_temp, store) = store();
+
+        store.set("db-password", "hunter2").unwrap();
+
+        let path = store.secret_path("db-password");
+        let contents = fs::read(&path).unwrap();
+        assert!(!contents.windows(7).any(|window| window == b"hunter2"));
+        assert_eq!(
+            fs::metadata(&path).unwrap().permissions().mode() & 0o777,
+            0o600
+        );
+    }
+
+    #[test]
+    fn binds_ciphertext_to_secret_name() {
+        let (_temp, store) = store();
+        store.set("one", "value").unwrap();
+
+        fs::copy(store.secret_path("one"), store.secret_path("two")).unwrap();
+
+        assert!(matches!(
+            store.get("two").unwrap_err(),
+            SecretError::Decryption(..)
+        ));
+    }
+
+    #[test]
+    fn deletes_secrets() {
+        let (_temp, store) = store();
+        store.set("token", "abc").unwrap();
+
+        store.delete("token").unwrap();
+
+        assert!(matches!(
+            store.get("token").unwrap_err(),
+            SecretError::NotFound(..)
+        ));
+        assert!(matches!(
+            store.delete("token").unwrap_err(),
+            SecretError::NotFound(..)
+        ));
+    }
+
+    #[test]
+    fn rejects_invalid_names() {
+        let (_temp, store) = store();
+
+        assert!(matches!(
+            store.set("../escape", "value").unwrap_err(),
+            SecretError::InvalidName(..)
+        ));
+    }
+
+    #[test]
+    fn resolves_layered_environment() {
+        let (_temp, store) = store();
+        store.set("db-password", "hunter2").unwrap();
+        let scene = env(&[("MODE", "production"), ("REGION", "us-east")]);
+        let subroutine = env(&[
+            ("MODE", "staging"),
+            (
+                "DATABASE_URL",
+                "postgres://app:${secret:db-password}@db/app",
+            ),
+        ]);
+
+        let resolved = resolve_environment([&scene, &subroutine], &store).unwrap();
+
+        assert_eq!(resolved["MODE"], "staging");
+        assert_eq!(resolved["REGION"], "us-east");
+        assert_eq!(resolved["DATABASE_URL"], "postgres://app:hunter2@db/app");
+    }
+
+    #[test]
+    fn fails_to_resolve_missing_secrets() {
+        let (_temp, store) = store();
+        let environment = env(&[("TOKEN", "${secret:missing}")]);
+
+        assert!(matches!(
+            resolve_environment([&environment], &store).unwrap_err(),
+            SecretError::NotFound(..)
+        ));
+    }
+
+    #[test]
+    fn validates_environment() {
+        assert!(validate_environment(&env(&[("GOOD_NAME", "${secret:ok}")])).is_ok());
+        assert!(matches!(
+            validate_environment(&env(&[("1BAD", "value")])).unwrap_err(),
+            SecretError::InvalidVariable(..)
+        ));
+        assert!(matches!(
+            validate_environment(&env(&[("GOOD", "${secret:}")])).unwrap_err(),
+            SecretError::InvalidName(..)
+        ));
+    }
+}
@@ -1,5 +1,14 @@
+use crate::attach::AttachError;
+use crate::cgroups::CgroupError;
 use crate::entities::{EntityId, EntityIdError, EntityRepositoryError};
-use crate::images::ImageIdError;
+use crate::health::HealthProbeError;
+use crate::images::{ImageIdError, ImageVerificationError, SubroutineImageStoreError};
+use crate::logs::LogRotationError;
+use crate::ports::PortAllocatorError;
+use crate::privileges::PrivilegesError;
+use crate::rlimits::ProcessLimitsError;
+use crate::secrets::SecretError;
+use crate::utils::process::DaemonizeError;
 
 #[derive(thiserror::Error, Debug)]
 pub enum EntityServiceError {
@@ -11,8 +20,32 @@ pub enum EntityServiceError {
     NotFound(EntityId),
     #[error("Entity already exists")]
     NotUnique(String),
+    #[error("Entity in use: {0}")]
+    InUse(String),
     #[error("Repository error occurred")]
     Repository(#[from] EntityRepositoryError),
+    #[error("Image verification failed")]
+    ImageVerification(#[from] ImageVerificationError),
+    #[error("Image store error")]
+    ImageStore(#[from] SubroutineImageStoreError),
+    #[error("Invalid environment: {0}")]
+    InvalidEnvironment(#[from] SecretError),
+    #[error("Invalid health probe: {0}")]
+    InvalidHealthProbe(#[from] HealthProbeError),
+    #[error("Invalid process limits: {0}")]
+    InvalidProcessLimits(#[from] ProcessLimitsError),
+    #[error("Invalid log rotation: {0}")]
+    InvalidLogRotation(#[from] LogRotationError),
+    #[error("Resource limits error: {0}")]
+    Cgroup(#[from] CgroupError),
+    #[error("Port allocation failed")]
+    PortAllocation(#[from] PortAllocatorError),
+    #[error("Invalid privileges: {0}")]
+    Privileges(#[from] PrivilegesError),
+    #[error("Unable to attach to subroutine")]
+    Attach(#[from] AttachError),
+    #[error("Unable to launch subroutine")]
+    Launch(#[from] DaemonizeError),
     #[error(transparent)]
     Unexpected(#[from] anyhow::Error),
 }
@@ -2,13 +2,18 @@ use async_trait::async_trait;
 use log::{trace, warn};
 
 use crate::entities::{SceneEntity, SceneEntityRepository, SceneEntityRepositoryQuery};
+use crate::secrets::validate_environment;
 use crate::services::{EntityServiceError, EntityServiceResult};
 
 use super::{CreateScene, CreateSceneInput, SceneEntityService};
 
 impl From<&CreateSceneInput<'_>> for SceneEntity {
     fn from(input: &CreateSceneInput<'_>) -> SceneEntity {
-        SceneEntity::new(input.name.into())
+        let mut scene = SceneEntity::new(input.name.into());
+        if let Some(environment) = input.environment {
+            scene.environment = environment.clone();
+        }
+        scene
     }
 }
 
@@ -22,6 +27,9 @@ where
         input: &'a CreateSceneInput<'a>,
     ) -> EntityServiceResult<SceneEntity> {
         trace!("SceneEntityService#create({:?})", input);
+        if let Some(environment) = input.environment {
+            validate_environment(environment)?;
+        }
 
         // ensure a scene does not exist for this name
         let query = SceneEntityRepositoryQuery::builder()
@@ -40,6 +48,7 @@ where
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
     use std::sync::Arc;
 
     use rstest::*;
@@ -63,7 +72,7 @@ mod tests {
 
         let service = SceneEntityService::new(Arc::new(mock_scene_entity_repository));
 
-        let res = service.create(&CreateSceneInput { name: "existing" }).await;
+        let res = service.create(&CreateSceneInput::new("existing")).await;
 
         assert!(res.is_err());
         assert!(matches!(
@@ -101,10 +110,46 @@ mod tests {
         let service = SceneEntityService::new(Arc::new(mock_scene_entity_repository));
 
         service
-            .create(&CreateSceneInput {
-                name: &mock_scene_entity.name,
-            })
+            .create(&CreateSceneInput::new(&mock_scene_entity.name))
             .await
             .unwrap();
     }
+
+    #[rstest]
+    #[tokio::test]
+    async fn stores_scene_environment(
+        mut mock_scene_entity_repository: MockSceneEntityRepository,
+        mock_scene_entity: SceneEntity,
+    ) {
+        mock_scene_entity_repository
+            .expect_scenes_exists()
+            .return_once(|_| Ok(false));
+        mock_scene_entity_repository
+            .expect_scenes_create()
+            .withf(|scene| scene.environment["REGION"] == "us-east")
+            .return_once(Ok);
+        let service = SceneEntityService::new(Arc::new(mock_scene_entity_repository));
+        let environment = HashMap::from([("REGION".to_string(), "us-east".to_string())]);
+
+        service
+            .create(&CreateSceneInput::new(&mock_scene_entity.name).with_environment(&environment))
+            .await
+            .unwrap();
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn rejects_invalid_environment(mock_scene_entity: SceneEntity) {
+        let service = SceneEntityService::new(Arc::new(MockSceneEntityRepository::default()));
+        let environment = HashMap::from([("".to_string(), "value".to_string())]);
+
+        let res = service
+            .create(&CreateSceneInput::new(&mock_scene_entity.name).with_environment(&environment))
+            .await;
+
+        assert!(matches!(
+            res.unwrap_err(),
+            EntityServiceError::InvalidEnvironment(..)
+        ));
+    }
 }
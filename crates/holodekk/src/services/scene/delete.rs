@@ -1,7 +1,11 @@
 use async_trait::async_trait;
 use log::trace;
 
-use crate::entities::{EntityRepositoryError, SceneEntityId, SceneEntityRepository};
+use crate::entities::{
+    EntityRepositoryError, SceneEntityId, SceneEntityRepository, SubroutineEntityRepository,
+    SubroutineEntityRepositoryQuery,
+};
+use crate::enums::SubroutineStatus;
 use crate::services::{EntityServiceError, EntityServiceResult};
 
 use super::{DeleteScene, DeleteSceneInput, SceneEntityService};
@@ -9,7 +13,7 @@ use super::{DeleteScene, DeleteSceneInput, SceneEntityService};
 #[async_trait]
 impl<R> DeleteScene for SceneEntityService<R>
 where
-    R: SceneEntityRepository,
+    R: SceneEntityRepository + SubroutineEntityRepository,
 {
     async fn delete<'a>(&self, input: &'a DeleteSceneInput<'a>) -> EntityServiceResult<()> {
         trace!("SceneEntityService#delete({:?}", input);
@@ -22,9 +26,32 @@ where
             _ => EntityServiceError::from(err),
         })?;
 
+        // the scene's user can't go to another scene while subroutines still run as it (those
+        // not yet reported on included)
+        let subroutines = self
+            .repo
+            .subroutines_find(
+                SubroutineEntityRepositoryQuery::builder()
+                    .for_scene_entity(&scene.id)
+                    .build(),
+            )
+            .await?;
+        if subroutines.iter().any(|subroutine| {
+            subroutine.status == SubroutineStatus::Unknown || subroutine.status.is_supervised()
+        }) {
+            return Err(EntityServiceError::InUse(format!(
+                "scene {} has running subroutines",
+                scene.id
+            )));
+        }
+
         // remove scene from the repository
         self.repo.scenes_delete(&scene.id).await?;
 
+        if let Some(scene_users) = self.scene_users.as_ref() {
+            scene_users.release(&scene.id)?;
+        }
+
         Ok(())
     }
 }
@@ -35,34 +62,52 @@ mod tests {
 
     use mockall::predicate::*;
     use rstest::*;
+    use tempfile::tempdir;
 
     use crate::entities::{
-        fixtures::{mock_scene_entity, mock_scene_entity_repository},
-        EntityRepositoryError, MockSceneEntityRepository, SceneEntity,
+        fixtures::{
+            mock_entity_repository, mock_scene_entity, mock_subroutine_entity, MockEntityRepository,
+        },
+        EntityRepositoryError, SceneEntity, SubroutineEntity,
     };
+    use crate::privileges::{SceneUserAllocator, UserRange};
 
     use super::*;
 
-    async fn execute(repo: MockSceneEntityRepository, id: &str) -> EntityServiceResult<()> {
+    async fn execute(repo: MockEntityRepository, id: &str) -> EntityServiceResult<()> {
         let service = SceneEntityService::new(Arc::new(repo));
 
         service.delete(&DeleteSceneInput::new(id)).await
     }
 
+    /// Sets the repository up with the scene, and the given subroutines in it.
+    fn existing_scene(
+        repo: &mut MockEntityRepository,
+        scene: &SceneEntity,
+        subroutines: Vec<SubroutineEntity>,
+    ) {
+        let entity = scene.clone();
+        repo.expect_scenes_get().return_once(move |_| Ok(entity));
+        let id = scene.id.clone();
+        repo.expect_subroutines_find()
+            .withf(move |query| query.scene_entity_id == Some(&id))
+            .return_once(move |_| Ok(subroutines));
+    }
+
     #[rstest]
     #[tokio::test]
     async fn returns_error_for_non_existent_scene(
-        mut mock_scene_entity_repository: MockSceneEntityRepository,
+        mut mock_entity_repository: MockEntityRepository,
     ) {
         let mock_id = SceneEntityId::generate();
 
         // scene does not exist
-        mock_scene_entity_repository
+        mock_entity_repository
             .expect_scenes_get()
             .with(eq(mock_id.clone()))
             .return_once(move |id| Err(EntityRepositoryError::NotFound(id.clone())));
 
-        let res = execute(mock_scene_entity_repository, &mock_id).await;
+        let res = execute(mock_entity_repository, &mock_id).await;
 
         assert!(matches!(res.unwrap_err(), EntityServiceError::NotFound(..)));
     }
@@ -70,46 +115,113 @@ mod tests {
     #[rstest]
     #[tokio::test]
     async fn removes_entry_in_repository(
-        mut mock_scene_entity_repository: MockSceneEntityRepository,
+        mut mock_entity_repository: MockEntityRepository,
         mock_scene_entity: SceneEntity,
     ) {
         // scene exists
-        {
-            let entity = mock_scene_entity.clone();
-            mock_scene_entity_repository
-                .expect_scenes_get()
-                .return_once(move |_| Ok(entity));
-        }
+        existing_scene(&mut mock_entity_repository, &mock_scene_entity, vec![]);
 
         // expect deletion
-        mock_scene_entity_repository
+        mock_entity_repository
             .expect_scenes_delete()
             .with(eq(mock_scene_entity.id.clone()))
             .return_once(move |_| Ok(()));
 
-        execute(mock_scene_entity_repository, &mock_scene_entity.id)
+        execute(mock_entity_repository, &mock_scene_entity.id)
             .await
             .unwrap();
     }
 
+    #[rstest]
+    #[tokio::test]
+    async fn releases_scene_user(
+        mut mock_entity_repository: MockEntityRepository,
+        mock_scene_entity: SceneEntity,
+        mut mock_subroutine_entity: SubroutineEntity,
+    ) {
+        let temp = tempdir().unwrap();
+        let scene_users = Arc::new(
+            SceneUserAllocator::from_file(temp.path().join("users.json"), UserRange::default())
+                .unwrap(),
+        );
+        let id = scene_users.allocate(&mock_scene_entity.id).unwrap();
+        mock_subroutine_entity.status = SubroutineStatus::Exited { code: 0 };
+        existing_scene(
+            &mut mock_entity_repository,
+            &mock_scene_entity,
+            vec![mock_subroutine_entity],
+        );
+        mock_entity_repository
+            .expect_scenes_delete()
+            .return_once(move |_| Ok(()));
+        let service = SceneEntityService::new(Arc::new(mock_entity_repository))
+            .with_scene_users(scene_users.clone());
+
+        service
+            .delete(&DeleteSceneInput::new(&mock_scene_entity.id))
+            .await
+            .unwrap();
+
+        assert_eq!(scene_users.release(&mock_scene_entity.id).unwrap(), None);
+        assert_eq!(
+            scene_users.allocate(&SceneEntityId::generate()).unwrap(),
+            id
+        );
+    }
+
+    #[rstest]
+    #[case(SubroutineStatus::Unknown)]
+    #[case(SubroutineStatus::Running(42))]
+    #[case(SubroutineStatus::Restarting { attempt: 1 })]
+    #[tokio::test]
+    async fn refuses_to_delete_scenes_with_running_subroutines(
+        mut mock_entity_repository: MockEntityRepository,
+        mock_scene_entity: SceneEntity,
+        mut mock_subroutine_entity: SubroutineEntity,
+        #[case] status: SubroutineStatus,
+    ) {
+        let temp = tempdir().unwrap();
+        let scene_users = Arc::new(
+            SceneUserAllocator::from_file(temp.path().join("users.json"), UserRange::default())
+                .unwrap(),
+        );
+        let id = scene_users.allocate(&mock_scene_entity.id).unwrap();
+        mock_subroutine_entity.status = status;
+        existing_scene(
+            &mut mock_entity_repository,
+            &mock_scene_entity,
+            vec![mock_subroutine_entity],
+        );
+        mock_entity_repository.expect_scenes_delete().never();
+        let service = SceneEntityService::new(Arc::new(mock_entity_repository))
+            .with_scene_users(scene_users.clone());
+
+        let res = service
+            .delete(&DeleteSceneInput::new(&mock_scene_entity.id))
+            .await;
+
+        assert!(matches!(res.unwrap_err(), EntityServiceError::InUse(..)));
+        // the scene keeps its user
+        assert_eq!(scene_users.allocate(&mock_scene_entity.id).unwrap(), id);
+        assert_ne!(
+            scene_users.allocate(&SceneEntityId::generate()).unwrap(),
+            id
+        );
+    }
+
     #[rstest]
     #[tokio::test]
     async fn returns_ok(
-        mut mock_scene_entity_repository: MockSceneEntityRepository,
+        mut mock_entity_repository: MockEntityRepository,
         mock_scene_entity: SceneEntity,
     ) {
-        {
-            let entity = mock_scene_entity.clone();
-            mock_scene_entity_repository
-                .expect_scenes_get()
-                .return_once(move |_| Ok(entity));
-        }
+        existing_scene(&mut mock_entity_repository, &mock_scene_entity, vec![]);
 
-        mock_scene_entity_repository
+        mock_entity_repository
             .expect_scenes_delete()
             .return_once(move |_| Ok(()));
 
-        let result = execute(mock_scene_entity_repository, &mock_scene_entity.id).await;
+        let result = execute(mock_entity_repository, &mock_scene_entity.id).await;
 
         assert!(result.is_ok());
     }
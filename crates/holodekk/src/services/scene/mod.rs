@@ -1,6 +1,8 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
 use crate::entities::{SceneEntity, SceneEntityRepository};
+use crate::privileges::SceneUserAllocator;
 
 use super::EntityServiceResult;
 
@@ -39,11 +41,21 @@ pub trait GetScene: Send + Sync + 'static {
 #[derive(Clone, Debug)]
 pub struct CreateSceneInput<'c> {
     pub name: &'c str,
+    pub environment: Option<&'c HashMap<String, String>>,
 }
 
 impl<'c> CreateSceneInput<'c> {
     pub fn new(name: &'c str) -> Self {
-        Self { name }
+        Self {
+            name,
+            environment: None,
+        }
+    }
+
+    /// Environment inherited by the scene's subroutines.
+    pub fn with_environment(mut self, environment: &'c HashMap<String, String>) -> Self {
+        self.environment = Some(environment);
+        self
     }
 }
 
@@ -98,6 +110,7 @@ where
     R: SceneEntityRepository,
 {
     repo: Arc<R>,
+    scene_users: Option<Arc<SceneUserAllocator>>,
 }
 
 impl<R> SceneEntityService<R>
@@ -105,7 +118,16 @@ where
     R: SceneEntityRepository,
 {
     pub fn new(repo: Arc<R>) -> Self {
-        Self { repo }
+        Self {
+            repo,
+            scene_users: None,
+        }
+    }
+
+    /// Releases each scene's user allocation when the scene is deleted.
+    pub fn with_scene_users(mut self, scene_users: Arc<SceneUserAllocator>) -> Self {
+        self.scene_users = Some(scene_users);
+        self
     }
 }
 
@@ -1,10 +1,14 @@
+use std::collections::HashMap;
+
 use async_trait::async_trait;
+use log::{debug, warn};
 
 use crate::entities::{
     SceneEntityId, SubroutineEntity, SubroutineEntityRepository, SubroutineEntityRepositoryQuery,
 };
 use crate::enums::SubroutineStatus;
-use crate::images::SubroutineImageId;
+use crate::images::{SubroutineImage, SubroutineImageId};
+use crate::secrets::validate_environment;
 use crate::services::{EntityServiceError, EntityServiceResult};
 
 use super::{CreateSubroutine, CreateSubroutineInput, SubroutineEntityService};
@@ -20,6 +24,26 @@ where
     ) -> EntityServiceResult<SubroutineEntity> {
         let scene_entity_id: SceneEntityId = input.scene_entity_id.parse()?;
         let subroutine_image_id: SubroutineImageId = input.subroutine_image_id.parse()?;
+        if let Some(environment) = input.environment {
+            validate_environment(environment)?;
+        }
+        if let Some(health_probe) = input.health_probe {
+            health_probe.validate()?;
+        }
+        input.limits.validate()?;
+        input.process_limits.validate()?;
+        input.log_rotation.validate()?;
+        if let Some(run_as) = input.run_as {
+            run_as.validate()?;
+        }
+
+        if let Some(verifier) = self.verifier.clone() {
+            // verification re-extracts the image, so keep it off the async workers
+            let image_id = subroutine_image_id.clone();
+            tokio::task::spawn_blocking(move || verifier.verify(&image_id))
+                .await
+                .map_err(anyhow::Error::from)??;
+        }
 
         let query = SubroutineEntityRepositoryQuery::builder()
             .for_scene_entity(&scene_entity_id)
@@ -34,27 +58,205 @@ where
         } else {
             let mut subroutine = SubroutineEntity::new(&scene_entity_id, &subroutine_image_id);
             subroutine.status = SubroutineStatus::Unknown;
-            let subroutine = self.repo.subroutines_create(subroutine).await?;
-            Ok(subroutine)
+            let mut endpoint_name = None;
+            let image = match self.images.as_ref() {
+                Some(images) => Some(images.get(&subroutine_image_id)?),
+                None => None,
+            };
+            if let Some(manifest) = image.as_ref().and_then(|image| image.manifest.as_ref()) {
+                if let Some(environment) = manifest.environment() {
+                    subroutine.environment = environment.clone();
+                }
+                subroutine.port = manifest.port();
+                endpoint_name = Some(manifest.name().to_string());
+            }
+            if let Some(environment) = input.environment {
+                subroutine.environment.extend(environment.clone());
+            }
+            subroutine.health_probe = input.health_probe.cloned().map(Box::new);
+            subroutine.restart_policy = input.restart_policy;
+            subroutine.limits = input.limits;
+            subroutine.process_limits = input.process_limits;
+            subroutine.log_rotation = input.log_rotation;
+            subroutine.log_format = input.log_format;
+            subroutine.stdin = input.stdin;
+            subroutine.tty = input.tty;
+            subroutine.run_as = input.run_as.cloned().unwrap_or_default();
+            if let Some(scene_users) = self.scene_users.as_ref() {
+                if subroutine.run_as.uid.is_none() {
+                    let id = scene_users.allocate(&scene_entity_id)?;
+                    subroutine.run_as.uid = Some(id);
+                    subroutine.run_as.gid = Some(id);
+                }
+            }
+
+            let ports = self.ports.as_ref().filter(|_| subroutine.port.is_some());
+            if let (Some(ports), Some(name)) = (ports, endpoint_name) {
+                let assignment =
+                    ports.allocate(&scene_entity_id, &subroutine.id, &name, subroutine.port)?;
+                subroutine.host_port = Some(assignment.port);
+            }
+
+            let subroutine_id = subroutine.id.clone();
+            let result = self
+                .store_and_launch(subroutine, input.scene_environment, image)
+                .await;
+            if result.is_err() {
+                if let Some(ports) = ports {
+                    if let Err(err) = ports.release(&subroutine_id) {
+                        warn!(
+                            "Failed to release port of subroutine {} after its creation failed: {}",
+                            subroutine_id, err
+                        );
+                    }
+                }
+            }
+            result
         }
     }
 }
 
+impl<R> SubroutineEntityService<R>
+where
+    R: SubroutineEntityRepository,
+{
+    /// Stores a new subroutine, then launches it (given a launcher, and its image), removing it
+    /// again if it can't be launched.
+    async fn store_and_launch(
+        &self,
+        subroutine: SubroutineEntity,
+        scene_environment: Option<&HashMap<String, String>>,
+        image: Option<SubroutineImage>,
+    ) -> EntityServiceResult<SubroutineEntity> {
+        let launch = match (self.launcher.as_ref(), image) {
+            (Some(launcher), Some(image)) => {
+                // resolved before anything is stored, so a missing secret fails the request
+                let discovery_environment = self
+                    .ports
+                    .as_ref()
+                    .map(|ports| ports.discovery_environment(&subroutine.scene_entity_id))
+                    .unwrap_or_default();
+                let no_environment = HashMap::new();
+                let environment = launcher.environment(
+                    &discovery_environment,
+                    scene_environment.unwrap_or(&no_environment),
+                    &subroutine,
+                )?;
+                Some((launcher.clone(), image, environment))
+            }
+            _ => None,
+        };
+
+        let subroutine = self.repo.subroutines_create(subroutine).await?;
+        if let Some((launcher, image, environment)) = launch {
+            // spawning waits for the shim to detach
+            let launching = subroutine.clone();
+            let launched = tokio::task::spawn_blocking(move || {
+                launcher.launch(&launching, &image, environment)
+            })
+            .await;
+            let launched = match launched {
+                Ok(launched) => launched.map_err(EntityServiceError::from),
+                Err(err) => Err(anyhow::Error::from(err).into()),
+            };
+            match launched {
+                Ok(pid) => debug!("Launched subroutine {} (shim pid {})", subroutine.id, pid),
+                Err(err) => {
+                    if let Err(cleanup) = self.repo.subroutines_delete(&subroutine.id).await {
+                        warn!(
+                            "Failed to remove subroutine {} after its launch failed: {}",
+                            subroutine.id, cleanup
+                        );
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        Ok(subroutine)
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use std::collections::HashMap;
     use std::sync::Arc;
 
     use rstest::*;
+    use tempfile::{tempdir, TempDir};
     use timestamps::Timestamps;
 
+    use crate::cgroups::{CgroupError, ResourceLimits};
     use crate::entities::{
         fixtures::{mock_scene_entity, mock_subroutine_entity_repository},
         MockSubroutineEntityRepository, SceneEntity, SubroutineEntityRepositoryQuery,
+        MANIFEST_FILE,
     };
-    use crate::images::{fixtures::mock_subroutine_image, SubroutineImage};
+    use crate::images::{
+        fixtures::{mock_subroutine_image, signing_key},
+        sign_image, FilesystemSubroutineImageStore, ImageVerificationError, ImageVerifier,
+        SignaturePolicy, SignatureStatus, SubroutineImage, SubroutineImageStore,
+        SubroutineImageStoreError, TrustStore,
+    };
+
+    use crate::health::{HealthProbe, ProbeCheck};
+    use crate::logs::LogFormat;
+    use crate::ports::{PortAllocator, PortRange};
+    use crate::privileges::{PrivilegesError, RunAs, SceneUserAllocator, UserRange};
+    use crate::rlimits::ProcessLimits;
 
     use super::*;
 
+    struct VerifierFixture {
+        _temp: TempDir,
+        store: Arc<FilesystemSubroutineImageStore>,
+        trust: TrustStore,
+        image: SubroutineImage,
+    }
+
+    impl VerifierFixture {
+        fn new() -> Self {
+            let temp = tempdir().unwrap();
+            let dir = temp.path().join("src");
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join("holodekk.rb"), "puts 'hello'\n").unwrap();
+
+            let store = Arc::new(FilesystemSubroutineImageStore::from_root(
+                temp.path().join("images"),
+            ));
+            let image = store
+                .create_from_directory(&"acme/widgets".into(), &dir)
+                .unwrap();
+            let trust = TrustStore::from_root(temp.path().join("trust"));
+            Self {
+                _temp: temp,
+                store,
+                trust,
+                image,
+            }
+        }
+
+        fn verifier(&self, policy: SignaturePolicy) -> Arc<ImageVerifier> {
+            Arc::new(ImageVerifier::new(
+                self.store.clone(),
+                self.trust.clone(),
+                policy,
+            ))
+        }
+    }
+
+    fn repo_expecting_create() -> MockSubroutineEntityRepository {
+        let mut repo = MockSubroutineEntityRepository::default();
+        repo.expect_subroutines_exists()
+            .return_once(move |_| Ok(false));
+        repo.expect_subroutines_create()
+            .return_once(move |mut sub| {
+                sub.created();
+                sub.updated();
+                Ok(sub)
+            });
+        repo
+    }
+
     async fn execute(
         repo: MockSubroutineEntityRepository,
         scene: &str,
@@ -174,4 +376,545 @@ mod tests {
         assert_eq!(new_subroutine.subroutine_image_id, image_id);
         assert_eq!(new_subroutine.status, status);
     }
+
+    #[rstest]
+    #[tokio::test]
+    async fn uses_image_manifest_as_defaults(mock_scene_entity: SceneEntity) {
+        let temp = tempdir().unwrap();
+        let dir = temp.path().join("src");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join(MANIFEST_FILE),
+            r#"{"name": "widgets", "environment": {"MODE": "test"}, "port": 4567}"#,
+        )
+        .unwrap();
+        let store = Arc::new(FilesystemSubroutineImageStore::from_root(
+            temp.path().join("images"),
+        ));
+        let image = store
+            .create_from_directory(&"acme/widgets".into(), &dir)
+            .unwrap();
+        let service =
+            SubroutineEntityService::new(Arc::new(repo_expecting_create())).with_images(store);
+
+        let subroutine = service
+            .create(&CreateSubroutineInput::new(
+                &mock_scene_entity.id,
+                &image.id,
+            ))
+            .await
+            .unwrap();
+
+        assert_eq!(subroutine.environment.get("MODE").unwrap(), "test");
+        assert_eq!(subroutine.port, Some(4567));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn allocates_host_port_for_declared_port(mock_scene_entity: SceneEntity) {
+        let temp = tempdir().unwrap();
+        let dir = temp.path().join("src");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join(MANIFEST_FILE),
+            r#"{"name": "widgets", "port": 4567}"#,
+        )
+        .unwrap();
+        let store = Arc::new(FilesystemSubroutineImageStore::from_root(
+            temp.path().join("images"),
+        ));
+        let image = store
+            .create_from_directory(&"acme/widgets".into(), &dir)
+            .unwrap();
+        let ports = Arc::new(
+            PortAllocator::from_file(temp.path().join("ports.json"), PortRange::default()).unwrap(),
+        );
+        let service = SubroutineEntityService::new(Arc::new(repo_expecting_create()))
+            .with_images(store)
+            .with_port_allocator(ports.clone());
+
+        let subroutine = service
+            .create(&CreateSubroutineInput::new(
+                &mock_scene_entity.id,
+                &image.id,
+            ))
+            .await
+            .unwrap();
+
+        let endpoints = ports.endpoints(&mock_scene_entity.id);
+        assert_eq!(endpoints.len(), 1);
+        assert_eq!(endpoints[0].name, "widgets");
+        assert_eq!(endpoints[0].subroutine_entity_id, subroutine.id);
+        assert_eq!(subroutine.host_port, Some(endpoints[0].port));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn overrides_manifest_environment(mock_scene_entity: SceneEntity) {
+        let temp = tempdir().unwrap();
+        let dir = temp.path().join("src");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join(MANIFEST_FILE),
+            r#"{"name": "widgets", "environment": {"MODE": "test", "LEVEL": "1"}}"#,
+        )
+        .unwrap();
+        let store = Arc::new(FilesystemSubroutineImageStore::from_root(
+            temp.path().join("images"),
+        ));
+        let image = store
+            .create_from_directory(&"acme/widgets".into(), &dir)
+            .unwrap();
+        let service =
+            SubroutineEntityService::new(Arc::new(repo_expecting_create())).with_images(store);
+        let environment = HashMap::from([
+            ("MODE".to_string(), "production".to_string()),
+            ("TOKEN".to_string(), "${secret:token}".to_string()),
+        ]);
+
+        let subroutine = service
+            .create(
+                &CreateSubroutineInput::new(&mock_scene_entity.id, &image.id)
+                    .with_environment(&environment),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(subroutine.environment["MODE"], "production");
+        assert_eq!(subroutine.environment["LEVEL"], "1");
+        assert_eq!(subroutine.environment["TOKEN"], "${secret:token}");
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn rejects_invalid_environment(
+        mock_scene_entity: SceneEntity,
+        mock_subroutine_image: SubroutineImage,
+    ) {
+        let service =
+            SubroutineEntityService::new(Arc::new(MockSubroutineEntityRepository::default()));
+        let environment = HashMap::from([("NOT-VALID".to_string(), "value".to_string())]);
+
+        let res = service
+            .create(
+                &CreateSubroutineInput::new(&mock_scene_entity.id, &mock_subroutine_image.id)
+                    .with_environment(&environment),
+            )
+            .await;
+
+        assert!(matches!(
+            res.unwrap_err(),
+            EntityServiceError::InvalidEnvironment(..)
+        ));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn stores_health_probe(
+        mock_scene_entity: SceneEntity,
+        mock_subroutine_image: SubroutineImage,
+    ) {
+        let service = SubroutineEntityService::new(Arc::new(repo_expecting_create()));
+        let probe = HealthProbe::new(ProbeCheck::Tcp { port: None });
+
+        let subroutine = service
+            .create(
+                &CreateSubroutineInput::new(&mock_scene_entity.id, &mock_subroutine_image.id)
+                    .with_health_probe(&probe),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(subroutine.health_probe, Some(Box::new(probe)));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn rejects_invalid_health_probe(
+        mock_scene_entity: SceneEntity,
+        mock_subroutine_image: SubroutineImage,
+    ) {
+        let service =
+            SubroutineEntityService::new(Arc::new(MockSubroutineEntityRepository::default()));
+        let probe = HealthProbe::new(ProbeCheck::Exec { command: vec![] });
+
+        let res = service
+            .create(
+                &CreateSubroutineInput::new(&mock_scene_entity.id, &mock_subroutine_image.id)
+                    .with_health_probe(&probe),
+            )
+            .await;
+
+        assert!(matches!(
+            res.unwrap_err(),
+            EntityServiceError::InvalidHealthProbe(..)
+        ));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn runs_as_scene_user_by_default(
+        mock_scene_entity: SceneEntity,
+        mock_subroutine_image: SubroutineImage,
+    ) {
+        let temp = tempdir().unwrap();
+        let scene_users = Arc::new(
+            SceneUserAllocator::from_file(temp.path().join("users.json"), UserRange::default())
+                .unwrap(),
+        );
+        let service = SubroutineEntityService::new(Arc::new(repo_expecting_create()))
+            .with_scene_users(scene_users.clone());
+
+        let subroutine = service
+            .create(&CreateSubroutineInput::new(
+                &mock_scene_entity.id,
+                &mock_subroutine_image.id,
+            ))
+            .await
+            .unwrap();
+
+        let id = scene_users.allocate(&mock_scene_entity.id).unwrap();
+        assert_eq!(subroutine.run_as.uid, Some(id));
+        assert_eq!(subroutine.run_as.gid, Some(id));
+        assert!(subroutine.run_as.no_new_privs);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn keeps_explicit_user(
+        mock_scene_entity: SceneEntity,
+        mock_subroutine_image: SubroutineImage,
+    ) {
+        let temp = tempdir().unwrap();
+        let scene_users = Arc::new(
+            SceneUserAllocator::from_file(temp.path().join("users.json"), UserRange::default())
+                .unwrap(),
+        );
+        let service = SubroutineEntityService::new(Arc::new(repo_expecting_create()))
+            .with_scene_users(scene_users);
+        let run_as = RunAs::default()
+            .with_user(1000, 100)
+            .with_capabilities(vec!["CAP_NET_BIND_SERVICE".into()]);
+
+        let subroutine = service
+            .create(
+                &CreateSubroutineInput::new(&mock_scene_entity.id, &mock_subroutine_image.id)
+                    .with_run_as(&run_as),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(subroutine.run_as, run_as);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn rejects_unknown_capabilities(
+        mock_scene_entity: SceneEntity,
+        mock_subroutine_image: SubroutineImage,
+    ) {
+        let service =
+            SubroutineEntityService::new(Arc::new(MockSubroutineEntityRepository::default()));
+        let run_as = RunAs::default().with_capabilities(vec!["CAP_EVERYTHING".into()]);
+
+        let res = service
+            .create(
+                &CreateSubroutineInput::new(&mock_scene_entity.id, &mock_subroutine_image.id)
+                    .with_run_as(&run_as),
+            )
+            .await;
+
+        assert!(matches!(
+            res.unwrap_err(),
+            EntityServiceError::Privileges(PrivilegesError::UnknownCapability(..))
+        ));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn rejects_users_without_groups(
+        mock_scene_entity: SceneEntity,
+        mock_subroutine_image: SubroutineImage,
+    ) {
+        let service =
+            SubroutineEntityService::new(Arc::new(MockSubroutineEntityRepository::default()));
+        let run_as = RunAs {
+            uid: Some(1000),
+            ..RunAs::default()
+        };
+
+        let res = service
+            .create(
+                &CreateSubroutineInput::new(&mock_scene_entity.id, &mock_subroutine_image.id)
+                    .with_run_as(&run_as),
+            )
+            .await;
+
+        assert!(matches!(
+            res.unwrap_err(),
+            EntityServiceError::Privileges(PrivilegesError::MissingGroup(1000))
+        ));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn rejects_groups_without_users(
+        mock_scene_entity: SceneEntity,
+        mock_subroutine_image: SubroutineImage,
+    ) {
+        let service =
+            SubroutineEntityService::new(Arc::new(MockSubroutineEntityRepository::default()));
+        let run_as = RunAs {
+            gid: Some(0),
+            ..RunAs::default()
+        };
+
+        let res = service
+            .create(
+                &CreateSubroutineInput::new(&mock_scene_entity.id, &mock_subroutine_image.id)
+                    .with_run_as(&run_as),
+            )
+            .await;
+
+        assert!(matches!(
+            res.unwrap_err(),
+            EntityServiceError::Privileges(PrivilegesError::MissingUser(0))
+        ));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn stores_process_limits(
+        mock_scene_entity: SceneEntity,
+        mock_subroutine_image: SubroutineImage,
+    ) {
+        let service = SubroutineEntityService::new(Arc::new(repo_expecting_create()));
+        let limits = ProcessLimits::default()
+            .with_open_files(256)
+            .with_deadline(3600, 30);
+
+        let subroutine = service
+            .create(
+                &CreateSubroutineInput::new(&mock_scene_entity.id, &mock_subroutine_image.id)
+                    .with_process_limits(limits),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(subroutine.process_limits, limits);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn stores_log_format(
+        mock_scene_entity: SceneEntity,
+        mock_subroutine_image: SubroutineImage,
+    ) {
+        let service = SubroutineEntityService::new(Arc::new(repo_expecting_create()));
+
+        let subroutine = service
+            .create(
+                &CreateSubroutineInput::new(&mock_scene_entity.id, &mock_subroutine_image.id)
+                    .with_log_format(LogFormat::Json),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(subroutine.log_format, LogFormat::Json);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn stores_stdin(mock_scene_entity: SceneEntity, mock_subroutine_image: SubroutineImage) {
+        let service = SubroutineEntityService::new(Arc::new(repo_expecting_create()));
+
+        let subroutine = service
+            .create(
+                &CreateSubroutineInput::new(&mock_scene_entity.id, &mock_subroutine_image.id)
+                    .with_stdin(true),
+            )
+            .await
+            .unwrap();
+
+        assert!(subroutine.stdin);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn stores_tty(mock_scene_entity: SceneEntity, mock_subroutine_image: SubroutineImage) {
+        let service = SubroutineEntityService::new(Arc::new(repo_expecting_create()));
+
+        let subroutine = service
+            .create(
+                &CreateSubroutineInput::new(&mock_scene_entity.id, &mock_subroutine_image.id)
+                    .with_tty(true),
+            )
+            .await
+            .unwrap();
+
+        assert!(subroutine.tty);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn rejects_invalid_process_limits(
+        mock_scene_entity: SceneEntity,
+        mock_subroutine_image: SubroutineImage,
+    ) {
+        let service =
+            SubroutineEntityService::new(Arc::new(MockSubroutineEntityRepository::default()));
+
+        let res = service
+            .create(
+                &CreateSubroutineInput::new(&mock_scene_entity.id, &mock_subroutine_image.id)
+                    .with_process_limits(ProcessLimits::default().with_processes(0)),
+            )
+            .await;
+
+        assert!(matches!(
+            res.unwrap_err(),
+            EntityServiceError::InvalidProcessLimits(..)
+        ));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn rejects_invalid_limits(
+        mock_scene_entity: SceneEntity,
+        mock_subroutine_image: SubroutineImage,
+    ) {
+        let service =
+            SubroutineEntityService::new(Arc::new(MockSubroutineEntityRepository::default()));
+
+        let res = service
+            .create(
+                &CreateSubroutineInput::new(&mock_scene_entity.id, &mock_subroutine_image.id)
+                    .with_limits(ResourceLimits::default().with_pids(0)),
+            )
+            .await;
+
+        assert!(matches!(
+            res.unwrap_err(),
+            EntityServiceError::Cgroup(CgroupError::InvalidLimits(..))
+        ));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn returns_error_when_image_is_missing(
+        mock_scene_entity: SceneEntity,
+        mock_subroutine_image: SubroutineImage,
+    ) {
+        let temp = tempdir().unwrap();
+        let store = Arc::new(FilesystemSubroutineImageStore::from_root(temp.path()));
+        let mut repo = MockSubroutineEntityRepository::default();
+        repo.expect_subroutines_exists()
+            .return_once(move |_| Ok(false));
+        let service = SubroutineEntityService::new(Arc::new(repo)).with_images(store);
+
+        let res = service
+            .create(&CreateSubroutineInput::new(
+                &mock_scene_entity.id,
+                &mock_subroutine_image.id,
+            ))
+            .await;
+
+        assert!(matches!(
+            res.unwrap_err(),
+            EntityServiceError::ImageStore(SubroutineImageStoreError::NotFound(..))
+        ));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn rejects_unsigned_image_when_signatures_are_required(mock_scene_entity: SceneEntity) {
+        let fixture = VerifierFixture::new();
+        let service =
+            SubroutineEntityService::new(Arc::new(MockSubroutineEntityRepository::default()))
+                .with_image_verifier(fixture.verifier(SignaturePolicy::Require));
+
+        let res = service
+            .create(&CreateSubroutineInput::new(
+                &mock_scene_entity.id,
+                &fixture.image.id,
+            ))
+            .await;
+
+        assert!(matches!(
+            res.unwrap_err(),
+            EntityServiceError::ImageVerification(ImageVerificationError::Rejected(
+                _,
+                SignatureStatus::Unsigned
+            ))
+        ));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn accepts_image_signed_by_trusted_key(mock_scene_entity: SceneEntity) {
+        let fixture = VerifierFixture::new();
+        let key = signing_key();
+        fixture.trust.add("acme", &key.verifying_key()).unwrap();
+        sign_image(fixture.store.as_ref(), &fixture.image.id, &key).unwrap();
+        let service = SubroutineEntityService::new(Arc::new(repo_expecting_create()))
+            .with_image_verifier(fixture.verifier(SignaturePolicy::Require));
+
+        service
+            .create(&CreateSubroutineInput::new(
+                &mock_scene_entity.id,
+                &fixture.image.id,
+            ))
+            .await
+            .unwrap();
+
+        let image = fixture.store.get(&fixture.image.id).unwrap();
+        assert_eq!(
+            image.verification.unwrap().status,
+            SignatureStatus::Verified {
+                key: "acme".to_string()
+            }
+        );
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn records_failed_verification_when_only_warning(mock_scene_entity: SceneEntity) {
+        let fixture = VerifierFixture::new();
+        sign_image(fixture.store.as_ref(), &fixture.image.id, &signing_key()).unwrap();
+        let service = SubroutineEntityService::new(Arc::new(repo_expecting_create()))
+            .with_image_verifier(fixture.verifier(SignaturePolicy::Warn));
+
+        service
+            .create(&CreateSubroutineInput::new(
+                &mock_scene_entity.id,
+                &fixture.image.id,
+            ))
+            .await
+            .unwrap();
+
+        let image = fixture.store.get(&fixture.image.id).unwrap();
+        assert_eq!(
+            image.verification.unwrap().status,
+            SignatureStatus::Untrusted
+        );
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn skips_verification_when_policy_is_off(mock_scene_entity: SceneEntity) {
+        let fixture = VerifierFixture::new();
+        let service = SubroutineEntityService::new(Arc::new(repo_expecting_create()))
+            .with_image_verifier(fixture.verifier(SignaturePolicy::Off));
+
+        service
+            .create(&CreateSubroutineInput::new(
+                &mock_scene_entity.id,
+                &fixture.image.id,
+            ))
+            .await
+            .unwrap();
+
+        let image = fixture.store.get(&fixture.image.id).unwrap();
+        assert!(image.verification.is_none());
+    }
 }
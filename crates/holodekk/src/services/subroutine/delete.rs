@@ -31,6 +31,9 @@ where
 
         // remove subroutine from the repository
         self.repo.subroutines_delete(&subroutine.id).await?;
+        if let Some(ports) = self.ports.as_ref() {
+            ports.release(&subroutine.id)?;
+        }
 
         Ok(())
     }
@@ -47,6 +50,7 @@ mod tests {
         fixtures::{mock_subroutine_entity, mock_subroutine_entity_repository},
         EntityRepositoryError, MockSubroutineEntityRepository, SubroutineEntity,
     };
+    use crate::ports::{PortAllocator, PortRange};
 
     use super::*;
 
@@ -131,4 +135,44 @@ mod tests {
 
         assert!(res.is_ok());
     }
+
+    #[rstest]
+    #[tokio::test]
+    async fn releases_allocated_port(
+        mut mock_subroutine_entity_repository: MockSubroutineEntityRepository,
+        mock_subroutine_entity: SubroutineEntity,
+    ) {
+        let temp = tempfile::tempdir().unwrap();
+        let ports = Arc::new(
+            PortAllocator::from_file(temp.path().join("ports.json"), PortRange::default()).unwrap(),
+        );
+        ports
+            .allocate(
+                &mock_subroutine_entity.scene_entity_id,
+                &mock_subroutine_entity.id,
+                "api",
+                None,
+            )
+            .unwrap();
+        {
+            let sub = mock_subroutine_entity.clone();
+            mock_subroutine_entity_repository
+                .expect_subroutines_get()
+                .return_once(move |_| Ok(sub));
+        }
+        mock_subroutine_entity_repository
+            .expect_subroutines_delete()
+            .return_once(|_| Ok(()));
+        let service = SubroutineEntityService::new(Arc::new(mock_subroutine_entity_repository))
+            .with_port_allocator(ports.clone());
+
+        service
+            .delete(&DeleteSubroutineInput::new(&mock_subroutine_entity.id))
+            .await
+            .unwrap();
+
+        assert!(ports
+            .endpoints(&mock_subroutine_entity.scene_entity_id)
+            .is_empty());
+    }
 }
@@ -0,0 +1,96 @@
+use async_trait::async_trait;
+use log::trace;
+
+use crate::entities::{SceneEntityId, SubroutineEntityRepository};
+use crate::ports::PortAssignment;
+
+use super::{EntityServiceResult, FindEndpoints, FindEndpointsInput, SubroutineEntityService};
+
+#[async_trait]
+impl<R> FindEndpoints for SubroutineEntityService<R>
+where
+    R: SubroutineEntityRepository,
+{
+    async fn endpoints<'a>(
+        &self,
+        input: &'a FindEndpointsInput<'a>,
+    ) -> EntityServiceResult<Vec<PortAssignment>> {
+        trace!("SubroutineEntityService::endpoints({:?})", input);
+
+        let scene_entity_id: SceneEntityId = input.scene_entity_id.parse()?;
+        Ok(self
+            .ports
+            .as_ref()
+            .map(|ports| ports.endpoints(&scene_entity_id))
+            .unwrap_or_default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rstest::*;
+    use tempfile::tempdir;
+
+    use crate::entities::{
+        fixtures::{mock_scene_entity, mock_subroutine_entity_repository},
+        MockSubroutineEntityRepository, SceneEntity, SubroutineEntityId,
+    };
+    use crate::ports::{PortAllocator, PortRange};
+
+    use super::*;
+
+    #[rstest]
+    #[tokio::test]
+    async fn returns_nothing_without_allocator(
+        mock_subroutine_entity_repository: MockSubroutineEntityRepository,
+        mock_scene_entity: SceneEntity,
+    ) {
+        let service = SubroutineEntityService::new(Arc::new(mock_subroutine_entity_repository));
+
+        let endpoints = service
+            .endpoints(&FindEndpointsInput::new(&mock_scene_entity.id))
+            .await
+            .unwrap();
+
+        assert!(endpoints.is_empty());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn returns_endpoints_for_scene(
+        mock_subroutine_entity_repository: MockSubroutineEntityRepository,
+        mock_scene_entity: SceneEntity,
+    ) {
+        let temp = tempdir().unwrap();
+        let ports = Arc::new(
+            PortAllocator::from_file(temp.path().join("ports.json"), PortRange::default()).unwrap(),
+        );
+        let assignment = ports
+            .allocate(
+                &mock_scene_entity.id,
+                &SubroutineEntityId::generate(),
+                "api",
+                None,
+            )
+            .unwrap();
+        ports
+            .allocate(
+                &SceneEntityId::generate(),
+                &SubroutineEntityId::generate(),
+                "other",
+                None,
+            )
+            .unwrap();
+        let service = SubroutineEntityService::new(Arc::new(mock_subroutine_entity_repository))
+            .with_port_allocator(ports);
+
+        let endpoints = service
+            .endpoints(&FindEndpointsInput::new(&mock_scene_entity.id))
+            .await
+            .unwrap();
+
+        assert_eq!(endpoints, vec![assignment]);
+    }
+}
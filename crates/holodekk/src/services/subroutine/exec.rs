@@ -0,0 +1,160 @@
+use async_trait::async_trait;
+use log::trace;
+use tokio::io::AsyncWriteExt;
+use tokio::net::UnixStream;
+
+use crate::attach::{AttachError, AttachRequest, Frame, FrameKind};
+use crate::entities::{
+    EntityRepositoryError, SceneEntityId, SubroutineEntityId, SubroutineEntityRepository,
+};
+use crate::services::{EntityServiceError, EntityServiceResult};
+use crate::SubroutinePaths;
+
+use super::{ExecSubroutine, ExecSubroutineInput, SubroutineEntityService};
+
+#[async_trait]
+impl<R> ExecSubroutine for SubroutineEntityService<R>
+where
+    R: SubroutineEntityRepository,
+{
+    async fn exec<'a>(
+        &self,
+        input: &'a ExecSubroutineInput<'a>,
+    ) -> EntityServiceResult<UnixStream> {
+        trace!("SubroutineEntityService::exec({:?})", input);
+
+        let scene_entity_id: SceneEntityId = input.scene_entity_id.parse()?;
+        let id: SubroutineEntityId = input.id.parse()?;
+        let subroutine = self
+            .repo
+            .subroutines_get(&id)
+            .await
+            .map_err(|err| match err {
+                EntityRepositoryError::NotFound(id) => EntityServiceError::NotFound(id),
+                _ => EntityServiceError::from(err),
+            })?;
+        // (subroutines in other scenes are as good as missing)
+        if subroutine.scene_entity_id != scene_entity_id {
+            return Err(EntityServiceError::NotFound(id));
+        }
+
+        // without paths there's no finding the shim
+        let Some(paths) = self.paths.as_ref() else {
+            return Err(AttachError::Rejected("shim sockets aren't available".into()).into());
+        };
+        let paths = SubroutinePaths::build(paths.clone(), &subroutine);
+        let mut stream = UnixStream::connect(paths.socket())
+            .await
+            .map_err(AttachError::from)?;
+
+        let mut request = AttachRequest::default().with_exec(input.command.to_vec());
+        if input.stdin {
+            request = request.with_stdin();
+        }
+        let hello = Frame::json(FrameKind::Hello, &request)?;
+        stream
+            .write_all(&hello.encode())
+            .await
+            .map_err(AttachError::from)?;
+        Ok(stream)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::Arc;
+
+    use rstest::*;
+    use tempfile::tempdir;
+
+    use crate::entities::{
+        fixtures::{mock_subroutine_entity, mock_subroutine_entity_repository},
+        MockSubroutineEntityRepository, SubroutineEntity,
+    };
+    use crate::HolodekkPaths;
+
+    use super::*;
+
+    #[rstest]
+    #[tokio::test]
+    async fn returns_error_for_nonexisting_subroutine(
+        mut mock_subroutine_entity_repository: MockSubroutineEntityRepository,
+    ) {
+        let id = SubroutineEntityId::generate();
+        mock_subroutine_entity_repository
+            .expect_subroutines_get()
+            .return_once(|id| Err(EntityRepositoryError::NotFound(id.to_owned())));
+        let service = SubroutineEntityService::new(Arc::new(mock_subroutine_entity_repository));
+        let command = vec!["ls".to_string()];
+        let scene_id = SceneEntityId::generate();
+
+        let res = service
+            .exec(&ExecSubroutineInput::new(&scene_id, &id, &command))
+            .await;
+
+        assert!(matches!(res.unwrap_err(), EntityServiceError::NotFound(..)));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn returns_error_for_subroutines_in_other_scenes(
+        mut mock_subroutine_entity_repository: MockSubroutineEntityRepository,
+        mock_subroutine_entity: SubroutineEntity,
+    ) {
+        let temp = tempdir().unwrap();
+        let paths = Arc::new(HolodekkPaths::new(temp.path(), temp.path(), temp.path()));
+        let root = paths.subroutines_root().join(&mock_subroutine_entity.id);
+        fs::create_dir_all(&root).unwrap();
+        let listener = std::os::unix::net::UnixListener::bind(root.join("log.sock")).unwrap();
+        listener.set_nonblocking(true).unwrap();
+        let id = mock_subroutine_entity.id.clone();
+        mock_subroutine_entity_repository
+            .expect_subroutines_get()
+            .return_once(move |_| Ok(mock_subroutine_entity));
+        let service = SubroutineEntityService::new(Arc::new(mock_subroutine_entity_repository))
+            .with_paths(paths);
+        let command = vec!["ls".to_string()];
+        let other_scene = SceneEntityId::generate();
+
+        let res = service
+            .exec(&ExecSubroutineInput::new(&other_scene, &id, &command))
+            .await;
+
+        assert!(matches!(res.unwrap_err(), EntityServiceError::NotFound(..)));
+        assert!(listener.accept().is_err());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn sends_the_command_to_the_shim(
+        mut mock_subroutine_entity_repository: MockSubroutineEntityRepository,
+        mock_subroutine_entity: SubroutineEntity,
+    ) {
+        let temp = tempdir().unwrap();
+        let paths = Arc::new(HolodekkPaths::new(temp.path(), temp.path(), temp.path()));
+        let root = paths.subroutines_root().join(&mock_subroutine_entity.id);
+        fs::create_dir_all(&root).unwrap();
+        let listener = std::os::unix::net::UnixListener::bind(root.join("log.sock")).unwrap();
+        let id = mock_subroutine_entity.id.clone();
+        let scene_id = mock_subroutine_entity.scene_entity_id.clone();
+        mock_subroutine_entity_repository
+            .expect_subroutines_get()
+            .return_once(move |_| Ok(mock_subroutine_entity));
+        let service = SubroutineEntityService::new(Arc::new(mock_subroutine_entity_repository))
+            .with_paths(paths);
+        let command = vec!["ls".to_string(), "-l".to_string()];
+
+        service
+            .exec(&ExecSubroutineInput::new(&scene_id, &id, &command).with_stdin())
+            .await
+            .unwrap();
+
+        let (mut shim, _) = listener.accept().unwrap();
+        let hello = Frame::read_from(&mut shim).unwrap().unwrap();
+        let request: AttachRequest = serde_json::from_slice(&hello.payload).unwrap();
+        assert_eq!(hello.kind, FrameKind::Hello);
+        assert_eq!(request.exec, Some(command));
+        assert!(request.stdin);
+    }
+}
@@ -0,0 +1,110 @@
+use async_trait::async_trait;
+use log::trace;
+
+use crate::entities::{
+    EntityRepositoryError, SubroutineEntity, SubroutineEntityId, SubroutineEntityRepository,
+};
+use crate::services::{EntityServiceError, EntityServiceResult};
+
+use super::{SubroutineEntityService, UpdateSubroutineHealth, UpdateSubroutineHealthInput};
+
+#[async_trait]
+impl<R> UpdateSubroutineHealth for SubroutineEntityService<R>
+where
+    R: SubroutineEntityRepository,
+{
+    async fn update_health<'a>(
+        &self,
+        input: &'a UpdateSubroutineHealthInput<'a>,
+    ) -> EntityServiceResult<SubroutineEntity> {
+        trace!("SubroutineEntityService::update_health({:?})", input);
+
+        let id: SubroutineEntityId = input.id.parse()?;
+
+        let subroutine = self
+            .repo
+            .subroutines_update(&id, None, Some(input.health))
+            .await
+            .map_err(|err| match err {
+                EntityRepositoryError::NotFound(id) => EntityServiceError::NotFound(id),
+                _ => EntityServiceError::from(err),
+            })?;
+        Ok(subroutine)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use mockall::predicate::eq;
+    use rstest::*;
+
+    use crate::entities::{
+        fixtures::{mock_subroutine_entity, mock_subroutine_entity_repository},
+        MockSubroutineEntityRepository,
+    };
+    use crate::enums::SubroutineHealth;
+
+    use super::*;
+
+    async fn execute(
+        repo: MockSubroutineEntityRepository,
+        id: &str,
+        health: SubroutineHealth,
+    ) -> EntityServiceResult<SubroutineEntity> {
+        let service = SubroutineEntityService::new(Arc::new(repo));
+
+        service
+            .update_health(&UpdateSubroutineHealthInput::new(id, health))
+            .await
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn returns_error_for_nonexisting_subroutine(
+        mut mock_subroutine_entity_repository: MockSubroutineEntityRepository,
+    ) {
+        let id = SubroutineEntityId::generate();
+        mock_subroutine_entity_repository
+            .expect_subroutines_update()
+            .return_once(|id, _, _| Err(EntityRepositoryError::NotFound(id.to_owned())));
+
+        let res = execute(
+            mock_subroutine_entity_repository,
+            &id,
+            SubroutineHealth::Healthy,
+        )
+        .await;
+
+        assert!(matches!(res.unwrap_err(), EntityServiceError::NotFound(..)));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn updates_health_in_repository(
+        mut mock_subroutine_entity_repository: MockSubroutineEntityRepository,
+        mock_subroutine_entity: SubroutineEntity,
+    ) {
+        let mut updated = mock_subroutine_entity.clone();
+        updated.health = SubroutineHealth::Unhealthy;
+        mock_subroutine_entity_repository
+            .expect_subroutines_update()
+            .with(
+                eq(mock_subroutine_entity.id.clone()),
+                eq(None),
+                eq(Some(SubroutineHealth::Unhealthy)),
+            )
+            .return_once(move |_, _, _| Ok(updated));
+
+        let subroutine = execute(
+            mock_subroutine_entity_repository,
+            &mock_subroutine_entity.id,
+            SubroutineHealth::Unhealthy,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(subroutine.health, SubroutineHealth::Unhealthy);
+    }
+}
@@ -0,0 +1,106 @@
+use async_trait::async_trait;
+use log::trace;
+
+use crate::entities::{EntityRepositoryError, SubroutineEntityId, SubroutineEntityRepository};
+use crate::logs::{list_logs, LogFile};
+use crate::services::{EntityServiceError, EntityServiceResult};
+use crate::SubroutinePaths;
+
+use super::{FindSubroutineLogs, FindSubroutineLogsInput, SubroutineEntityService};
+
+#[async_trait]
+impl<R> FindSubroutineLogs for SubroutineEntityService<R>
+where
+    R: SubroutineEntityRepository,
+{
+    async fn logs<'a>(
+        &self,
+        input: &'a FindSubroutineLogsInput<'a>,
+    ) -> EntityServiceResult<Vec<LogFile>> {
+        trace!("SubroutineEntityService::logs({:?})", input);
+
+        let id: SubroutineEntityId = input.id.parse()?;
+        let subroutine = self
+            .repo
+            .subroutines_get(&id)
+            .await
+            .map_err(|err| match err {
+                EntityRepositoryError::NotFound(id) => EntityServiceError::NotFound(id),
+                _ => EntityServiceError::from(err),
+            })?;
+
+        // without paths there's nowhere to look
+        let Some(paths) = self.paths.as_ref() else {
+            return Ok(Vec::new());
+        };
+        let paths = SubroutinePaths::build(paths.clone(), &subroutine);
+        list_logs(paths.logfile()).map_err(|err| EntityServiceError::Unexpected(err.into()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::Arc;
+
+    use rstest::*;
+    use tempfile::tempdir;
+
+    use crate::entities::{
+        fixtures::{mock_subroutine_entity, mock_subroutine_entity_repository},
+        MockSubroutineEntityRepository, SubroutineEntity,
+    };
+    use crate::HolodekkPaths;
+
+    use super::*;
+
+    #[rstest]
+    #[tokio::test]
+    async fn returns_error_for_nonexisting_subroutine(
+        mut mock_subroutine_entity_repository: MockSubroutineEntityRepository,
+    ) {
+        let id = SubroutineEntityId::generate();
+        mock_subroutine_entity_repository
+            .expect_subroutines_get()
+            .return_once(|id| Err(EntityRepositoryError::NotFound(id.to_owned())));
+        let service = SubroutineEntityService::new(Arc::new(mock_subroutine_entity_repository));
+
+        let res = service.logs(&FindSubroutineLogsInput::new(&id)).await;
+
+        assert!(matches!(res.unwrap_err(), EntityServiceError::NotFound(..)));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn lists_current_and_rotated_logs(
+        mut mock_subroutine_entity_repository: MockSubroutineEntityRepository,
+        mock_subroutine_entity: SubroutineEntity,
+    ) {
+        let temp = tempdir().unwrap();
+        let paths = Arc::new(HolodekkPaths::new(temp.path(), temp.path(), temp.path()));
+        let root = paths.subroutines_root().join(&mock_subroutine_entity.id);
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join("subroutine.log"), "current\n").unwrap();
+        fs::write(
+            root.join("subroutine.log.20240101T000000.000000Z.gz"),
+            "rotated",
+        )
+        .unwrap();
+        let id = mock_subroutine_entity.id.clone();
+        mock_subroutine_entity_repository
+            .expect_subroutines_get()
+            .return_once(move |_| Ok(mock_subroutine_entity));
+        let service = SubroutineEntityService::new(Arc::new(mock_subroutine_entity_repository))
+            .with_paths(paths);
+
+        let logs = service
+            .logs(&FindSubroutineLogsInput::new(&id))
+            .await
+            .unwrap();
+
+        assert_eq!(logs.len(), 2);
+        assert_eq!(logs[0].name, "subroutine.log");
+        assert!(!logs[0].rotated);
+        assert!(logs[1].rotated && logs[1].compressed);
+    }
+}
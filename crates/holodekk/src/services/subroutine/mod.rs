@@ -1,12 +1,25 @@
+use std::collections::HashMap;
 use std::sync::Arc;
 
-use crate::entities::{SubroutineEntity, SubroutineEntityRepository};
+use crate::cgroups::{Cgroups, ResourceLimits, ResourceUsage};
+use crate::entities::{SubroutineEntity, SubroutineEntityRepository, SubroutineStatusChange};
+use crate::enums::SubroutineHealth;
+use crate::health::HealthProbe;
+use crate::images::{ImageVerifier, SubroutineImageStore};
+use crate::logs::{LogFile, LogFormat, LogRotation};
+use crate::ports::{PortAllocator, PortAssignment};
+use crate::privileges::{RunAs, SceneUserAllocator};
+use crate::restart::RestartPolicy;
+use crate::rlimits::ProcessLimits;
+use crate::shim::ShimLauncher;
+use crate::HolodekkPaths;
 
 use super::EntityServiceResult;
 
 use async_trait::async_trait;
 #[cfg(test)]
 use mockall::automock;
+use tokio::net::UnixStream;
 
 #[cfg_attr(test, automock)]
 #[async_trait]
@@ -41,10 +54,75 @@ pub trait GetSubroutine: Send + Sync + 'static {
     ) -> EntityServiceResult<SubroutineEntity>;
 }
 
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait ExecSubroutine: Send + Sync + 'static {
+    /// Asks the subroutine's shim to exec a command alongside it, returning the connection (on
+    /// which the shim's hello is the first thing to arrive; see [`crate::attach`]).
+    async fn exec<'a>(&self, input: &'a ExecSubroutineInput<'a>)
+        -> EntityServiceResult<UnixStream>;
+}
+
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait FindEndpoints: Send + Sync + 'static {
+    async fn endpoints<'a>(
+        &self,
+        input: &'a FindEndpointsInput<'a>,
+    ) -> EntityServiceResult<Vec<PortAssignment>>;
+}
+
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait GetSubroutineUsage: Send + Sync + 'static {
+    async fn usage<'a>(
+        &self,
+        input: &'a GetSubroutineUsageInput<'a>,
+    ) -> EntityServiceResult<ResourceUsage>;
+}
+
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait FindSubroutineLogs: Send + Sync + 'static {
+    async fn logs<'a>(
+        &self,
+        input: &'a FindSubroutineLogsInput<'a>,
+    ) -> EntityServiceResult<Vec<LogFile>>;
+}
+
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait UpdateSubroutineHealth: Send + Sync + 'static {
+    async fn update_health<'a>(
+        &self,
+        input: &'a UpdateSubroutineHealthInput<'a>,
+    ) -> EntityServiceResult<SubroutineEntity>;
+}
+
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait UpdateSubroutineStatus: Send + Sync + 'static {
+    async fn update_status<'a>(
+        &self,
+        input: &'a UpdateSubroutineStatusInput<'a>,
+    ) -> EntityServiceResult<SubroutineEntity>;
+}
+
 #[derive(Clone, Debug)]
 pub struct CreateSubroutineInput<'c> {
     pub scene_entity_id: &'c str,
     pub subroutine_image_id: &'c str,
+    pub environment: Option<&'c HashMap<String, String>>,
+    pub scene_environment: Option<&'c HashMap<String, String>>,
+    pub health_probe: Option<&'c HealthProbe>,
+    pub restart_policy: RestartPolicy,
+    pub limits: ResourceLimits,
+    pub run_as: Option<&'c RunAs>,
+    pub process_limits: ProcessLimits,
+    pub log_rotation: LogRotation,
+    pub log_format: LogFormat,
+    pub stdin: bool,
+    pub tty: bool,
 }
 
 impl<'c> CreateSubroutineInput<'c> {
@@ -52,8 +130,85 @@ impl<'c> CreateSubroutineInput<'c> {
         Self {
             scene_entity_id,
             subroutine_image_id,
+            environment: None,
+            scene_environment: None,
+            health_probe: None,
+            restart_policy: RestartPolicy::Never,
+            limits: ResourceLimits::default(),
+            run_as: None,
+            process_limits: ProcessLimits::default(),
+            log_rotation: LogRotation::default(),
+            log_format: LogFormat::default(),
+            stdin: false,
+            tty: false,
         }
     }
+
+    /// Environment variables (overriding the image manifest's defaults).
+    pub fn with_environment(mut self, environment: &'c HashMap<String, String>) -> Self {
+        self.environment = Some(environment);
+        self
+    }
+
+    /// Environment of the subroutine's scene (which its own environment overrides when it's
+    /// launched).
+    pub fn with_scene_environment(mut self, environment: &'c HashMap<String, String>) -> Self {
+        self.scene_environment = Some(environment);
+        self
+    }
+
+    /// Probe the subroutine's shim should run to check its health.
+    pub fn with_health_probe(mut self, health_probe: &'c HealthProbe) -> Self {
+        self.health_probe = Some(health_probe);
+        self
+    }
+
+    pub fn with_restart_policy(mut self, restart_policy: RestartPolicy) -> Self {
+        self.restart_policy = restart_policy;
+        self
+    }
+
+    /// cgroup limits the shim applies to the subroutine.
+    pub fn with_limits(mut self, limits: ResourceLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// User and privileges to run the subroutine with (rather than the scene's defaults).
+    pub fn with_run_as(mut self, run_as: &'c RunAs) -> Self {
+        self.run_as = Some(run_as);
+        self
+    }
+
+    /// rlimits and deadline the shim applies to the subroutine.
+    pub fn with_process_limits(mut self, process_limits: ProcessLimits) -> Self {
+        self.process_limits = process_limits;
+        self
+    }
+
+    /// When the shim rotates the subroutine's log.
+    pub fn with_log_rotation(mut self, log_rotation: LogRotation) -> Self {
+        self.log_rotation = log_rotation;
+        self
+    }
+
+    /// How the shim writes entries to the subroutine's log.
+    pub fn with_log_format(mut self, log_format: LogFormat) -> Self {
+        self.log_format = log_format;
+        self
+    }
+
+    /// Give the subroutine a stdin attached clients can write to.
+    pub fn with_stdin(mut self, stdin: bool) -> Self {
+        self.stdin = stdin;
+        self
+    }
+
+    /// Run the subroutine in a pseudo-terminal (which attached clients can resize).
+    pub fn with_tty(mut self, tty: bool) -> Self {
+        self.tty = tty;
+        self
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -67,6 +222,32 @@ impl<'c> DeleteSubroutineInput<'c> {
     }
 }
 
+#[derive(Clone, Debug)]
+pub struct ExecSubroutineInput<'e> {
+    /// Scene the subroutine is expected to belong to.
+    pub scene_entity_id: &'e str,
+    pub id: &'e str,
+    pub command: &'e [String],
+    /// Forward the client's input to the command's stdin.
+    pub stdin: bool,
+}
+
+impl<'e> ExecSubroutineInput<'e> {
+    pub fn new(scene_entity_id: &'e str, id: &'e str, command: &'e [String]) -> Self {
+        Self {
+            scene_entity_id,
+            id,
+            command,
+            stdin: false,
+        }
+    }
+
+    pub fn with_stdin(mut self) -> Self {
+        self.stdin = true;
+        self
+    }
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub struct FindSubroutinesInput<'f> {
     pub scene_entity_id: Option<&'f str>,
@@ -82,6 +263,17 @@ impl<'f> FindSubroutinesInput<'f> {
     }
 }
 
+#[derive(Clone, Debug)]
+pub struct FindEndpointsInput<'f> {
+    pub scene_entity_id: &'f str,
+}
+
+impl<'f> FindEndpointsInput<'f> {
+    pub fn new(scene_entity_id: &'f str) -> Self {
+        Self { scene_entity_id }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct GetSubroutineInput<'c> {
     pub id: &'c str,
@@ -93,21 +285,108 @@ impl<'c> GetSubroutineInput<'c> {
     }
 }
 
+#[derive(Clone, Debug)]
+pub struct GetSubroutineUsageInput<'c> {
+    pub id: &'c str,
+}
+
+impl<'c> GetSubroutineUsageInput<'c> {
+    pub fn new(id: &'c str) -> Self {
+        Self { id }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct FindSubroutineLogsInput<'c> {
+    pub id: &'c str,
+}
+
+impl<'c> FindSubroutineLogsInput<'c> {
+    pub fn new(id: &'c str) -> Self {
+        Self { id }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct UpdateSubroutineHealthInput<'u> {
+    pub id: &'u str,
+    pub health: SubroutineHealth,
+}
+
+impl<'u> UpdateSubroutineHealthInput<'u> {
+    pub fn new(id: &'u str, health: SubroutineHealth) -> Self {
+        Self { id, health }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct UpdateSubroutineStatusInput<'u> {
+    pub id: &'u str,
+    pub change: SubroutineStatusChange,
+}
+
+impl<'u> UpdateSubroutineStatusInput<'u> {
+    pub fn new(id: &'u str, change: SubroutineStatusChange) -> Self {
+        Self { id, change }
+    }
+}
+
 pub trait SubroutineEntityServiceMethods:
-    CreateSubroutine + DeleteSubroutine + FindSubroutines + GetSubroutine
+    CreateSubroutine
+    + DeleteSubroutine
+    + ExecSubroutine
+    + FindEndpoints
+    + FindSubroutineLogs
+    + FindSubroutines
+    + GetSubroutine
+    + GetSubroutineUsage
+    + UpdateSubroutineHealth
+    + UpdateSubroutineStatus
 {
 }
 impl<T> SubroutineEntityServiceMethods for T where
-    T: CreateSubroutine + DeleteSubroutine + FindSubroutines + GetSubroutine
+    T: CreateSubroutine
+        + DeleteSubroutine
+        + ExecSubroutine
+        + FindEndpoints
+        + FindSubroutineLogs
+        + FindSubroutines
+        + GetSubroutine
+        + GetSubroutineUsage
+        + UpdateSubroutineHealth
+        + UpdateSubroutineStatus
 {
 }
 
-#[derive(Debug)]
 pub struct SubroutineEntityService<R>
 where
     R: SubroutineEntityRepository,
 {
     repo: Arc<R>,
+    images: Option<Arc<dyn SubroutineImageStore>>,
+    verifier: Option<Arc<ImageVerifier>>,
+    ports: Option<Arc<PortAllocator>>,
+    cgroups: Option<Arc<Cgroups>>,
+    scene_users: Option<Arc<SceneUserAllocator>>,
+    paths: Option<Arc<HolodekkPaths>>,
+    launcher: Option<Arc<ShimLauncher>>,
+}
+
+impl<R> std::fmt::Debug for SubroutineEntityService<R>
+where
+    R: SubroutineEntityRepository + std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SubroutineEntityService")
+            .field("repo", &self.repo)
+            .field("verifier", &self.verifier)
+            .field("ports", &self.ports)
+            .field("cgroups", &self.cgroups)
+            .field("scene_users", &self.scene_users)
+            .field("paths", &self.paths)
+            .field("launcher", &self.launcher)
+            .finish_non_exhaustive()
+    }
 }
 
 impl<R> SubroutineEntityService<R>
@@ -115,14 +394,72 @@ where
     R: SubroutineEntityRepository,
 {
     pub fn new(repo: Arc<R>) -> Self {
-        Self { repo }
+        Self {
+            repo,
+            images: None,
+            verifier: None,
+            ports: None,
+            cgroups: None,
+            scene_users: None,
+            paths: None,
+            launcher: None,
+        }
+    }
+
+    /// Uses the manifests recorded in the image store as defaults for new subroutines.
+    pub fn with_images(mut self, images: Arc<dyn SubroutineImageStore>) -> Self {
+        self.images = Some(images);
+        self
+    }
+
+    /// Checks image signatures (per the verifier's policy) before creating subroutines.
+    pub fn with_image_verifier(mut self, verifier: Arc<ImageVerifier>) -> Self {
+        self.verifier = Some(verifier);
+        self
+    }
+
+    /// Allocates host ports for subroutines whose manifest declares a port.
+    pub fn with_port_allocator(mut self, ports: Arc<PortAllocator>) -> Self {
+        self.ports = Some(ports);
+        self
+    }
+
+    /// Reads subroutine resource usage from the given cgroup hierarchy.
+    pub fn with_cgroups(mut self, cgroups: Arc<Cgroups>) -> Self {
+        self.cgroups = Some(cgroups);
+        self
+    }
+
+    /// Runs subroutines as their scene's user, unless they name a user of their own.
+    pub fn with_scene_users(mut self, scene_users: Arc<SceneUserAllocator>) -> Self {
+        self.scene_users = Some(scene_users);
+        self
+    }
+
+    /// Finds subroutine logs (and shim sockets) beneath the given paths.
+    pub fn with_paths(mut self, paths: Arc<HolodekkPaths>) -> Self {
+        self.paths = Some(paths);
+        self
+    }
+
+    /// Launches subroutines (from their image, so along with [`Self::with_images`]) as they're
+    /// created.
+    pub fn with_launcher(mut self, launcher: Arc<ShimLauncher>) -> Self {
+        self.launcher = Some(launcher);
+        self
     }
 }
 
 mod create;
 mod delete;
+mod endpoints;
+mod exec;
 mod find;
 mod get;
+mod health;
+mod logs;
+mod status;
+mod usage;
 
 #[cfg(test)]
 pub mod fixtures {
@@ -143,6 +480,16 @@ pub mod fixtures {
             async fn delete<'a>(&self, input: &'a DeleteSubroutineInput<'a>) -> EntityServiceResult<()>;
         }
 
+        #[async_trait]
+        impl ExecSubroutine for SubroutineEntityService {
+            async fn exec<'a>(&self, input: &'a ExecSubroutineInput<'a>) -> EntityServiceResult<UnixStream>;
+        }
+
+        #[async_trait]
+        impl FindEndpoints for SubroutineEntityService {
+            async fn endpoints<'a>(&self, input: &'a FindEndpointsInput<'a>) -> EntityServiceResult<Vec<PortAssignment>>;
+        }
+
         #[async_trait]
         impl FindSubroutines for SubroutineEntityService {
             async fn find<'a>(&self, input: &'a FindSubroutinesInput<'a>) -> EntityServiceResult<Vec<SubroutineEntity>>;
@@ -152,6 +499,26 @@ pub mod fixtures {
         impl GetSubroutine for SubroutineEntityService {
             async fn get<'a>(&self, input: &'a GetSubroutineInput<'a>) -> EntityServiceResult<SubroutineEntity>;
         }
+
+        #[async_trait]
+        impl GetSubroutineUsage for SubroutineEntityService {
+            async fn usage<'a>(&self, input: &'a GetSubroutineUsageInput<'a>) -> EntityServiceResult<ResourceUsage>;
+        }
+
+        #[async_trait]
+        impl FindSubroutineLogs for SubroutineEntityService {
+            async fn logs<'a>(&self, input: &'a FindSubroutineLogsInput<'a>) -> EntityServiceResult<Vec<LogFile>>;
+        }
+
+        #[async_trait]
+        impl UpdateSubroutineHealth for SubroutineEntityService {
+            async fn update_health<'a>(&self, input: &'a UpdateSubroutineHealthInput<'a>) -> EntityServiceResult<SubroutineEntity>;
+        }
+
+        #[async_trait]
+        impl UpdateSubroutineStatus for SubroutineEntityService {
+            async fn update_status<'a>(&self, input: &'a UpdateSubroutineStatusInput<'a>) -> EntityServiceResult<SubroutineEntity>;
+        }
     }
 
     #[fixture]
@@ -164,6 +531,16 @@ pub mod fixtures {
         MockDeleteSubroutine::default()
     }
 
+    #[fixture]
+    pub fn mock_exec_subroutine() -> MockExecSubroutine {
+        MockExecSubroutine::default()
+    }
+
+    #[fixture]
+    pub fn mock_find_endpoints() -> MockFindEndpoints {
+        MockFindEndpoints::default()
+    }
+
     #[fixture]
     pub fn mock_find_subroutines() -> MockFindSubroutines {
         MockFindSubroutines::default()
@@ -174,6 +551,26 @@ pub mod fixtures {
         MockGetSubroutine::default()
     }
 
+    #[fixture]
+    pub fn mock_get_subroutine_usage() -> MockGetSubroutineUsage {
+        MockGetSubroutineUsage::default()
+    }
+
+    #[fixture]
+    pub fn mock_find_subroutine_logs() -> MockFindSubroutineLogs {
+        MockFindSubroutineLogs::default()
+    }
+
+    #[fixture]
+    pub fn mock_update_subroutine_health() -> MockUpdateSubroutineHealth {
+        MockUpdateSubroutineHealth::default()
+    }
+
+    #[fixture]
+    pub fn mock_update_subroutine_status() -> MockUpdateSubroutineStatus {
+        MockUpdateSubroutineStatus::default()
+    }
+
     #[fixture]
     pub fn mock_subroutine_service() -> MockSubroutineEntityService {
         MockSubroutineEntityService::default()
@@ -0,0 +1,111 @@
+use async_trait::async_trait;
+use log::trace;
+
+use crate::entities::{
+    EntityRepositoryError, SubroutineEntity, SubroutineEntityId, SubroutineEntityRepository,
+};
+use crate::services::{EntityServiceError, EntityServiceResult};
+
+use super::{SubroutineEntityService, UpdateSubroutineStatus, UpdateSubroutineStatusInput};
+
+#[async_trait]
+impl<R> UpdateSubroutineStatus for SubroutineEntityService<R>
+where
+    R: SubroutineEntityRepository,
+{
+    async fn update_status<'a>(
+        &self,
+        input: &'a UpdateSubroutineStatusInput<'a>,
+    ) -> EntityServiceResult<SubroutineEntity> {
+        trace!("SubroutineEntityService::update_status({:?})", input);
+
+        let id: SubroutineEntityId = input.id.parse()?;
+
+        let subroutine = self
+            .repo
+            .subroutines_update(&id, Some(input.change.clone()), None)
+            .await
+            .map_err(|err| match err {
+                EntityRepositoryError::NotFound(id) => EntityServiceError::NotFound(id),
+                _ => EntityServiceError::from(err),
+            })?;
+        Ok(subroutine)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use mockall::predicate::eq;
+    use rstest::*;
+
+    use crate::entities::{
+        fixtures::{mock_subroutine_entity, mock_subroutine_entity_repository},
+        MockSubroutineEntityRepository, SubroutineStatusChange,
+    };
+    use crate::enums::SubroutineStatus;
+
+    use super::*;
+
+    async fn execute(
+        repo: MockSubroutineEntityRepository,
+        id: &str,
+        change: SubroutineStatusChange,
+    ) -> EntityServiceResult<SubroutineEntity> {
+        let service = SubroutineEntityService::new(Arc::new(repo));
+
+        service
+            .update_status(&UpdateSubroutineStatusInput::new(id, change))
+            .await
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn returns_error_for_nonexisting_subroutine(
+        mut mock_subroutine_entity_repository: MockSubroutineEntityRepository,
+    ) {
+        let id = SubroutineEntityId::generate();
+        mock_subroutine_entity_repository
+            .expect_subroutines_update()
+            .return_once(|id, _, _| Err(EntityRepositoryError::NotFound(id.to_owned())));
+
+        let res = execute(
+            mock_subroutine_entity_repository,
+            &id,
+            SubroutineStatusChange::new(SubroutineStatus::Crashed),
+        )
+        .await;
+
+        assert!(matches!(res.unwrap_err(), EntityServiceError::NotFound(..)));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn updates_status_in_repository(
+        mut mock_subroutine_entity_repository: MockSubroutineEntityRepository,
+        mock_subroutine_entity: SubroutineEntity,
+    ) {
+        let change = SubroutineStatusChange::new(SubroutineStatus::CrashLoopBackOff(3));
+        let mut updated = mock_subroutine_entity.clone();
+        updated.change_status(change.clone());
+        mock_subroutine_entity_repository
+            .expect_subroutines_update()
+            .with(
+                eq(mock_subroutine_entity.id.clone()),
+                eq(Some(change.clone())),
+                eq(None),
+            )
+            .return_once(move |_, _, _| Ok(updated));
+
+        let subroutine = execute(
+            mock_subroutine_entity_repository,
+            &mock_subroutine_entity.id,
+            change,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(subroutine.status, SubroutineStatus::CrashLoopBackOff(3));
+    }
+}
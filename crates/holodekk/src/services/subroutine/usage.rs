@@ -0,0 +1,120 @@
+use async_trait::async_trait;
+use log::trace;
+
+use crate::cgroups::ResourceUsage;
+use crate::entities::{EntityRepositoryError, SubroutineEntityId, SubroutineEntityRepository};
+use crate::services::{EntityServiceError, EntityServiceResult};
+
+use super::{GetSubroutineUsage, GetSubroutineUsageInput, SubroutineEntityService};
+
+#[async_trait]
+impl<R> GetSubroutineUsage for SubroutineEntityService<R>
+where
+    R: SubroutineEntityRepository,
+{
+    async fn usage<'a>(
+        &self,
+        input: &'a GetSubroutineUsageInput<'a>,
+    ) -> EntityServiceResult<ResourceUsage> {
+        trace!("SubroutineEntityService::usage({:?})", input);
+
+        let id: SubroutineEntityId = input.id.parse()?;
+        self.repo
+            .subroutines_get(&id)
+            .await
+            .map_err(|err| match err {
+                EntityRepositoryError::NotFound(id) => EntityServiceError::NotFound(id),
+                _ => EntityServiceError::from(err),
+            })?;
+
+        // subroutines without a group (yet) haven't used anything
+        match self.cgroups.as_ref().and_then(|cgroups| cgroups.find(&id)) {
+            Some(group) => Ok(group.usage()?),
+            None => Ok(ResourceUsage::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::Arc;
+
+    use rstest::*;
+    use tempfile::tempdir;
+
+    use crate::cgroups::{Cgroups, ResourceLimits, DEFAULT_CGROUP_SLICE};
+    use crate::entities::{
+        fixtures::{mock_subroutine_entity, mock_subroutine_entity_repository},
+        MockSubroutineEntityRepository, SubroutineEntity,
+    };
+
+    use super::*;
+
+    #[rstest]
+    #[tokio::test]
+    async fn returns_error_for_nonexisting_subroutine(
+        mut mock_subroutine_entity_repository: MockSubroutineEntityRepository,
+    ) {
+        let id = SubroutineEntityId::generate();
+        mock_subroutine_entity_repository
+            .expect_subroutines_get()
+            .return_once(|id| Err(EntityRepositoryError::NotFound(id.to_owned())));
+        let service = SubroutineEntityService::new(Arc::new(mock_subroutine_entity_repository));
+
+        let res = service.usage(&GetSubroutineUsageInput::new(&id)).await;
+
+        assert!(matches!(res.unwrap_err(), EntityServiceError::NotFound(..)));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn returns_nothing_without_group(
+        mut mock_subroutine_entity_repository: MockSubroutineEntityRepository,
+        mock_subroutine_entity: SubroutineEntity,
+    ) {
+        let temp = tempdir().unwrap();
+        let id = mock_subroutine_entity.id.clone();
+        mock_subroutine_entity_repository
+            .expect_subroutines_get()
+            .return_once(move |_| Ok(mock_subroutine_entity));
+        let service = SubroutineEntityService::new(Arc::new(mock_subroutine_entity_repository))
+            .with_cgroups(Arc::new(Cgroups::new(temp.path(), DEFAULT_CGROUP_SLICE)));
+
+        let usage = service
+            .usage(&GetSubroutineUsageInput::new(&id))
+            .await
+            .unwrap();
+
+        assert_eq!(usage, ResourceUsage::default());
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn reads_usage_from_group(
+        mut mock_subroutine_entity_repository: MockSubroutineEntityRepository,
+        mock_subroutine_entity: SubroutineEntity,
+    ) {
+        let temp = tempdir().unwrap();
+        let cgroups = Cgroups::new(temp.path(), DEFAULT_CGROUP_SLICE);
+        let group = cgroups
+            .create(&mock_subroutine_entity.id, &ResourceLimits::default())
+            .unwrap();
+        fs::write(group.path().join("memory.current"), "8192").unwrap();
+        fs::write(group.path().join("pids.current"), "3").unwrap();
+        let id = mock_subroutine_entity.id.clone();
+        mock_subroutine_entity_repository
+            .expect_subroutines_get()
+            .return_once(move |_| Ok(mock_subroutine_entity));
+        let service = SubroutineEntityService::new(Arc::new(mock_subroutine_entity_repository))
+            .with_cgroups(Arc::new(cgroups));
+
+        let usage = service
+            .usage(&GetSubroutineUsageInput::new(&id))
+            .await
+            .unwrap();
+
+        assert_eq!(usage.memory_current, 8192);
+        assert_eq!(usage.pids_current, 3);
+    }
+}
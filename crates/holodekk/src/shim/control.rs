@@ -0,0 +1,343 @@
+//! The protocol spoken over a subroutine shim's control socket (`control.sock`).
+//!
+//! Requests and responses are JSON, one per line.  Each request is answered in turn, except
+//! `Stop`, which is answered (with the final [`ShimStatus`]) once the subroutine has exited, and
+//! `Subscribe`, after which the connection only carries [`ShimStatus`]es: the current one, then
+//! another for every change.
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use nix::sys::signal::Signal;
+use serde::{Deserialize, Serialize};
+
+use crate::attach::Backpressure;
+use crate::entities::SubroutineExit;
+use crate::enums::{SubroutineHealth, SubroutineStatus};
+use crate::errors::error_chain_fmt;
+
+/// Name of the control socket (within the subroutine's directory).
+pub const CONTROL_SOCKET: &str = "control.sock";
+
+#[derive(thiserror::Error)]
+pub enum ShimControlError {
+    #[error("Shim refused request: {0}")]
+    Rejected(String),
+    #[error("Unexpected response from shim")]
+    UnexpectedResponse(ControlResponse),
+    #[error("Shim closed the control connection")]
+    Closed,
+    #[error("Invalid signal: {0}")]
+    InvalidSignal(String),
+    #[error("Shim control IO error")]
+    Io(#[from] io::Error),
+    #[error("Shim control message serialization error")]
+    Serialization(#[from] serde_json::Error),
+}
+
+impl std::fmt::Debug for ShimControlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+pub type ShimControlResult<T> = std::result::Result<T, ShimControlError>;
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "command", rename_all = "snake_case")]
+pub enum ControlRequest {
+    /// Sends the subroutine a signal (by name, e.g. `SIGHUP`).
+    Signal {
+        signal: String,
+    },
+    /// Terminates the subroutine (without restarting it), killing it if it's still running
+    /// `timeout` seconds later.
+    Stop {
+        timeout: u64,
+    },
+    Status,
+    /// Streams the shim's status every time it changes.
+    Subscribe,
+}
+
+impl ControlRequest {
+    /// The signal a `Signal` request is for.
+    pub fn parse_signal(signal: &str) -> ShimControlResult<Signal> {
+        Signal::from_str(signal).map_err(|_| ShimControlError::InvalidSignal(signal.to_string()))
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "response", rename_all = "snake_case")]
+pub enum ControlResponse {
+    Ok,
+    Status(ShimStatus),
+    Error { message: String },
+}
+
+/// What the shim knows of its subroutine.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ShimStatus {
+    pub status: SubroutineStatus,
+    pub changed_at: DateTime<Utc>,
+    #[serde(default)]
+    pub health: SubroutineHealth,
+    /// Pid of the running subroutine (if it's running).
+    pub pid: Option<u32>,
+    /// When the current (or last) incarnation of the subroutine was started.
+    pub started_at: DateTime<Utc>,
+    pub last_exit: Option<SubroutineExit>,
+    /// Number of times the subroutine has been restarted.
+    pub restarts: u32,
+    /// Clients attached to the subroutine's output (or to processes exec'd alongside it).
+    #[serde(default)]
+    pub clients: Vec<AttachedClient>,
+}
+
+/// How an attached client is keeping up with its output.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct AttachedClient {
+    pub backpressure: Backpressure,
+    /// Output (in bytes) waiting to be sent.
+    pub pending: usize,
+    /// Output (in bytes) dropped because the client fell behind.
+    pub dropped: u64,
+}
+
+/// Controls a subroutine through its shim's control socket.
+#[derive(Debug)]
+pub struct ShimClient {
+    reader: BufReader<UnixStream>,
+    writer: UnixStream,
+}
+
+impl ShimClient {
+    pub fn connect<P: AsRef<Path>>(socket: P) -> ShimControlResult<Self> {
+        let writer = UnixStream::connect(socket)?;
+        Ok(Self {
+            reader: BufReader::new(writer.try_clone()?),
+            writer,
+        })
+    }
+
+    /// Gives up on requests the shim hasn't answered within `timeout`.
+    pub fn with_timeout(self, timeout: Duration) -> ShimControlResult<Self> {
+        self.writer.set_read_timeout(Some(timeout))?;
+        self.writer.set_write_timeout(Some(timeout))?;
+        Ok(self)
+    }
+
+    pub fn signal(&mut self, signal: Signal) -> ShimControlResult<()> {
+        match self.request(&ControlRequest::Signal {
+            signal: signal.as_str().to_string(),
+        })? {
+            ControlResponse::Ok => Ok(()),
+            response => Err(ShimControlError::UnexpectedResponse(response)),
+        }
+    }
+
+    /// Stops the subroutine, returning its final status once it has exited.
+    pub fn stop(&mut self, timeout: Duration) -> ShimControlResult<ShimStatus> {
+        self.expect_status(&ControlRequest::Stop {
+            timeout: timeout.as_secs(),
+        })
+    }
+
+    pub fn status(&mut self) -> ShimControlResult<ShimStatus> {
+        self.expect_status(&ControlRequest::Status)
+    }
+
+    /// Turns the connection into a stream of status changes.
+    pub fn subscribe(mut self) -> ShimControlResult<ShimEvents> {
+        self.send(&ControlRequest::Subscribe)?;
+        Ok(ShimEvents { client: self })
+    }
+
+    fn expect_status(&mut self, request: &ControlRequest) -> ShimControlResult<ShimStatus> {
+        match self.request(request)? {
+            ControlResponse::Status(status) => Ok(status),
+            response => Err(ShimControlError::UnexpectedResponse(response)),
+        }
+    }
+
+    fn request(&mut self, request: &ControlRequest) -> ShimControlResult<ControlResponse> {
+        self.send(request)?;
+        match self.receive()? {
+            Some(ControlResponse::Error { message }) => Err(ShimControlError::Rejected(message)),
+            Some(response) => Ok(response),
+            None => Err(ShimControlError::Closed),
+        }
+    }
+
+    fn send(&mut self, request: &ControlRequest) -> ShimControlResult<()> {
+        let mut line = serde_json::to_vec(request)?;
+        line.push(b'\n');
+        self.writer.write_all(&line)?;
+        Ok(())
+    }
+
+    fn receive(&mut self) -> ShimControlResult<Option<ControlResponse>> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        Ok(Some(serde_json::from_str(&line)?))
+    }
+}
+
+/// Status changes from a subscribed [`ShimClient`], until the shim goes away.
+#[derive(Debug)]
+pub struct ShimEvents {
+    client: ShimClient,
+}
+
+impl Iterator for ShimEvents {
+    type Item = ShimControlResult<ShimStatus>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.client.receive() {
+            Ok(Some(ControlResponse::Status(status))) => Some(Ok(status)),
+            Ok(Some(ControlResponse::Error { message })) => {
+                Some(Err(ShimControlError::Rejected(message)))
+            }
+            Ok(Some(response)) => Some(Err(ShimControlError::UnexpectedResponse(response))),
+            Ok(None) => None,
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    use tempfile::tempdir;
+
+    use super::*;
+
+    fn status(status: SubroutineStatus) -> ShimStatus {
+        ShimStatus {
+            status,
+            changed_at: Utc::now(),
+            health: SubroutineHealth::Unknown,
+            pid: None,
+            started_at: Utc::now(),
+            last_exit: None,
+            restarts: 0,
+            clients: vec![],
+        }
+    }
+
+    /// Answers a single request with `responses`, returning the request.
+    fn serve_once(
+        socket: &Path,
+        responses: Vec<ControlResponse>,
+    ) -> thread::JoinHandle<ControlRequest> {
+        let listener = UnixListener::bind(socket).unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut line = String::new();
+            BufReader::new(&stream).read_line(&mut line).unwrap();
+            for response in responses {
+                let mut line = serde_json::to_vec(&response).unwrap();
+                line.push(b'\n');
+                stream.write_all(&line).unwrap();
+            }
+            serde_json::from_str(&line).unwrap()
+        })
+    }
+
+    #[test]
+    fn sends_signals_by_name() {
+        let temp = tempdir().unwrap();
+        let socket = temp.path().join(CONTROL_SOCKET);
+        let server = serve_once(&socket, vec![ControlResponse::Ok]);
+
+        ShimClient::connect(&socket)
+            .unwrap()
+            .signal(Signal::SIGHUP)
+            .unwrap();
+
+        assert_eq!(
+            server.join().unwrap(),
+            ControlRequest::Signal {
+                signal: "SIGHUP".into()
+            }
+        );
+        assert_eq!(
+            ControlRequest::parse_signal("SIGHUP").unwrap(),
+            Signal::SIGHUP
+        );
+        assert!(ControlRequest::parse_signal("SIGNOPE").is_err());
+    }
+
+    #[test]
+    fn stops_and_reports_final_status() {
+        let temp = tempdir().unwrap();
+        let socket = temp.path().join(CONTROL_SOCKET);
+        let stopped = ShimStatus {
+            last_exit: Some(SubroutineExit {
+                status: SubroutineStatus::Killed { signal: 15 },
+                exited_at: Utc::now(),
+                stderr_tail: vec![],
+            }),
+            ..status(SubroutineStatus::Stopped)
+        };
+        let server = serve_once(&socket, vec![ControlResponse::Status(stopped.clone())]);
+
+        let status = ShimClient::connect(&socket)
+            .unwrap()
+            .stop(Duration::from_secs(10))
+            .unwrap();
+
+        assert_eq!(status, stopped);
+        assert_eq!(server.join().unwrap(), ControlRequest::Stop { timeout: 10 });
+    }
+
+    #[test]
+    fn reports_rejections() {
+        let temp = tempdir().unwrap();
+        let socket = temp.path().join(CONTROL_SOCKET);
+        let server = serve_once(
+            &socket,
+            vec![ControlResponse::Error {
+                message: "nope".into(),
+            }],
+        );
+
+        let err = ShimClient::connect(&socket).unwrap().status().unwrap_err();
+
+        assert!(matches!(err, ShimControlError::Rejected(message) if message == "nope"));
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn streams_status_changes() {
+        let temp = tempdir().unwrap();
+        let socket = temp.path().join(CONTROL_SOCKET);
+        let server = serve_once(
+            &socket,
+            vec![
+                ControlResponse::Status(status(SubroutineStatus::Running(42))),
+                ControlResponse::Status(status(SubroutineStatus::Stopped)),
+            ],
+        );
+
+        let events: Vec<_> = ShimClient::connect(&socket)
+            .unwrap()
+            .subscribe()
+            .unwrap()
+            .map(|status| status.unwrap().status)
+            .collect();
+
+        assert_eq!(
+            events,
+            vec![SubroutineStatus::Running(42), SubroutineStatus::Stopped]
+        );
+        assert_eq!(server.join().unwrap(), ControlRequest::Subscribe);
+    }
+}
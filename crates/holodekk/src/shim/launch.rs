@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::entities::{SubroutineEntity, SubroutineEntityId};
+use crate::images::SubroutineImage;
+use crate::secrets::{resolve_environment, SecretResult, SecretStore};
+use crate::utils::process::DaemonizeError;
+use crate::HolodekkPaths;
+
+use super::{ShimCommand, SubroutineSpec};
+
+/// Launches subroutines, each under a shim of its own: those holodekkd creates, and images run
+/// as they are (by `holodekk bundle run`).
+#[derive(Clone, Debug)]
+pub struct ShimLauncher {
+    paths: Arc<HolodekkPaths>,
+    secrets: Arc<SecretStore>,
+}
+
+impl ShimLauncher {
+    pub fn new(paths: Arc<HolodekkPaths>, secrets: Arc<SecretStore>) -> Self {
+        Self { paths, secrets }
+    }
+
+    /// Environment the subroutine is run with: the scene's discovery variables, overridden by
+    /// the scene's environment, overridden in turn by the subroutine's own, with secret
+    /// references replaced by the secrets' values.
+    pub fn environment(
+        &self,
+        discovery_environment: &HashMap<String, String>,
+        scene_environment: &HashMap<String, String>,
+        subroutine: &SubroutineEntity,
+    ) -> SecretResult<HashMap<String, String>> {
+        resolve_environment(
+            [
+                discovery_environment,
+                scene_environment,
+                &subroutine.environment,
+            ],
+            &self.secrets,
+        )
+    }
+
+    /// Environment an image is run with on its own: its manifest's, with secret references
+    /// replaced by the secrets' values.
+    pub fn image_environment(
+        &self,
+        image: &SubroutineImage,
+    ) -> SecretResult<HashMap<String, String>> {
+        resolve_environment(
+            image
+                .manifest
+                .as_ref()
+                .and_then(|manifest| manifest.environment()),
+            &self.secrets,
+        )
+    }
+
+    /// Spawns the subroutine's shim (running the image's files), returning the shim's pid.
+    ///
+    /// `environment` should come from [`ShimLauncher::environment`].  This blocks until the shim
+    /// has detached.
+    pub fn launch(
+        &self,
+        subroutine: &SubroutineEntity,
+        image: &SubroutineImage,
+        environment: HashMap<String, String>,
+    ) -> Result<i32, DaemonizeError> {
+        let name = image
+            .manifest
+            .as_ref()
+            .map(|manifest| manifest.name().to_string())
+            .unwrap_or_else(|| image.name.to_string());
+        ShimCommand::new(&subroutine.id, &image.path, name)
+            .with_scene(&subroutine.scene_entity_id)
+            .with_image(&image.id)
+            .with_spec(SubroutineSpec::for_subroutine(subroutine, image))
+            .with_environment(environment)
+            .spawn(&self.paths)
+    }
+
+    /// Spawns a shim running the image as it is (see [`SubroutineSpec::for_image`]), returning
+    /// the shim's pid.
+    ///
+    /// `environment` should come from [`ShimLauncher::image_environment`].
+    pub fn launch_image(
+        &self,
+        id: &SubroutineEntityId,
+        image: &SubroutineImage,
+        name: &str,
+        environment: HashMap<String, String>,
+    ) -> Result<i32, DaemonizeError> {
+        ShimCommand::new(id, &image.path, name)
+            .with_image(&image.id)
+            .with_spec(SubroutineSpec::for_image(image))
+            .with_environment(environment)
+            .spawn(&self.paths)
+    }
+}
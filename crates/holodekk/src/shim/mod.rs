@@ -0,0 +1,276 @@
+//! Launching subroutines under the `holodekk-subroutine` shim.
+//!
+//! The shim daemonizes itself, then supervises the subroutine (capturing its output, forwarding
+//! signals, restarting it, etc.).  It's spawned with [`daemonize`], like holodekk's other
+//! daemons, so the caller learns the shim's pid once it's detached.  How the subroutine is run
+//! is described by a [`SubroutineSpec`], which is written to a file and handed to the shim.
+//! Once running, the shim is controlled through its control socket, with a [`ShimClient`].
+//!
+//! The subroutine's resolved environment is handed to the shim on a pipe (never in the spec, or
+//! on its command line), as it may contain secrets.  holodekkd launches subroutines with a
+//! [`ShimLauncher`], which resolves that environment first.
+//!
+//! The shim also keeps a [`ShimState`] file up to date, so a restarted holodekkd can find (and
+//! adopt) the shims it left running, with [`recover_subroutines`].
+mod control;
+pub use control::*;
+mod launch;
+pub use launch::*;
+mod recovery;
+pub use recovery::*;
+mod spec;
+pub use spec::*;
+mod state;
+pub use state::*;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::Command;
+use std::thread::{self, JoinHandle};
+
+use log::warn;
+use nix::fcntl::{fcntl, FcntlArg, FdFlag, OFlag};
+use nix::unistd::pipe2;
+
+use crate::entities::{SceneEntityId, SubroutineEntityId};
+use crate::images::SubroutineImageId;
+use crate::utils::fs::ensure_directory;
+use crate::utils::process::{daemonize, DaemonizeError};
+use crate::HolodekkPaths;
+
+/// Name of the shim executable (within the holodekk bin directory).
+pub const SHIM_BINARY: &str = "holodekk-subroutine";
+
+/// Name of the file the shim records its [`ShimState`] in (within its root).
+pub const STATE_FILE: &str = "state.json";
+
+/// Name of the file the shim's [`SubroutineSpec`] is written to (within its root).
+pub const SPEC_FILE: &str = "spec.json";
+
+/// Command line for running a subroutine under the shim.
+#[derive(Clone, PartialEq)]
+pub struct ShimCommand {
+    subroutine_id: SubroutineEntityId,
+    path: PathBuf,
+    subroutine: String,
+    scene_id: Option<SceneEntityId>,
+    image_id: Option<SubroutineImageId>,
+    spec: Option<SubroutineSpec>,
+    environment: Option<HashMap<String, String>>,
+}
+
+impl std::fmt::Debug for ShimCommand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // the environment's values may be secrets
+        f.debug_struct("ShimCommand")
+            .field("subroutine_id", &self.subroutine_id)
+            .field("path", &self.path)
+            .field("subroutine", &self.subroutine)
+            .field("scene_id", &self.scene_id)
+            .field("image_id", &self.image_id)
+            .field("spec", &self.spec)
+            .field(
+                "environment",
+                &self
+                    .environment
+                    .as_ref()
+                    .map(|env| env.keys().collect::<Vec<_>>()),
+            )
+            .finish()
+    }
+}
+
+impl ShimCommand {
+    pub fn new<P, S>(subroutine_id: &SubroutineEntityId, path: P, subroutine: S) -> Self
+    where
+        P: Into<PathBuf>,
+        S: Into<String>,
+    {
+        Self {
+            subroutine_id: subroutine_id.to_owned(),
+            path: path.into(),
+            subroutine: subroutine.into(),
+            scene_id: None,
+            image_id: None,
+            spec: None,
+            environment: None,
+        }
+    }
+
+    /// Reports the subroutine's status (and health) to holodekkd, as part of the given scene.
+    pub fn with_scene(mut self, scene_id: &SceneEntityId) -> Self {
+        self.scene_id = Some(scene_id.to_owned());
+        self
+    }
+
+    /// Records the image the subroutine was created from (in the shim's state file).
+    pub fn with_image(mut self, image_id: &SubroutineImageId) -> Self {
+        self.image_id = Some(image_id.to_owned());
+        self
+    }
+
+    pub fn with_spec(mut self, spec: SubroutineSpec) -> Self {
+        self.spec = Some(spec);
+        self
+    }
+
+    /// Variables to run the subroutine with (fully resolved), written to the shim on a pipe.
+    pub fn with_environment(mut self, environment: HashMap<String, String>) -> Self {
+        self.environment = Some(environment);
+        self
+    }
+
+    /// Directory the shim keeps the subroutine's runtime state in.
+    pub fn root(&self, paths: &HolodekkPaths) -> PathBuf {
+        paths.subroutines_root().join(&self.subroutine_id)
+    }
+
+    /// Pidfile written by the shim once it has detached.
+    pub fn pidfile(&self, paths: &HolodekkPaths) -> PathBuf {
+        self.root(paths).join("shim.pid")
+    }
+
+    /// Where the shim records its [`ShimState`].
+    pub fn state_file(&self, paths: &HolodekkPaths) -> PathBuf {
+        self.root(paths).join(STATE_FILE)
+    }
+
+    /// The shim's control socket.
+    pub fn control_socket(&self, paths: &HolodekkPaths) -> PathBuf {
+        self.root(paths).join(CONTROL_SOCKET)
+    }
+
+    /// Where the spec is written for the shim to read.
+    pub fn spec_file(&self, paths: &HolodekkPaths) -> PathBuf {
+        self.root(paths).join(SPEC_FILE)
+    }
+
+    /// The shim's command line (less the options [`daemonize`] adds).
+    pub fn command(&self, paths: &HolodekkPaths) -> Command {
+        let mut command = Command::new(paths.bin_root().join(SHIM_BINARY));
+        command
+            .arg("--id")
+            .arg(&self.subroutine_id)
+            .arg("--path")
+            .arg(&self.path)
+            .arg("--subroutine")
+            .arg(&self.subroutine);
+        if let Some(scene_id) = self.scene_id.as_ref() {
+            command.arg("--scene").arg(scene_id);
+        }
+        if let Some(image_id) = self.image_id.as_ref() {
+            command.arg("--image").arg(image_id);
+        }
+        if self.spec.is_some() {
+            command.arg("--spec").arg(self.spec_file(paths));
+        }
+        command
+    }
+
+    /// Spawns the shim, returning its pid once it has detached.
+    pub fn spawn(&self, paths: &HolodekkPaths) -> Result<i32, DaemonizeError> {
+        ensure_directory(self.root(paths))?;
+        if let Some(spec) = self.spec.as_ref() {
+            spec.save(self.spec_file(paths))?;
+        }
+
+        let mut command = self.command(paths);
+        let environment = match self.environment.as_ref() {
+            Some(environment) => Some(pass_environment(&mut command, environment)?),
+            None => None,
+        };
+        let result = daemonize(paths, command, self.pidfile(paths));
+
+        if let Some((reader, writer)) = environment {
+            // closing our read end fails the write, if the shim never read it
+            drop(reader);
+            match writer.join() {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => warn!("Failed to hand environment to shim: {}", err),
+                Err(_) => warn!("Environment writer panicked"),
+            }
+        }
+        result
+    }
+}
+
+/// Arranges for `environment` to reach the shim on a pipe (given as `--environment-fd`),
+/// returning the pipe's read end (to be closed once the shim is spawned) and the thread writing
+/// to it.
+///
+/// Both ends are close-on-exec here; only the shim's copy of the read end has the flag cleared
+/// (after it's forked), so no other process we spawn can inherit it.
+fn pass_environment(
+    command: &mut Command,
+    environment: &HashMap<String, String>,
+) -> io::Result<(OwnedFd, JoinHandle<io::Result<()>>)> {
+    let json = serde_json::to_vec(environment)?;
+    let (reader, writer) = pipe2(OFlag::O_CLOEXEC)?;
+
+    let fd = reader.as_raw_fd();
+    command.arg("--environment-fd").arg(fd.to_string());
+    unsafe {
+        command.pre_exec(move || {
+            fcntl(fd, FcntlArg::F_SETFD(FdFlag::empty()))?;
+            Ok(())
+        });
+    }
+
+    // written from a thread, as the environment may not fit in the pipe's buffer
+    let mut writer = File::from(writer);
+    let writer = thread::spawn(move || writer.write_all(&json));
+    Ok((reader, writer))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+
+    use super::*;
+
+    #[test]
+    fn builds_shim_command_line() {
+        let paths = HolodekkPaths::new("/var/lib/holodekk", "/run/holodekk", "/usr/local/bin");
+        let id = SubroutineEntityId::generate();
+        let scene = SceneEntityId::generate();
+        let image = SubroutineImageId::generate(&"widgets".into());
+
+        let shim = ShimCommand::new(&id, "/srv/widgets", "default")
+            .with_scene(&scene)
+            .with_image(&image)
+            .with_spec(SubroutineSpec::default());
+        let command = shim.command(&paths);
+
+        assert_eq!(
+            command.get_program(),
+            OsStr::new("/usr/local/bin/holodekk-subroutine")
+        );
+        let args: Vec<_> = command.get_args().collect();
+        assert_eq!(
+            args,
+            [
+                "--id",
+                &id,
+                "--path",
+                "/srv/widgets",
+                "--subroutine",
+                "default",
+                "--scene",
+                &scene,
+                "--image",
+                &image,
+                "--spec",
+                &format!("/run/holodekk/subroutines/{}/spec.json", id),
+            ]
+            .map(OsStr::new)
+        );
+        assert_eq!(
+            shim.pidfile(&paths),
+            PathBuf::from(format!("/run/holodekk/subroutines/{}/shim.pid", id))
+        );
+    }
+}
@@ -0,0 +1,419 @@
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+
+use log::{debug, info, warn};
+
+use crate::entities::{
+    EntityRepositoryError, EntityRepositoryResult, SubroutineEntity, SubroutineEntityRepository,
+    SubroutineEntityRepositoryQuery, SubroutineStatusChange,
+};
+use crate::enums::SubroutineStatus;
+
+use super::{ShimRecovery, ShimState, ShimStateError, SubroutineSpec, SPEC_FILE, STATE_FILE};
+
+/// How long a shim has to answer before it's taken for dead.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Reads the state file of every shim under `subroutines_root`, skipping any that can't be
+/// read (with a warning).
+pub fn find_shims(subroutines_root: &Path) -> io::Result<Vec<ShimState>> {
+    if !subroutines_root.try_exists()? {
+        return Ok(Vec::new());
+    }
+    let mut shims = Vec::new();
+    for entry in fs::read_dir(subroutines_root)? {
+        let state_file = entry?.path().join(STATE_FILE);
+        match ShimState::load(&state_file) {
+            Ok(state) => shims.push(state),
+            Err(ShimStateError::Io(err)) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => warn!("Unable to read {}: {}", state_file.display(), err),
+        }
+    }
+    Ok(shims)
+}
+
+/// Brings the repository up to date with the shims left behind by an earlier holodekkd,
+/// returning the subroutines whose shims are still running (which are adopted).
+///
+/// Adoption is reconciliation only: a shim goes on supervising its subroutine, and reporting
+/// status and health changes to holodekkd's API itself, so there's nothing else to hand it to.
+///
+/// - subroutines with live shims get their shims' current status and health (and are recreated,
+///   with the settings recorded in their shims' specs, if the repository has lost them)
+/// - subroutines whose shims have gone get the final status the shims recorded, or are marked
+///   `Crashed` if the shims died without recording one
+/// - subroutines the repository has as running, but which have no shim at all, are marked
+///   `Crashed`
+pub async fn recover_subroutines<R>(
+    subroutines_root: &Path,
+    repo: &R,
+) -> EntityRepositoryResult<Vec<SubroutineEntity>>
+where
+    R: SubroutineEntityRepository,
+{
+    let shims = find_shims(subroutines_root).unwrap_or_else(|err| {
+        warn!(
+            "Unable to search {} for shims: {}",
+            subroutines_root.display(),
+            err
+        );
+        Vec::new()
+    });
+
+    let mut adopted = Vec::new();
+    let mut found = HashSet::new();
+    for state in shims {
+        let id = state.subroutine_id.clone();
+        found.insert(id.clone());
+        let recovery = {
+            let state = state.clone();
+            tokio::task::spawn_blocking(move || state.recover(PROBE_TIMEOUT)).await
+        };
+        let recovery = recovery.unwrap_or_else(|err| {
+            warn!("Unable to probe shim for subroutine {}: {}", id, err);
+            state.crashed()
+        });
+        let change = recovery.change();
+        let health = match &recovery {
+            ShimRecovery::Running(status) => Some(status.health),
+            _ => None,
+        };
+
+        match repo.subroutines_get(&id).await {
+            Ok(_) => {
+                let entity = repo.subroutines_update(&id, Some(change), health).await?;
+                match recovery {
+                    ShimRecovery::Running(_) => {
+                        info!("Adopting running shim for subroutine {}", id);
+                        adopted.push(entity);
+                    }
+                    ShimRecovery::Finished(_) => {
+                        debug!("Shim for subroutine {} has finished", id);
+                    }
+                    ShimRecovery::Crashed(_) => warn!("Shim for subroutine {} crashed", id),
+                }
+            }
+            Err(EntityRepositoryError::NotFound(_)) => {
+                let (Some(health), Some(scene_id), Some(image_id)) =
+                    (health, state.scene_id.as_ref(), state.image_id.as_ref())
+                else {
+                    debug!("Ignoring shim for unknown subroutine {}", id);
+                    continue;
+                };
+                info!("Adopting running shim for missing subroutine {}", id);
+                let mut entity = SubroutineEntity::new(scene_id, image_id);
+                let spec_file = subroutines_root.join(&id).join(SPEC_FILE);
+                match SubroutineSpec::load(&spec_file) {
+                    Ok(spec) => spec.restore(&mut entity),
+                    Err(err) => warn!("Unable to read {}: {}", spec_file.display(), err),
+                }
+                entity.id = id;
+                entity.change_status(change);
+                entity.health = health;
+                adopted.push(repo.subroutines_create(entity).await?);
+            }
+            Err(err) => return Err(err),
+        }
+    }
+
+    // anything still supposedly running has lost its shim
+    let subroutines = repo
+        .subroutines_find(SubroutineEntityRepositoryQuery::default())
+        .await?;
+    for subroutine in subroutines {
+        if !found.contains(&subroutine.id) && subroutine.status.is_supervised() {
+            warn!("No shim found for subroutine {}", subroutine.id);
+            repo.subroutines_update(
+                &subroutine.id,
+                Some(SubroutineStatusChange::new(SubroutineStatus::Crashed)),
+                None,
+            )
+            .await?;
+        }
+    }
+
+    Ok(adopted)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixListener;
+    use std::sync::Arc;
+    use std::thread;
+
+    use chrono::Utc;
+    use rstest::*;
+    use tempfile::{tempdir, TempDir};
+
+    use crate::cgroups::ResourceLimits;
+    use crate::entities::fixtures::mock_subroutine_entity;
+    use crate::enums::{SubroutineHealth, SubroutineKind};
+    use crate::health::{HealthProbe, ProbeCheck};
+    use crate::images::SubroutineImage;
+    use crate::logs::{LogFormat, LogRotation};
+    use crate::privileges::RunAs;
+    use crate::repositories::memory::{MemoryDatabase, MemoryRepository};
+    use crate::restart::RestartPolicy;
+    use crate::shim::{ControlResponse, ShimStatus, CONTROL_SOCKET};
+
+    use super::*;
+
+    #[fixture]
+    fn temp() -> TempDir {
+        tempdir().unwrap()
+    }
+
+    #[fixture]
+    fn repo() -> MemoryRepository {
+        MemoryRepository::new(Arc::new(MemoryDatabase::new()))
+    }
+
+    /// Writes a state file for the subroutine (as its shim would).
+    fn shim(temp: &TempDir, subroutine: &SubroutineEntity, status: SubroutineStatus) -> ShimState {
+        let root = temp.path().join(&subroutine.id);
+        fs::create_dir_all(&root).unwrap();
+        let mut state = ShimState::new(
+            &subroutine.id,
+            "0.1.0",
+            root.join(CONTROL_SOCKET),
+            root.join("log.sock"),
+        )
+        .with_scene(&subroutine.scene_entity_id)
+        .with_image(&subroutine.subroutine_image_id);
+        state.status = SubroutineStatusChange::new(status);
+        state.save(root.join(STATE_FILE)).unwrap();
+        state
+    }
+
+    /// Answers a status request on the shim's control socket.
+    fn serve_status(state: &ShimState, status: SubroutineStatus) -> thread::JoinHandle<()> {
+        let listener = UnixListener::bind(&state.control_socket).unwrap();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut line = String::new();
+            BufReader::new(&stream).read_line(&mut line).unwrap();
+            let response = ControlResponse::Status(ShimStatus {
+                status,
+                changed_at: Utc::now(),
+                health: SubroutineHealth::Healthy,
+                pid: None,
+                started_at: Utc::now(),
+                last_exit: None,
+                restarts: 0,
+                clients: vec![],
+            });
+            let mut line = serde_json::to_vec(&response).unwrap();
+            line.push(b'\n');
+            stream.write_all(&line).unwrap();
+        })
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn adopts_running_shims(
+        temp: TempDir,
+        repo: MemoryRepository,
+        mock_subroutine_entity: SubroutineEntity,
+    ) {
+        repo.subroutines_create(mock_subroutine_entity.clone())
+            .await
+            .unwrap();
+        let state = shim(
+            &temp,
+            &mock_subroutine_entity,
+            SubroutineStatus::Running(42),
+        );
+        let server = serve_status(&state, SubroutineStatus::Running(42));
+
+        let adopted = recover_subroutines(temp.path(), &repo).await.unwrap();
+        server.join().unwrap();
+
+        assert_eq!(adopted.len(), 1);
+        let subroutine = repo
+            .subroutines_get(&mock_subroutine_entity.id)
+            .await
+            .unwrap();
+        assert_eq!(subroutine.status, SubroutineStatus::Running(42));
+        assert_eq!(subroutine.health, SubroutineHealth::Healthy);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn recreates_missing_subroutines_with_running_shims(
+        temp: TempDir,
+        repo: MemoryRepository,
+        mock_subroutine_entity: SubroutineEntity,
+    ) {
+        let state = shim(
+            &temp,
+            &mock_subroutine_entity,
+            SubroutineStatus::Running(42),
+        );
+        let server = serve_status(&state, SubroutineStatus::Running(42));
+
+        let adopted = recover_subroutines(temp.path(), &repo).await.unwrap();
+        server.join().unwrap();
+
+        assert_eq!(adopted[0].id, mock_subroutine_entity.id);
+        let subroutine = repo
+            .subroutines_get(&mock_subroutine_entity.id)
+            .await
+            .unwrap();
+        assert_eq!(
+            subroutine.scene_entity_id,
+            mock_subroutine_entity.scene_entity_id
+        );
+        assert_eq!(subroutine.status, SubroutineStatus::Running(42));
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn recreates_missing_subroutines_with_their_settings(
+        temp: TempDir,
+        repo: MemoryRepository,
+        mut mock_subroutine_entity: SubroutineEntity,
+    ) {
+        mock_subroutine_entity.environment =
+            HashMap::from([("DATABASE_URL".into(), "${secret:db}".into())]);
+        mock_subroutine_entity.port = Some(4567);
+        mock_subroutine_entity.host_port = Some(32768);
+        mock_subroutine_entity.health_probe =
+            Some(Box::new(HealthProbe::new(ProbeCheck::Tcp { port: None })));
+        mock_subroutine_entity.restart_policy = RestartPolicy::OnFailure { max_retries: 3 };
+        mock_subroutine_entity.limits = ResourceLimits::default().with_memory(64 * 1024 * 1024);
+        mock_subroutine_entity.run_as = RunAs::default().with_user(1000, 1000);
+        mock_subroutine_entity.process_limits.open_files = Some(256);
+        mock_subroutine_entity.log_rotation = LogRotation::default().with_max_size(1024);
+        mock_subroutine_entity.log_format = LogFormat::Json;
+        mock_subroutine_entity.stdin = true;
+        mock_subroutine_entity.tty = true;
+        let state = shim(
+            &temp,
+            &mock_subroutine_entity,
+            SubroutineStatus::Running(42),
+        );
+        let image = SubroutineImage::new("acme/widgets".into(), temp.path(), SubroutineKind::Ruby);
+        SubroutineSpec::for_subroutine(&mock_subroutine_entity, &image)
+            .save(temp.path().join(&mock_subroutine_entity.id).join(SPEC_FILE))
+            .unwrap();
+        let server = serve_status(&state, SubroutineStatus::Running(42));
+
+        recover_subroutines(temp.path(), &repo).await.unwrap();
+        server.join().unwrap();
+
+        let subroutine = repo
+            .subroutines_get(&mock_subroutine_entity.id)
+            .await
+            .unwrap();
+        assert_eq!(subroutine.environment, mock_subroutine_entity.environment);
+        assert_eq!(subroutine.port, Some(4567));
+        assert_eq!(subroutine.host_port, Some(32768));
+        assert_eq!(subroutine.health_probe, mock_subroutine_entity.health_probe);
+        assert_eq!(
+            subroutine.restart_policy,
+            mock_subroutine_entity.restart_policy
+        );
+        assert_eq!(subroutine.limits, mock_subroutine_entity.limits);
+        assert_eq!(subroutine.run_as, mock_subroutine_entity.run_as);
+        assert_eq!(
+            subroutine.process_limits,
+            mock_subroutine_entity.process_limits
+        );
+        assert_eq!(subroutine.log_rotation, mock_subroutine_entity.log_rotation);
+        assert_eq!(subroutine.log_format, LogFormat::Json);
+        assert!(subroutine.stdin);
+        assert!(subroutine.tty);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn marks_subroutines_with_dead_shims_crashed(
+        temp: TempDir,
+        repo: MemoryRepository,
+        mock_subroutine_entity: SubroutineEntity,
+    ) {
+        repo.subroutines_create(mock_subroutine_entity.clone())
+            .await
+            .unwrap();
+        shim(
+            &temp,
+            &mock_subroutine_entity,
+            SubroutineStatus::Running(42),
+        );
+
+        let adopted = recover_subroutines(temp.path(), &repo).await.unwrap();
+
+        assert!(adopted.is_empty());
+        let subroutine = repo
+            .subroutines_get(&mock_subroutine_entity.id)
+            .await
+            .unwrap();
+        assert_eq!(subroutine.status, SubroutineStatus::Crashed);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn keeps_final_status_of_finished_shims(
+        temp: TempDir,
+        repo: MemoryRepository,
+        mock_subroutine_entity: SubroutineEntity,
+    ) {
+        repo.subroutines_create(mock_subroutine_entity.clone())
+            .await
+            .unwrap();
+        let mut state = shim(&temp, &mock_subroutine_entity, SubroutineStatus::Stopped);
+        state.finished = true;
+        state
+            .save(
+                temp.path()
+                    .join(&mock_subroutine_entity.id)
+                    .join(STATE_FILE),
+            )
+            .unwrap();
+
+        recover_subroutines(temp.path(), &repo).await.unwrap();
+
+        let subroutine = repo
+            .subroutines_get(&mock_subroutine_entity.id)
+            .await
+            .unwrap();
+        assert_eq!(subroutine.status, SubroutineStatus::Stopped);
+    }
+
+    #[rstest]
+    #[tokio::test]
+    async fn marks_running_subroutines_without_shims_crashed(
+        temp: TempDir,
+        repo: MemoryRepository,
+        mut mock_subroutine_entity: SubroutineEntity,
+    ) {
+        mock_subroutine_entity.status = SubroutineStatus::Running(42);
+        repo.subroutines_create(mock_subroutine_entity.clone())
+            .await
+            .unwrap();
+
+        recover_subroutines(temp.path(), &repo).await.unwrap();
+
+        let subroutine = repo
+            .subroutines_get(&mock_subroutine_entity.id)
+            .await
+            .unwrap();
+        assert_eq!(subroutine.status, SubroutineStatus::Crashed);
+    }
+
+    #[rstest]
+    fn skips_unreadable_state_files(temp: TempDir) {
+        let root = temp.path().join("broken");
+        fs::create_dir_all(&root).unwrap();
+        fs::write(root.join(STATE_FILE), "{").unwrap();
+        fs::create_dir_all(temp.path().join("empty")).unwrap();
+
+        assert!(find_shims(temp.path()).unwrap().is_empty());
+        assert!(find_shims(&temp.path().join("missing")).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,362 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::cgroups::{CgroupError, ResourceLimits};
+use crate::entities::SubroutineEntity;
+use crate::enums::SubroutineKind;
+use crate::errors::error_chain_fmt;
+use crate::health::{HealthProbe, HealthProbeError};
+use crate::images::SubroutineImage;
+use crate::logs::{LogFormat, LogRotation, LogRotationError};
+use crate::privileges::{PrivilegesError, RunAs};
+use crate::restart::RestartPolicy;
+use crate::rlimits::{ProcessLimits, ProcessLimitsError};
+use crate::runtimes::{LaunchCommand, RuntimeError, RuntimeRegistry, RuntimeResult};
+
+#[derive(thiserror::Error)]
+pub enum SubroutineSpecError {
+    #[error("Invalid health probe")]
+    HealthProbe(#[from] HealthProbeError),
+    #[error("Invalid resource limits")]
+    Limits(#[from] CgroupError),
+    #[error("Invalid privileges")]
+    RunAs(#[from] PrivilegesError),
+    #[error("Invalid process limits")]
+    ProcessLimits(#[from] ProcessLimitsError),
+    #[error("Invalid log rotation")]
+    LogRotation(#[from] LogRotationError),
+}
+
+impl std::fmt::Debug for SubroutineSpecError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+/// Everything the shim needs to launch (and supervise) a subroutine, handed over as a file.
+///
+/// Any of the shim's own options override the corresponding setting here.
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Serialize)]
+pub struct SubroutineSpec {
+    /// Selects the runtime used to launch the subroutine (detected from its files when unset).
+    #[serde(default)]
+    pub kind: Option<SubroutineKind>,
+    /// Program and arguments to run instead of the runtime's launch command.
+    #[serde(default)]
+    pub command: Vec<String>,
+    /// Arguments appended to the launch command.
+    #[serde(default)]
+    pub args: Vec<String>,
+    /// Environment variables set for the subroutine.
+    #[serde(default)]
+    pub env: BTreeMap<String, String>,
+    /// Layers (tar archives, base first) to build the subroutine's root filesystem from.
+    #[serde(default)]
+    pub layers: Vec<PathBuf>,
+    #[serde(default)]
+    pub host_port: Option<u16>,
+    #[serde(default)]
+    pub health_probe: Option<HealthProbe>,
+    #[serde(default)]
+    pub limits: Option<ResourceLimits>,
+    #[serde(default)]
+    pub run_as: Option<RunAs>,
+    #[serde(default)]
+    pub process_limits: Option<ProcessLimits>,
+    #[serde(default)]
+    pub restart_policy: RestartPolicy,
+    #[serde(default)]
+    pub log_format: LogFormat,
+    #[serde(default)]
+    pub log_rotation: LogRotation,
+    /// Give the subroutine a stdin that attached clients can write to (rather than `/dev/null`).
+    #[serde(default)]
+    pub stdin: bool,
+    /// Run the subroutine in a pseudo-terminal (its controlling terminal), rather than with
+    /// stdio pipes.  Implies `stdin`.
+    #[serde(default)]
+    pub tty: bool,
+    /// Port the subroutine expects traffic on.  Unused by the shim; recorded so the subroutine
+    /// can be recovered (see [`SubroutineSpec::restore`]).
+    #[serde(default)]
+    pub port: Option<u16>,
+    /// The subroutine's own environment as it was given (with secret references, not the
+    /// secrets' values).  Unused by the shim, which is handed the resolved environment; recorded
+    /// so the subroutine can be recovered (see [`SubroutineSpec::restore`]).
+    #[serde(default)]
+    pub subroutine_env: BTreeMap<String, String>,
+}
+
+impl SubroutineSpec {
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+    }
+
+    /// Spec for running the given image as it is (as `holodekk bundle run` does): with its
+    /// detected kind, and listening on its manifest's port.
+    ///
+    /// The manifest's environment isn't part of the spec (see [`ShimCommand::with_environment`]).
+    ///
+    /// [`ShimCommand::with_environment`]: super::ShimCommand::with_environment
+    pub fn for_image(image: &SubroutineImage) -> Self {
+        Self {
+            kind: (image.kind != SubroutineKind::Unknown).then_some(image.kind),
+            host_port: image.manifest.as_ref().and_then(|manifest| manifest.port()),
+            ..Default::default()
+        }
+    }
+
+    /// Spec for running a subroutine created (by holodekkd) from the given image: the image's
+    /// spec (see [`SubroutineSpec::for_image`]), with the subroutine's own settings.
+    ///
+    /// The subroutine's resolved environment isn't part of the spec (see
+    /// [`ShimCommand::with_environment`]); only its own variables are recorded, as they were given.
+    ///
+    /// [`ShimCommand::with_environment`]: super::ShimCommand::with_environment
+    pub fn for_subroutine(subroutine: &SubroutineEntity, image: &SubroutineImage) -> Self {
+        Self {
+            port: subroutine.port,
+            subroutine_env: subroutine
+                .environment
+                .iter()
+                .map(|(k, v)| (k.to_owned(), v.to_owned()))
+                .collect(),
+            host_port: subroutine.host_port,
+            health_probe: subroutine.health_probe.as_deref().cloned(),
+            limits: (subroutine.limits != ResourceLimits::default()).then_some(subroutine.limits),
+            run_as: Some(subroutine.run_as.clone()),
+            process_limits: Some(subroutine.process_limits),
+            restart_policy: subroutine.restart_policy,
+            log_rotation: subroutine.log_rotation,
+            log_format: subroutine.log_format,
+            stdin: subroutine.stdin,
+            tty: subroutine.tty,
+            ..Self::for_image(image)
+        }
+    }
+
+    /// Gives a subroutine the settings recorded by [`SubroutineSpec::for_subroutine`] (when
+    /// recovering one the repository has lost from its shim).
+    pub fn restore(&self, subroutine: &mut SubroutineEntity) {
+        subroutine.environment = self
+            .subroutine_env
+            .iter()
+            .map(|(k, v)| (k.to_owned(), v.to_owned()))
+            .collect();
+        subroutine.port = self.port;
+        subroutine.host_port = self.host_port;
+        subroutine.health_probe = self.health_probe.clone().map(Box::new);
+        subroutine.limits = self.limits.unwrap_or_default();
+        subroutine.run_as = self.run_as.clone().unwrap_or_default();
+        subroutine.process_limits = self.process_limits.unwrap_or_default();
+        subroutine.restart_policy = self.restart_policy;
+        subroutine.log_rotation = self.log_rotation;
+        subroutine.log_format = self.log_format;
+        subroutine.stdin = self.stdin;
+        subroutine.tty = self.tty;
+    }
+
+    pub fn with_kind(mut self, kind: SubroutineKind) -> Self {
+        self.kind = Some(kind);
+        self
+    }
+
+    pub fn with_command(mut self, command: Vec<String>) -> Self {
+        self.command = command;
+        self
+    }
+
+    pub fn with_args(mut self, args: Vec<String>) -> Self {
+        self.args = args;
+        self
+    }
+
+    pub fn with_env(mut self, env: BTreeMap<String, String>) -> Self {
+        self.env = env;
+        self
+    }
+
+    pub fn with_stdin(mut self) -> Self {
+        self.stdin = true;
+        self
+    }
+
+    pub fn with_tty(mut self) -> Self {
+        self.tty = true;
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), SubroutineSpecError> {
+        if let Some(probe) = self.health_probe.as_ref() {
+            probe.validate()?;
+        }
+        if let Some(limits) = self.limits.as_ref() {
+            limits.validate()?;
+        }
+        if let Some(run_as) = self.run_as.as_ref() {
+            run_as.validate()?;
+        }
+        if let Some(process_limits) = self.process_limits.as_ref() {
+            process_limits.validate()?;
+        }
+        self.log_rotation.validate()?;
+        Ok(())
+    }
+
+    /// Resolves the command used to run the subroutine at `path` (its working directory).
+    ///
+    /// An explicit command wins; otherwise the launch command comes from the runtime for the
+    /// subroutine's kind (or the first runtime which recognizes it).
+    pub fn launch_command<P: AsRef<Path>>(
+        &self,
+        path: P,
+        runtimes: &RuntimeRegistry,
+    ) -> RuntimeResult<LaunchCommand> {
+        let path = path.as_ref();
+        let command = match self.command.split_first() {
+            Some((program, args)) => args
+                .iter()
+                .fold(LaunchCommand::new(program, path), |command, arg| {
+                    command.arg(arg)
+                }),
+            None => {
+                let runtime = match self.kind {
+                    Some(kind) => runtimes.get(kind)?,
+                    None => runtimes
+                        .detect(path)
+                        .ok_or(RuntimeError::Unsupported(SubroutineKind::Unknown))?,
+                };
+                runtime.launch_command(path)?
+            }
+        };
+        Ok(self
+            .args
+            .iter()
+            .fold(command, |command, arg| command.arg(arg))
+            .envs(self.env.iter()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::tempdir;
+
+    use crate::entities::{SceneEntityId, SubroutineManifest};
+    use crate::runtimes::RUN_SUBCOMMAND;
+
+    use super::*;
+
+    #[test]
+    fn runs_explicit_commands() {
+        let temp = tempdir().unwrap();
+        let spec = SubroutineSpec::default()
+            .with_command(vec!["/usr/bin/ping".into(), "127.0.0.1".into()])
+            .with_args(vec!["-c".into(), "1".into()]);
+
+        let command = spec
+            .launch_command(temp.path(), &RuntimeRegistry::default())
+            .unwrap();
+
+        assert_eq!(command.program, "/usr/bin/ping");
+        assert_eq!(command.args, vec!["127.0.0.1", "-c", "1"]);
+        assert_eq!(command.working_dir, temp.path());
+    }
+
+    #[test]
+    fn runs_images_on_their_manifest_port() {
+        let temp = tempdir().unwrap();
+        let mut image =
+            SubroutineImage::new("acme/widgets".into(), temp.path(), SubroutineKind::Ruby);
+        image.manifest = Some(
+            SubroutineManifest::parse(r#"{"name": "widgets", "port": 4567}"#.as_bytes()).unwrap(),
+        );
+
+        let spec = SubroutineSpec::for_image(&image);
+
+        assert_eq!(spec.kind, Some(SubroutineKind::Ruby));
+        assert_eq!(spec.host_port, Some(4567));
+    }
+
+    #[test]
+    fn runs_subroutines_with_their_settings() {
+        let temp = tempdir().unwrap();
+        let image = SubroutineImage::new("acme/widgets".into(), temp.path(), SubroutineKind::Ruby);
+        let mut subroutine = SubroutineEntity::new(&SceneEntityId::generate(), &image.id);
+        subroutine.host_port = Some(8080);
+        subroutine.log_format = LogFormat::Json;
+        subroutine.stdin = true;
+        subroutine.tty = true;
+
+        let spec = SubroutineSpec::for_subroutine(&subroutine, &image);
+
+        assert_eq!(spec.kind, Some(SubroutineKind::Ruby));
+        assert_eq!(spec.host_port, Some(8080));
+        assert_eq!(spec.log_format, LogFormat::Json);
+        assert!(spec.stdin);
+        assert!(spec.tty);
+    }
+
+    #[test]
+    fn resolves_runtime_by_kind() {
+        let temp = tempdir().unwrap();
+        fs::write(temp.path().join("holodekk.rb"), "puts 'hello'\n").unwrap();
+        let spec = SubroutineSpec::default()
+            .with_kind(SubroutineKind::Ruby)
+            .with_env(BTreeMap::from([("RACK_ENV".into(), "production".into())]));
+
+        let command = spec
+            .launch_command(temp.path(), &RuntimeRegistry::default())
+            .unwrap();
+
+        assert_eq!(command.program, "ruby");
+        assert!(command.args.contains(&RUN_SUBCOMMAND.to_string()));
+        assert_eq!(command.working_dir, temp.path());
+        assert_eq!(command.env.get("RACK_ENV").unwrap(), "production");
+    }
+
+    #[test]
+    fn rejects_unrecognized_subroutines() {
+        let temp = tempdir().unwrap();
+
+        assert!(matches!(
+            SubroutineSpec::default()
+                .launch_command(temp.path(), &RuntimeRegistry::default())
+                .unwrap_err(),
+            RuntimeError::Unsupported(SubroutineKind::Unknown)
+        ));
+    }
+
+    #[test]
+    fn validates_settings() {
+        let spec = SubroutineSpec {
+            limits: Some(ResourceLimits::default().with_memory(0)),
+            ..Default::default()
+        };
+
+        assert!(matches!(
+            spec.validate().unwrap_err(),
+            SubroutineSpecError::Limits(..)
+        ));
+    }
+
+    #[test]
+    fn round_trips_through_files() {
+        let temp = tempdir().unwrap();
+        let path = temp.path().join("spec.json");
+        let spec = SubroutineSpec::default()
+            .with_kind(SubroutineKind::Shell)
+            .with_args(vec!["--verbose".into()]);
+
+        spec.save(&path).unwrap();
+
+        assert_eq!(SubroutineSpec::load(&path).unwrap(), spec);
+    }
+}
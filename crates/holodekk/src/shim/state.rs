@@ -0,0 +1,298 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::entities::{SceneEntityId, SubroutineEntityId, SubroutineStatusChange};
+use crate::enums::SubroutineStatus;
+use crate::errors::error_chain_fmt;
+use crate::images::SubroutineImageId;
+
+use super::{ShimClient, ShimStatus};
+
+/// Version of the state file written by this release.
+pub const SHIM_STATE_VERSION: u32 = 1;
+
+#[derive(thiserror::Error)]
+pub enum ShimStateError {
+    #[error("Unsupported shim state version: {0}")]
+    UnsupportedVersion(u32),
+    #[error("Shim state IO error")]
+    Io(#[from] io::Error),
+    #[error("Shim state serialization error")]
+    Serialization(#[from] serde_json::Error),
+}
+
+impl std::fmt::Debug for ShimStateError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        error_chain_fmt(self, f)
+    }
+}
+
+pub type ShimStateResult<T> = std::result::Result<T, ShimStateError>;
+
+/// What a shim records of itself and its subroutine (in its [`STATE_FILE`](super::STATE_FILE)),
+/// so holodekkd can find it again after a restart.
+///
+/// The file is rewritten every time the subroutine's status changes, and a last time as the
+/// shim exits (when it's marked `finished`).
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct ShimState {
+    pub version: u32,
+    pub subroutine_id: SubroutineEntityId,
+    #[serde(default)]
+    pub scene_id: Option<SceneEntityId>,
+    #[serde(default)]
+    pub image_id: Option<SubroutineImageId>,
+    /// Version of the shim that wrote the file.
+    pub shim_version: String,
+    pub shim_pid: u32,
+    pub shim_started_at: DateTime<Utc>,
+    /// Pid of the running subroutine (if it's running).
+    pub pid: Option<u32>,
+    /// When the current (or last) incarnation of the subroutine was started.
+    pub started_at: DateTime<Utc>,
+    pub control_socket: PathBuf,
+    pub attach_socket: PathBuf,
+    /// The subroutine's current status (and how it last exited).
+    pub status: SubroutineStatusChange,
+    /// The shim has exited (so `status` is final).
+    #[serde(default)]
+    pub finished: bool,
+}
+
+/// Just enough of a state file to tell whether the rest can be read.
+#[derive(Deserialize)]
+struct Versioned {
+    version: u32,
+}
+
+impl ShimState {
+    /// State for the calling process (the shim), before the subroutine has been started.
+    pub fn new<S, P>(
+        subroutine_id: &SubroutineEntityId,
+        shim_version: S,
+        control_socket: P,
+        attach_socket: P,
+    ) -> Self
+    where
+        S: Into<String>,
+        P: Into<PathBuf>,
+    {
+        let now = Utc::now();
+        Self {
+            version: SHIM_STATE_VERSION,
+            subroutine_id: subroutine_id.to_owned(),
+            scene_id: None,
+            image_id: None,
+            shim_version: shim_version.into(),
+            shim_pid: std::process::id(),
+            shim_started_at: now,
+            pid: None,
+            started_at: now,
+            control_socket: control_socket.into(),
+            attach_socket: attach_socket.into(),
+            status: SubroutineStatusChange::new(SubroutineStatus::Unknown),
+            finished: false,
+        }
+    }
+
+    pub fn with_scene(mut self, scene_id: &SceneEntityId) -> Self {
+        self.scene_id = Some(scene_id.to_owned());
+        self
+    }
+
+    pub fn with_image(mut self, image_id: &SubroutineImageId) -> Self {
+        self.image_id = Some(image_id.to_owned());
+        self
+    }
+
+    /// Reads a state file, refusing versions this release doesn't understand.
+    pub fn load<P: AsRef<Path>>(path: P) -> ShimStateResult<Self> {
+        let json = fs::read(path)?;
+        let Versioned { version } = serde_json::from_slice(&json)?;
+        if version != SHIM_STATE_VERSION {
+            return Err(ShimStateError::UnsupportedVersion(version));
+        }
+        Ok(serde_json::from_slice(&json)?)
+    }
+
+    /// Writes the state file (replacing it whole, so readers never see part of one).
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> ShimStateResult<()> {
+        let path = path.as_ref();
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".tmp");
+        fs::write(&partial, serde_json::to_vec(self)?)?;
+        fs::rename(&partial, path)?;
+        Ok(())
+    }
+
+    /// What became of the subroutine: the shim's current status if it's still running
+    /// (answering on its control socket within `timeout`), otherwise whatever it recorded.
+    pub fn recover(&self, timeout: Duration) -> ShimRecovery {
+        let status = ShimClient::connect(&self.control_socket)
+            .and_then(|client| client.with_timeout(timeout))
+            .and_then(|mut client| client.status());
+        match status {
+            Ok(status) => ShimRecovery::Running(status),
+            Err(_) if self.finished => ShimRecovery::Finished(self.status.clone()),
+            Err(_) => self.crashed(),
+        }
+    }
+
+    /// The shim taken for dead (keeping the subroutine's last recorded exit).
+    pub fn crashed(&self) -> ShimRecovery {
+        ShimRecovery::Crashed(SubroutineStatusChange {
+            status: SubroutineStatus::Crashed,
+            changed_at: Utc::now(),
+            exit: self.status.exit.clone(),
+        })
+    }
+}
+
+/// What became of a shim found by its state file (see [`ShimState::recover`]).
+#[derive(Clone, Debug, PartialEq)]
+pub enum ShimRecovery {
+    /// The shim is still running.
+    Running(ShimStatus),
+    /// The shim exited, having recorded the subroutine's final status.
+    Finished(SubroutineStatusChange),
+    /// The shim went away without recording a final status.
+    Crashed(SubroutineStatusChange),
+}
+
+impl ShimRecovery {
+    /// The subroutine's status, as a change to apply to its entity.
+    pub fn change(&self) -> SubroutineStatusChange {
+        match self {
+            ShimRecovery::Running(status) => SubroutineStatusChange {
+                status: status.status,
+                changed_at: status.changed_at,
+                exit: status.last_exit.clone(),
+            },
+            ShimRecovery::Finished(change) | ShimRecovery::Crashed(change) => change.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    use rstest::*;
+    use tempfile::{tempdir, TempDir};
+
+    use crate::entities::SubroutineExit;
+    use crate::enums::SubroutineHealth;
+    use crate::images::ImageName;
+    use crate::shim::{ControlResponse, CONTROL_SOCKET, STATE_FILE};
+
+    use super::*;
+
+    #[fixture]
+    fn temp() -> TempDir {
+        tempdir().unwrap()
+    }
+
+    fn state(temp: &TempDir) -> ShimState {
+        ShimState::new(
+            &SubroutineEntityId::generate(),
+            "0.1.0",
+            temp.path().join(CONTROL_SOCKET),
+            temp.path().join("log.sock"),
+        )
+    }
+
+    fn exit() -> SubroutineExit {
+        SubroutineExit {
+            status: SubroutineStatus::Exited { code: 1 },
+            exited_at: Utc::now(),
+            stderr_tail: vec!["oops".into()],
+        }
+    }
+
+    #[rstest]
+    fn saves_and_loads_state(temp: TempDir) {
+        let path = temp.path().join(STATE_FILE);
+        let state = state(&temp)
+            .with_scene(&SceneEntityId::generate())
+            .with_image(&SubroutineImageId::generate(&ImageName::from("test")));
+
+        state.save(&path).unwrap();
+
+        assert_eq!(ShimState::load(&path).unwrap(), state);
+        assert!(!temp.path().join("state.json.tmp").exists());
+    }
+
+    #[rstest]
+    fn refuses_other_versions(temp: TempDir) {
+        let path = temp.path().join(STATE_FILE);
+        fs::write(&path, r#"{"version":2,"something":"new"}"#).unwrap();
+
+        assert!(matches!(
+            ShimState::load(&path).unwrap_err(),
+            ShimStateError::UnsupportedVersion(2)
+        ));
+    }
+
+    #[rstest]
+    fn recovers_final_status_of_finished_shims(temp: TempDir) {
+        let mut state = state(&temp);
+        state.status =
+            SubroutineStatusChange::new(SubroutineStatus::Exited { code: 1 }).with_exit(exit());
+        state.finished = true;
+
+        let recovery = state.recover(Duration::from_secs(1));
+
+        assert_eq!(recovery, ShimRecovery::Finished(state.status.clone()));
+    }
+
+    #[rstest]
+    fn recovers_dead_shims_as_crashed(temp: TempDir) {
+        let mut state = state(&temp);
+        state.status = SubroutineStatusChange::new(SubroutineStatus::Running(42)).with_exit(exit());
+
+        let change = state.recover(Duration::from_secs(1)).change();
+
+        assert_eq!(change.status, SubroutineStatus::Crashed);
+        assert_eq!(change.exit, state.status.exit);
+    }
+
+    #[rstest]
+    fn recovers_status_of_running_shims(temp: TempDir) {
+        let state = state(&temp);
+        let listener = UnixListener::bind(&state.control_socket).unwrap();
+        let status = ShimStatus {
+            status: SubroutineStatus::Running(42),
+            changed_at: Utc::now(),
+            health: SubroutineHealth::Healthy,
+            pid: Some(42),
+            started_at: Utc::now(),
+            last_exit: None,
+            restarts: 0,
+            clients: vec![],
+        };
+        let shim = {
+            let status = status.clone();
+            thread::spawn(move || {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut line = String::new();
+                BufReader::new(&stream).read_line(&mut line).unwrap();
+                let mut response = serde_json::to_vec(&ControlResponse::Status(status)).unwrap();
+                response.push(b'\n');
+                stream.write_all(&response).unwrap();
+            })
+        };
+
+        let recovery = state.recover(Duration::from_secs(1));
+        shim.join().unwrap();
+
+        assert_eq!(recovery, ShimRecovery::Running(status));
+        assert_eq!(recovery.change().status, SubroutineStatus::Running(42));
+    }
+}
@@ -1,11 +1,17 @@
 use std::ffi::{CStr, CString};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
 
-pub use libc::{c_char, c_int, c_ulong, pid_t};
+pub use libc::{c_char, c_int, c_ulong, dev_t, gid_t, mode_t, pid_t, uid_t};
 
 pub use libc::{
-    PR_SET_CHILD_SUBREAPER, PR_SET_PDEATHSIG, STDERR_FILENO, STDIN_FILENO, STDOUT_FILENO,
+    EINVAL, EIO, MNT_DETACH, O_CLOEXEC, O_WRONLY, PR_CAPBSET_DROP, PR_SET_CHILD_SUBREAPER,
+    PR_SET_NO_NEW_PRIVS, PR_SET_PDEATHSIG, RLIMIT_AS, RLIMIT_CORE, RLIMIT_NOFILE, RLIMIT_NPROC,
+    STDERR_FILENO, STDIN_FILENO, STDOUT_FILENO, S_IFCHR,
 };
 
+pub type Resource = libc::__rlimit_resource_t;
+
 macro_rules! syscall {
     ($fn: ident ( $($arg: expr),* $(,)* ) ) => {{
         let res = unsafe { libc::$fn($($arg, )*) };
@@ -40,6 +46,12 @@ impl std::fmt::Display for Error {
 
 impl std::error::Error for Error {}
 
+impl Error {
+    pub fn errno(&self) -> Errno {
+        self.errno
+    }
+}
+
 pub trait Num {
     fn is_err(&self) -> bool;
 }
@@ -74,10 +86,14 @@ impl Num for isize {
     }
 }
 
+// read directly (rather than through std::io::Error), as it's used in forked children
 fn errno() -> Errno {
-    std::io::Error::last_os_error()
-        .raw_os_error()
-        .expect("errno")
+    unsafe { *libc::__errno_location() }
+}
+
+pub fn chdir(path: &CStr) -> Result<()> {
+    syscall!(chdir(path.as_ptr()))?;
+    Ok(())
 }
 
 pub fn close(fd: c_int) -> Result<()> {
@@ -101,6 +117,16 @@ pub fn execv(argv: &[CString]) {
     }
 }
 
+/// Executes `path`, returning only on failure.
+///
+/// # Safety
+///
+/// `argv` and `envp` must each end with a null pointer, and otherwise point to valid C strings.
+pub unsafe fn execve(path: &CStr, argv: &[*const c_char], envp: &[*const c_char]) -> Error {
+    libc::execve(path.as_ptr(), argv.as_ptr(), envp.as_ptr());
+    Error { errno: errno() }
+}
+
 pub fn _exit(code: c_int) -> ! {
     unsafe {
         libc::_exit(code);
@@ -114,11 +140,55 @@ pub fn fork() -> Result<Option<Pid>> {
     }
 }
 
+fn path_cstring(path: &Path) -> CString {
+    CString::new(path.as_os_str().as_bytes()).unwrap()
+}
+
+pub fn mknod(path: &Path, mode: mode_t, dev: dev_t) -> Result<()> {
+    let path_c = path_cstring(path);
+    syscall!(mknod(path_c.as_ptr(), mode, dev))?;
+    Ok(())
+}
+
+pub fn mount(source: &str, target: &Path, fstype: &str, flags: c_ulong, data: &str) -> Result<()> {
+    let source_c = CString::new(source).unwrap();
+    let target_c = path_cstring(target);
+    let fstype_c = CString::new(fstype).unwrap();
+    let data_c = CString::new(data).unwrap();
+    syscall!(mount(
+        source_c.as_ptr(),
+        target_c.as_ptr(),
+        fstype_c.as_ptr(),
+        flags,
+        data_c.as_ptr() as *const libc::c_void,
+    ))?;
+    Ok(())
+}
+
 pub fn open(path: &str, flags: c_int) -> Result<c_int> {
     let path_c = CString::new(path).unwrap();
     syscall!(open(path_c.as_ptr(), flags))
 }
 
+/// Opens the given path (unlike [`open`], without allocating).
+pub fn open_cstr(path: &CStr, flags: c_int) -> Result<c_int> {
+    syscall!(open(path.as_ptr(), flags))
+}
+
+/// Opens a pseudo-terminal, returning its (master, slave) pair.
+pub fn openpty() -> Result<(c_int, c_int)> {
+    let mut master: c_int = -1;
+    let mut slave: c_int = -1;
+    syscall!(openpty(
+        &mut master,
+        &mut slave,
+        std::ptr::null_mut(),
+        std::ptr::null(),
+        std::ptr::null(),
+    ))?;
+    Ok((master, slave))
+}
+
 pub fn prctl(
     option: c_int,
     arg2: c_ulong,
@@ -130,11 +200,95 @@ pub fn prctl(
     Ok(())
 }
 
+pub fn setgid(gid: gid_t) -> Result<()> {
+    syscall!(setgid(gid))?;
+    Ok(())
+}
+
+pub fn setgroups(groups: &[gid_t]) -> Result<()> {
+    syscall!(setgroups(groups.len(), groups.as_ptr()))?;
+    Ok(())
+}
+
+pub fn setuid(uid: uid_t) -> Result<()> {
+    syscall!(setuid(uid))?;
+    Ok(())
+}
+
+/// Sets the file mode creation mask, returning the previous mask.
+pub fn umask(mask: mode_t) -> mode_t {
+    unsafe { libc::umask(mask) }
+}
+
+/// Sets both the soft and hard limit for the given resource.
+pub fn setrlimit(resource: Resource, limit: u64) -> Result<()> {
+    let rlimit = libc::rlimit {
+        rlim_cur: limit as libc::rlim_t,
+        rlim_max: limit as libc::rlim_t,
+    };
+    syscall!(setrlimit(resource, &rlimit))?;
+    Ok(())
+}
+
+pub fn write(fd: c_int, buf: &[u8]) -> Result<usize> {
+    let written = syscall!(write(fd, buf.as_ptr() as *const libc::c_void, buf.len()))?;
+    Ok(written as usize)
+}
+
+pub fn setxattr(path: &Path, name: &str, value: &[u8]) -> Result<()> {
+    let path_c = path_cstring(path);
+    let name_c = CString::new(name).unwrap();
+    syscall!(setxattr(
+        path_c.as_ptr(),
+        name_c.as_ptr(),
+        value.as_ptr() as *const libc::c_void,
+        value.len(),
+        0,
+    ))?;
+    Ok(())
+}
+
 pub fn setsid() -> Result<()> {
     syscall!(setsid())?;
     Ok(())
 }
 
+/// Makes the terminal the calling process' controlling terminal.
+pub fn set_controlling_terminal(fd: c_int) -> Result<()> {
+    syscall!(ioctl(fd, libc::TIOCSCTTY, 0))?;
+    Ok(())
+}
+
+/// Sets a terminal's window size (signalling its foreground process group).
+pub fn set_window_size(fd: c_int, rows: u16, cols: u16) -> Result<()> {
+    let size = libc::winsize {
+        ws_row: rows,
+        ws_col: cols,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    syscall!(ioctl(fd, libc::TIOCSWINSZ, &size))?;
+    Ok(())
+}
+
+/// Gets a terminal's window size, as (rows, columns).
+pub fn window_size(fd: c_int) -> Result<(u16, u16)> {
+    let mut size = libc::winsize {
+        ws_row: 0,
+        ws_col: 0,
+        ws_xpixel: 0,
+        ws_ypixel: 0,
+    };
+    syscall!(ioctl(fd, libc::TIOCGWINSZ, &mut size))?;
+    Ok((size.ws_row, size.ws_col))
+}
+
+pub fn umount2(target: &Path, flags: c_int) -> Result<()> {
+    let target_c = path_cstring(target);
+    syscall!(umount2(target_c.as_ptr(), flags))?;
+    Ok(())
+}
+
 pub fn waitpid(pid: Pid, status: &mut c_int, flags: c_int) -> Result<()> {
     syscall!(waitpid(pid, status, flags))?;
     Ok(())
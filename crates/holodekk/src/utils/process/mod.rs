@@ -1,15 +1,13 @@
+use std::collections::HashMap;
 use std::fs::{self, File};
-use std::io::Read;
-use std::os::{
-    fd::AsRawFd,
-    unix::io::{FromRawFd, RawFd},
-};
+use std::io::{self, Read};
+use std::os::fd::{AsRawFd, OwnedFd};
 use std::path::Path;
 use std::process::Command;
 
 use log::{debug, warn};
 use nix::{
-    fcntl::OFlag,
+    fcntl::{fcntl, FcntlArg, FdFlag, OFlag},
     sys::{
         signal::{kill, SIGINT, SIGKILL},
         wait::waitpid,
@@ -80,10 +78,13 @@ impl PidSyncMessage {
     }
 }
 
-pub fn setup_sync_pipe() -> std::result::Result<(File, RawFd), DaemonSyncError> {
-    let (parent_fd, child_fd) = pipe2(OFlag::empty())?;
-    let sync_pipe = unsafe { File::from_raw_fd(parent_fd.as_raw_fd()) };
-    Ok((sync_pipe, child_fd.as_raw_fd()))
+/// Creates the pipe a daemon reports its pid on: our end, and the daemon's (which only the
+/// daemon should hold open, once it's been spawned).
+pub fn setup_sync_pipe() -> std::result::Result<(File, OwnedFd), DaemonSyncError> {
+    let (parent_fd, child_fd) = pipe2(OFlag::O_CLOEXEC)?;
+    // the daemon inherits its end
+    fcntl(child_fd.as_raw_fd(), FcntlArg::F_SETFD(FdFlag::empty()))?;
+    Ok((File::from(parent_fd), child_fd))
 }
 
 pub fn read_pid_from_sync_pipe(mut sync_pipe: File) -> std::result::Result<i32, DaemonSyncError> {
@@ -139,11 +140,13 @@ where
     command.arg("--bin-path");
     command.arg(paths.bin_root());
     command.arg("--sync-pipe");
-    command.arg(child_fd.to_string());
+    command.arg(child_fd.as_raw_fd().to_string());
 
     debug!("Spawning daemon: {:?}", command);
 
     let output = command.output()?;
+    // leaving the daemon the only writer, so we see EOF if it dies without reporting
+    drop(child_fd);
 
     if output.status.success() {
         let pid = get_daemon_pid(sync_pipe, pidfile.as_ref())?;
@@ -195,3 +198,77 @@ pub fn terminate_daemon(pid: i32) -> std::result::Result<i32, DaemonTerminationE
         }
     }
 }
+
+/// Lists the descendants of the given process (its children, their children, and so on), as
+/// found in `/proc`.
+pub fn descendants(pid: Pid) -> io::Result<Vec<Pid>> {
+    let mut children: HashMap<Pid, Vec<Pid>> = HashMap::new();
+    for entry in fs::read_dir("/proc")? {
+        let entry = entry?;
+        let Some(child) = entry
+            .file_name()
+            .to_str()
+            .and_then(|name| name.parse().ok())
+        else {
+            continue;
+        };
+        // processes can exit while we look
+        let Ok(stat) = fs::read_to_string(entry.path().join("stat")) else {
+            continue;
+        };
+        if let Some(parent) = parent_pid(&stat) {
+            children
+                .entry(parent)
+                .or_default()
+                .push(Pid::from_raw(child));
+        }
+    }
+
+    let mut found = Vec::new();
+    let mut pending = vec![pid];
+    while let Some(pid) = pending.pop() {
+        if let Some(children) = children.remove(&pid) {
+            pending.extend_from_slice(&children);
+            found.extend(children);
+        }
+    }
+    Ok(found)
+}
+
+/// Reads the parent pid from the contents of `/proc/<pid>/stat`.
+fn parent_pid(stat: &str) -> Option<Pid> {
+    // the command name (in parentheses) can contain anything, so fields are counted after it
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields
+        .split_whitespace()
+        .nth(1)?
+        .parse()
+        .ok()
+        .map(Pid::from_raw)
+}
+
+#[cfg(test)]
+mod tests {
+    use nix::unistd::getpid;
+
+    use super::*;
+
+    #[test]
+    fn reads_parent_pid_from_stat() {
+        let stat = "4242 (odd) name) S 17 4242 4242 0 -1 4194560 120 0 0 0";
+
+        assert_eq!(parent_pid(stat), Some(Pid::from_raw(17)));
+        assert_eq!(parent_pid("garbage"), None);
+    }
+
+    #[test]
+    fn finds_descendants() {
+        let mut child = Command::new("sleep").arg("5").spawn().unwrap();
+
+        let found = descendants(getpid()).unwrap();
+
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert!(found.contains(&Pid::from_raw(child.id() as i32)));
+    }
+}
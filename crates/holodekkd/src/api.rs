@@ -3,19 +3,36 @@ use std::sync::Arc;
 use axum::{response::IntoResponse, routing::get, Json, Router};
 use serde::{Deserialize, Serialize};
 
-use holodekk::apis::http::{entity::scene, ApiState};
+use holodekk::apis::http::{entity::scene, registry, secrets, ApiState};
+use holodekk::cgroups::Cgroups;
 use holodekk::entities::{SceneEntityRepository, SubroutineEntityRepository};
+use holodekk::images::{ImageVerifier, SubroutineImageStore};
+use holodekk::ports::PortAllocator;
+use holodekk::privileges::SceneUserAllocator;
+use holodekk::registry::Registry;
+use holodekk::secrets::SecretStore;
 use holodekk::services::{scene::SceneEntityService, subroutine::SubroutineEntityService};
+use holodekk::shim::ShimLauncher;
 use holodekk::utils::{
     servers::{start_http_server, HttpServerHandle},
     ConnectionInfo,
 };
+use holodekk::HolodekkPaths;
 
 pub struct HolodekkdApiState<R>
 where
     R: SceneEntityRepository + SubroutineEntityRepository,
 {
     repo: Arc<R>,
+    registry: Arc<Registry>,
+    secrets: Arc<SecretStore>,
+    images: Option<Arc<dyn SubroutineImageStore>>,
+    verifier: Option<Arc<ImageVerifier>>,
+    ports: Option<Arc<PortAllocator>>,
+    cgroups: Option<Arc<Cgroups>>,
+    scene_users: Option<Arc<SceneUserAllocator>>,
+    paths: Option<Arc<HolodekkPaths>>,
+    launcher: Option<Arc<ShimLauncher>>,
     scene_entity_service: Arc<SceneEntityService<R>>,
     subroutine_entity_service: Arc<SubroutineEntityService<R>>,
 }
@@ -24,19 +41,114 @@ impl<R> HolodekkdApiState<R>
 where
     R: SceneEntityRepository + SubroutineEntityRepository,
 {
-    pub fn new(repo: Arc<R>) -> Self {
+    pub fn new(repo: Arc<R>, registry: Arc<Registry>, secrets: Arc<SecretStore>) -> Self {
         let scene_entity_service = Arc::new(SceneEntityService::new(repo.clone()));
         let subroutine_entity_service = Arc::new(SubroutineEntityService::new(repo.clone()));
         Self {
             repo,
+            registry,
+            secrets,
+            images: None,
+            verifier: None,
+            ports: None,
+            cgroups: None,
+            scene_users: None,
+            paths: None,
+            launcher: None,
             scene_entity_service,
             subroutine_entity_service,
         }
     }
 
+    /// Uses image manifests as defaults for new subroutines.
+    pub fn with_images(mut self, images: Arc<dyn SubroutineImageStore>) -> Self {
+        self.images = Some(images);
+        self.rebuild_subroutine_entity_service();
+        self
+    }
+
+    /// Applies image signature verification to subroutine creation.
+    pub fn with_image_verifier(mut self, verifier: Arc<ImageVerifier>) -> Self {
+        self.verifier = Some(verifier);
+        self.rebuild_subroutine_entity_service();
+        self
+    }
+
+    /// Allocates host ports to subroutines that declare one.
+    pub fn with_port_allocator(mut self, ports: Arc<PortAllocator>) -> Self {
+        self.ports = Some(ports);
+        self.rebuild_subroutine_entity_service();
+        self
+    }
+
+    /// Reports subroutine resource usage from the given cgroup hierarchy.
+    pub fn with_cgroups(mut self, cgroups: Arc<Cgroups>) -> Self {
+        self.cgroups = Some(cgroups);
+        self.rebuild_subroutine_entity_service();
+        self
+    }
+
+    /// Runs each scene's subroutines as a user of its own.
+    pub fn with_scene_users(mut self, scene_users: Arc<SceneUserAllocator>) -> Self {
+        self.scene_entity_service = Arc::new(
+            SceneEntityService::new(self.repo.clone()).with_scene_users(scene_users.clone()),
+        );
+        self.scene_users = Some(scene_users);
+        self.rebuild_subroutine_entity_service();
+        self
+    }
+
+    /// Serves subroutine logs from beneath the given paths.
+    pub fn with_paths(mut self, paths: Arc<HolodekkPaths>) -> Self {
+        self.paths = Some(paths);
+        self.rebuild_subroutine_entity_service();
+        self
+    }
+
+    /// Launches subroutines under the shim as they're created.
+    pub fn with_launcher(mut self, launcher: Arc<ShimLauncher>) -> Self {
+        self.launcher = Some(launcher);
+        self.rebuild_subroutine_entity_service();
+        self
+    }
+
+    fn rebuild_subroutine_entity_service(&mut self) {
+        let mut service = SubroutineEntityService::new(self.repo.clone());
+        if let Some(images) = self.images.as_ref() {
+            service = service.with_images(images.clone());
+        }
+        if let Some(verifier) = self.verifier.as_ref() {
+            service = service.with_image_verifier(verifier.clone());
+        }
+        if let Some(ports) = self.ports.as_ref() {
+            service = service.with_port_allocator(ports.clone());
+        }
+        if let Some(cgroups) = self.cgroups.as_ref() {
+            service = service.with_cgroups(cgroups.clone());
+        }
+        if let Some(scene_users) = self.scene_users.as_ref() {
+            service = service.with_scene_users(scene_users.clone());
+        }
+        if let Some(paths) = self.paths.as_ref() {
+            service = service.with_paths(paths.clone());
+        }
+        if let Some(launcher) = self.launcher.as_ref() {
+            service = service.with_launcher(launcher.clone());
+        }
+        self.subroutine_entity_service = Arc::new(service);
+    }
+
     pub fn repo(&self) -> Arc<R> {
         self.repo.clone()
     }
+
+    pub fn registry(&self) -> Arc<Registry> {
+        self.registry.clone()
+    }
+
+    pub fn secrets(&self) -> Arc<SecretStore> {
+        self.secrets.clone()
+    }
 }
 
 impl<R> ApiState<SceneEntityService<R>, SubroutineEntityService<R>> for HolodekkdApiState<R>
@@ -58,6 +170,8 @@ where
 {
     Router::new()
         .route("/health", get(health))
+        .nest("/registry", registry::router(api_state.registry()))
+        .nest("/secrets", secrets::router(api_state.secrets()))
         .nest("/scenes", scene::router(api_state))
 }
 
@@ -70,11 +184,10 @@ impl Server {
         Self { handle }
     }
 
-    pub fn start<R>(config: &ConnectionInfo, repo: Arc<R>) -> Self
+    pub fn start<R>(config: &ConnectionInfo, state: HolodekkdApiState<R>) -> Self
     where
         R: SceneEntityRepository + SubroutineEntityRepository,
     {
-        let state = HolodekkdApiState::new(repo);
         let handle = start_http_server(config, router(Arc::new(state)));
 
         Self::new(handle)
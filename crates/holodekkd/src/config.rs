@@ -1,12 +1,19 @@
 use std::path::{Path, PathBuf};
 
-use holodekk::{repositories::RepositoryKind, utils::ConnectionInfo, HolodekkPaths};
+use holodekk::{
+    cgroups::Cgroups, images::SignaturePolicy, ports::PortRange, privileges::UserRange,
+    repositories::RepositoryKind, utils::ConnectionInfo, HolodekkPaths,
+};
 
 #[derive(Clone, Debug)]
 pub struct HolodekkdConfig {
     paths: HolodekkPaths,
     holodekk_api_config: ConnectionInfo,
     repo_kind: RepositoryKind,
+    signature_policy: SignaturePolicy,
+    port_range: PortRange,
+    cgroups: Cgroups,
+    user_range: UserRange,
 }
 
 impl HolodekkdConfig {
@@ -16,6 +23,8 @@ impl HolodekkdConfig {
         bin_root: P,
         holodekk_api_config: ConnectionInfo,
         repo_kind: RepositoryKind,
+        signature_policy: SignaturePolicy,
+        port_range: PortRange,
     ) -> Self
     where
         P: AsRef<Path> + Into<PathBuf>,
@@ -28,9 +37,18 @@ impl HolodekkdConfig {
             paths,
             holodekk_api_config,
             repo_kind,
+            signature_policy,
+            port_range,
+            cgroups: Cgroups::default(),
+            user_range: UserRange::default(),
         }
     }
 
+    /// Uses the given cgroup hierarchy (rather than the system default).
+    pub fn with_cgroups(self, cgroups: Cgroups) -> Self {
+        Self { cgroups, ..self }
+    }
+
     pub fn paths(&self) -> &HolodekkPaths {
         &self.paths
     }
@@ -39,6 +57,27 @@ impl HolodekkdConfig {
         self.repo_kind
     }
 
+    pub fn signature_policy(&self) -> SignaturePolicy {
+        self.signature_policy
+    }
+
+    pub fn port_range(&self) -> PortRange {
+        self.port_range
+    }
+
+    /// Allocates scene users from the given range (rather than the default).
+    pub fn with_user_range(self, user_range: UserRange) -> Self {
+        Self { user_range, ..self }
+    }
+
+    pub fn user_range(&self) -> UserRange {
+        self.user_range
+    }
+
+    pub fn cgroups(&self) -> &Cgroups {
+        &self.cgroups
+    }
+
     pub fn holodekk_api_config(&self) -> &ConnectionInfo {
         &self.holodekk_api_config
     }
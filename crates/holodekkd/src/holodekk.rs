@@ -11,7 +11,10 @@ use holodekk::entities::{
     SceneName,
 };
 use holodekk::enums::SceneStatus;
+use holodekk::ports::PortAllocatorError;
+use holodekk::privileges::PrivilegesError;
 use holodekk::services::scene::{FindScenes, FindScenesInput, SceneEntityService};
+use holodekk::shim::recover_subroutines;
 use holodekk::utils::process::terminate_daemon;
 use holodekk::ScenePaths;
 
@@ -27,6 +30,10 @@ pub enum HolodekkError {
     Scene(#[from] SceneError),
     #[error("IO error")]
     Io(#[from] std::io::Error),
+    #[error("Unable to load port assignments")]
+    Ports(#[source] PortAllocatorError),
+    #[error("Unable to load scene user assignments")]
+    Users(#[source] PrivilegesError),
     #[error("Error during Holodekk initialization: {0}")]
     Initialization(String),
 }
@@ -76,6 +83,7 @@ where
         let (events_tx, events_rx) = channel(32);
 
         let scenes = initialize_scenes(config.clone(), repo.clone()).await?;
+        initialize_subroutines(config.clone(), repo.clone()).await?;
 
         let scene_watcher = repo.subscribe_scenes().await.unwrap();
 
@@ -220,3 +228,22 @@ where
 
     Ok(scenes)
 }
+
+/// Adopts the shims left running by an earlier holodekkd, and brings the repository up to date
+/// with what became of the rest.
+///
+/// Adopted shims aren't handed to anything: they keep reporting to the API themselves (see
+/// [`recover_subroutines`]), so this only reconciles the repository with them.
+pub async fn initialize_subroutines<R>(
+    config: Arc<HolodekkdConfig>,
+    repo: Arc<R>,
+) -> Result<(), HolodekkError>
+where
+    R: EntityRepository,
+{
+    let adopted = recover_subroutines(config.paths().subroutines_root(), repo.as_ref())
+        .await
+        .map_err(|err| HolodekkError::Initialization(format!("{:?}", err)))?;
+    info!("Adopted {} running subroutine(s)", adopted.len());
+    Ok(())
+}
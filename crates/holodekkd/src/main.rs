@@ -6,12 +6,19 @@ use clap::Parser;
 use log::debug;
 
 use holodekk::{
+    cgroups::{Cgroups, DEFAULT_CGROUP_ROOT, DEFAULT_CGROUP_SLICE},
     entities::EntityRepository,
+    images::{FilesystemSubroutineImageStore, ImageVerifier, SignaturePolicy, TrustStore},
+    ports::{PortAllocator, PortRange},
+    privileges::{SceneUserAllocator, UserRange},
+    registry::Registry,
     repositories::{
         etcd::EtcdRepository,
         memory::{MemoryDatabase, MemoryRepository},
         RepositoryKind,
     },
+    secrets::SecretStore,
+    shim::ShimLauncher,
     utils::{
         signals::{SignalKind, Signals},
         ConnectionInfo,
@@ -20,7 +27,7 @@ use holodekk::{
 
 use holodekkd::config::HolodekkdConfig;
 
-use holodekkd::api::Server;
+use holodekkd::api::{HolodekkdApiState, Server};
 use holodekkd::holodekk::{Holodekk, HolodekkError};
 
 #[derive(Parser, Debug)]
@@ -45,6 +52,26 @@ struct Options {
     /// Holodekk API port
     #[arg(long, value_enum)]
     repository: RepositoryKind,
+
+    /// Image signature policy applied when creating subroutines
+    #[arg(long, value_enum, default_value = "warn")]
+    signature_policy: SignaturePolicy,
+
+    /// Range host ports are allocated to subroutines from
+    #[arg(long, default_value = "20000-29999")]
+    port_range: PortRange,
+
+    /// Range user ids are allocated to scenes from
+    #[arg(long, default_value = "200000-265535")]
+    user_range: UserRange,
+
+    /// Root of the cgroup v2 hierarchy
+    #[arg(long, default_value = DEFAULT_CGROUP_ROOT)]
+    cgroup_root: PathBuf,
+
+    /// cgroup subroutine groups are created under
+    #[arg(long, default_value = DEFAULT_CGROUP_SLICE)]
+    cgroup_slice: String,
 }
 
 fn ensure_directory<P: AsRef<Path>>(path: P) -> std::io::Result<()> {
@@ -60,13 +87,19 @@ async fn main() -> std::result::Result<(), HolodekkError> {
 
     let api_config = ConnectionInfo::tcp(&options.port, None);
 
-    let holodekkd_config = Arc::new(HolodekkdConfig::new(
-        &options.data_root,
-        &options.exec_root,
-        &options.bin_path,
-        api_config,
-        options.repository,
-    ));
+    let holodekkd_config = Arc::new(
+        HolodekkdConfig::new(
+            &options.data_root,
+            &options.exec_root,
+            &options.bin_path,
+            api_config,
+            options.repository,
+            options.signature_policy,
+            options.port_range,
+        )
+        .with_cgroups(Cgroups::new(&options.cgroup_root, &options.cgroup_slice))
+        .with_user_range(options.user_range),
+    );
 
     env_logger::init();
 
@@ -78,6 +111,9 @@ async fn main() -> std::result::Result<(), HolodekkError> {
     // ensure required paths exist
     ensure_directory(holodekkd_config.paths().scenes_root())?;
     ensure_directory(holodekkd_config.paths().subroutines_root())?;
+    ensure_directory(holodekkd_config.paths().images_root())?;
+    ensure_directory(holodekkd_config.paths().registry_root())?;
+    ensure_directory(holodekkd_config.paths().trust_root())?;
 
     match holodekkd_config.repo_kind() {
         RepositoryKind::Memory => {
@@ -103,7 +139,35 @@ where
     R: EntityRepository,
 {
     let holodekk = Holodekk::start(config.clone(), repo.clone()).await?;
-    let mut api_server = Server::start(config.holodekk_api_config(), repo.clone());
+    let registry = Arc::new(Registry::new(config.paths()));
+    registry.init()?;
+    let secrets = Arc::new(SecretStore::new(config.paths()));
+    secrets.init()?;
+    let images = Arc::new(FilesystemSubroutineImageStore::new(config.paths()));
+    let trust = TrustStore::new(config.paths());
+    let verifier = Arc::new(ImageVerifier::new(
+        images.clone(),
+        trust,
+        config.signature_policy(),
+    ));
+    let ports = Arc::new(
+        PortAllocator::new(config.paths(), config.port_range()).map_err(HolodekkError::Ports)?,
+    );
+    let scene_users = Arc::new(
+        SceneUserAllocator::new(config.paths(), config.user_range())
+            .map_err(HolodekkError::Users)?,
+    );
+    let paths = Arc::new(config.paths().clone());
+    let launcher = Arc::new(ShimLauncher::new(paths.clone(), secrets.clone()));
+    let state = HolodekkdApiState::new(repo.clone(), registry, secrets)
+        .with_images(images)
+        .with_image_verifier(verifier)
+        .with_port_allocator(ports)
+        .with_cgroups(Arc::new(config.cgroups().clone()))
+        .with_scene_users(scene_users)
+        .with_paths(paths)
+        .with_launcher(launcher);
+    let mut api_server = Server::start(config.holodekk_api_config(), state);
 
     let signal = Signals::new().await;
     match signal {
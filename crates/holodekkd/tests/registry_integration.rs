@@ -0,0 +1,235 @@
+use std::fs;
+use std::net::TcpListener;
+use std::path::Path;
+use std::sync::Arc;
+
+use futures_util::FutureExt;
+use tempfile::{tempdir, TempDir};
+use tokio::{
+    sync::oneshot::{channel, Sender},
+    task::JoinHandle,
+};
+
+use holodekk::images::{
+    parse_signing_key, sign_image, FilesystemSubroutineImageStore, ImageName, SignatureStatus,
+    SubroutineImageStore, TrustStore,
+};
+use holodekk::registry::{Registry, RegistryClient, RegistryClientError};
+use holodekk::repositories::memory::{MemoryDatabase, MemoryRepository};
+use holodekk::secrets::SecretStore;
+
+use holodekkd::api::{router, HolodekkdApiState};
+
+struct Daemon {
+    root: TempDir,
+    endpoint: String,
+    shutdown_tx: Sender<()>,
+    handle: JoinHandle<std::result::Result<(), hyper::Error>>,
+}
+
+impl Daemon {
+    async fn stop(self) {
+        self.shutdown_tx.send(()).unwrap();
+        self.handle.await.unwrap().unwrap();
+    }
+
+    fn client(&self) -> RegistryClient {
+        // small chunks, so uploads are spread over several requests
+        RegistryClient::new(&self.endpoint).with_chunk_size(512)
+    }
+}
+
+async fn launch_daemon() -> Daemon {
+    let root = tempdir().unwrap();
+    let registry = Arc::new(Registry::from_root(root.path().join("registry")));
+    registry.init().unwrap();
+    let repo = Arc::new(MemoryRepository::new(Arc::new(MemoryDatabase::new())));
+    let secrets = Arc::new(SecretStore::from_root(root.path().join("secrets")));
+    secrets.init().unwrap();
+    let state = Arc::new(HolodekkdApiState::new(repo, registry, secrets));
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let endpoint = format!("http://{}/registry", listener.local_addr().unwrap());
+    let (shutdown_tx, shutdown_rx) = channel();
+    let handle = tokio::spawn(async move {
+        axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(router(state).into_make_service())
+            .with_graceful_shutdown(shutdown_rx.map(drop))
+            .await
+    });
+
+    Daemon {
+        root,
+        endpoint,
+        shutdown_tx,
+        handle,
+    }
+}
+
+fn image_store(root: &Path, name: &str) -> FilesystemSubroutineImageStore {
+    let store = FilesystemSubroutineImageStore::from_root(root.join(name));
+    store.init().unwrap();
+    store
+}
+
+fn sample_directory(root: &Path) -> std::path::PathBuf {
+    let dir = root.join("src");
+    fs::create_dir_all(dir.join("lib")).unwrap();
+    fs::write(dir.join("holodekk.rb"), "puts 'hello'\n").unwrap();
+    // large enough to need several chunks
+    fs::write(dir.join("lib").join("data.txt"), "holodekk\n".repeat(1000)).unwrap();
+    dir
+}
+
+#[tokio::test]
+async fn pushes_and_pulls_images_between_daemons() {
+    let temp = tempdir().unwrap();
+    let name = ImageName::from("acme/widgets");
+    let origin = image_store(temp.path(), "origin");
+    let image = origin
+        .create_from_directory(&name, sample_directory(temp.path()))
+        .unwrap();
+
+    let first = launch_daemon().await;
+    let second = launch_daemon().await;
+
+    // origin -> first daemon -> intermediate store -> second daemon -> final store
+    first
+        .client()
+        .push(&origin, &image.id, "latest")
+        .await
+        .unwrap();
+    let intermediate = image_store(temp.path(), "intermediate");
+    let pulled = first
+        .client()
+        .pull(&intermediate, &name, "latest")
+        .await
+        .unwrap();
+    second
+        .client()
+        .push(&intermediate, &pulled.id, "v1")
+        .await
+        .unwrap();
+    let last = image_store(temp.path(), "final");
+    let pulled = second.client().pull(&last, &name, "v1").await.unwrap();
+
+    assert_eq!(pulled.digest, image.digest);
+    assert_eq!(pulled.kind, image.kind);
+    assert_eq!(
+        fs::read_to_string(pulled.path.join("lib").join("data.txt")).unwrap(),
+        "holodekk\n".repeat(1000)
+    );
+
+    first.stop().await;
+    second.stop().await;
+}
+
+#[tokio::test]
+async fn pushing_twice_reuses_existing_blobs() {
+    let temp = tempdir().unwrap();
+    let name = ImageName::from("acme/widgets");
+    let origin = image_store(temp.path(), "origin");
+    let image = origin
+        .create_from_directory(&name, sample_directory(temp.path()))
+        .unwrap();
+    let daemon = launch_daemon().await;
+
+    let first = daemon
+        .client()
+        .push(&origin, &image.id, "v1")
+        .await
+        .unwrap();
+    let second = daemon
+        .client()
+        .push(&origin, &image.id, "v2")
+        .await
+        .unwrap();
+
+    assert_eq!(first, second);
+    daemon.stop().await;
+}
+
+#[tokio::test]
+async fn pulled_images_keep_their_signatures() {
+    let temp = tempdir().unwrap();
+    let name = ImageName::from("acme/widgets");
+    let origin = image_store(temp.path(), "origin");
+    let image = origin
+        .create_from_directory(&name, sample_directory(temp.path()))
+        .unwrap();
+    let key = parse_signing_key(&"07".repeat(32)).unwrap();
+    sign_image(&origin, &image.id, &key).unwrap();
+    let daemon = launch_daemon().await;
+
+    daemon
+        .client()
+        .push(&origin, &image.id, "latest")
+        .await
+        .unwrap();
+    let store = image_store(temp.path(), "pulled");
+    let pulled = daemon.client().pull(&store, &name, "latest").await.unwrap();
+
+    let trust = TrustStore::from_root(temp.path().join("trust"));
+    trust.add("acme", &key.verifying_key()).unwrap();
+    assert_eq!(
+        trust
+            .verify(pulled.digest.as_ref(), &pulled.signatures)
+            .unwrap(),
+        SignatureStatus::Verified {
+            key: "acme".to_string()
+        }
+    );
+    daemon.stop().await;
+}
+
+#[tokio::test]
+async fn pull_rejects_corrupted_blobs() {
+    let temp = tempdir().unwrap();
+    let name = ImageName::from("acme/widgets");
+    let origin = image_store(temp.path(), "origin");
+    let image = origin
+        .create_from_directory(&name, sample_directory(temp.path()))
+        .unwrap();
+    let daemon = launch_daemon().await;
+    daemon
+        .client()
+        .push(&origin, &image.id, "latest")
+        .await
+        .unwrap();
+
+    let blob = daemon
+        .root
+        .path()
+        .join("registry/blobs/sha256")
+        .join(image.digest.as_ref().unwrap().hex());
+    fs::write(blob, "tampered").unwrap();
+
+    let store = image_store(temp.path(), "pulled");
+    let result = daemon.client().pull(&store, &name, "latest").await;
+
+    assert!(matches!(
+        result.unwrap_err(),
+        RegistryClientError::DigestMismatch { .. }
+    ));
+    assert!(store.find().unwrap().is_empty());
+    daemon.stop().await;
+}
+
+#[tokio::test]
+async fn pull_fails_for_unknown_tags() {
+    let temp = tempdir().unwrap();
+    let daemon = launch_daemon().await;
+    let store = image_store(temp.path(), "pulled");
+
+    let result = daemon
+        .client()
+        .pull(&store, &ImageName::from("acme/widgets"), "latest")
+        .await;
+
+    assert!(matches!(
+        result.unwrap_err(),
+        RegistryClientError::Status(hyper::StatusCode::NOT_FOUND, _)
+    ));
+    daemon.stop().await;
+}
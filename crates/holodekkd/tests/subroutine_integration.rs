@@ -0,0 +1,162 @@
+use std::net::TcpListener;
+use std::sync::Arc;
+
+use futures_util::FutureExt;
+use tempfile::{tempdir, TempDir};
+use tokio::{
+    sync::oneshot::{channel, Sender},
+    task::JoinHandle,
+};
+
+use holodekk::apis::http::entity::subroutine::{SubroutineClient, SubroutineClientError};
+use holodekk::entities::{
+    SceneEntity, SceneEntityRepository, SubroutineEntity, SubroutineEntityId,
+    SubroutineEntityRepository, SubroutineExit, SubroutineStatusChange,
+};
+use holodekk::enums::{SubroutineHealth, SubroutineStatus};
+use holodekk::images::SubroutineImageId;
+use holodekk::registry::Registry;
+use holodekk::repositories::memory::{MemoryDatabase, MemoryRepository};
+use holodekk::secrets::SecretStore;
+
+use holodekkd::api::{router, HolodekkdApiState};
+
+struct Daemon {
+    _root: TempDir,
+    endpoint: String,
+    repo: Arc<MemoryRepository>,
+    shutdown_tx: Sender<()>,
+    handle: JoinHandle<std::result::Result<(), hyper::Error>>,
+}
+
+impl Daemon {
+    async fn stop(self) {
+        self.shutdown_tx.send(()).unwrap();
+        self.handle.await.unwrap().unwrap();
+    }
+
+    async fn create_subroutine(&self) -> SubroutineEntity {
+        let scene = self
+            .repo
+            .scenes_create(SceneEntity::new("reports".into()))
+            .await
+            .unwrap();
+        self.repo
+            .subroutines_create(SubroutineEntity::new(
+                &scene.id,
+                &SubroutineImageId::generate(&"acme/widgets".into()),
+            ))
+            .await
+            .unwrap()
+    }
+}
+
+async fn launch_daemon() -> Daemon {
+    let root = tempdir().unwrap();
+    let repo = Arc::new(MemoryRepository::new(Arc::new(MemoryDatabase::new())));
+    let secrets = Arc::new(SecretStore::from_root(root.path().join("secrets")));
+    secrets.init().unwrap();
+    let registry = Arc::new(Registry::from_root(root.path().join("registry")));
+    let state = Arc::new(HolodekkdApiState::new(repo.clone(), registry, secrets));
+
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let endpoint = format!("http://{}", listener.local_addr().unwrap());
+    let (shutdown_tx, shutdown_rx) = channel();
+    let handle = tokio::spawn(async move {
+        axum::Server::from_tcp(listener)
+            .unwrap()
+            .serve(router(state).into_make_service())
+            .with_graceful_shutdown(shutdown_rx.map(drop))
+            .await
+    });
+
+    Daemon {
+        _root: root,
+        endpoint,
+        repo,
+        shutdown_tx,
+        handle,
+    }
+}
+
+#[tokio::test]
+async fn records_reported_health() {
+    let daemon = launch_daemon().await;
+    let subroutine = daemon.create_subroutine().await;
+    let client = SubroutineClient::new(
+        &daemon.endpoint,
+        &subroutine.scene_entity_id,
+        &subroutine.id,
+    );
+
+    client
+        .report_health(SubroutineHealth::Unhealthy)
+        .await
+        .unwrap();
+
+    assert_eq!(
+        daemon
+            .repo
+            .subroutines_get(&subroutine.id)
+            .await
+            .unwrap()
+            .health,
+        SubroutineHealth::Unhealthy
+    );
+    daemon.stop().await;
+}
+
+#[tokio::test]
+async fn records_reported_status() {
+    let daemon = launch_daemon().await;
+    let subroutine = daemon.create_subroutine().await;
+    let client = SubroutineClient::new(
+        &daemon.endpoint,
+        &subroutine.scene_entity_id,
+        &subroutine.id,
+    );
+
+    let exit = SubroutineExit {
+        status: SubroutineStatus::Exited { code: 2 },
+        exited_at: chrono::Utc::now(),
+        stderr_tail: vec!["no such file".into()],
+    };
+    client
+        .report_status(
+            SubroutineStatusChange::new(SubroutineStatus::Exited { code: 2 })
+                .with_exit(exit.clone()),
+        )
+        .await
+        .unwrap();
+    client
+        .report_status(SubroutineStatusChange::new(
+            SubroutineStatus::CrashLoopBackOff(3),
+        ))
+        .await
+        .unwrap();
+
+    let recorded = daemon.repo.subroutines_get(&subroutine.id).await.unwrap();
+    assert_eq!(recorded.status, SubroutineStatus::CrashLoopBackOff(3));
+    assert!(recorded.status_changed_at.is_some());
+    assert_eq!(recorded.last_exit, Some(exit));
+    daemon.stop().await;
+}
+
+#[tokio::test]
+async fn reports_for_unknown_subroutines_are_rejected() {
+    let daemon = launch_daemon().await;
+    let subroutine = daemon.create_subroutine().await;
+    let client = SubroutineClient::new(
+        &daemon.endpoint,
+        &subroutine.scene_entity_id,
+        &SubroutineEntityId::generate(),
+    );
+
+    let result = client.report_health(SubroutineHealth::Healthy).await;
+
+    assert!(matches!(
+        result.unwrap_err(),
+        SubroutineClientError::Status(hyper::StatusCode::NOT_FOUND, _)
+    ));
+    daemon.stop().await;
+}